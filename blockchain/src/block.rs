@@ -1,36 +1,140 @@
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use fiitcoin::{
+    handler::{Handler, TxHandler},
+    hash::{Hasher, Sha256Hasher},
+    tx::TxError,
+    utxo::UTXOPool,
+};
 use rsa::pkcs1v15::VerifyingKey;
-use sha2::{Digest, Sha256};
+use sha2::Sha256;
+
+use crate::blockchain::Blockchain;
 
-pub const COINBASE: u32 = 625;
+pub const COINBASE: u64 = 625;
 
 pub type Sha256Digest = [u8; 32];
 
 #[derive(Debug)]
 pub struct IncompleteBlock {
     prev: Sha256Digest,
+    is_genesis: bool,
+    timestamp: u64,
+    difficulty: u32,
     coinbase: fiitcoin::tx::Tx,
     txs: Vec<fiitcoin::tx::Tx>,
+    hasher: &'static dyn Hasher,
 }
 
 impl IncompleteBlock {
     pub fn new(prev: Sha256Digest, address: &VerifyingKey<Sha256>) -> Self {
-        let coinbase = fiitcoin::tx::Tx::coinbase(COINBASE, address);
+        Self::with_hasher(prev, address, &Sha256Hasher)
+    }
+
+    /// Same as `new`, but the block and its coinbase are hashed by given hasher
+    pub fn with_hasher(
+        prev: Sha256Digest,
+        address: &VerifyingKey<Sha256>,
+        hasher: &'static dyn Hasher,
+    ) -> Self {
+        Self::with_reward(prev, address, COINBASE, hasher)
+    }
+
+    /// Same as `with_hasher`, but the coinbase pays given reward instead of
+    /// `COINBASE`, see `ChainParams`
+    pub fn with_reward(
+        prev: Sha256Digest,
+        address: &VerifyingKey<Sha256>,
+        reward: u64,
+        hasher: &'static dyn Hasher,
+    ) -> Self {
+        let coinbase = fiitcoin::tx::Tx::block_coinbase_with(reward, address, prev, hasher);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
         Self {
             prev,
+            is_genesis: false,
+            timestamp,
+            difficulty: 0,
             coinbase,
             txs: vec![],
+            hasher,
         }
     }
 
-    pub fn finalize(self) -> Block {
-        let raw = self.raw();
+    /// Creates the first block of a chain, which has no parent
+    pub fn genesis(address: &VerifyingKey<Sha256>) -> Self {
+        Self::genesis_with_hasher(address, &Sha256Hasher)
+    }
 
-        let mut hasher = Sha256::new();
-        hasher.update(raw);
+    /// Same as `genesis`, but the block and its coinbase are hashed by given hasher
+    pub fn genesis_with_hasher(
+        address: &VerifyingKey<Sha256>,
+        hasher: &'static dyn Hasher,
+    ) -> Self {
+        Self {
+            is_genesis: true,
+            ..Self::with_hasher([0; 32], address, hasher)
+        }
+    }
+
+    /// Same as `genesis`, but the coinbase has an output for each of given
+    /// values and addresses, e.g. to fund several participants of a test
+    /// network. The outputs become UTXOs of the genesis pool, see
+    /// `genesis_utxos`. Fails if there are no payments or they don't fit
+    /// into one coinbase.
+    pub fn genesis_split(payments: &[(u64, &VerifyingKey<Sha256>)]) -> Result<Self, TxError> {
+        let coinbase =
+            fiitcoin::tx::Tx::block_coinbase_split_with(payments, [0; 32], &Sha256Hasher)?;
+        // there is at least one payment, otherwise the coinbase would fail
+        Ok(Self {
+            coinbase,
+            ..Self::genesis(payments[0].1)
+        })
+    }
+
+    /// Overrides the creation time of the block, in seconds since UNIX epoch
+    pub fn set_timestamp(&mut self, timestamp: u64) {
+        self.timestamp = timestamp;
+    }
+
+    /// Sets the number of leading zero bits the hash of the block must have
+    pub fn set_difficulty(&mut self, difficulty: u32) {
+        self.difficulty = difficulty;
+    }
+
+    /// Mines the block, searching for a nonce which satisfies its difficulty
+    pub fn finalize(self) -> Block {
+        let merkle_root = block_merkle_root(self.hasher, &self.coinbase, &self.txs);
+        let mut nonce = 0;
+        let hash = loop {
+            let hash = hash_header(
+                self.hasher,
+                (!self.is_genesis).then_some(&self.prev),
+                self.timestamp,
+                self.difficulty,
+                nonce,
+                &merkle_root,
+            );
+            if leading_zero_bits(&hash) >= self.difficulty {
+                break hash;
+            }
+            nonce += 1;
+        };
 
         Block {
-            hash: hasher.finalize().into(),
+            hash,
             prev: self.prev,
+            is_genesis: self.is_genesis,
+            merkle_root,
+            timestamp: self.timestamp,
+            difficulty: self.difficulty,
+            nonce,
             coinbase: self.coinbase,
             txs: self.txs,
         }
@@ -40,19 +144,256 @@ impl IncompleteBlock {
         self.txs.push(tx);
     }
 
-    fn raw(&self) -> Vec<u8> {
-        let mut b = vec![];
+    /// Adds all given txs, skipping those which are already in this block
+    pub fn add_txs(&mut self, txs: impl IntoIterator<Item = fiitcoin::tx::Tx>) {
+        for tx in txs {
+            if tx.hash() == self.coinbase.hash() || self.txs.iter().any(|t| t.hash() == tx.hash()) {
+                log::debug!("tx {:?} is already in block", tx.hash());
+                continue;
+            }
+            self.txs.push(tx);
+        }
+    }
+}
+
+/// Builds a block, validating each tx against the pool of the parent block
+/// updated by already added txs, so the finished block isn't rejected
+/// because of its txs
+pub struct BlockBuilder {
+    block: IncompleteBlock,
+    handler: Handler,
+}
 
-        if !self.prev.iter().all(|&x| x == 0) {
-            // not a genesis block
-            b.extend(self.prev);
+impl BlockBuilder {
+    /// Builder of the block on top of its parent retained by the chain, txs
+    /// are validated by the handler of the chain, see `Blockchain::handler`.
+    /// Returns `None` if the parent isn't retained.
+    pub fn new(block: IncompleteBlock, chain: &Blockchain) -> Option<Self> {
+        let parent_pool = chain.utxo_pool_at(block.prev)?;
+        Some(Self {
+            handler: chain.handler(parent_pool.clone()),
+            block,
+        })
+    }
+
+    /// Adds the tx if it is valid and not yet in the block, returns whether
+    /// it was added. Txs spending outputs of other txs must be added after
+    /// them.
+    pub fn add_tx(&mut self, tx: fiitcoin::tx::Tx) -> bool {
+        if tx.hash() == self.block.coinbase.hash()
+            || self.block.txs.iter().any(|t| t.hash() == tx.hash())
+        {
+            log::debug!("tx {:?} is already in block", tx.hash());
+            return false;
+        }
+        if !self.handler.is_tx_valid(&tx) {
+            log::debug!("tx {:?} is invalid on top of parent", tx.hash());
+            return false;
         }
 
-        for tx in self.txs.iter() {
-            b.extend(tx.hash());
+        self.handler.apply_tx(&tx);
+        self.block.add_tx(tx);
+        true
+    }
+
+    /// Returns pool of the parent updated by added txs
+    pub fn pool(&self) -> &UTXOPool {
+        self.handler.pool()
+    }
+
+    pub fn finalize(self) -> Block {
+        self.block.finalize()
+    }
+}
+
+/// Hashes the block header, `prev` is `None` only for a genesis block. Txs
+/// are committed to by the Merkle root, so the header can be validated
+/// without them.
+fn hash_header(
+    hasher: &dyn Hasher,
+    prev: Option<&Sha256Digest>,
+    timestamp: u64,
+    difficulty: u32,
+    nonce: u64,
+    merkle_root: &Sha256Digest,
+) -> Sha256Digest {
+    let mut b = vec![];
+
+    if let Some(prev) = prev {
+        b.extend(prev);
+    }
+
+    b.extend(timestamp.to_be_bytes());
+    b.extend(difficulty.to_be_bytes());
+    b.extend(nonce.to_be_bytes());
+    b.extend(merkle_root);
+
+    hasher.hash(&b)
+}
+
+/// Returns Merkle root of given hashes. Each level hashes concatenated pairs
+/// of the level below, the last hash of a level with odd length is paired
+/// with itself. Root of a single hash is the hash itself, of no hashes all
+/// zeros.
+pub fn merkle_root(hasher: &dyn Hasher, hashes: &[Sha256Digest]) -> Sha256Digest {
+    if hashes.is_empty() {
+        return [0; 32];
+    }
+
+    let mut level = hashes.to_vec();
+    while level.len() > 1 {
+        level = merkle_level_above(hasher, &level);
+    }
+    level[0]
+}
+
+/// Returns hashes needed to recompute `merkle_root` from the hash at given
+/// index, i.e. its sibling at each level from the lowest one up. `None` if
+/// the index is out of bounds.
+pub fn merkle_path(
+    hasher: &dyn Hasher,
+    hashes: &[Sha256Digest],
+    index: usize,
+) -> Option<Vec<Sha256Digest>> {
+    if index >= hashes.len() {
+        return None;
+    }
+
+    let mut path = vec![];
+    let mut level = hashes.to_vec();
+    let mut index = index;
+    while level.len() > 1 {
+        path.push(*level.get(index ^ 1).unwrap_or(&level[index]));
+        level = merkle_level_above(hasher, &level);
+        index /= 2;
+    }
+    Some(path)
+}
+
+/// Recomputes Merkle root from the hash at given index and its `merkle_path`,
+/// `None` if the index doesn't fit into a tree of the path's depth
+pub fn merkle_root_from_path(
+    hasher: &dyn Hasher,
+    hash: Sha256Digest,
+    index: usize,
+    path: &[Sha256Digest],
+) -> Option<Sha256Digest> {
+    let mut hash = hash;
+    let mut index = index;
+    for sibling in path.iter() {
+        let (left, right) = match index % 2 {
+            0 => (&hash, sibling),
+            _ => (sibling, &hash),
+        };
+        let mut b = left.to_vec();
+        b.extend(right);
+        hash = hasher.hash(&b);
+        index /= 2;
+    }
+    (index == 0).then_some(hash)
+}
+
+/// Hashes concatenated pairs of the level, the last hash of a level with odd
+/// length is paired with itself
+fn merkle_level_above(hasher: &dyn Hasher, level: &[Sha256Digest]) -> Vec<Sha256Digest> {
+    level
+        .chunks(2)
+        .map(|pair| {
+            let mut b = pair[0].to_vec();
+            b.extend(pair.get(1).unwrap_or(&pair[0]));
+            hasher.hash(&b)
+        })
+        .collect()
+}
+
+/// Merkle root of the coinbase followed by the txs of a block
+fn block_merkle_root(
+    hasher: &dyn Hasher,
+    coinbase: &fiitcoin::tx::Tx,
+    txs: &[fiitcoin::tx::Tx],
+) -> Sha256Digest {
+    merkle_root(hasher, &merkle_leaves(coinbase, txs))
+}
+
+fn merkle_leaves(coinbase: &fiitcoin::tx::Tx, txs: &[fiitcoin::tx::Tx]) -> Vec<Sha256Digest> {
+    std::iter::once(coinbase)
+        .chain(txs.iter())
+        .map(|tx| tx.hash())
+        .collect()
+}
+
+fn leading_zero_bits(hash: &Sha256Digest) -> u32 {
+    let mut bits = 0;
+    for byte in hash.iter() {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
         }
+    }
+    bits
+}
+
+/// Part of a block, which is hashed, so its hash and proof of work can be
+/// validated without the txs, e.g. when syncing headers first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    hash: Sha256Digest,
+    prev: Sha256Digest,
+    is_genesis: bool,
+    merkle_root: Sha256Digest,
+    timestamp: u64,
+    difficulty: u32,
+    nonce: u64,
+}
+
+impl BlockHeader {
+    pub fn hash(&self) -> [u8; 32] {
+        self.hash
+    }
+
+    pub fn prev(&self) -> [u8; 32] {
+        self.prev
+    }
+
+    pub fn is_genesis(&self) -> bool {
+        self.is_genesis
+    }
+
+    /// Merkle root of the coinbase followed by txs of the block, see
+    /// `merkle_root`
+    pub fn merkle_root(&self) -> [u8; 32] {
+        self.merkle_root
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Checks whether the hash corresponds to the rest of the header, hashed
+    /// by given hasher
+    pub fn is_hash_valid_with(&self, hasher: &dyn Hasher) -> bool {
+        self.hash
+            == hash_header(
+                hasher,
+                (!self.is_genesis).then_some(&self.prev),
+                self.timestamp,
+                self.difficulty,
+                self.nonce,
+                &self.merkle_root,
+            )
+    }
 
-        b
+    /// Checks whether the hash satisfies the difficulty
+    pub fn is_pow_valid(&self) -> bool {
+        leading_zero_bits(&self.hash) >= self.difficulty
     }
 }
 
@@ -60,6 +401,11 @@ impl IncompleteBlock {
 pub struct Block {
     hash: Sha256Digest,
     prev: Sha256Digest,
+    is_genesis: bool,
+    merkle_root: Sha256Digest,
+    timestamp: u64,
+    difficulty: u32,
+    nonce: u64,
     coinbase: fiitcoin::tx::Tx,
     txs: Vec<fiitcoin::tx::Tx>,
 }
@@ -81,8 +427,84 @@ impl Block {
         self.prev
     }
 
+    pub fn is_genesis(&self) -> bool {
+        self.is_genesis
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    pub fn merkle_root(&self) -> [u8; 32] {
+        self.merkle_root
+    }
+
+    /// Returns header of this block, without its txs
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            hash: self.hash,
+            prev: self.prev,
+            is_genesis: self.is_genesis,
+            merkle_root: self.merkle_root,
+            timestamp: self.timestamp,
+            difficulty: self.difficulty,
+            nonce: self.nonce,
+        }
+    }
+
+    /// Whether the coinbase and txs of this block contain a tx twice. Merkle
+    /// root pairs the last hash of an odd level with itself, so repeating
+    /// the last txs keeps the root and the hash of the block unchanged, see
+    /// CVE-2012-2459.
+    pub fn has_duplicate_txs(&self) -> bool {
+        let leaves = merkle_leaves(&self.coinbase, &self.txs);
+        let unique: HashSet<&Sha256Digest> = leaves.iter().collect();
+        unique.len() != leaves.len()
+    }
+
+    /// Returns index of the tx among the coinbase followed by txs of this
+    /// block and its `merkle_path`, `None` if the tx isn't in this block
+    pub fn merkle_path_of(
+        &self,
+        tx_hash: [u8; 32],
+        hasher: &dyn Hasher,
+    ) -> Option<(usize, Vec<Sha256Digest>)> {
+        let leaves = merkle_leaves(&self.coinbase, &self.txs);
+        let index = leaves.iter().position(|&hash| hash == tx_hash)?;
+        Some((index, merkle_path(hasher, &leaves, index)?))
+    }
+
+    /// Checks whether the hash of this block corresponds to its contents
+    pub fn is_hash_valid(&self) -> bool {
+        self.is_hash_valid_with(&Sha256Hasher)
+    }
+
+    /// Same as `is_hash_valid`, but the contents are hashed by given hasher
+    pub fn is_hash_valid_with(&self, hasher: &dyn Hasher) -> bool {
+        self.merkle_root == block_merkle_root(hasher, &self.coinbase, &self.txs)
+            && self.header().is_hash_valid_with(hasher)
+    }
+
+    /// Checks whether the hash of this block satisfies its difficulty
+    pub fn is_pow_valid(&self) -> bool {
+        leading_zero_bits(&self.hash) >= self.difficulty
+    }
+
     /// # DO NOT USE, don't use this function outside tests!
     pub fn set_prev(&mut self, prev: Sha256Digest) {
         self.prev = prev;
     }
+
+    /// # DO NOT USE, don't use this function outside tests!
+    pub fn txs_mut(&mut self) -> &mut Vec<fiitcoin::tx::Tx> {
+        &mut self.txs
+    }
 }
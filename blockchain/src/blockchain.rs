@@ -1,30 +1,274 @@
-use fiitcoin::{handler::TxHandler, tx::Tx, utxo::UTXOPool};
-use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
+use core::fmt;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::{block::Block, tx_pool::TxPool};
+use fiitcoin::{
+    handler::{saturating_value_sum, Handler, SignatureCache, TxHandler},
+    hash::{to_hex, Hasher, Sha256Hasher},
+    keys::PublicKey,
+    tx::{Output, Tx},
+    utxo::{UTXOPool, UTXO},
+};
+use ringbuffer::{AllocRingBuffer, RingBuffer};
+
+use crate::{
+    block::{merkle_root_from_path, Block, BlockHeader, COINBASE},
+    tx_pool::{fee, TxPool},
+};
 
 pub const CUT_OFF_AGE: usize = 12;
 
+/// Maximum number of blocks with unknown parents kept around, when full the
+/// oldest orphan is dropped
+pub const MAX_ORPHANS: usize = 64;
+
+/// Maximum number of imported headers waiting for their bodies, see
+/// `Blockchain::import_headers`
+pub const MAX_HEADERS: usize = 1024;
+
+/// Desired time between two blocks, in seconds
+pub const TARGET_BLOCK_TIME: u64 = 10;
+
+/// Number of mempool rounds for which a tx stays pending, see
+/// `Blockchain::advance_mempool_round`
+pub const MEMPOOL_EXPIRY: u64 = 50;
+
+/// How far ahead of the local clock a block's timestamp may be, in seconds
+pub const MAX_FUTURE_DRIFT: u64 = 2 * 60 * 60;
+
+/// Highest difficulty retargeting can reach, a 256 bit hash can't have more
+/// leading zero bits anyway
+pub const MAX_DIFFICULTY: u32 = 255;
+
+/// Number of blocks whose timestamps are considered when retargeting. The
+/// difficulty changes by at most one bit and only after it was unchanged for
+/// this many blocks.
+pub const RETARGET_WINDOW: usize = 6;
+
+/// Number of blocks, which must be built on top of a block, before its
+/// coinbase outputs count as spendable, see `spendable_balance_of`
+pub const COINBASE_MATURITY: usize = 6;
+
 pub type BlockNode = (Block, UTXOPool);
 
+/// Parameters of a chain, which can differ between networks, e.g. a testnet
+/// with a smaller reward. Default values are those of the main chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainParams {
+    /// Value of the single output of a coinbase tx
+    pub coinbase_reward: u64,
+    /// Number of retained blocks, older ones are forgotten. Retargeting
+    /// looks only at retained blocks, so difficulty never changes unless it
+    /// is more than `RETARGET_WINDOW`.
+    pub cut_off_age: usize,
+    /// Maximum number of txs in a block, the coinbase isn't counted
+    pub max_txs_per_block: usize,
+    /// Desired time between two blocks, in seconds
+    pub target_block_time: u64,
+    /// Number of mempool rounds for which a tx stays pending
+    pub mempool_expiry: u64,
+    /// Number of blocks built on top of a block, after which its coinbase
+    /// is mature, it should be at most `cut_off_age`
+    pub coinbase_maturity: usize,
+    /// Difficulty isn't increased by retargeting above this value
+    pub max_difficulty: u32,
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self {
+            coinbase_reward: COINBASE,
+            cut_off_age: CUT_OFF_AGE,
+            max_txs_per_block: usize::MAX,
+            target_block_time: TARGET_BLOCK_TIME,
+            mempool_expiry: MEMPOOL_EXPIRY,
+            coinbase_maturity: COINBASE_MATURITY,
+            max_difficulty: MAX_DIFFICULTY,
+        }
+    }
+}
+
+/// Status of an output at the best tip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStatus {
+    /// Output is in the UTXO pool at the best tip
+    Unspent,
+    /// Output was created in the best tip or one of its retained ancestors,
+    /// but was spent since
+    Spent,
+    /// Output wasn't created in any retained block on the chain at the best
+    /// tip
+    Unknown,
+    /// Output is a data output created in the best tip or one of its retained
+    /// ancestors, it is never pooled, so it can't be spent
+    Unspendable,
+}
+
+/// Outcome of returning txs of blocks disconnected by a reorg to the mempool
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReorgReport {
+    /// Txs which are valid on the new best chain, so they are pending again
+    pub reinstated: Vec<[u8; 32]>,
+    /// Txs which became invalid, e.g. because the new best chain spent
+    /// their inputs
+    pub dropped: Vec<[u8; 32]>,
+}
+
+/// Proof that a tx is in a block, which can be verified only with the block
+/// header, see `verify_inclusion`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InclusionProof {
+    pub block_hash: [u8; 32],
+    /// Height of the block in the retained best chain, 0 is the oldest
+    /// retained block
+    pub height: usize,
+    /// Index of the tx among the coinbase followed by txs of the block
+    pub index: usize,
+    /// Merkle path of the tx, see `block::merkle_path`
+    pub path: Vec<[u8; 32]>,
+}
+
+/// Checks that the proof leads from the tx hash to the Merkle root of the
+/// header, whose hash must be valid and be the block hash of the proof
+pub fn verify_inclusion(header: &BlockHeader, proof: &InclusionProof, tx_hash: [u8; 32]) -> bool {
+    verify_inclusion_with(header, proof, tx_hash, &Sha256Hasher)
+}
+
+/// Same as `verify_inclusion`, but hashes are computed by given hasher
+pub fn verify_inclusion_with(
+    header: &BlockHeader,
+    proof: &InclusionProof,
+    tx_hash: [u8; 32],
+    hasher: &dyn Hasher,
+) -> bool {
+    header.hash() == proof.block_hash
+        && header.is_hash_valid_with(hasher)
+        && merkle_root_from_path(hasher, tx_hash, proof.index, &proof.path)
+            == Some(header.merkle_root())
+}
+
 #[derive(Debug)]
 pub struct Blockchain {
-    chain: ConstGenericRingBuffer<BlockNode, CUT_OFF_AGE>,
+    chain: AllocRingBuffer<BlockNode>,
     mempool: TxPool,
+    /// Blocks whose parent wasn't known when they arrived, they are connected
+    /// once the parent is accepted
+    orphans: Vec<Block>,
+    /// Hashes of blocks which left the best chain, when the last accepted
+    /// block switched the best tip to another fork
+    disconnected: Vec<[u8; 32]>,
+    /// Imported headers of blocks, whose bodies weren't accepted yet, in
+    /// order in which they were imported
+    headers: Vec<BlockHeader>,
+    params: ChainParams,
+    hasher: &'static dyn Hasher,
+    /// Shared by all handlers validating txs of this chain, `None` unless
+    /// enabled by `enable_signature_cache`
+    signature_cache: Option<SignatureCache>,
 }
 
 impl Blockchain {
     pub fn new(genesis: Block, utxo_pool: UTXOPool) -> Self {
-        let mut chain = ConstGenericRingBuffer::new();
+        Self::with_hasher(genesis, utxo_pool, &Sha256Hasher)
+    }
+
+    /// Creates a chain, which expects blocks to be hashed by given hasher
+    pub fn with_hasher(genesis: Block, utxo_pool: UTXOPool, hasher: &'static dyn Hasher) -> Self {
+        Self::with_params(genesis, utxo_pool, ChainParams::default(), hasher)
+    }
+
+    /// Creates a chain with given parameters, blocks are expected to be
+    /// hashed by given hasher. Panics if the block isn't a genesis block or
+    /// `cut_off_age` is 0, see `try_with_params`.
+    pub fn with_params(
+        genesis: Block,
+        utxo_pool: UTXOPool,
+        params: ChainParams,
+        hasher: &'static dyn Hasher,
+    ) -> Self {
+        if let Err(err) = check_genesis(&genesis, &params) {
+            panic!("{}", err);
+        }
+        Self::with_checked_params(genesis, utxo_pool, params, hasher)
+    }
+
+    fn with_checked_params(
+        genesis: Block,
+        utxo_pool: UTXOPool,
+        params: ChainParams,
+        hasher: &'static dyn Hasher,
+    ) -> Self {
+        let mut chain = AllocRingBuffer::new(params.cut_off_age);
         chain.push((genesis, utxo_pool));
         let mempool = TxPool::new();
-        Self { chain, mempool }
+        Self {
+            chain,
+            mempool,
+            orphans: vec![],
+            disconnected: vec![],
+            headers: vec![],
+            params,
+            hasher,
+            signature_cache: None,
+        }
+    }
+
+    /// Same as `new`, but fails unless the pool contains exactly the outputs
+    /// of the genesis coinbase, see `genesis_pool`
+    pub fn try_new(genesis: Block, utxo_pool: UTXOPool) -> Result<Self, GenesisError> {
+        Self::try_with_params(genesis, utxo_pool, ChainParams::default(), &Sha256Hasher)
+    }
+
+    /// Same as `with_params`, but fails instead of panicking, and also if
+    /// the pool doesn't contain exactly the outputs of the genesis coinbase
+    pub fn try_with_params(
+        genesis: Block,
+        utxo_pool: UTXOPool,
+        params: ChainParams,
+        hasher: &'static dyn Hasher,
+    ) -> Result<Self, GenesisError> {
+        check_genesis(&genesis, &params)?;
+        if utxo_pool != genesis_pool(&genesis) {
+            return Err(GenesisError::PoolMismatch);
+        }
+        Ok(Self::with_checked_params(
+            genesis, utxo_pool, params, hasher,
+        ))
+    }
+
+    pub fn hasher(&self) -> &'static dyn Hasher {
+        self.hasher
+    }
+
+    /// Caches verified signatures of txs, so a tx validated again, e.g. when
+    /// composing a block and then when accepting it, isn't verified twice.
+    /// The cache lives as long as this chain and keeps at most
+    /// `SIGNATURE_CACHE_SIZE` signatures.
+    pub fn enable_signature_cache(&mut self) {
+        self.signature_cache.get_or_insert_with(SignatureCache::new);
+    }
+
+    pub fn signature_cache(&self) -> Option<&SignatureCache> {
+        self.signature_cache.as_ref()
+    }
+
+    /// Returns handler of the pool, which uses signature cache of this chain
+    pub(crate) fn handler(&self, pool: UTXOPool) -> Handler {
+        handler_with_cache(pool, self.signature_cache.as_ref())
+    }
+
+    pub fn params(&self) -> &ChainParams {
+        &self.params
     }
 
     pub fn at_block_hash(&self, hash: [u8; 32]) -> Option<&BlockNode> {
         self.chain.iter().find(|bn| bn.0.hash() == hash)
     }
 
+    /// Returns the most recently accepted block, which isn't necessarily the
+    /// highest one, see `best_tip`
     pub fn block_at_max_height(&self) -> &Block {
         &self
             .chain
@@ -41,33 +285,1038 @@ impl Blockchain {
             .1
     }
 
+    /// Returns UTXO pool after applying the block at `best_tip`
+    pub fn utxo_pool_at_best_tip(&self) -> &UTXOPool {
+        self.utxo_pool_at(self.best_tip().hash())
+            .expect("best tip is a retained block")
+    }
+
+    /// Returns UTXO pool after applying the retained block with given hash
+    pub fn utxo_pool_at(&self, hash: [u8; 32]) -> Option<&UTXOPool> {
+        self.at_block_hash(hash).map(|(_, pool)| pool)
+    }
+
+    /// Returns status of the output of given tx at the best tip
+    pub fn output_status(&self, tx_hash: [u8; 32], output_idx: u16) -> OutputStatus {
+        if self
+            .utxo_pool_at_best_tip()
+            .contains(&UTXO::new(tx_hash, output_idx))
+        {
+            return OutputStatus::Unspent;
+        }
+
+        let mut current = Some(self.best_tip());
+        while let Some(block) = current {
+            let created = std::iter::once(block.coinbase())
+                .chain(block.txs().iter())
+                .filter(|tx| tx.hash() == tx_hash)
+                .find_map(|tx| tx.output(output_idx));
+            match created {
+                Some(output) if output.is_data() => return OutputStatus::Unspendable,
+                Some(_) => return OutputStatus::Spent,
+                None => {}
+            }
+            current = self.at_block_hash(block.prev()).map(|(parent, _)| parent);
+        }
+        OutputStatus::Unknown
+    }
+
+    /// Returns true only if the output is known and already spent at the best
+    /// tip, see `output_status`
+    pub fn is_spent(&self, tx_hash: [u8; 32], output_idx: u16) -> bool {
+        self.output_status(tx_hash, output_idx) == OutputStatus::Spent
+    }
+
+    /// Returns all retained blocks which have no retained children, in order
+    /// in which they were accepted
+    pub fn tips(&self) -> Vec<&Block> {
+        self.chain
+            .iter()
+            .map(|(block, _)| block)
+            .filter(|block| !self.chain.iter().any(|(c, _)| c.prev() == block.hash()))
+            .collect()
+    }
+
+    /// Returns the tip with the most retained ancestors. If there are more
+    /// such tips, the most recently accepted one is chosen.
+    pub fn best_tip(&self) -> &Block {
+        let mut best = None;
+        for tip in self.tips() {
+            let height = self.retained_height(tip);
+            match best {
+                Some((_, best_height)) if best_height > height => {}
+                _ => best = Some((tip, height)),
+            }
+        }
+        best.expect("can't have no tips, where is genesis?").0
+    }
+
+    /// Returns retained blocks of the canonical chain ending at `best_tip`,
+    /// from the oldest one to the tip. Blocks of other forks are left out.
+    pub fn best_chain(&self) -> Vec<&Block> {
+        let mut chain = vec![self.best_tip()];
+        while let Some((parent, _)) = self.at_block_hash(chain[chain.len() - 1].prev()) {
+            chain.push(parent);
+        }
+        chain.reverse();
+        chain
+    }
+
+    /// Returns a Graphviz DOT graph of retained blocks, each labeled by the
+    /// start of its hash and its number of txs, with an edge from each block
+    /// to its retained parent. Blocks of the best chain and edges between
+    /// them are bold.
+    pub fn to_dot(&self) -> String {
+        let best: HashSet<[u8; 32]> = self.best_chain().iter().map(|b| b.hash()).collect();
+        let style = |hash: &[u8; 32]| {
+            if best.contains(hash) {
+                " style=bold"
+            } else {
+                ""
+            }
+        };
+
+        let mut dot = String::from("digraph blockchain {\n    rankdir=RL;\n");
+        for (block, _) in self.chain.iter() {
+            let hash = block.hash();
+            dot.push_str(&format!(
+                "    \"{}\" [label=\"{}\\n{} txs\"{}];\n",
+                to_hex(&hash),
+                to_hex(&hash[..4]),
+                block.txs().len(),
+                style(&hash)
+            ));
+        }
+        // parent of a best chain block is in the best chain too
+        for (block, _) in self.chain.iter() {
+            if self.at_block_hash(block.prev()).is_some() {
+                dot.push_str(&format!(
+                    "    \"{}\" -> \"{}\"{};\n",
+                    to_hex(&block.hash()),
+                    to_hex(&block.prev()),
+                    if best.contains(&block.hash()) {
+                        " [style=bold]"
+                    } else {
+                        ""
+                    }
+                ));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Returns hashes of the coinbase followed by hashes of txs of the
+    /// retained block, in the order of the block, e.g. for a peer relaying
+    /// only txids, which then requests txs missing from its mempool
+    pub fn block_txids(&self, hash: [u8; 32]) -> Option<Vec<[u8; 32]>> {
+        let (block, _) = self.at_block_hash(hash)?;
+        let txids = std::iter::once(block.coinbase())
+            .chain(block.txs().iter())
+            .map(|tx| tx.hash())
+            .collect();
+        Some(txids)
+    }
+
+    /// Returns proof that the tx is in a block of the best chain, `None` if
+    /// it isn't in any retained block of it
+    pub fn inclusion_proof(&self, tx_hash: [u8; 32]) -> Option<InclusionProof> {
+        self.best_chain()
+            .into_iter()
+            .enumerate()
+            .find_map(|(height, block)| {
+                let (index, path) = block.merkle_path_of(tx_hash, self.hasher)?;
+                Some(InclusionProof {
+                    block_hash: block.hash(),
+                    height,
+                    index,
+                    path,
+                })
+            })
+    }
+
+    /// Number of blocks, which left the best chain when the last accepted
+    /// block switched the best tip to another fork. 0 if the block extended
+    /// the best chain or the best tip didn't change.
+    pub fn last_reorg_depth(&self) -> usize {
+        self.disconnected.len()
+    }
+
+    /// Returns txs of blocks disconnected by the last reorg to the mempool,
+    /// if they are still valid at the best tip, and prunes the mempool. Txs
+    /// are admitted by `add_tx` in order of the disconnected blocks, so they
+    /// replace pending txs only if they pay more. Txs already included in
+    /// the best chain aren't reported. Should be called after a block
+    /// switched the best tip, see `last_reorg_depth`.
+    pub fn on_reorg(&mut self) -> ReorgReport {
+        let in_best_chain: HashSet<[u8; 32]> = self
+            .best_chain()
+            .iter()
+            .flat_map(|block| block.txs().iter().map(|tx| tx.hash()))
+            .collect();
+        // disconnected blocks are ordered from the previous best tip
+        let orphaned: Vec<Tx> = self
+            .disconnected
+            .iter()
+            .rev()
+            .filter_map(|&hash| self.at_block_hash(hash))
+            .flat_map(|(block, _)| block.txs().iter())
+            .filter(|tx| !in_best_chain.contains(&tx.hash()))
+            .cloned()
+            .collect();
+
+        self.prune_mempool();
+        let mut report = ReorgReport::default();
+        for tx in orphaned {
+            let hash = tx.hash();
+            if self.mempool.tx(hash).is_some() || self.add_tx(tx) {
+                report.reinstated.push(hash);
+            } else {
+                report.dropped.push(hash);
+            }
+        }
+        report
+    }
+
+    /// Retained blocks from the previous best tip back to the first block
+    /// shared with the current best chain
+    fn disconnected_from(&self, prev_best: [u8; 32]) -> Vec<[u8; 32]> {
+        let best_chain: HashSet<[u8; 32]> =
+            self.best_chain().iter().map(|block| block.hash()).collect();
+        let mut disconnected = vec![];
+        let mut current = self.at_block_hash(prev_best);
+        while let Some((block, _)) = current {
+            if best_chain.contains(&block.hash()) {
+                break;
+            }
+            disconnected.push(block.hash());
+            current = self.at_block_hash(block.prev());
+        }
+        disconnected
+    }
+
+    /// Number of retained ancestors of the block
+    fn retained_height(&self, block: &Block) -> usize {
+        let mut height = 0;
+        let mut prev = block.prev();
+        while let Some((parent, _)) = self.at_block_hash(prev) {
+            height += 1;
+            prev = parent.prev();
+        }
+        height
+    }
+
+    /// Imports headers of blocks, whose bodies are added later by
+    /// `add_block`. Each header must have a valid hash and proof of work,
+    /// extend a retained block or another imported header and mustn't be
+    /// older than its parent. Difficulty of a header extending a retained
+    /// block must be at least `difficulty_after` it, of a header extending
+    /// another header at least one less than its parent's, as the exact one
+    /// is known only for retained blocks. At most `MAX_HEADERS` headers are
+    /// kept, headers no longer linked to a retained block are dropped first.
+    /// Either all headers are imported or none, already known ones are
+    /// skipped.
+    pub fn import_headers(&mut self, headers: Vec<BlockHeader>) -> Result<(), SyncError> {
+        self.prune_headers();
+        let mut imported: Vec<BlockHeader> = vec![];
+        for header in headers {
+            let hash = header.hash();
+            let is_known = self.at_block_hash(hash).is_some()
+                || self
+                    .headers
+                    .iter()
+                    .chain(imported.iter())
+                    .any(|h| h.hash() == hash);
+            if is_known {
+                continue;
+            }
+            if header.is_genesis() {
+                return Err(SyncError::UnexpectedGenesis);
+            }
+            if !header.is_hash_valid_with(self.hasher) {
+                return Err(SyncError::InvalidHash(hash));
+            }
+            if !header.is_pow_valid() {
+                return Err(SyncError::InvalidProofOfWork(hash));
+            }
+
+            let parent = self
+                .at_block_hash(header.prev())
+                .map(|(parent, _)| {
+                    let min_difficulty = self.difficulty_after(parent.hash());
+                    (parent.timestamp(), min_difficulty.unwrap_or(0))
+                })
+                .or_else(|| {
+                    self.headers
+                        .iter()
+                        .chain(imported.iter())
+                        .find(|h| h.hash() == header.prev())
+                        .map(|parent| (parent.timestamp(), parent.difficulty().saturating_sub(1)))
+                });
+            match parent {
+                None => return Err(SyncError::BrokenLink(hash)),
+                Some((timestamp, _)) if header.timestamp() < timestamp => {
+                    return Err(SyncError::InvalidTimestamp(hash))
+                }
+                Some((_, min_difficulty)) if header.difficulty() < min_difficulty => {
+                    return Err(SyncError::InvalidDifficulty(hash))
+                }
+                Some(_) => imported.push(header),
+            }
+            if self.headers.len() + imported.len() > MAX_HEADERS {
+                return Err(SyncError::TooManyHeaders);
+            }
+        }
+
+        self.headers.extend(imported);
+        Ok(())
+    }
+
+    /// Drops imported headers, which don't extend a retained block, neither
+    /// directly nor through other headers, e.g. because their ancestor
+    /// became older than the cut-off age
+    fn prune_headers(&mut self) {
+        let mut linked: HashSet<[u8; 32]> = HashSet::new();
+        // parents are always imported before their children
+        for header in self.headers.iter() {
+            if linked.contains(&header.prev()) || self.at_block_hash(header.prev()).is_some() {
+                linked.insert(header.hash());
+            }
+        }
+        self.headers
+            .retain(|header| linked.contains(&header.hash()));
+    }
+
+    /// Hashes of imported headers, whose blocks weren't accepted yet, in
+    /// order in which they were imported
+    pub fn missing_bodies(&self) -> Vec<[u8; 32]> {
+        self.headers.iter().map(|header| header.hash()).collect()
+    }
+
+    /// Number of unspent outputs at the best tip
+    pub fn utxo_count(&self) -> usize {
+        self.utxo_pool_at_best_tip().outputs().len()
+    }
+
+    /// Numbers of unspent outputs at the best tip bucketed by value. Buckets
+    /// are powers of two keyed by their smallest value, e.g. bucket 4 counts
+    /// values 4 to 7, bucket 0 only outputs with zero value. Empty buckets
+    /// are missing.
+    pub fn value_histogram(&self) -> BTreeMap<u64, usize> {
+        let mut histogram = BTreeMap::new();
+        for output in self.utxo_pool_at_best_tip().outputs() {
+            let bucket = match output.value() {
+                0 => 0,
+                value => 1 << (u64::BITS - 1 - value.leading_zeros()),
+            };
+            *histogram.entry(bucket).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Sums of values of unspent outputs at the best tip by addresses of keys
+    /// they are locked to, see `PublicKey::address`, saturated at `u64::MAX`
+    pub fn balances(&self) -> HashMap<[u8; 20], u64> {
+        let mut balances = HashMap::new();
+        for output in self.utxo_pool_at_best_tip().outputs() {
+            if let Some(pub_key) = output.public_key() {
+                let balance = balances.entry(pub_key.address()).or_insert(0u64);
+                *balance = balance.saturating_add(output.value());
+            }
+        }
+        balances
+    }
+
+    /// Hashes of coinbase txs, which aren't mature at the best tip. Those
+    /// are coinbases of the best tip and its retained ancestors, on top of
+    /// which fewer than `coinbase_maturity` blocks were built.
+    pub fn immature_coinbases(&self) -> HashSet<[u8; 32]> {
+        let mut immature = HashSet::new();
+        let mut current = Some(self.best_tip());
+        while let Some(block) = current {
+            if immature.len() == self.params.coinbase_maturity {
+                break;
+            }
+            immature.insert(block.coinbase().hash());
+            current = self.at_block_hash(block.prev()).map(|(parent, _)| parent);
+        }
+        immature
+    }
+
+    /// Number of blocks waiting for their parent to arrive
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
     pub fn tx_pool_at_max_height(&self) -> &TxPool {
         &self.mempool
     }
 
+    /// Returns the mempool, which is the same as `tx_pool_at_max_height`, but
+    /// its txs are pending on top of the best tip, see `prune_mempool`
+    pub fn tx_pool_at_best_tip(&self) -> &TxPool {
+        &self.mempool
+    }
+
     pub fn add_block(&mut self, block: Block) -> bool {
-        let node = match self.at_block_hash(block.prev()) {
-            Some(parent) => parent,
-            None => return false,
-        };
+        match self.try_add_block(block) {
+            Ok(_) => true,
+            Err(err) => {
+                log::warn!("Block rejected, {}", err);
+                false
+            }
+        }
+    }
 
-        let mut handler = fiitcoin::handler::Handler::new(node.1.clone());
-        let txs: Vec<&fiitcoin::tx::Tx> = block.txs().iter().map(|tx| tx).collect();
+    pub fn try_add_block(&mut self, block: Block) -> Result<(), AddBlockError> {
+        // orphans connected by the block may switch the best tip further
+        let prev_best = self.best_tip().hash();
+        self.connect_block(block)?;
+        self.disconnected = self.disconnected_from(prev_best);
+        Ok(())
+    }
 
-        if handler.handle(txs).len() != block.txs().len() {
-            log::warn!("Block contained invalid txs!");
-            return false;
+    /// Adds the block with orphans waiting for it, see `try_add_block`
+    fn connect_block(&mut self, block: Block) -> Result<(), AddBlockError> {
+        if self.at_block_hash(block.hash()).is_some() {
+            return Err(AddBlockError::AlreadyKnown);
+        }
+        if block.is_genesis() {
+            return Err(AddBlockError::UnexpectedGenesis);
+        }
+        // checked before stashing orphans, which are deduplicated by hash, so
+        // a mutated copy can't shadow the valid block with the same hash
+        if block.has_duplicate_txs() {
+            return Err(AddBlockError::DuplicateTxs);
+        }
+        let node = match self.at_block_hash(block.prev()) {
+            Some(parent) => parent,
+            None => {
+                self.validate_orphan(&block)?;
+                self.stash_orphan(block);
+                return Err(AddBlockError::UnknownParent);
+            }
         };
+        self.validate_header(&block, &node.0)?;
+        // the parent keeps its pool for other blocks extending it, so the
+        // block is validated and applied on the only copy of it
+        let mut pool = node.1.clone();
+        apply_block_to_pool_with(
+            &mut pool,
+            &block,
+            &self.params,
+            self.hasher,
+            self.signature_cache.as_ref(),
+        )?;
 
         for tx in block.txs().iter() {
             self.mempool.remove(tx.hash());
         }
-        self.chain.push((block, handler.move_pool()));
+        let hash = block.hash();
+        self.chain.push((block, pool));
+        self.headers.retain(|header| header.hash() != hash);
+        self.prune_headers();
+        self.prune_mempool();
+        self.connect_orphans(hash);
+
+        Ok(())
+    }
+
+    /// Checks the proof of work of a block with unknown parent, so orphans
+    /// can't be stashed for free. Its exact difficulty isn't known without
+    /// the parent, but it can be at most one lower than difficulty of the
+    /// easiest retained block, like of a header extending an imported one,
+    /// see `import_headers`.
+    fn validate_orphan(&self, block: &Block) -> Result<(), AddBlockError> {
+        if !block.is_hash_valid_with(self.hasher) {
+            return Err(AddBlockError::InvalidHash);
+        }
+        if !block.is_pow_valid() {
+            return Err(AddBlockError::InvalidProofOfWork);
+        }
+        let min_difficulty = self
+            .chain
+            .iter()
+            .map(|(retained, _)| retained.difficulty())
+            .min()
+            .unwrap_or_default()
+            .saturating_sub(1);
+        if block.difficulty() < min_difficulty {
+            return Err(AddBlockError::InvalidDifficulty);
+        }
+        Ok(())
+    }
+
+    /// Keeps a block with unknown parent, so it can be connected later, see
+    /// `validate_orphan`
+    fn stash_orphan(&mut self, block: Block) {
+        if self.orphans.iter().any(|o| o.hash() == block.hash()) {
+            return;
+        }
+        if self.orphans.len() >= MAX_ORPHANS {
+            self.orphans.remove(0);
+        }
+        self.orphans.push(block);
+    }
+
+    /// Tries to add all orphans, which are children of the given block. Their
+    /// own orphaned children are connected recursively.
+    fn connect_orphans(&mut self, parent: [u8; 32]) {
+        let (children, rest): (Vec<Block>, Vec<Block>) = std::mem::take(&mut self.orphans)
+            .into_iter()
+            .partition(|o| o.prev() == parent);
+        self.orphans = rest;
+
+        for child in children {
+            if let Err(err) = self.connect_block(child) {
+                log::debug!("orphan block rejected, {}", err);
+            }
+        }
+    }
+
+    /// Removes txs from mempool which can't be applied on top of the best
+    /// tip, e.g. because a fork spent their inputs. Returns hashes of removed
+    /// txs.
+    pub fn prune_mempool(&mut self) -> Vec<[u8; 32]> {
+        let mut handler = self.handler(self.utxo_pool_at_best_tip().clone());
+        let valid: HashSet<[u8; 32]> = handler
+            .handle(self.mempool.txs())
+            .iter()
+            .map(|tx| tx.hash())
+            .collect();
+
+        let invalid: Vec<[u8; 32]> = self
+            .mempool
+            .txs()
+            .iter()
+            .map(|tx| tx.hash())
+            .filter(|hash| !valid.contains(hash))
+            .collect();
+        for &hash in invalid.iter() {
+            log::debug!("pruning tx {:?} from mempool", hash);
+            self.mempool.remove(hash);
+        }
+        invalid
+    }
+
+    /// Re-validates all retained blocks against the UTXO pool of their
+    /// parent. Blocks whose parent isn't retained, e.g. the oldest one or a
+    /// fork of a forgotten block, are trusted. Difficulty is checked only if
+    /// all blocks needed for retargeting are retained, and timestamps aren't
+    /// compared to the local clock, which was done when blocks were accepted.
+    /// Returns all found errors with index of the invalid block in the
+    /// retained window, where 0 is the oldest retained block.
+    pub fn verify_full(&self) -> Result<(), Vec<(usize, AddBlockError)>> {
+        let mut errors = vec![];
+
+        for (height, (block, _)) in self.chain.iter().enumerate() {
+            if !block.is_hash_valid_with(self.hasher) {
+                errors.push((height, AddBlockError::InvalidHash));
+                continue;
+            }
+
+            let parent = self
+                .chain
+                .iter()
+                .take(height)
+                .find(|(parent, _)| parent.hash() == block.prev());
+            let Some((parent, parent_pool)) = parent else {
+                continue;
+            };
+            let res = self.revalidate_header(block, parent).and_then(|_| {
+                apply_block_to_pool_with(
+                    &mut parent_pool.clone(),
+                    block,
+                    &self.params,
+                    self.hasher,
+                    self.signature_cache.as_ref(),
+                )
+            });
+            if let Err(err) = res {
+                errors.push((height, err));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Same as `validate_header`, but without the local clock, and difficulty
+    /// is checked only if it can be retargeted as when the block was accepted
+    fn revalidate_header(&self, block: &Block, parent: &Block) -> Result<(), AddBlockError> {
+        if block.timestamp() < parent.timestamp() {
+            return Err(AddBlockError::InvalidTimestamp);
+        }
+        if self.has_retarget_history(parent)
+            && self.difficulty_after(parent.hash()) != Some(block.difficulty())
+        {
+            return Err(AddBlockError::InvalidDifficulty);
+        }
+        Ok(())
+    }
 
+    /// Whether `RETARGET_WINDOW` ancestors of the block are retained, or all
+    /// of them back to the genesis, so `difficulty_after` it is the same as
+    /// when its children were accepted
+    fn has_retarget_history(&self, block: &Block) -> bool {
+        let mut current = block;
+        for _ in 0..RETARGET_WINDOW {
+            match self.at_block_hash(current.prev()) {
+                Some((parent, _)) => current = parent,
+                None => return current.is_genesis(),
+            }
+        }
         true
     }
 
-    pub fn add_tx(&mut self, tx: Tx) {
+    /// Difficulty which must be used by a block extending the block at max
+    /// height, which `BlockHandler::create_block` builds on, see
+    /// `difficulty_after` for other parents
+    pub fn next_difficulty(&self) -> u32 {
+        self.difficulty_after(self.block_at_max_height().hash())
+            .expect("block at max height must be retained")
+    }
+
+    /// Difficulty which must be used by a block extending the block with
+    /// given hash. The timespan of the last `RETARGET_WINDOW` blocks is
+    /// compared to the expected one, if it is less than a half of it, the
+    /// difficulty is increased, if it is more than double, it is decreased.
+    /// Only retained blocks are considered, so with `cut_off_age` of at most
+    /// `RETARGET_WINDOW` it is always the parent's difficulty. Returns `None`
+    /// if the parent isn't retained.
+    pub fn difficulty_after(&self, parent_hash: [u8; 32]) -> Option<u32> {
+        let (parent, _) = self.at_block_hash(parent_hash)?;
+        let difficulty = parent.difficulty();
+
+        let mut window = vec![parent];
+        while window.len() <= RETARGET_WINDOW {
+            let last = window[window.len() - 1];
+            match self.at_block_hash(last.prev()) {
+                Some((block, _)) => window.push(block),
+                // not enough history to retarget
+                None => return Some(difficulty),
+            }
+        }
+
+        if window[..RETARGET_WINDOW]
+            .iter()
+            .any(|b| b.difficulty() != difficulty)
+        {
+            // difficulty was changed recently
+            return Some(difficulty);
+        }
+
+        let timespan = parent
+            .timestamp()
+            .saturating_sub(window[RETARGET_WINDOW].timestamp());
+        let expected = self.params.target_block_time * RETARGET_WINDOW as u64;
+        if timespan < expected / 2 {
+            Some(difficulty.saturating_add(1).min(self.params.max_difficulty))
+        } else if timespan > expected * 2 {
+            Some(difficulty.saturating_sub(1))
+        } else {
+            Some(difficulty)
+        }
+    }
+
+    /// Validates parts of the block which depend on its ancestors or on the
+    /// local clock
+    fn validate_header(&self, block: &Block, parent: &Block) -> Result<(), AddBlockError> {
+        if block.timestamp() < parent.timestamp() {
+            return Err(AddBlockError::InvalidTimestamp);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        if block.timestamp() > now.saturating_add(MAX_FUTURE_DRIFT) {
+            return Err(AddBlockError::TimestampInFuture);
+        }
+        if self.difficulty_after(parent.hash()) != Some(block.difficulty()) {
+            return Err(AddBlockError::InvalidDifficulty);
+        }
+        Ok(())
+    }
+
+    /// # DO NOT USE, don't use this function outside tests!
+    pub fn block_mut(&mut self, hash: [u8; 32]) -> Option<&mut Block> {
+        self.chain
+            .iter_mut()
+            .find(|bn| bn.0.hash() == hash)
+            .map(|bn| &mut bn.0)
+    }
+
+    /// Returns sum of values of all unspent outputs at the best tip, saturated
+    /// at `u64::MAX`. Fees aren't claimed by coinbase txs, so they are burned
+    /// and not included.
+    pub fn circulating_supply(&self) -> u64 {
+        saturating_value_sum(self.utxo_pool_at_best_tip().outputs())
+    }
+
+    /// Returns hashes of mempool txs, which spend any of the outputs spent
+    /// by `tx`
+    pub fn pending_conflicts(&self, tx: &Tx) -> Vec<[u8; 32]> {
+        self.mempool.conflicts(tx)
+    }
+
+    /// Starts a new mempool round and drops txs admitted more than
+    /// `mempool_expiry` rounds ago, returns their hashes. Txs admitted in
+    /// round `r` are pending until round `r + mempool_expiry` inclusive.
+    pub fn advance_mempool_round(&mut self) -> Vec<[u8; 32]> {
+        self.mempool.advance_round();
+        let cutoff = self
+            .mempool
+            .round()
+            .saturating_sub(self.params.mempool_expiry);
+        let expired = self.mempool.expire_older_than(cutoff);
+        if !expired.is_empty() {
+            log::debug!("{} txs expired from mempool", expired.len());
+        }
+        expired
+    }
+
+    /// Returns sorted hashes of mempool txs, which would be evicted by
+    /// admitting `tx`, its conflicts together with all of their descendants
+    fn pending_evictions(&self, tx: &Tx) -> Vec<[u8; 32]> {
+        let mut evicted = vec![];
+        for conflict in self.pending_conflicts(tx) {
+            evicted.push(conflict);
+            evicted.extend(self.mempool.descendants(conflict));
+        }
+        evicted.sort();
+        evicted.dedup();
+        evicted
+    }
+
+    /// UTXO pool at the best tip with outputs of pooled txs, which wouldn't
+    /// be evicted by `tx`, see `pending_evictions`, so it can be validated
+    /// even when spending pending parents
+    pub(crate) fn pool_with_pending(&self, tx: &Tx) -> UTXOPool {
+        self.pool_with_pending_except(&self.pending_evictions(tx))
+    }
+
+    /// UTXO pool at the best tip with outputs of pooled txs other than the
+    /// `excluded` ones. Data outputs are skipped, as when txs are applied.
+    fn pool_with_pending_except(&self, excluded: &[[u8; 32]]) -> UTXOPool {
+        let mut pool = self.utxo_pool_at_best_tip().clone();
+        for pooled in self.mempool.txs() {
+            if excluded.contains(&pooled.hash()) {
+                continue;
+            }
+            for (idx, output) in pooled.outputs().iter().enumerate() {
+                if output.is_data() {
+                    continue;
+                }
+                pool.add_utxo(UTXO::new(pooled.hash(), idx as u16), output);
+            }
+        }
+        pool
+    }
+
+    /// Adds tx to the mempool and returns whether it was admitted. The tx has
+    /// to be valid at the best tip with pending parents applied, see
+    /// `pool_with_pending`. A tx conflicting with pooled txs is admitted only
+    /// if its fee is higher than the sum of fees of all of them and of their
+    /// descendants, each counted once, they are then all replaced by it.
+    pub fn add_tx(&mut self, tx: Tx) -> bool {
+        self.add_tx_paying(tx, 0)
+    }
+
+    /// Same as `add_tx`, but the valid tx is also rejected when its fee,
+    /// computed with pending parents applied, is lower than `min_fee`
+    pub(crate) fn add_tx_paying(&mut self, tx: Tx, min_fee: u64) -> bool {
+        let handler = self.handler(self.pool_with_pending(&tx));
+        if !handler.is_tx_valid(&tx) {
+            log::debug!("tx {:?} is invalid", tx.hash());
+            return false;
+        }
+        let tx_fee = fee(handler.pool(), &tx);
+        if tx_fee < min_fee {
+            log::debug!(
+                "tx {:?} pays fee {}, min fee is {}",
+                tx.hash(),
+                tx_fee,
+                min_fee
+            );
+            return false;
+        }
+
+        let conflicts = self.pending_conflicts(&tx);
+        if !conflicts.is_empty() {
+            // descendants spend outputs of pooled txs, so their fees are
+            // computed with all pending outputs
+            let pending = self.pool_with_pending_except(&[]);
+            let evictions = self.pending_evictions(&tx);
+            let replaced_fee = evictions
+                .iter()
+                .filter_map(|&hash| self.mempool.tx(hash))
+                .fold(0u64, |sum, pooled| {
+                    sum.saturating_add(fee(&pending, pooled))
+                });
+            if replaced_fee >= tx_fee {
+                log::debug!(
+                    "tx {:?} conflicts with pooled txs {:?}",
+                    tx.hash(),
+                    conflicts
+                );
+                return false;
+            }
+            for hash in evictions {
+                log::debug!("tx {:?} replaced by {:?} in mempool", hash, tx.hash());
+                self.mempool.remove(hash);
+            }
+        }
+
         self.mempool.add(tx);
+        true
+    }
+}
+
+/// Sum of values of unspent outputs at the best tip owned by the key, without
+/// immature coinbase outputs, see `Blockchain::immature_coinbases`. Unlike
+/// `balance_of`, it is the value the owner can spend right now. Saturated at
+/// `u64::MAX`.
+pub fn spendable_balance_of(chain: &Blockchain, pub_key: impl Into<PublicKey>) -> u64 {
+    let immature = chain.immature_coinbases();
+    let owned = chain.utxo_pool_at_best_tip().owned_by(pub_key);
+    saturating_value_sum(
+        owned
+            .into_iter()
+            .filter(|(utxo, _)| !immature.contains(&utxo.tx_hash()))
+            .map(|(_, output)| output),
+    )
+}
+
+/// Returns all payment outputs of the genesis coinbase as UTXOs, ordered
+/// by their index
+pub fn genesis_utxos(genesis: &Block) -> Vec<(UTXO, Output)> {
+    let coinbase = genesis.coinbase();
+    coinbase
+        .outputs()
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| !output.is_data())
+        .map(|(i, output)| (UTXO::new(coinbase.hash(), i as u16), output.clone()))
+        .collect()
+}
+
+/// Returns the pool of outputs of the genesis coinbase, which is the only
+/// pool consistent with the genesis block, see `genesis_utxos`
+pub fn genesis_pool(genesis: &Block) -> UTXOPool {
+    genesis_utxos(genesis).into_iter().collect()
+}
+
+/// Checks what every chain needs, regardless of its genesis pool
+fn check_genesis(genesis: &Block, params: &ChainParams) -> Result<(), GenesisError> {
+    if !genesis.is_genesis() {
+        return Err(GenesisError::NotGenesis);
+    }
+    if params.cut_off_age == 0 {
+        return Err(GenesisError::NoRetainedBlocks);
+    }
+    Ok(())
+}
+
+fn handler_with_cache(pool: UTXOPool, signature_cache: Option<&SignatureCache>) -> Handler {
+    let mut handler = Handler::new(pool);
+    if let Some(cache) = signature_cache {
+        handler.set_signature_cache(cache.clone());
+    }
+    handler
+}
+
+/// Validates block against the pool and applies its txs to it, in place, so
+/// callers owning a pool don't have to clone it. Outputs spent by the block
+/// are staged before its txs are handled, so when the block is invalid the
+/// pool is left unchanged.
+pub fn apply_block_to_pool(pool: &mut UTXOPool, block: &Block) -> Result<(), AddBlockError> {
+    apply_block_to_pool_with(pool, block, &ChainParams::default(), &Sha256Hasher, None)
+}
+
+/// Same as `apply_block_to_pool`, but with given chain parameters, hasher and
+/// signature cache
+pub fn apply_block_to_pool_with(
+    pool: &mut UTXOPool,
+    block: &Block,
+    params: &ChainParams,
+    hasher: &dyn Hasher,
+    signature_cache: Option<&SignatureCache>,
+) -> Result<(), AddBlockError> {
+    if !block.is_hash_valid_with(hasher) {
+        return Err(AddBlockError::InvalidHash);
+    }
+    if !block.is_pow_valid() {
+        return Err(AddBlockError::InvalidProofOfWork);
+    }
+    if block.txs().len() > params.max_txs_per_block {
+        return Err(AddBlockError::TooManyTxs(block.txs().len()));
+    }
+
+    let coinbase = block.coinbase();
+    let is_coinbase_valid = coinbase.is_coinbase()
+        && coinbase.output_len() == 1
+        && coinbase.output(0).map(|o| o.value()) == Some(params.coinbase_reward)
+        && coinbase.prev_block() == Some(block.prev());
+    if !is_coinbase_valid {
+        return Err(AddBlockError::InvalidCoinbase);
+    }
+
+    // every input must reference an existing output, either from the pool
+    // or from another tx in this block
+    let block_outputs: HashSet<UTXO> = block
+        .txs()
+        .iter()
+        .flat_map(|tx| (0..tx.output_len()).map(|idx| UTXO::new(tx.hash(), idx as u16)))
+        .collect();
+    let mut staged = HashMap::new();
+    for tx in block.txs().iter() {
+        for input in tx.inputs().iter() {
+            let utxo = UTXO::new(input.output_tx_hash(), input.output_idx());
+            match pool.utxo_output(&utxo) {
+                Some(output) => {
+                    staged.insert(utxo, output.clone());
+                }
+                None if block_outputs.contains(&utxo) => {}
+                None => return Err(AddBlockError::OrphanedInput(utxo)),
+            }
+        }
+    }
+
+    let mut handler = handler_with_cache(std::mem::take(pool), signature_cache);
+    let txs: Vec<&Tx> = block.txs().iter().collect();
+    let effects = handler.handle_with_effects(txs);
+    if effects.len() != block.txs().len() {
+        for effect in effects.iter().rev() {
+            for utxo in effect.created.iter() {
+                handler.pool_mut().remove_utxo(utxo);
+            }
+            for utxo in effect.spent.iter() {
+                if let Some(output) = staged.get(utxo) {
+                    handler.pool_mut().add_utxo(utxo.clone(), output);
+                }
+            }
+        }
+        *pool = handler.move_pool();
+        return Err(AddBlockError::InvalidTxs);
+    };
+    handler.apply_tx(coinbase);
+
+    *pool = handler.move_pool();
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AddBlockError {
+    AlreadyKnown,
+    UnexpectedGenesis,
+    UnknownParent,
+    InvalidHash,
+    InvalidProofOfWork,
+    InvalidTimestamp,
+    /// Block's timestamp is more than `MAX_FUTURE_DRIFT` ahead of now
+    TimestampInFuture,
+    InvalidDifficulty,
+    InvalidCoinbase,
+    /// Block contains more txs than allowed by the chain parameters
+    TooManyTxs(usize),
+    /// Input references an output, which is neither in the parent pool nor
+    /// created by another tx in the block
+    OrphanedInput(UTXO),
+    InvalidTxs,
+    /// Block contains a tx twice, see `Block::has_duplicate_txs`
+    DuplicateTxs,
+}
+
+impl fmt::Display for AddBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddBlockError::AlreadyKnown => write!(f, "block was already accepted"),
+            AddBlockError::UnexpectedGenesis => write!(f, "chain already has a genesis block"),
+            AddBlockError::UnknownParent => {
+                write!(f, "parent block is unknown, block kept as an orphan")
+            }
+            AddBlockError::InvalidHash => write!(f, "hash doesn't match block contents"),
+            AddBlockError::InvalidProofOfWork => {
+                write!(f, "hash doesn't satisfy block difficulty")
+            }
+            AddBlockError::InvalidTimestamp => write!(f, "block is older than its parent"),
+            AddBlockError::TimestampInFuture => write!(f, "block is too far in the future"),
+            AddBlockError::InvalidDifficulty => write!(f, "unexpected block difficulty"),
+            AddBlockError::InvalidCoinbase => write!(f, "invalid coinbase tx"),
+            AddBlockError::TooManyTxs(len) => write!(f, "block contains too many txs ({})", len),
+            AddBlockError::OrphanedInput(utxo) => write!(
+                f,
+                "input references unknown output {:?}-{}",
+                utxo.tx_hash(),
+                utxo.output_idx()
+            ),
+            AddBlockError::InvalidTxs => write!(f, "block contained invalid txs"),
+            AddBlockError::DuplicateTxs => write!(f, "block contains a tx twice"),
+        }
+    }
+}
+impl std::error::Error for AddBlockError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum GenesisError {
+    NotGenesis,
+    /// Pool doesn't contain exactly the outputs of the genesis coinbase
+    PoolMismatch,
+    /// Parameters don't retain any block, `cut_off_age` is 0
+    NoRetainedBlocks,
+}
+
+impl fmt::Display for GenesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenesisError::NotGenesis => write!(f, "block isn't a genesis block"),
+            GenesisError::PoolMismatch => {
+                write!(f, "pool doesn't match outputs of the genesis coinbase")
+            }
+            GenesisError::NoRetainedBlocks => write!(f, "chain must retain at least one block"),
+        }
+    }
+}
+impl std::error::Error for GenesisError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SyncError {
+    UnexpectedGenesis,
+    InvalidHash([u8; 32]),
+    InvalidProofOfWork([u8; 32]),
+    InvalidTimestamp([u8; 32]),
+    /// Header's difficulty is lower than the one expected after its parent
+    InvalidDifficulty([u8; 32]),
+    /// Header's parent is neither a retained block nor an imported header
+    BrokenLink([u8; 32]),
+    /// Importing the headers would keep more than `MAX_HEADERS` of them
+    TooManyHeaders,
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::UnexpectedGenesis => write!(f, "chain already has a genesis block"),
+            SyncError::InvalidHash(hash) => {
+                write!(f, "hash {:?} doesn't match header contents", hash)
+            }
+            SyncError::InvalidProofOfWork(hash) => {
+                write!(f, "hash {:?} doesn't satisfy header difficulty", hash)
+            }
+            SyncError::InvalidTimestamp(hash) => {
+                write!(f, "header {:?} is older than its parent", hash)
+            }
+            SyncError::InvalidDifficulty(hash) => {
+                write!(f, "difficulty of header {:?} is too low", hash)
+            }
+            SyncError::BrokenLink(hash) => write!(f, "parent of header {:?} is unknown", hash),
+            SyncError::TooManyHeaders => {
+                write!(f, "at most {} headers can be imported", MAX_HEADERS)
+            }
+        }
     }
 }
+impl std::error::Error for SyncError {}
@@ -1,52 +1,159 @@
-use fiitcoin::{
-    handler::{Handler, TxHandler},
-    tx::Tx,
-};
+use fiitcoin::tx::Tx;
 use rsa::pkcs1v15::VerifyingKey;
 use sha2::Sha256;
 
 use crate::{
     block::{Block, IncompleteBlock},
-    blockchain::Blockchain,
+    blockchain::{AddBlockError, Blockchain},
 };
 
+/// Reason why `BlockHandler::try_process_block` rejected a block
+pub type ProcessError = AddBlockError;
+
 #[derive(Debug)]
 pub struct BlockHandler {
     chain: Blockchain,
+    /// Relay policy, txs paying lower fee aren't admitted to the mempool
+    min_relay_fee: u64,
 }
 
 impl BlockHandler {
     pub fn new(chain: Blockchain) -> Self {
-        Self { chain }
+        Self {
+            chain,
+            min_relay_fee: 0,
+        }
+    }
+
+    /// Sets the minimum fee of txs admitted by `process_tx`, 0 by default.
+    /// It is a policy of this node, not a consensus rule, so blocks with txs
+    /// paying lower fees are still accepted.
+    pub fn set_min_relay_fee(&mut self, fee: u64) {
+        self.min_relay_fee = fee;
+    }
+
+    pub fn min_relay_fee(&self) -> u64 {
+        self.min_relay_fee
+    }
+
+    pub fn chain(&self) -> &Blockchain {
+        &self.chain
     }
 
     pub fn hash_at_max_height(&self) -> [u8; 32] {
         self.chain.block_at_max_height().hash()
     }
 
+    /// Same as `try_process_block`, but only returns whether the block was
+    /// added, the reason of a rejection is logged
     pub fn process_block(&mut self, block: Block) -> bool {
-        self.chain.add_block(block)
+        match self.try_process_block(block) {
+            Ok(_) => true,
+            Err(err) => {
+                log::warn!("Block rejected, {}", err);
+                false
+            }
+        }
     }
 
-    pub fn process_tx(&mut self, tx: Tx) {
-        self.chain.add_tx(tx);
+    /// Adds block to the chain and returns its hash, see
+    /// `Blockchain::try_add_block`. If it caused a reorg, txs of disconnected
+    /// blocks are returned to the mempool, see `Blockchain::on_reorg`.
+    pub fn try_process_block(&mut self, block: Block) -> Result<[u8; 32], ProcessError> {
+        let hash = block.hash();
+        self.chain.try_add_block(block)?;
+        if self.chain.last_reorg_depth() > 0 {
+            let report = self.chain.on_reorg();
+            log::info!(
+                "reorg reinstated {} txs and dropped {} txs",
+                report.reinstated.len(),
+                report.dropped.len()
+            );
+        }
+        Ok(hash)
     }
 
-    pub fn create_block(&self, address: &VerifyingKey<Sha256>) -> Block {
+    /// Adds blocks in given order, e.g. received during sync. Blocks whose
+    /// parent comes later in the batch are kept as orphans until it arrives,
+    /// so they are accepted as long as they fit into the orphan buffer.
+    /// Returns for each block whether it was added to the chain.
+    pub fn process_blocks(&mut self, blocks: Vec<Block>) -> Vec<bool> {
+        let mut accepted = vec![false; blocks.len()];
+        let mut pending: Vec<(usize, [u8; 32])> = vec![];
+        for (idx, block) in blocks.into_iter().enumerate() {
+            let hash = block.hash();
+            let is_known = self.chain.at_block_hash(hash).is_some();
+            if !self.process_block(block) {
+                if !is_known {
+                    pending.push((idx, hash));
+                }
+                continue;
+            }
+            accepted[idx] = true;
+            // the block may have connected orphans from earlier in the batch
+            pending.retain(|&(idx, hash)| {
+                let is_added = self.chain.at_block_hash(hash).is_some();
+                accepted[idx] |= is_added;
+                !is_added
+            });
+        }
+        accepted
+    }
+
+    /// Adds tx to the mempool, returns false if it was invalid or rejected
+    /// because of conflicting pooled txs, see `Blockchain::add_tx`, or by
+    /// relay policy, see `set_min_relay_fee`. The fee is computed only for a
+    /// valid tx, with outputs of its pending parents applied.
+    pub fn process_tx(&mut self, tx: Tx) -> bool {
+        // relay policy, unlike validity of txs in blocks
+        self.chain.add_tx_paying(tx, self.min_relay_fee)
+    }
+
+    /// Creates a block on top of the block at max height with the best txs
+    /// from the mempool. Each created block starts a new mempool round, see
+    /// `Blockchain::advance_mempool_round`.
+    ///
+    /// Unlike queries, which read the best tip, the block extends the most
+    /// recently accepted block, so a miner keeps building on a fork it has
+    /// just received. Pooled txs were admitted at the best tip, so they are
+    /// validated again against the pool of the parent and only txs valid on
+    /// it are included.
+    pub fn create_block(&mut self, address: &VerifyingKey<Sha256>) -> Block {
         let parent = self.chain.block_at_max_height();
-        let mut new_b = IncompleteBlock::new(parent.hash(), address);
+        let params = self.chain.params();
+        let mut new_b = IncompleteBlock::with_reward(
+            parent.hash(),
+            address,
+            params.coinbase_reward,
+            self.chain.hasher(),
+        );
+        new_b.set_difficulty(self.chain.next_difficulty());
 
         let utxo_pool = self.chain.utxo_pool_at_max_height();
-        let mut handler = Handler::new(utxo_pool.clone());
-
         let tx_pool = self.chain.tx_pool_at_max_height();
-        let txs = tx_pool.txs();
-        let handled = handler.handle(txs);
-
-        for &tx in handled.iter() {
+        let handler = self.chain.handler(utxo_pool.clone());
+        for (tx, _) in tx_pool.select_with_handler(handler, params.max_txs_per_block) {
             new_b.add_tx(tx.clone());
         }
-        new_b.finalize()
+        let block = new_b.finalize();
+        self.chain.advance_mempool_round();
+        block
+    }
+
+    /// Returns txs which `create_block` would include right now on top of
+    /// the block at max height, in the same order, and the sum of their
+    /// fees. Nothing is created and the mempool round isn't advanced. The
+    /// coinbase reward is fixed by `ChainParams::coinbase_reward`, so it
+    /// doesn't depend on the miner.
+    pub fn simulate_block(&self) -> (Vec<&Tx>, u64) {
+        let utxo_pool = self.chain.utxo_pool_at_max_height();
+        let tx_pool = self.chain.tx_pool_at_max_height();
+        let handler = self.chain.handler(utxo_pool.clone());
+        let selected = tx_pool.select_with_handler(handler, self.chain.params().max_txs_per_block);
+        let fees = selected
+            .iter()
+            .fold(0u64, |sum, (_, fee)| sum.saturating_add(*fee));
+        (selected.into_iter().map(|(tx, _)| tx).collect(), fees)
     }
 
     pub fn create_fork(
@@ -54,15 +161,19 @@ impl BlockHandler {
         parent_hash: [u8; 32],
         address: &VerifyingKey<Sha256>,
     ) -> Option<Block> {
-        let (parent, utxo_pool) = self.chain.at_block_hash(parent_hash)?;
-        let mut new_b = IncompleteBlock::new(parent.hash(), address);
-        let mut handler = Handler::new(utxo_pool.clone());
+        let utxo_pool = self.chain.utxo_pool_at(parent_hash)?;
+        let params = self.chain.params();
+        let mut new_b = IncompleteBlock::with_reward(
+            parent_hash,
+            address,
+            params.coinbase_reward,
+            self.chain.hasher(),
+        );
+        new_b.set_difficulty(self.chain.difficulty_after(parent_hash)?);
 
         let tx_pool = self.chain.tx_pool_at_max_height();
-        let txs = tx_pool.txs();
-        let handled = handler.handle(txs);
-
-        for &tx in handled.iter() {
+        let handler = self.chain.handler(utxo_pool.clone());
+        for (tx, _) in tx_pool.select_with_handler(handler, params.max_txs_per_block) {
             new_b.add_tx(tx.clone());
         }
         Some(new_b.finalize())
@@ -1,4 +1,7 @@
 pub mod block;
 pub mod blockchain;
 pub mod handler;
+pub mod query;
+#[cfg(feature = "test-util")]
+pub mod testing;
 pub mod tx_pool;
@@ -0,0 +1,167 @@
+use fiitcoin::{
+    handler::balance_of,
+    hash::to_hex,
+    keys::PublicKey,
+    tx::{Output, Tx},
+};
+use serde::Serialize;
+
+use crate::{
+    block::Block,
+    blockchain::{spendable_balance_of, Blockchain},
+};
+
+/// Read only facade over a chain, whose responses are plain serializable
+/// values, e.g. for serving them over HTTP. Hashes and addresses are hex
+/// encoded, blocks, txs, balances and UTXOs are those at the best tip.
+pub struct ChainQuery<'a> {
+    chain: &'a Blockchain,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockDto {
+    pub hash: String,
+    pub prev: String,
+    pub timestamp: u64,
+    pub difficulty: u32,
+    pub nonce: u64,
+    pub coinbase: TxDto,
+    pub txs: Vec<TxDto>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TxDto {
+    pub hash: String,
+    pub inputs: Vec<InputDto>,
+    pub outputs: Vec<OutputDto>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InputDto {
+    pub tx_hash: String,
+    pub output_idx: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutputDto {
+    pub value: u64,
+    /// Address of the key the output is locked to, see
+    /// `PublicKey::address`, missing for data outputs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Data carried by a data output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BalanceDto {
+    /// Address of the key, see `PublicKey::address`
+    pub address: String,
+    /// Value of all unspent outputs
+    pub balance: u64,
+    /// Value of unspent outputs without immature coinbase outputs
+    pub spendable_balance: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UtxoDto {
+    pub tx_hash: String,
+    pub output_idx: u16,
+    pub value: u64,
+}
+
+impl<'a> ChainQuery<'a> {
+    pub fn new(chain: &'a Blockchain) -> Self {
+        Self { chain }
+    }
+
+    /// Returns retained block with given hash
+    pub fn get_block(&self, hash: [u8; 32]) -> Option<BlockDto> {
+        self.chain
+            .at_block_hash(hash)
+            .map(|(block, _)| BlockDto::from(block))
+    }
+
+    /// Returns tx with given hash from the best chain, or from the mempool
+    /// if it isn't in a block yet
+    pub fn get_tx(&self, hash: [u8; 32]) -> Option<TxDto> {
+        self.chain
+            .best_chain()
+            .into_iter()
+            .flat_map(|block| std::iter::once(block.coinbase()).chain(block.txs().iter()))
+            .find(|tx| tx.hash() == hash)
+            .or_else(|| self.chain.tx_pool_at_best_tip().tx(hash))
+            .map(TxDto::from)
+    }
+
+    pub fn get_balance(&self, pub_key: impl Into<PublicKey>) -> BalanceDto {
+        let pub_key = pub_key.into();
+        BalanceDto {
+            address: to_hex(&pub_key.address()),
+            balance: balance_of(self.chain.utxo_pool_at_best_tip(), &pub_key),
+            spendable_balance: spendable_balance_of(self.chain, &pub_key),
+        }
+    }
+
+    /// Returns the best tip, see `Blockchain::best_tip`
+    pub fn get_tip(&self) -> BlockDto {
+        BlockDto::from(self.chain.best_tip())
+    }
+
+    /// Returns unspent outputs owned by the key, ordered by tx hash and
+    /// output index
+    pub fn get_utxos_of(&self, pub_key: impl Into<PublicKey>) -> Vec<UtxoDto> {
+        let mut owned = self.chain.utxo_pool_at_best_tip().owned_by(pub_key);
+        owned.sort_by_key(|(utxo, _)| (utxo.tx_hash(), utxo.output_idx()));
+        owned
+            .into_iter()
+            .map(|(utxo, output)| UtxoDto {
+                tx_hash: to_hex(&utxo.tx_hash()),
+                output_idx: utxo.output_idx(),
+                value: output.value(),
+            })
+            .collect()
+    }
+}
+
+impl From<&Block> for BlockDto {
+    fn from(block: &Block) -> Self {
+        Self {
+            hash: to_hex(&block.hash()),
+            prev: to_hex(&block.prev()),
+            timestamp: block.timestamp(),
+            difficulty: block.difficulty(),
+            nonce: block.nonce(),
+            coinbase: TxDto::from(block.coinbase()),
+            txs: block.txs().iter().map(TxDto::from).collect(),
+        }
+    }
+}
+
+impl From<&Tx> for TxDto {
+    fn from(tx: &Tx) -> Self {
+        Self {
+            hash: to_hex(&tx.hash()),
+            inputs: tx
+                .inputs()
+                .iter()
+                .map(|input| InputDto {
+                    tx_hash: to_hex(&input.output_tx_hash()),
+                    output_idx: input.output_idx(),
+                })
+                .collect(),
+            outputs: tx.outputs().iter().map(OutputDto::from).collect(),
+        }
+    }
+}
+
+impl From<&Output> for OutputDto {
+    fn from(output: &Output) -> Self {
+        Self {
+            value: output.value(),
+            address: output.public_key().map(|pk| to_hex(&pk.address())),
+            data: output.data().map(to_hex),
+        }
+    }
+}
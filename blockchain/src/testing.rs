@@ -0,0 +1,43 @@
+//! Helpers for tests of code built on top of this crate, available with the
+//! `test-util` feature.
+//!
+//! ```
+//! use blockchain::{
+//!     block::COINBASE,
+//!     testing::{setup_handler, Participant},
+//! };
+//!
+//! // small keys make tests fast, never use them outside tests
+//! let bob = Participant::with_key_bits(512);
+//! let (mut handler, genesis_tx) = setup_handler(&bob);
+//!
+//! assert_eq!(Some(COINBASE), genesis_tx.output(0).map(|o| o.value()));
+//! let block = handler.create_block(&bob.vk);
+//! assert!(handler.process_block(block));
+//! ```
+
+use fiitcoin::{tx::Tx, utxo::UTXOPool};
+
+pub use fiitcoin::testing::{Participant, DEFAULT_KEY_BITS};
+
+use crate::{
+    block::{Block, IncompleteBlock},
+    blockchain::{genesis_pool, Blockchain},
+    handler::BlockHandler,
+};
+
+/// Creates a chain, whose genesis coinbase is owned by the `receiver`
+pub fn setup_handler(receiver: &Participant) -> (BlockHandler, Tx) {
+    let genesis = IncompleteBlock::genesis(&receiver.vk).finalize();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let chain = Blockchain::try_new(genesis, pool).unwrap();
+    (BlockHandler::new(chain), genesis_tx)
+}
+
+/// Creates a pool containing only the coinbase of the genesis block
+pub fn setup_pool(genesis_block: &Block) -> (UTXOPool, Tx) {
+    (
+        genesis_pool(genesis_block),
+        genesis_block.coinbase().clone(),
+    )
+}
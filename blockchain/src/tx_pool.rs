@@ -1,32 +1,228 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use fiitcoin::tx::Tx;
+use fiitcoin::{
+    handler::{value_sum, Handler, TxHandler},
+    tx::Tx,
+    utxo::{UTXOPool, UTXO},
+};
 
+/// Pending txs, each remembers the round in which it was admitted, so txs
+/// which never get into a block can expire
 #[derive(Debug)]
 pub struct TxPool {
-    txs: HashMap<[u8; 32], Tx>,
+    txs: HashMap<[u8; 32], PooledTx>,
+    round: u64,
+}
+
+#[derive(Debug)]
+struct PooledTx {
+    tx: Tx,
+    admitted: u64,
 }
 
 impl TxPool {
     pub fn new() -> Self {
         Self {
             txs: HashMap::new(),
+            round: 0,
         }
     }
 
     pub fn txs(&self) -> Vec<&Tx> {
-        self.txs.values().collect()
+        self.txs.values().map(|pooled| &pooled.tx).collect()
     }
 
     pub fn tx(&self, hash: [u8; 32]) -> Option<&Tx> {
-        self.txs.get(&hash)
+        self.txs.get(&hash).map(|pooled| &pooled.tx)
+    }
+
+    /// Current admission round, newly added txs are admitted in it
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    pub fn advance_round(&mut self) {
+        self.round += 1;
+    }
+
+    /// Round in which the tx was admitted, `None` if it isn't pooled
+    pub fn admitted_in(&self, hash: [u8; 32]) -> Option<u64> {
+        self.txs.get(&hash).map(|pooled| pooled.admitted)
+    }
+
+    /// Removes txs admitted before the `cutoff` round and returns their
+    /// sorted hashes
+    pub fn expire_older_than(&mut self, cutoff: u64) -> Vec<[u8; 32]> {
+        let mut expired: Vec<[u8; 32]> = self
+            .txs
+            .iter()
+            .filter(|(_, pooled)| pooled.admitted < cutoff)
+            .map(|(&hash, _)| hash)
+            .collect();
+        expired.sort();
+        for hash in expired.iter() {
+            self.txs.remove(hash);
+        }
+        expired
+    }
+
+    /// Selects at most `max` txs valid on top of `pool` for a new block.
+    /// Txs are chosen greedily by the highest fee among those valid after
+    /// applying already selected ones, so parents always precede their
+    /// children and from conflicting txs only one is selected.
+    pub fn select_for_block(&self, pool: &UTXOPool, max: usize) -> Vec<&Tx> {
+        self.select_with_fees(pool, max)
+            .into_iter()
+            .map(|(tx, _)| tx)
+            .collect()
+    }
+
+    /// Same as `select_for_block`, but each selected tx is returned with its
+    /// fee
+    pub fn select_with_fees(&self, pool: &UTXOPool, max: usize) -> Vec<(&Tx, u64)> {
+        self.select_with_handler(Handler::new(pool.clone()), max)
+    }
+
+    /// Same as `select_with_fees`, but txs are validated and applied by
+    /// given handler of the pool. Each candidate is validated only once, when
+    /// all of its inputs are unspent, afterwards only its inputs are checked.
+    pub(crate) fn select_with_handler(&self, mut handler: Handler, max: usize) -> Vec<(&Tx, u64)> {
+        let mut candidates = self.txs();
+        let mut validated = HashSet::new();
+        let mut selected = vec![];
+
+        while selected.len() < max {
+            // inputs are only removed from the pool, so a tx failing with
+            // all of them unspent can never become valid
+            candidates.retain(|tx| {
+                if validated.contains(&tx.hash()) || !spends_unspent(handler.pool(), tx) {
+                    return true;
+                }
+                let is_valid = handler.is_tx_valid(tx);
+                if is_valid {
+                    validated.insert(tx.hash());
+                }
+                is_valid
+            });
+            let best = candidates
+                .iter()
+                .enumerate()
+                .filter(|(_, tx)| validated.contains(&tx.hash()))
+                .filter(|(_, tx)| spends_unspent(handler.pool(), tx))
+                .map(|(i, tx)| (fee(handler.pool(), tx), tx.hash(), i))
+                .max();
+            let Some((fee, _, i)) = best else {
+                break;
+            };
+
+            let tx = candidates.swap_remove(i);
+            handler.apply_tx(tx);
+            selected.push((tx, fee));
+        }
+
+        selected
+    }
+
+    /// Returns sorted hashes of pooled txs, other than `tx` itself, which
+    /// spend any of the outputs spent by `tx`
+    pub fn conflicts(&self, tx: &Tx) -> Vec<[u8; 32]> {
+        let spent: HashSet<UTXO> = tx
+            .inputs()
+            .iter()
+            .map(|i| UTXO::new(i.output_tx_hash(), i.output_idx()))
+            .collect();
+        let mut conflicts: Vec<[u8; 32]> = self
+            .txs()
+            .into_iter()
+            .filter(|pooled| pooled.hash() != tx.hash())
+            .filter(|pooled| {
+                pooled
+                    .inputs()
+                    .iter()
+                    .any(|i| spent.contains(&UTXO::new(i.output_tx_hash(), i.output_idx())))
+            })
+            .map(|pooled| pooled.hash())
+            .collect();
+        conflicts.sort();
+        conflicts
+    }
+
+    /// Returns sorted hashes of pooled txs spending outputs of the tx,
+    /// directly or through other pooled txs
+    pub fn descendants(&self, hash: [u8; 32]) -> Vec<[u8; 32]> {
+        let mut descendants = HashSet::new();
+        let mut parents = vec![hash];
+        while let Some(parent) = parents.pop() {
+            for pooled in self.txs() {
+                let spends_parent = pooled.inputs().iter().any(|i| i.output_tx_hash() == parent);
+                if spends_parent && descendants.insert(pooled.hash()) {
+                    parents.push(pooled.hash());
+                }
+            }
+        }
+        let mut descendants: Vec<[u8; 32]> = descendants.into_iter().collect();
+        descendants.sort();
+        descendants
+    }
+
+    /// Removes and returns txs selected by `select_for_block`, in the same
+    /// order, other txs are left in this pool
+    pub fn drain_best(&mut self, pool: &UTXOPool, max: usize) -> Vec<Tx> {
+        let selected: Vec<[u8; 32]> = self
+            .select_for_block(pool, max)
+            .iter()
+            .map(|tx| tx.hash())
+            .collect();
+        selected
+            .iter()
+            .filter_map(|hash| self.txs.remove(hash))
+            .map(|pooled| pooled.tx)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
     }
 
+    /// Adds tx admitted in the current round, a tx which is already pooled
+    /// keeps its original admission round
     pub fn add(&mut self, tx: Tx) {
-        self.txs.insert(tx.hash(), tx);
+        let admitted = self.round;
+        self.txs
+            .entry(tx.hash())
+            .or_insert(PooledTx { tx, admitted });
     }
 
     pub fn remove(&mut self, hash: [u8; 32]) {
         self.txs.remove(&hash);
     }
 }
+
+impl Default for TxPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether all inputs of the tx are in the pool
+fn spends_unspent(pool: &UTXOPool, tx: &Tx) -> bool {
+    tx.inputs()
+        .iter()
+        .all(|i| pool.contains(&UTXO::new(i.output_tx_hash(), i.output_idx())))
+}
+
+/// Fee of a tx whose inputs are all in the pool, inputs missing from the
+/// pool are counted as worthless
+pub(crate) fn fee(pool: &UTXOPool, tx: &Tx) -> u64 {
+    let input_value = value_sum(
+        tx.inputs()
+            .iter()
+            .filter_map(|i| pool.utxo_output(&UTXO::new(i.output_tx_hash(), i.output_idx()))),
+    );
+    let output_value = value_sum(tx.outputs());
+    u64::try_from(input_value.saturating_sub(output_value)).unwrap_or(u64::MAX)
+}
@@ -1,20 +1,7 @@
 use std::sync::Once;
 
-use blockchain::{
-    block::{Block, IncompleteBlock},
-    blockchain::Blockchain,
-    handler::BlockHandler,
-};
-use fiitcoin::{
-    tx::{Tx, UnsignedTx},
-    utxo::{UTXOPool, UTXO},
-};
-use rsa::{
-    pkcs1v15::{SigningKey, VerifyingKey},
-    signature::Keypair,
-    RsaPrivateKey,
-};
-use sha2::Sha256;
+use blockchain::testing::Participant;
+use fiitcoin::tx::{Tx, UnsignedTx};
 
 static INIT: Once = Once::new();
 
@@ -24,30 +11,11 @@ pub fn initialize() {
     });
 }
 
-pub struct Participant {
-    pub sk: SigningKey<Sha256>,
-    pub vk: VerifyingKey<Sha256>,
-}
-
-/// Only run in tests! Generated keys are of length 1024, not sufficiently
-/// secure by today's standards.
-impl Participant {
-    pub fn new() -> Self {
-        let mut rng = rand::thread_rng();
-        let bits = 1024;
-        let priv_key = RsaPrivateKey::new(&mut rng, bits).expect("failed to generate a key");
-        let sk = SigningKey::<Sha256>::new(priv_key);
-        let vk = sk.verifying_key();
-
-        Self { sk, vk }
-    }
-}
-
 pub struct NewTxParams<'a> {
     pub sender: &'a Participant,
-    pub inputs: &'a [(&'a Tx, u8)],
-    pub outputs: &'a [(&'a Participant, u32)],
-    pub return_to_sender: Option<u32>,
+    pub inputs: &'a [(&'a Tx, u16)],
+    pub outputs: &'a [(&'a Participant, u64)],
+    pub return_to_sender: Option<u64>,
 }
 
 pub fn new_tx(params: NewTxParams) -> Tx {
@@ -76,18 +44,3 @@ fn create_unsigned_tx(params: &NewTxParams) -> UnsignedTx {
     }
     tx
 }
-
-pub fn setup_handler(receiver: &Participant) -> (BlockHandler, Tx) {
-    let genesis = IncompleteBlock::new([0; 32], &receiver.vk).finalize();
-    let (pool, genesis_tx) = setup_pool(&genesis);
-    let chain = Blockchain::new(genesis, pool);
-    (BlockHandler::new(chain), genesis_tx)
-}
-
-pub fn setup_pool(genesis_block: &Block) -> (UTXOPool, Tx) {
-    let mut utxo_pool = UTXOPool::new();
-    let coinbase = genesis_block.coinbase();
-    let root_utxo = UTXO::new(coinbase.hash(), 0);
-    utxo_pool.add_utxo(root_utxo, &coinbase.output(0).unwrap());
-    (utxo_pool, coinbase.clone())
-}
@@ -1,9 +1,31 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 use blockchain::{
-    block::{IncompleteBlock, COINBASE},
-    blockchain::CUT_OFF_AGE,
+    block::{merkle_root, BlockBuilder, IncompleteBlock, COINBASE},
+    blockchain::{
+        apply_block_to_pool, genesis_pool, genesis_utxos, spendable_balance_of, verify_inclusion,
+        AddBlockError, Blockchain, ChainParams, GenesisError, OutputStatus, ReorgReport, SyncError,
+        COINBASE_MATURITY, CUT_OFF_AGE, MAX_FUTURE_DRIFT, MAX_HEADERS, MAX_ORPHANS,
+        RETARGET_WINDOW,
+    },
+    handler::{BlockHandler, ProcessError},
+    query::ChainQuery,
+    testing::{setup_handler, setup_pool, Participant},
+    tx_pool::TxPool,
+};
+use common::{new_tx, NewTxParams};
+use fiitcoin::{
+    handler::{balance_of, Handler, TxHandler},
+    hash::{to_hex, DoubleSha256Hasher, Sha256Hasher},
+    keys::PublicKey,
+    tx::{Tx, TxError, UnsignedTx, MAX_OUTPUTS},
+    utxo::{UTXOPool, UTXO},
 };
-use common::{new_tx, setup_handler, NewTxParams, Participant};
 use rsa::signature::{SignatureEncoding, Signer};
+use serde_json::json;
 
 mod common;
 
@@ -148,7 +170,7 @@ fn reject_new_genesis_block() {
     let block = handler.create_block(&bob.vk);
     assert!(handler.process_block(block));
 
-    let new_genesis = IncompleteBlock::new([0; 32], &alice.vk).finalize();
+    let new_genesis = IncompleteBlock::genesis(&alice.vk).finalize();
     assert!(!handler.process_block(new_genesis));
 }
 
@@ -326,10 +348,17 @@ fn utxo_from_fork() {
         outputs: &[(&alice, 400)],
         return_to_sender: None,
     });
-    handler.process_tx(tx1_fork.clone());
-    let block = handler.create_fork(genesis_block_hash, &alice.vk).unwrap();
-    assert_eq!(1, block.txs().len());
-    assert!(handler.process_block(block));
+    // double spend at the best tip, so only a fork can include it
+    assert!(!handler.process_tx(tx1_fork.clone()));
+    let mut block = IncompleteBlock::new(genesis_block_hash, &alice.vk);
+    block.set_difficulty(
+        handler
+            .chain()
+            .difficulty_after(genesis_block_hash)
+            .unwrap(),
+    );
+    block.add_tx(tx1_fork);
+    assert!(handler.process_block(block.finalize()));
 
     let tx2_depends_on_tx1 = new_tx(NewTxParams {
         sender: &alice,
@@ -497,17 +526,14 @@ fn oldest_fork_is_max_height() {
     common::initialize();
 
     let bob = Participant::new();
-    let alice = Participant::new();
+    // forks by the same miner on the same parent would be the same block
+    let miners: Vec<Participant> = (0..8).map(|_| Participant::new()).collect();
     let (mut handler, _genesis_tx) = setup_handler(&bob);
     let genesis_block_hash = handler.hash_at_max_height();
 
     let mut last_hash = [0; 32];
-    for i in 0..8 {
-        let miner = match i % 2 {
-            0 => &bob.vk,
-            _ => &alice.vk,
-        };
-        let block = handler.create_fork(genesis_block_hash, miner).unwrap();
+    for miner in miners.iter() {
+        let block = handler.create_fork(genesis_block_hash, &miner.vk).unwrap();
         last_hash = block.hash();
         assert!(handler.process_block(block));
     }
@@ -523,13 +549,14 @@ fn new_blocks_on_oldest_fork() {
     let bob = Participant::new();
     let alice = Participant::new();
     let charlie = Participant::new();
+    let fork_miners = [Participant::new(), Participant::new(), Participant::new()];
     let (mut handler, _genesis_tx) = setup_handler(&bob);
     let genesis_block_hash = handler.hash_at_max_height();
 
     let mut last_hash = [0; 32];
-    for _ in 0..3 {
+    for fork_miner in fork_miners.iter() {
         let block = handler
-            .create_fork(genesis_block_hash, &charlie.vk)
+            .create_fork(genesis_block_hash, &fork_miner.vk)
             .unwrap();
         let mut previous_block = block.hash();
         assert!(handler.process_block(block));
@@ -551,6 +578,53 @@ fn new_blocks_on_oldest_fork() {
     assert!(handler.process_block(new_block));
 }
 
+#[test]
+fn created_block_extends_max_height_with_txs_valid_on_it() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+    let genesis_hash = handler.hash_at_max_height();
+
+    let split = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&bob, 300)],
+        return_to_sender: Some(300),
+    });
+    assert!(handler.process_tx(split.clone()));
+    for _ in 0..2 {
+        let block = handler.create_block(&bob.vk);
+        assert!(handler.process_block(block));
+    }
+
+    // shorter fork without the split tx
+    let fork = handler.create_fork(genesis_hash, &alice.vk).unwrap();
+    let fork_hash = fork.hash();
+    assert!(handler.process_block(fork));
+    assert_ne!(fork_hash, handler.chain().best_tip().hash());
+
+    // valid only at the best tip
+    let pooled = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&split, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: None,
+    });
+    assert!(handler.process_tx(pooled));
+
+    assert!(handler.simulate_block().0.is_empty());
+    assert_eq!(
+        handler.chain().difficulty_after(fork_hash),
+        Some(handler.chain().next_difficulty())
+    );
+    let block = handler.create_block(&alice.vk);
+    assert_eq!(fork_hash, block.prev());
+    assert!(block.txs().is_empty());
+    assert!(handler.process_block(block));
+}
+
 // Phase 3 test 27
 #[test]
 fn reject_block_with_cut_off_parent() {
@@ -574,9 +648,7 @@ fn reject_block_with_cut_off_parent() {
     assert!(handler.process_block(block));
 
     // 2nd longer fork
-    let block = handler
-        .create_fork(genesis_block_hash, &charlie.vk)
-        .unwrap();
+    let block = handler.create_fork(genesis_block_hash, &alice.vk).unwrap();
     let mut previous_block = block.hash();
     assert!(handler.process_block(block));
     for j in 0..=CUT_OFF_AGE {
@@ -591,3 +663,2352 @@ fn reject_block_with_cut_off_parent() {
     let new_block = handler.create_fork(first_last_block, &charlie.vk);
     assert!(new_block.is_none());
 }
+
+#[test]
+fn circulating_supply_grows_by_coinbase() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let (mut handler, _genesis_tx) = setup_handler(&bob);
+    assert_eq!(COINBASE, handler.chain().circulating_supply());
+
+    let blocks = 3;
+    for _ in 0..blocks {
+        let block = handler.create_block(&bob.vk);
+        assert!(handler.process_block(block));
+    }
+
+    assert_eq!(
+        COINBASE + blocks * COINBASE,
+        handler.chain().circulating_supply()
+    );
+}
+
+#[test]
+fn circulating_supply_at_best_tip() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, _genesis_tx) = setup_handler(&bob);
+    let genesis_hash = handler.hash_at_max_height();
+
+    for _ in 0..2 {
+        let block = handler.create_block(&bob.vk);
+        assert!(handler.process_block(block));
+    }
+
+    // shorter fork is the most recently accepted block, but not the best tip
+    let fork = handler.create_fork(genesis_hash, &alice.vk).unwrap();
+    assert!(handler.process_block(fork));
+
+    let chain = handler.chain();
+    assert_ne!(chain.block_at_max_height().hash(), chain.best_tip().hash());
+    assert_eq!(3 * COINBASE, chain.circulating_supply());
+    assert_eq!(
+        chain.balances().values().sum::<u64>(),
+        chain.circulating_supply()
+    );
+}
+
+#[test]
+fn circulating_supply_excludes_fees() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 100)],
+        return_to_sender: Some(500),
+    });
+    handler.process_tx(tx1);
+    let block = handler.create_block(&alice.vk);
+    assert!(handler.process_block(block));
+
+    let fee = 25;
+    assert_eq!(2 * COINBASE - fee, handler.chain().circulating_supply());
+}
+
+#[test]
+fn add_txs_skips_duplicates() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (handler, genesis_tx) = setup_handler(&bob);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 100)],
+        return_to_sender: Some(500),
+    });
+
+    let mut block = IncompleteBlock::new(handler.hash_at_max_height(), &bob.vk);
+    block.add_txs(vec![tx1.clone(), tx1.clone()]);
+    block.add_txs(vec![tx1.clone()]);
+    let block = block.finalize();
+    assert_eq!(1, block.txs().len());
+    assert_eq!(tx1.hash(), block.txs()[0].hash());
+
+    let coinbase = IncompleteBlock::new(handler.hash_at_max_height(), &bob.vk)
+        .finalize()
+        .coinbase()
+        .clone();
+    let mut block = IncompleteBlock::new(handler.hash_at_max_height(), &bob.vk);
+    block.add_txs(vec![coinbase]);
+    assert_eq!(0, block.finalize().txs().len());
+}
+
+#[test]
+fn verify_full_chain() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(300),
+    });
+    let tx2 = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx1, 0)],
+        outputs: &[(&bob, 300)],
+        return_to_sender: None,
+    });
+
+    let mut block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk);
+    block.add_tx(tx1);
+    assert!(chain.add_block(block.finalize()));
+    let mut block = IncompleteBlock::new(chain.block_at_max_height().hash(), &alice.vk);
+    block.add_tx(tx2);
+    let block = block.finalize();
+    let tampered_hash = block.hash();
+    assert!(chain.add_block(block));
+    let block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk);
+    assert!(chain.add_block(block.finalize()));
+
+    assert_eq!(Ok(()), chain.verify_full());
+
+    let random_signature = alice.sk.sign(b"random data").to_bytes();
+    chain.block_mut(tampered_hash).unwrap().txs_mut()[0]
+        .force_signature_on_input(0, random_signature);
+    assert_eq!(
+        Err(vec![(2, AddBlockError::InvalidTxs)]),
+        chain.verify_full()
+    );
+}
+
+#[test]
+fn verify_full_after_retarget_history_and_fork_parent_are_forgotten() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let mut genesis = IncompleteBlock::genesis(&bob.vk);
+    genesis.set_timestamp(1_000);
+    let genesis = genesis.finalize();
+    let (pool, _) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+
+    let block_on = |chain: &Blockchain, parent: [u8; 32], timestamp: u64| {
+        let mut block = IncompleteBlock::new(parent, &bob.vk);
+        block.set_timestamp(timestamp);
+        block.set_difficulty(chain.difficulty_after(parent).unwrap());
+        block.finalize()
+    };
+
+    // blocks in short intervals, so the difficulty is retargeted, the fork
+    // is accepted after the retargeted block
+    let mut best = vec![chain.block_at_max_height().hash()];
+    for i in 1..=CUT_OFF_AGE as u64 + 1 {
+        let block = block_on(&chain, best[best.len() - 1], 1_000 + i);
+        best.push(block.hash());
+        assert!(chain.add_block(block));
+        if i as usize == RETARGET_WINDOW + 1 {
+            let fork = block_on(&chain, best[2], 1_004);
+            assert!(chain.add_block(fork));
+        }
+    }
+    assert!(chain.block_at_max_height().difficulty() > 0);
+    assert!(chain.at_block_hash(best[2]).is_none());
+    assert!(chain.at_block_hash(best[3]).is_some());
+
+    assert_eq!(Ok(()), chain.verify_full());
+}
+
+#[test]
+fn difficulty_isnt_retargeted_when_window_isnt_retained() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let mut genesis = IncompleteBlock::genesis(&bob.vk);
+    genesis.set_timestamp(1_000);
+    let genesis = genesis.finalize();
+    let (pool, _) = setup_pool(&genesis);
+    let params = ChainParams {
+        cut_off_age: RETARGET_WINDOW,
+        ..ChainParams::default()
+    };
+    let mut chain = Blockchain::with_params(genesis, pool, params, &Sha256Hasher);
+
+    // intervals short enough to increase the difficulty with default params
+    for i in 1..=3 * RETARGET_WINDOW as u64 {
+        assert_eq!(0, chain.next_difficulty());
+        let mut block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk);
+        block.set_timestamp(1_000 + i);
+        assert!(chain.add_block(block.finalize()));
+    }
+    assert_eq!(0, chain.next_difficulty());
+}
+
+#[test]
+fn difficulty_increases_with_short_intervals() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let mut genesis = IncompleteBlock::genesis(&bob.vk);
+    genesis.set_timestamp(1_000);
+    let genesis = genesis.finalize();
+    let (pool, _) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+
+    for i in 1..=RETARGET_WINDOW as u64 {
+        assert_eq!(0, chain.next_difficulty());
+        let mut block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk);
+        block.set_timestamp(1_000 + i);
+        block.set_difficulty(chain.next_difficulty());
+        assert!(chain.add_block(block.finalize()));
+    }
+    assert_eq!(1, chain.next_difficulty());
+
+    let mut block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk);
+    block.set_timestamp(1_000 + RETARGET_WINDOW as u64 + 1);
+    assert_eq!(
+        Err(AddBlockError::InvalidDifficulty),
+        chain.try_add_block(block.finalize())
+    );
+
+    let mut block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk);
+    block.set_timestamp(1_000 + RETARGET_WINDOW as u64 + 1);
+    block.set_difficulty(chain.next_difficulty());
+    let block = block.finalize();
+    assert!(block.is_pow_valid());
+    assert!(chain.add_block(block));
+}
+
+#[test]
+fn difficulty_capped_by_max_difficulty() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let mut genesis = IncompleteBlock::genesis(&bob.vk);
+    genesis.set_timestamp(1_000);
+    let genesis = genesis.finalize();
+    let (pool, _) = setup_pool(&genesis);
+    let params = ChainParams {
+        max_difficulty: 0,
+        ..ChainParams::default()
+    };
+    let mut chain = Blockchain::with_params(genesis, pool, params, &Sha256Hasher);
+
+    for i in 1..=2 * RETARGET_WINDOW as u64 {
+        let mut block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk);
+        block.set_timestamp(1_000 + i);
+        assert!(chain.add_block(block.finalize()));
+    }
+    assert_eq!(0, chain.next_difficulty());
+}
+
+#[test]
+fn reject_block_too_far_in_future() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let (mut handler, _genesis_tx) = setup_handler(&bob);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let mut block = IncompleteBlock::new(handler.hash_at_max_height(), &bob.vk);
+    block.set_timestamp(now + MAX_FUTURE_DRIFT + 60);
+    assert_eq!(
+        Err(AddBlockError::TimestampInFuture),
+        handler.try_process_block(block.finalize())
+    );
+
+    let mut block = IncompleteBlock::new(handler.hash_at_max_height(), &bob.vk);
+    block.set_timestamp(now + MAX_FUTURE_DRIFT / 2);
+    assert!(handler.process_block(block.finalize()));
+}
+
+#[test]
+fn tips_of_competing_forks() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (mut handler, _genesis_tx) = setup_handler(&bob);
+    let genesis_block_hash = handler.hash_at_max_height();
+
+    let block = handler.create_block(&alice.vk);
+    assert!(handler.process_block(block));
+    let block = handler.create_block(&alice.vk);
+    let long_tip = block.hash();
+    assert!(handler.process_block(block));
+
+    let block = handler
+        .create_fork(genesis_block_hash, &charlie.vk)
+        .unwrap();
+    let short_tip = block.hash();
+    assert!(handler.process_block(block));
+
+    let tips: Vec<[u8; 32]> = handler.chain().tips().iter().map(|b| b.hash()).collect();
+    assert_eq!(vec![long_tip, short_tip], tips);
+    // the most recently accepted block isn't the best one
+    assert_eq!(short_tip, handler.hash_at_max_height());
+    assert_eq!(long_tip, handler.chain().best_tip().hash());
+}
+
+#[test]
+fn best_chain_follows_winning_fork() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (mut handler, _genesis_tx) = setup_handler(&bob);
+    let genesis_block_hash = handler.hash_at_max_height();
+
+    let mut winning = vec![genesis_block_hash];
+    for _ in 0..2 {
+        let block = handler.create_block(&alice.vk);
+        winning.push(block.hash());
+        assert!(handler.process_block(block));
+    }
+
+    let block = handler
+        .create_fork(genesis_block_hash, &charlie.vk)
+        .unwrap();
+    assert!(handler.process_block(block));
+
+    let best_chain: Vec<[u8; 32]> = handler
+        .chain()
+        .best_chain()
+        .iter()
+        .map(|b| b.hash())
+        .collect();
+    assert_eq!(winning, best_chain);
+}
+
+#[test]
+fn reorg_depth_when_fork_overtakes_best_tip() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (mut handler, _genesis_tx) = setup_handler(&bob);
+    let genesis_block_hash = handler.hash_at_max_height();
+
+    for _ in 0..2 {
+        let block = handler.create_block(&alice.vk);
+        assert!(handler.process_block(block));
+        assert_eq!(0, handler.chain().last_reorg_depth());
+    }
+
+    // shorter fork doesn't change the best tip
+    let block = handler
+        .create_fork(genesis_block_hash, &charlie.vk)
+        .unwrap();
+    let fork_hash = block.hash();
+    assert!(handler.process_block(block));
+    assert_eq!(0, handler.chain().last_reorg_depth());
+
+    // fork of the same height is accepted later, so it becomes the best
+    let block = handler.create_fork(fork_hash, &charlie.vk).unwrap();
+    let fork_tip = block.hash();
+    assert!(handler.process_block(block));
+    assert_eq!(fork_tip, handler.chain().best_tip().hash());
+    assert_eq!(2, handler.chain().last_reorg_depth());
+
+    let block = handler.create_block(&charlie.vk);
+    assert_eq!(fork_tip, block.prev());
+    assert!(handler.process_block(block));
+    assert_eq!(0, handler.chain().last_reorg_depth());
+}
+
+#[test]
+fn reorg_depth_when_connected_orphan_overtakes_best_tip() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let genesis_hash = genesis.hash();
+    let (pool, _genesis_tx) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+    let difficulty = chain.difficulty_after(genesis_hash).unwrap();
+    let mine = |prev: [u8; 32], miner: &Participant| {
+        let mut block = IncompleteBlock::new(prev, &miner.vk);
+        block.set_difficulty(difficulty);
+        block.finalize()
+    };
+
+    let main = mine(genesis_hash, &alice);
+    let main_tip = mine(main.hash(), &alice);
+    assert!(chain.add_block(main));
+    assert!(chain.add_block(main_tip));
+    let fork = mine(genesis_hash, &charlie);
+    let fork_parent = mine(fork.hash(), &charlie);
+    let fork_tip = mine(fork_parent.hash(), &charlie);
+    let fork_tip_hash = fork_tip.hash();
+    assert!(chain.add_block(fork));
+    assert_eq!(0, chain.last_reorg_depth());
+
+    assert_eq!(
+        Err(AddBlockError::UnknownParent),
+        chain.try_add_block(fork_tip)
+    );
+    assert!(chain.add_block(fork_parent));
+    assert_eq!(fork_tip_hash, chain.best_tip().hash());
+    assert_eq!(2, chain.last_reorg_depth());
+}
+
+#[test]
+fn reorg_reinstates_valid_txs_of_disconnected_blocks() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+
+    let common_block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk).finalize();
+    let common_hash = common_block.hash();
+    let common_coinbase = common_block.coinbase().clone();
+    assert!(chain.add_block(common_block));
+
+    let still_valid = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 100)],
+        return_to_sender: Some(COINBASE - 100),
+    });
+    let double_spent = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&common_coinbase, 0)],
+        outputs: &[(&alice, COINBASE)],
+        return_to_sender: None,
+    });
+    let winner = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&common_coinbase, 0)],
+        outputs: &[(&charlie, COINBASE)],
+        return_to_sender: None,
+    });
+
+    let mut block = IncompleteBlock::new(common_hash, &alice.vk);
+    block.add_txs([still_valid.clone(), double_spent.clone()]);
+    assert!(chain.add_block(block.finalize()));
+    assert_eq!(0, chain.last_reorg_depth());
+
+    // fork of the same height is accepted later, so it becomes the best
+    let mut block = IncompleteBlock::new(common_hash, &charlie.vk);
+    block.add_txs([winner]);
+    assert!(chain.add_block(block.finalize()));
+    assert_eq!(1, chain.last_reorg_depth());
+
+    assert_eq!(
+        ReorgReport {
+            reinstated: vec![still_valid.hash()],
+            dropped: vec![double_spent.hash()],
+        },
+        chain.on_reorg()
+    );
+    let mempool = chain.tx_pool_at_max_height();
+    assert!(mempool.tx(still_valid.hash()).is_some());
+    assert!(mempool.tx(double_spent.hash()).is_none());
+}
+
+#[test]
+fn reorg_doesnt_reinstate_txs_paying_less_than_pending_conflicts() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+    let genesis_hash = chain.block_at_max_height().hash();
+
+    let orphaned = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, COINBASE - 10)],
+        return_to_sender: None,
+    });
+    let orphaned_child = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&orphaned, 0)],
+        outputs: &[(&charlie, COINBASE - 20)],
+        return_to_sender: None,
+    });
+    let higher_fee = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&charlie, COINBASE - 30)],
+        return_to_sender: None,
+    });
+
+    let mut block = IncompleteBlock::new(genesis_hash, &alice.vk);
+    block.add_tx(orphaned.clone());
+    let block = block.finalize();
+    let mut child_block = IncompleteBlock::new(block.hash(), &alice.vk);
+    child_block.add_tx(orphaned_child.clone());
+    assert!(chain.add_block(block));
+    assert!(chain.add_block(child_block.finalize()));
+
+    // fork of the same height is accepted later, so it becomes the best
+    let fork = IncompleteBlock::new(genesis_hash, &charlie.vk).finalize();
+    let fork_tip = IncompleteBlock::new(fork.hash(), &charlie.vk).finalize();
+    assert!(chain.add_block(fork));
+    assert!(chain.add_block(fork_tip));
+    assert_eq!(2, chain.last_reorg_depth());
+
+    assert!(chain.add_tx(higher_fee.clone()));
+    assert_eq!(
+        ReorgReport {
+            reinstated: vec![],
+            dropped: vec![orphaned.hash(), orphaned_child.hash()],
+        },
+        chain.on_reorg()
+    );
+    let mempool = chain.tx_pool_at_max_height();
+    assert!(mempool.tx(higher_fee.hash()).is_some());
+    assert_eq!(1, mempool.len());
+}
+
+#[test]
+fn reject_already_accepted_block() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let (pool, _) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+
+    let same_block = || {
+        let mut block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk);
+        block.set_timestamp(chain.block_at_max_height().timestamp());
+        block.finalize()
+    };
+    let (block, duplicate) = (same_block(), same_block());
+    assert_eq!(block.hash(), duplicate.hash());
+
+    assert!(chain.add_block(block));
+    assert_eq!(
+        Err(AddBlockError::AlreadyKnown),
+        chain.try_add_block(duplicate)
+    );
+    assert_eq!(1, chain.tips().len());
+}
+
+#[test]
+fn chain_with_double_sha256() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let genesis = IncompleteBlock::genesis_with_hasher(&bob.vk, &DoubleSha256Hasher).finalize();
+    assert!(genesis.is_hash_valid_with(&DoubleSha256Hasher));
+    assert!(!genesis.is_hash_valid());
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let mut handler =
+        BlockHandler::new(Blockchain::with_hasher(genesis, pool, &DoubleSha256Hasher));
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 300),
+    });
+    handler.process_tx(tx);
+    let block = handler.create_block(&alice.vk);
+    assert_eq!(1, block.txs().len());
+    assert!(handler.process_block(block));
+    assert_eq!(Ok(()), handler.chain().verify_full());
+
+    let sha256_block = IncompleteBlock::new(handler.hash_at_max_height(), &bob.vk).finalize();
+    assert!(!handler.process_block(sha256_block));
+}
+
+#[test]
+fn select_for_block_orders_parents_and_excludes_conflicts() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+
+    // fee 25
+    let parent = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(300),
+    });
+    // fee 10
+    let child = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&parent, 0)],
+        outputs: &[(&charlie, 290)],
+        return_to_sender: None,
+    });
+    // fee 15, spends the same output as parent
+    let conflicting = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&charlie, 610)],
+        return_to_sender: None,
+    });
+
+    let mut tx_pool = TxPool::new();
+    tx_pool.add(child.clone());
+    tx_pool.add(conflicting);
+    tx_pool.add(parent.clone());
+
+    let selected: Vec<[u8; 32]> = tx_pool
+        .select_for_block(&pool, usize::MAX)
+        .iter()
+        .map(|tx| tx.hash())
+        .collect();
+    assert_eq!(vec![parent.hash(), child.hash()], selected);
+
+    let selected = tx_pool.select_for_block(&pool, 1);
+    assert_eq!(1, selected.len());
+    assert_eq!(parent.hash(), selected[0].hash());
+}
+
+#[test]
+fn drain_best_removes_selected_txs() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+
+    let split = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&bob, 200), (&bob, 200)],
+        return_to_sender: Some(COINBASE - 400),
+    });
+    let mut handler = Handler::new(pool);
+    assert_eq!(1, handler.handle(vec![&split]).len());
+    let pool = handler.move_pool();
+
+    let mut tx_pool = TxPool::new();
+    let mut by_fee = vec![];
+    for (idx, fee) in [(0, 5), (1, 20), (2, 10)] {
+        let value = split.output(idx).unwrap().value();
+        let tx = new_tx(NewTxParams {
+            sender: &bob,
+            inputs: &[(&split, idx)],
+            outputs: &[(&alice, value - fee)],
+            return_to_sender: None,
+        });
+        by_fee.push((fee, tx.hash()));
+        tx_pool.add(tx);
+    }
+    by_fee.sort();
+    by_fee.reverse();
+
+    let drained: Vec<[u8; 32]> = tx_pool
+        .drain_best(&pool, 2)
+        .iter()
+        .map(|tx| tx.hash())
+        .collect();
+    assert_eq!(vec![by_fee[0].1, by_fee[1].1], drained);
+    assert_eq!(1, tx_pool.len());
+    assert!(tx_pool.tx(by_fee[2].1).is_some());
+}
+
+#[test]
+fn prune_mempool_after_fork() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+
+    let split = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&bob, 300)],
+        return_to_sender: Some(300),
+    });
+    let mut block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk);
+    block.add_tx(split.clone());
+    assert!(chain.add_block(block.finalize()));
+    let fork_parent = chain.block_at_max_height().hash();
+    let block = IncompleteBlock::new(fork_parent, &alice.vk);
+    assert!(chain.add_block(block.finalize()));
+
+    let pooled = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&split, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: None,
+    });
+    let unrelated = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&split, 1)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: None,
+    });
+    chain.add_tx(pooled.clone());
+    chain.add_tx(unrelated.clone());
+
+    // fork spends the same output as the pooled tx
+    let double_spend = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&split, 0)],
+        outputs: &[(&charlie, 300)],
+        return_to_sender: None,
+    });
+    let mut block = IncompleteBlock::new(fork_parent, &charlie.vk);
+    block.set_difficulty(chain.difficulty_after(fork_parent).unwrap());
+    block.add_tx(double_spend);
+    assert!(chain.add_block(block.finalize()));
+
+    assert!(chain.tx_pool_at_max_height().tx(pooled.hash()).is_none());
+    assert!(chain.tx_pool_at_max_height().tx(unrelated.hash()).is_some());
+}
+
+#[test]
+fn side_fork_doesnt_prune_txs_valid_at_best_tip() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let genesis_hash = genesis.hash();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+
+    let split = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&bob, 300)],
+        return_to_sender: Some(300),
+    });
+    let mut block = IncompleteBlock::new(genesis_hash, &bob.vk);
+    block.add_tx(split.clone());
+    assert!(chain.add_block(block.finalize()));
+    let block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk);
+    assert!(chain.add_block(block.finalize()));
+
+    let pooled = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&split, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: None,
+    });
+    chain.add_tx(pooled.clone());
+
+    // shorter fork without the split tx
+    let block = IncompleteBlock::new(genesis_hash, &alice.vk);
+    assert!(chain.add_block(block.finalize()));
+    assert!(chain.utxo_pool_at_max_height().outputs().len() < chain.utxo_count());
+
+    assert!(chain.tx_pool_at_max_height().tx(pooled.hash()).is_some());
+}
+
+#[test]
+fn zero_prev_block_is_not_genesis() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let (mut handler, _genesis_tx) = setup_handler(&bob);
+
+    let mut genesis = IncompleteBlock::genesis(&bob.vk);
+    let mut zero_prev = IncompleteBlock::new([0; 32], &bob.vk);
+    genesis.set_timestamp(1_000);
+    zero_prev.set_timestamp(1_000);
+    let (genesis, zero_prev) = (genesis.finalize(), zero_prev.finalize());
+
+    assert!(genesis.is_genesis());
+    assert!(!zero_prev.is_genesis());
+    assert_eq!(genesis.prev(), zero_prev.prev());
+    assert_ne!(genesis.hash(), zero_prev.hash());
+    assert!(zero_prev.is_hash_valid());
+
+    let mut chain = Blockchain::new(genesis, UTXOPool::new());
+    assert_eq!(
+        Err(AddBlockError::UnknownParent),
+        chain.try_add_block(zero_prev)
+    );
+    let new_genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    assert_eq!(
+        Err(AddBlockError::UnexpectedGenesis),
+        chain.try_add_block(new_genesis)
+    );
+
+    let new_genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    assert!(!handler.process_block(new_genesis));
+}
+
+#[test]
+fn block_with_repeated_last_txs_doesnt_shadow_valid_one() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 300),
+    });
+    let tx2 = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx1, 0)],
+        outputs: &[(&bob, 300)],
+        return_to_sender: None,
+    });
+    let parent = chain.block_at_max_height();
+    let build = |txs: &[&Tx]| {
+        let mut block = IncompleteBlock::new(parent.hash(), &bob.vk);
+        block.set_timestamp(parent.timestamp() + 1);
+        for &tx in txs {
+            block.add_tx(tx.clone());
+        }
+        block.finalize()
+    };
+    let valid = build(&[&tx1, &tx2]);
+    let mutated = build(&[&tx1, &tx2, &tx2]);
+    assert_eq!(valid.hash(), mutated.hash());
+    assert!(!valid.has_duplicate_txs());
+    assert!(mutated.has_duplicate_txs());
+
+    assert_eq!(
+        Err(AddBlockError::DuplicateTxs),
+        chain.try_add_block(mutated)
+    );
+    assert_eq!(Ok(()), chain.try_add_block(valid));
+}
+
+#[test]
+fn reject_block_with_orphaned_input() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+
+    let valid_tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 100)],
+        return_to_sender: None,
+    });
+    let mut orphan_tx = UnsignedTx::new();
+    orphan_tx.add_input([7; 32], 0);
+    orphan_tx.add_output(100, &alice.vk);
+    let orphan_tx = orphan_tx.sing_inputs_and_finalize(&bob.sk).unwrap();
+
+    let mut block = IncompleteBlock::new(chain.block_at_max_height().hash(), &bob.vk);
+    block.add_txs([valid_tx, orphan_tx]);
+
+    assert_eq!(
+        Err(AddBlockError::OrphanedInput(UTXO::new([7; 32], 0))),
+        chain.try_add_block(block.finalize())
+    );
+    assert_eq!(1, chain.tips().len());
+}
+
+#[test]
+fn orphan_connected_after_parent_arrives() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let genesis_hash = genesis.hash();
+    let (pool, _) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+
+    let parent = IncompleteBlock::new(genesis_hash, &bob.vk).finalize();
+    let child = IncompleteBlock::new(parent.hash(), &bob.vk).finalize();
+    let grandchild = IncompleteBlock::new(child.hash(), &bob.vk).finalize();
+    let grandchild_hash = grandchild.hash();
+
+    assert_eq!(
+        Err(AddBlockError::UnknownParent),
+        chain.try_add_block(grandchild)
+    );
+    assert_eq!(
+        Err(AddBlockError::UnknownParent),
+        chain.try_add_block(child)
+    );
+    assert_eq!(2, chain.orphan_count());
+    assert_eq!(genesis_hash, chain.block_at_max_height().hash());
+
+    assert_eq!(Ok(()), chain.try_add_block(parent));
+    assert_eq!(0, chain.orphan_count());
+    assert_eq!(grandchild_hash, chain.best_tip().hash());
+    assert_eq!(1, chain.tips().len());
+}
+
+#[test]
+fn orphan_without_enough_work_isnt_stashed() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let mut genesis = IncompleteBlock::genesis(&bob.vk);
+    genesis.set_timestamp(1_000);
+    genesis.set_difficulty(2);
+    let genesis = genesis.finalize();
+    let (pool, _) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+    let orphan = |difficulty: u32| {
+        let mut block = IncompleteBlock::new([7; 32], &bob.vk);
+        block.set_timestamp(1_000);
+        block.set_difficulty(difficulty);
+        block.finalize()
+    };
+
+    for _ in 0..MAX_ORPHANS + 1 {
+        assert_eq!(
+            Err(AddBlockError::InvalidDifficulty),
+            chain.try_add_block(orphan(0))
+        );
+    }
+    assert_eq!(0, chain.orphan_count());
+
+    // the chain may have become easier since the retained blocks
+    assert_eq!(
+        Err(AddBlockError::UnknownParent),
+        chain.try_add_block(orphan(1))
+    );
+    assert_eq!(1, chain.orphan_count());
+}
+
+#[test]
+fn utxo_pool_at_retained_blocks() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+    let genesis_hash = handler.hash_at_max_height();
+    let genesis_utxo = UTXO::new(genesis_tx.hash(), 0);
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 300),
+    });
+    let tx_hash = tx.hash();
+    handler.process_tx(tx);
+    let block = handler.create_block(&alice.vk);
+    let block_hash = block.hash();
+    assert!(handler.process_block(block));
+    let block = handler.create_block(&alice.vk);
+    assert!(handler.process_block(block));
+
+    let chain = handler.chain();
+    let genesis_pool = chain.utxo_pool_at(genesis_hash).unwrap();
+    assert!(genesis_pool.contains(&genesis_utxo));
+    assert!(!genesis_pool.contains(&UTXO::new(tx_hash, 0)));
+
+    let mid_pool = chain.utxo_pool_at(block_hash).unwrap();
+    assert!(!mid_pool.contains(&genesis_utxo));
+    assert!(mid_pool.contains(&UTXO::new(tx_hash, 0)));
+    assert!(mid_pool.contains(&UTXO::new(tx_hash, 1)));
+
+    assert!(chain.utxo_pool_at([7; 32]).is_none());
+}
+
+#[test]
+fn block_builder_refuses_invalid_txs() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 300),
+    });
+    let tx2_from_tx1 = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx1, 0)],
+        outputs: &[(&bob, 300)],
+        return_to_sender: None,
+    });
+    let tx3_doublespend = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&bob, 100)],
+        return_to_sender: None,
+    });
+    let tx4_invalid_spender = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx1, 1)],
+        outputs: &[(&alice, 100)],
+        return_to_sender: None,
+    });
+
+    let chain = handler.chain();
+    let parent = chain.block_at_max_height().hash();
+    assert!(BlockBuilder::new(IncompleteBlock::new([7; 32], &bob.vk), chain).is_none());
+    let mut builder = BlockBuilder::new(IncompleteBlock::new(parent, &bob.vk), chain).unwrap();
+    assert!(!builder.add_tx(tx2_from_tx1.clone())); // parent not added yet
+    assert!(builder.add_tx(tx1.clone()));
+    assert!(!builder.add_tx(tx1));
+    assert!(builder.add_tx(tx2_from_tx1));
+    assert!(!builder.add_tx(tx3_doublespend));
+    assert!(!builder.add_tx(tx4_invalid_spender));
+    assert!(!builder.pool().contains(&UTXO::new(genesis_tx.hash(), 0)));
+
+    let block = builder.finalize();
+    assert_eq!(2, block.txs().len());
+    assert!(handler.process_block(block));
+}
+
+#[test]
+fn output_status_at_max_height() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 300),
+    });
+    let tx_hash = tx.hash();
+    handler.process_tx(tx);
+    assert_eq!(
+        OutputStatus::Unspent,
+        handler.chain().output_status(genesis_tx.hash(), 0)
+    );
+    assert_eq!(
+        OutputStatus::Unknown,
+        handler.chain().output_status(tx_hash, 0)
+    );
+
+    let block = handler.create_block(&alice.vk);
+    assert!(handler.process_block(block));
+
+    let chain = handler.chain();
+    assert_eq!(
+        OutputStatus::Spent,
+        chain.output_status(genesis_tx.hash(), 0)
+    );
+    assert!(chain.is_spent(genesis_tx.hash(), 0));
+    assert_eq!(OutputStatus::Unspent, chain.output_status(tx_hash, 1));
+    assert!(!chain.is_spent(tx_hash, 1));
+    assert_eq!(OutputStatus::Unknown, chain.output_status(tx_hash, 2));
+    assert_eq!(OutputStatus::Unknown, chain.output_status([7; 32], 0));
+    assert!(!chain.is_spent([7; 32], 0));
+}
+
+#[test]
+fn data_output_is_unspendable() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input(genesis_tx.hash(), 0);
+    tx.add_output(COINBASE, &alice.vk);
+    let data_idx = tx.add_data_output(b"memo".to_vec()).unwrap();
+    let tx = tx.sing_inputs_and_finalize(&bob.sk).unwrap();
+    let tx_hash = tx.hash();
+    assert!(handler.process_tx(tx));
+    let block = handler.create_block(&alice.vk);
+    assert!(handler.process_block(block));
+
+    let chain = handler.chain();
+    assert_eq!(OutputStatus::Unspent, chain.output_status(tx_hash, 0));
+    assert_eq!(
+        OutputStatus::Unspendable,
+        chain.output_status(tx_hash, data_idx)
+    );
+    assert!(!chain.is_spent(tx_hash, data_idx));
+    assert_eq!(
+        OutputStatus::Unknown,
+        chain.output_status(tx_hash, data_idx + 1)
+    );
+}
+
+#[test]
+fn pending_data_output_cant_be_spent() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let mut parent = UnsignedTx::new();
+    parent.add_input(genesis_tx.hash(), 0);
+    parent.add_output(COINBASE - 10, &bob.vk);
+    let data_idx = parent.add_data_output(b"memo".to_vec()).unwrap();
+    let parent = parent.sing_inputs_and_finalize(&bob.sk).unwrap();
+    let parent_hash = parent.hash();
+    assert!(handler.process_tx(parent));
+
+    let mut child = UnsignedTx::new();
+    child.add_input(parent_hash, data_idx);
+    child.add_output(0, &alice.vk);
+    let child = child.sing_inputs_and_finalize(&bob.sk).unwrap();
+    assert!(!handler.process_tx(child));
+    assert_eq!(1, handler.chain().tx_pool_at_max_height().len());
+}
+
+#[test]
+fn output_status_and_balances_follow_best_tip() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+    let genesis_block_hash = handler.hash_at_max_height();
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 300),
+    });
+    handler.process_tx(tx);
+    let block = handler.create_block(&alice.vk);
+    assert!(handler.process_block(block));
+    let block = handler.create_block(&alice.vk);
+    let best_tip = block.hash();
+    assert!(handler.process_block(block));
+
+    // shorter side fork becomes max height, but not the best tip
+    let fork = handler
+        .create_fork(genesis_block_hash, &charlie.vk)
+        .unwrap();
+    assert!(handler.process_block(fork));
+
+    let chain = handler.chain();
+    assert_ne!(chain.block_at_max_height().hash(), best_tip);
+    assert_eq!(chain.best_tip().hash(), best_tip);
+    assert!(chain.is_spent(genesis_tx.hash(), 0));
+    assert_eq!(
+        chain.utxo_pool_at_best_tip().outputs().len(),
+        chain.utxo_count()
+    );
+    let balances = chain.balances();
+    let alice_address = PublicKey::from(&alice.vk).address();
+    assert_eq!(Some(&(2 * COINBASE + 300)), balances.get(&alice_address));
+    assert_eq!(None, balances.get(&PublicKey::from(&charlie.vk).address()));
+}
+
+#[test]
+fn conflicting_tx_rejected_unless_fee_is_higher() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, COINBASE - 10)],
+        return_to_sender: None,
+    });
+    let tx1_hash = tx1.hash();
+    assert!(handler.process_tx(tx1));
+
+    let tx2_same_fee = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&charlie, COINBASE - 10)],
+        return_to_sender: None,
+    });
+    assert_eq!(
+        vec![tx1_hash],
+        handler.chain().pending_conflicts(&tx2_same_fee)
+    );
+    assert!(!handler.process_tx(tx2_same_fee));
+
+    let tx3_higher_fee = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&charlie, COINBASE - 20)],
+        return_to_sender: None,
+    });
+    let tx3_hash = tx3_higher_fee.hash();
+    assert!(handler.process_tx(tx3_higher_fee));
+
+    let mempool = handler.chain().tx_pool_at_max_height();
+    assert!(mempool.tx(tx1_hash).is_none());
+    assert!(mempool.tx(tx3_hash).is_some());
+}
+
+#[test]
+fn replaced_tx_evicts_its_descendants() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let parent = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, COINBASE - 10)],
+        return_to_sender: None,
+    });
+    let child = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&parent, 0)],
+        outputs: &[(&charlie, COINBASE - 20)],
+        return_to_sender: None,
+    });
+    let grandchild = new_tx(NewTxParams {
+        sender: &charlie,
+        inputs: &[(&child, 0)],
+        outputs: &[(&alice, COINBASE - 30)],
+        return_to_sender: None,
+    });
+    let (parent_hash, child_hash) = (parent.hash(), child.hash());
+    assert!(handler.process_tx(parent));
+    assert!(handler.process_tx(child.clone()));
+    assert_eq!(
+        vec![child_hash],
+        handler
+            .chain()
+            .tx_pool_at_max_height()
+            .descendants(parent_hash)
+    );
+
+    // pays more than the parent, but not more than the parent with its child
+    let replacement = |fee| {
+        new_tx(NewTxParams {
+            sender: &bob,
+            inputs: &[(&genesis_tx, 0)],
+            outputs: &[(&charlie, COINBASE - fee)],
+            return_to_sender: None,
+        })
+    };
+    assert!(!handler.process_tx(replacement(20)));
+
+    let replacement = replacement(21);
+    let replacement_hash = replacement.hash();
+    assert!(handler.process_tx(replacement));
+
+    let mempool = handler.chain().tx_pool_at_max_height();
+    assert!(mempool.tx(parent_hash).is_none());
+    assert!(mempool.tx(child_hash).is_none());
+    assert!(mempool.tx(replacement_hash).is_some());
+    assert_eq!(1, mempool.len());
+
+    // outputs of the evicted child aren't pending anymore
+    assert!(!handler.process_tx(grandchild));
+    assert!(!handler.process_tx(child));
+}
+
+#[test]
+fn replacement_outbids_all_conflicts_together() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let split = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&bob, 300)],
+        return_to_sender: Some(COINBASE - 300),
+    });
+    assert!(handler.process_tx(split.clone()));
+    let block = handler.create_block(&alice.vk);
+    assert!(handler.process_block(block));
+
+    let conflict1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&split, 0)],
+        outputs: &[(&alice, 290)],
+        return_to_sender: None,
+    });
+    let conflict2 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&split, 1)],
+        outputs: &[(&alice, COINBASE - 310)],
+        return_to_sender: None,
+    });
+    assert!(handler.process_tx(conflict1));
+    assert!(handler.process_tx(conflict2));
+
+    // pays more than each conflict, but not more than both of them
+    let replacement = |fee| {
+        new_tx(NewTxParams {
+            sender: &bob,
+            inputs: &[(&split, 0), (&split, 1)],
+            outputs: &[(&charlie, COINBASE - fee)],
+            return_to_sender: None,
+        })
+    };
+    assert_eq!(2, handler.chain().pending_conflicts(&replacement(11)).len());
+    assert!(!handler.process_tx(replacement(11)));
+    assert!(!handler.process_tx(replacement(20)));
+    assert_eq!(2, handler.chain().tx_pool_at_max_height().len());
+
+    let replacement = replacement(21);
+    let replacement_hash = replacement.hash();
+    assert!(handler.process_tx(replacement));
+
+    let mempool = handler.chain().tx_pool_at_max_height();
+    assert!(mempool.tx(replacement_hash).is_some());
+    assert_eq!(1, mempool.len());
+}
+
+#[test]
+fn invalid_tx_doesnt_replace_pooled_txs() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, COINBASE - 10)],
+        return_to_sender: None,
+    });
+    let tx1_hash = tx1.hash();
+    assert!(handler.process_tx(tx1));
+
+    // higher fee, but signed by a key not owning the output
+    let forged = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, COINBASE - 20)],
+        return_to_sender: None,
+    });
+    assert!(!handler.process_tx(forged));
+    assert!(handler
+        .chain()
+        .tx_pool_at_max_height()
+        .tx(tx1_hash)
+        .is_some());
+}
+
+#[test]
+fn chain_query_responses_as_json() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 300),
+    });
+    let tx_hash = tx.hash();
+    handler.process_tx(tx.clone());
+    let block = handler.create_block(&alice.vk);
+    let block_hash = block.hash();
+    assert!(handler.process_block(block));
+
+    let pending = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx, 0)],
+        outputs: &[(&bob, 300)],
+        return_to_sender: None,
+    });
+    let pending_hash = pending.hash();
+    assert!(handler.process_tx(pending));
+
+    let query = ChainQuery::new(handler.chain());
+
+    let tip = serde_json::to_value(query.get_tip()).unwrap();
+    assert_eq!(json!(to_hex(&block_hash)), tip["hash"]);
+    assert_eq!(1, tip["txs"].as_array().unwrap().len());
+    assert_eq!(json!(COINBASE), tip["coinbase"]["outputs"][0]["value"]);
+    assert_eq!(
+        serde_json::to_value(query.get_block(block_hash).unwrap()).unwrap(),
+        tip
+    );
+    assert!(query.get_block([0; 32]).is_none());
+
+    let tx = serde_json::to_value(query.get_tx(tx_hash).unwrap()).unwrap();
+    assert_eq!(json!(to_hex(&tx_hash)), tx["hash"]);
+    assert_eq!(
+        json!({ "tx_hash": to_hex(&genesis_tx.hash()), "output_idx": 0 }),
+        tx["inputs"][0]
+    );
+    assert_eq!(json!(300), tx["outputs"][0]["value"]);
+    let alice_address = to_hex(&PublicKey::from(&alice.vk).address());
+    assert_eq!(json!(alice_address), tx["outputs"][0]["address"]);
+    assert_eq!(
+        json!(to_hex(&pending_hash)),
+        serde_json::to_value(query.get_tx(pending_hash).unwrap()).unwrap()["hash"]
+    );
+
+    assert_eq!(
+        json!({
+            "address": alice_address,
+            "balance": 300 + COINBASE,
+            // alice mined the only block, so her coinbase isn't mature yet
+            "spendable_balance": 300
+        }),
+        serde_json::to_value(query.get_balance(&alice.vk)).unwrap()
+    );
+
+    let utxos = serde_json::to_value(query.get_utxos_of(&bob.vk)).unwrap();
+    assert_eq!(
+        json!([{ "tx_hash": to_hex(&tx_hash), "output_idx": 1, "value": COINBASE - 300 }]),
+        utxos
+    );
+}
+
+#[test]
+fn custom_chain_params() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let genesis_hash = genesis.hash();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let params = ChainParams {
+        coinbase_reward: 50,
+        cut_off_age: 3,
+        max_txs_per_block: 1,
+        ..ChainParams::default()
+    };
+    let mut chain = Blockchain::with_params(genesis, pool, params, &Sha256Hasher);
+
+    let default_reward = IncompleteBlock::new(genesis_hash, &alice.vk).finalize();
+    assert_eq!(
+        Err(AddBlockError::InvalidCoinbase),
+        chain.try_add_block(default_reward)
+    );
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 100)],
+        return_to_sender: Some(COINBASE - 100),
+    });
+    let tx2 = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx1, 0)],
+        outputs: &[(&bob, 100)],
+        return_to_sender: None,
+    });
+    let mut too_many = IncompleteBlock::with_reward(genesis_hash, &alice.vk, 50, &Sha256Hasher);
+    too_many.add_txs([tx1.clone(), tx2.clone()]);
+    assert_eq!(
+        Err(AddBlockError::TooManyTxs(2)),
+        chain.try_add_block(too_many.finalize())
+    );
+
+    let mut handler = BlockHandler::new(chain);
+    handler.process_tx(tx1);
+    handler.process_tx(tx2);
+    for _ in 0..params.cut_off_age {
+        let block = handler.create_block(&alice.vk);
+        assert!(block.txs().len() <= 1);
+        assert_eq!(Some(50), block.coinbase().output(0).map(|o| o.value()));
+        assert!(handler.process_block(block));
+    }
+    assert!(handler.chain().tx_pool_at_max_height().is_empty());
+    assert!(handler.chain().utxo_pool_at(genesis_hash).is_none());
+    assert!(handler.create_fork(genesis_hash, &alice.vk).is_none());
+}
+
+#[test]
+fn headers_imported_before_bodies() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let new_genesis = || {
+        let mut genesis = IncompleteBlock::genesis(&bob.vk);
+        genesis.set_timestamp(1_000);
+        genesis.finalize()
+    };
+    let genesis = new_genesis();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 100)],
+        return_to_sender: Some(COINBASE - 100),
+    });
+    let mut blocks = vec![];
+    let mut prev = genesis.hash();
+    for i in 0..3 {
+        let mut block = IncompleteBlock::new(prev, &alice.vk);
+        block.set_timestamp(1_000 + i * 10);
+        if i == 0 {
+            block.add_tx(tx.clone());
+        }
+        let block = block.finalize();
+        prev = block.hash();
+        blocks.push(block);
+    }
+    assert_eq!(
+        merkle_root(&Sha256Hasher, &[blocks[0].coinbase().hash(), tx.hash()]),
+        blocks[0].merkle_root()
+    );
+    let headers: Vec<_> = blocks.iter().map(|block| block.header()).collect();
+    let hashes: Vec<[u8; 32]> = blocks.iter().map(|block| block.hash()).collect();
+
+    let mut chain = Blockchain::new(new_genesis(), pool.clone());
+    let broken = vec![headers[0].clone(), headers[2].clone()];
+    assert_eq!(
+        Err(SyncError::BrokenLink(hashes[2])),
+        chain.import_headers(broken)
+    );
+    assert!(chain.missing_bodies().is_empty());
+
+    assert_eq!(Ok(()), chain.import_headers(headers.clone()));
+    assert_eq!(hashes, chain.missing_bodies());
+    // already known headers are skipped
+    assert_eq!(Ok(()), chain.import_headers(headers));
+    assert_eq!(hashes, chain.missing_bodies());
+
+    for block in blocks {
+        assert!(chain.add_block(block));
+    }
+    assert!(chain.missing_bodies().is_empty());
+    assert_eq!(hashes[2], chain.best_tip().hash());
+}
+
+#[test]
+fn imported_headers_checked_bounded_and_dropped_after_cut_off() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let mut genesis = IncompleteBlock::genesis(&bob.vk);
+    genesis.set_timestamp(1_000);
+    genesis.set_difficulty(2);
+    let genesis = genesis.finalize();
+    let genesis_hash = genesis.hash();
+    let (pool, _genesis_tx) = setup_pool(&genesis);
+    let params = ChainParams {
+        cut_off_age: 3,
+        ..ChainParams::default()
+    };
+    let mut chain = Blockchain::with_params(genesis, pool, params, &Sha256Hasher);
+    let mine = |prev: [u8; 32], miner: &Participant, difficulty: u32| {
+        let mut block = IncompleteBlock::new(prev, &miner.vk);
+        block.set_timestamp(1_000);
+        block.set_difficulty(difficulty);
+        block.finalize()
+    };
+
+    let fork = mine(genesis_hash, &alice, 2).header();
+    let too_easy = mine(fork.hash(), &alice, 0).header();
+    assert_eq!(
+        Err(SyncError::InvalidDifficulty(too_easy.hash())),
+        chain.import_headers(vec![fork.clone(), too_easy])
+    );
+    let too_easy = mine(genesis_hash, &alice, 1).header();
+    assert_eq!(
+        Err(SyncError::InvalidDifficulty(too_easy.hash())),
+        chain.import_headers(vec![too_easy])
+    );
+    assert_eq!(Ok(()), chain.import_headers(vec![fork.clone()]));
+
+    // fork header is dropped once genesis is older than the cut-off age
+    let mut prev = genesis_hash;
+    for _ in 0..params.cut_off_age {
+        let block = mine(prev, &bob, 2);
+        prev = block.hash();
+        assert!(chain.add_block(block));
+        assert_eq!(
+            chain.at_block_hash(genesis_hash).is_some(),
+            chain.missing_bodies() == vec![fork.hash()]
+        );
+    }
+    assert!(chain.missing_bodies().is_empty());
+
+    let mut headers = vec![];
+    for _ in 0..=MAX_HEADERS {
+        let header = mine(prev, &alice, 2).header();
+        prev = header.hash();
+        headers.push(header);
+    }
+    assert_eq!(
+        Err(SyncError::TooManyHeaders),
+        chain.import_headers(headers.clone())
+    );
+    headers.pop();
+    assert_eq!(Ok(()), chain.import_headers(headers));
+    assert_eq!(MAX_HEADERS, chain.missing_bodies().len());
+}
+
+#[test]
+fn process_shuffled_blocks() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let (mut handler, _) = setup_handler(&bob);
+
+    let mut blocks = vec![];
+    let mut prev = handler.hash_at_max_height();
+    for _ in 0..5 {
+        let block = IncompleteBlock::new(prev, &bob.vk).finalize();
+        prev = block.hash();
+        blocks.push(block);
+    }
+    let tip_hash = prev;
+    let mut shuffled: Vec<_> = blocks.into_iter().map(Some).collect();
+    let shuffled: Vec<_> = [3, 0, 4, 2, 1]
+        .iter()
+        .map(|&idx: &usize| shuffled[idx].take().unwrap())
+        .collect();
+
+    assert_eq!(vec![true; 5], handler.process_blocks(shuffled));
+    assert_eq!(0, handler.chain().orphan_count());
+    assert_eq!(tip_hash, handler.hash_at_max_height());
+
+    // same block twice in a batch is added only once
+    let next = IncompleteBlock::new(tip_hash, &bob.vk).finalize();
+    let next_hash = next.hash();
+    let duplicate = IncompleteBlock::new(tip_hash, &bob.vk).finalize();
+    assert_eq!(
+        vec![true, false],
+        handler.process_blocks(vec![next, duplicate])
+    );
+    assert_eq!(next_hash, handler.hash_at_max_height());
+}
+
+#[test]
+fn utxo_count_and_value_histogram() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+    assert_eq!(1, handler.chain().utxo_count());
+    assert_eq!(
+        BTreeMap::from([(512, 1)]),
+        handler.chain().value_histogram()
+    );
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300), (&alice, 3), (&alice, 1)],
+        return_to_sender: Some(COINBASE - 304),
+    });
+    handler.process_tx(tx);
+    let block = handler.create_block(&bob.vk);
+    assert!(handler.process_block(block));
+
+    // new coinbase 625, outputs 300, 3, 1 and change 321
+    assert_eq!(5, handler.chain().utxo_count());
+    assert_eq!(
+        BTreeMap::from([(1, 1), (2, 1), (256, 2), (512, 1)]),
+        handler.chain().value_histogram()
+    );
+}
+
+#[test]
+fn inclusion_proofs_of_txs_in_block() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let first = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 100), (&alice, 100), (&alice, 100)],
+        return_to_sender: Some(COINBASE - 300),
+    });
+    let txs: Vec<_> = (0..3)
+        .map(|idx| {
+            new_tx(NewTxParams {
+                sender: &alice,
+                inputs: &[(&first, idx)],
+                outputs: &[(&bob, 100)],
+                return_to_sender: None,
+            })
+        })
+        .collect();
+    let mut block = IncompleteBlock::new(handler.hash_at_max_height(), &bob.vk);
+    block.add_txs([first.clone()]);
+    block.add_txs(txs.iter().cloned());
+    let block = block.finalize();
+    let header = block.header();
+    let coinbase_hash = block.coinbase().hash();
+    assert!(handler.process_block(block));
+    handler.process_block(IncompleteBlock::new(header.hash(), &bob.vk).finalize());
+
+    let hashes = [
+        coinbase_hash,
+        first.hash(),
+        txs[0].hash(),
+        txs[1].hash(),
+        txs[2].hash(),
+    ];
+    for (index, &hash) in hashes.iter().enumerate() {
+        let proof = handler.chain().inclusion_proof(hash).unwrap();
+        assert_eq!(header.hash(), proof.block_hash);
+        assert_eq!(1, proof.height);
+        assert_eq!(index, proof.index);
+        assert_eq!(3, proof.path.len());
+        assert!(verify_inclusion(&header, &proof, hash));
+    }
+
+    let proof = handler.chain().inclusion_proof(txs[2].hash()).unwrap();
+    assert!(!verify_inclusion(&header, &proof, txs[1].hash()));
+    let genesis = handler.chain().best_chain()[0].header();
+    assert!(!verify_inclusion(&genesis, &proof, txs[2].hash()));
+    let mut too_deep = proof.clone();
+    too_deep.index = 1 << proof.path.len();
+    assert!(!verify_inclusion(&header, &too_deep, txs[2].hash()));
+}
+
+#[test]
+fn no_inclusion_proof_of_unknown_tx() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let in_block = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 100)],
+        return_to_sender: Some(COINBASE - 100),
+    });
+    let only_pending = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&in_block, 0)],
+        outputs: &[(&bob, 100)],
+        return_to_sender: None,
+    });
+    handler.process_tx(in_block.clone());
+    let block = handler.create_block(&bob.vk);
+    let header = block.header();
+    assert!(handler.process_block(block));
+    handler.process_tx(only_pending.clone());
+
+    assert_eq!(None, handler.chain().inclusion_proof(only_pending.hash()));
+    assert_eq!(None, handler.chain().inclusion_proof([7; 32]));
+
+    let proof = handler.chain().inclusion_proof(in_block.hash()).unwrap();
+    assert!(verify_inclusion(&header, &proof, in_block.hash()));
+    assert!(!verify_inclusion(&header, &proof, only_pending.hash()));
+}
+
+#[test]
+fn try_process_block_errors() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+    let genesis_hash = handler.hash_at_max_height();
+    let genesis_timestamp = handler.chain().block_at_max_height().timestamp();
+
+    let block = IncompleteBlock::new(genesis_hash, &bob.vk).finalize();
+    let block_hash = block.hash();
+    assert_eq!(Ok(block_hash), handler.try_process_block(block));
+
+    let duplicate = IncompleteBlock::new(genesis_hash, &bob.vk).finalize();
+    assert_eq!(duplicate.hash(), block_hash);
+    assert_eq!(
+        Err(ProcessError::AlreadyKnown),
+        handler.try_process_block(duplicate)
+    );
+
+    let genesis = IncompleteBlock::genesis(&alice.vk).finalize();
+    assert_eq!(
+        Err(ProcessError::UnexpectedGenesis),
+        handler.try_process_block(genesis)
+    );
+
+    let orphan = IncompleteBlock::new([7; 32], &bob.vk).finalize();
+    assert_eq!(
+        Err(ProcessError::UnknownParent),
+        handler.try_process_block(orphan)
+    );
+
+    let mut tampered = IncompleteBlock::new([7; 32], &alice.vk).finalize();
+    tampered.set_prev(block_hash);
+    assert_eq!(
+        Err(ProcessError::InvalidHash),
+        handler.try_process_block(tampered)
+    );
+
+    let mut too_old = IncompleteBlock::new(block_hash, &alice.vk);
+    too_old.set_timestamp(genesis_timestamp - 1);
+    assert_eq!(
+        Err(ProcessError::InvalidTimestamp),
+        handler.try_process_block(too_old.finalize())
+    );
+
+    let greedy = IncompleteBlock::with_reward(block_hash, &alice.vk, COINBASE + 1, &Sha256Hasher);
+    assert_eq!(
+        Err(ProcessError::InvalidCoinbase),
+        handler.try_process_block(greedy.finalize())
+    );
+
+    let overspending = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, COINBASE + 1)],
+        return_to_sender: None,
+    });
+    let mut invalid_tx = IncompleteBlock::new(block_hash, &alice.vk);
+    invalid_tx.add_tx(overspending);
+    assert_eq!(
+        Err(ProcessError::InvalidTxs),
+        handler.try_process_block(invalid_tx.finalize())
+    );
+
+    // parents older than the cut off age are forgotten
+    for _ in 0..CUT_OFF_AGE {
+        let block = handler.create_block(&bob.vk);
+        assert!(handler.try_process_block(block).is_ok());
+    }
+    let stale_fork = IncompleteBlock::new(block_hash, &alice.vk).finalize();
+    assert_eq!(
+        Err(ProcessError::UnknownParent),
+        handler.try_process_block(stale_fork)
+    );
+}
+
+#[test]
+fn unmined_txs_expire_from_mempool() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let params = ChainParams {
+        mempool_expiry: 2,
+        ..ChainParams::default()
+    };
+    let chain = Blockchain::with_params(genesis, pool, params, &Sha256Hasher);
+    let mut handler = BlockHandler::new(chain);
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 100)],
+        return_to_sender: None,
+    });
+    let tx_hash = tx.hash();
+    assert!(handler.process_tx(tx));
+    assert_eq!(
+        Some(0),
+        handler.chain().tx_pool_at_max_height().admitted_in(tx_hash)
+    );
+
+    // created blocks are never processed, so the tx stays unmined
+    for round in 1..=params.mempool_expiry {
+        handler.create_block(&bob.vk);
+        let mempool = handler.chain().tx_pool_at_max_height();
+        assert_eq!(round, mempool.round());
+        assert!(mempool.tx(tx_hash).is_some());
+    }
+    handler.create_block(&bob.vk);
+    assert!(handler.chain().tx_pool_at_max_height().is_empty());
+}
+
+#[test]
+fn balances_by_address() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 200), (&alice, 100), (&charlie, 25)],
+        return_to_sender: Some(COINBASE - 325),
+    });
+    let tx2 = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx1, 1)],
+        outputs: &[(&charlie, 60)],
+        return_to_sender: Some(40),
+    });
+    handler.process_tx(tx1);
+    handler.process_tx(tx2);
+    let block = handler.create_block(&charlie.vk);
+    assert!(handler.process_block(block));
+
+    let pool = handler.chain().utxo_pool_at_max_height();
+    let expected: HashMap<[u8; 20], u64> = [&bob, &alice, &charlie]
+        .iter()
+        .map(|p| {
+            let pub_key = PublicKey::from(&p.vk);
+            (pub_key.address(), balance_of(pool, &pub_key))
+        })
+        .collect();
+    assert_eq!(expected, handler.chain().balances());
+    assert_eq!(
+        COINBASE + 25 + 60,
+        handler.chain().balances()[&PublicKey::from(&charlie.vk).address()]
+    );
+}
+
+#[test]
+fn simulated_block_matches_created_block() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 310),
+    });
+    let tx2 = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx1, 0)],
+        outputs: &[(&bob, 275)],
+        return_to_sender: None,
+    });
+    handler.process_tx(tx1);
+    handler.process_tx(tx2);
+
+    let (simulated, fees) = handler.simulate_block();
+    assert_eq!(10 + 25, fees);
+    let simulated: Vec<[u8; 32]> = simulated.iter().map(|tx| tx.hash()).collect();
+    assert_eq!(
+        simulated,
+        handler
+            .simulate_block()
+            .0
+            .iter()
+            .map(|tx| tx.hash())
+            .collect::<Vec<_>>()
+    );
+
+    let block = handler.create_block(&bob.vk);
+    let created: Vec<[u8; 32]> = block.txs().iter().map(|tx| tx.hash()).collect();
+    assert_eq!(simulated, created);
+    assert_eq!(2, created.len());
+}
+
+#[test]
+fn dot_graph_of_forks() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, _genesis_tx) = setup_handler(&bob);
+    let genesis_block_hash = handler.hash_at_max_height();
+
+    let fork = handler.create_fork(genesis_block_hash, &alice.vk).unwrap();
+    let fork_hash = fork.hash();
+    assert!(handler.process_block(fork));
+    for _ in 0..2 {
+        let block = handler.create_block(&bob.vk);
+        assert!(handler.process_block(block));
+    }
+    let side = handler.create_fork(fork_hash, &alice.vk).unwrap();
+    let side_hash = side.hash();
+    assert!(handler.process_block(side));
+
+    let dot = handler.chain().to_dot();
+    assert!(dot.starts_with("digraph blockchain {"));
+    assert!(dot.ends_with("}\n"));
+    let nodes: Vec<&str> = dot.lines().filter(|l| l.contains("[label=")).collect();
+    let edges: Vec<&str> = dot.lines().filter(|l| l.contains(" -> ")).collect();
+    // genesis, fork block, two blocks on top of it and a side block
+    assert_eq!(5, nodes.len());
+    assert_eq!(4, edges.len());
+
+    let best = handler.chain().best_chain();
+    assert_eq!(4, best.len());
+    for block in best.iter() {
+        let hash = to_hex(&block.hash());
+        assert!(nodes
+            .iter()
+            .any(|l| l.starts_with(&format!("    \"{}\"", hash)) && l.contains("style=bold")));
+    }
+    let side_hash = to_hex(&side_hash);
+    assert!(nodes
+        .iter()
+        .any(|l| l.starts_with(&format!("    \"{}\"", side_hash)) && !l.contains("bold")));
+    assert_eq!(3, edges.iter().filter(|l| l.contains("bold")).count());
+    assert!(
+        edges.contains(&format!("    \"{}\" -> \"{}\";", side_hash, to_hex(&fork_hash)).as_str())
+    );
+}
+
+#[test]
+fn signature_cache_skips_repeated_verifications() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let genesis = IncompleteBlock::genesis(&bob.vk).finalize();
+    let (pool, genesis_tx) = setup_pool(&genesis);
+    let mut chain = Blockchain::new(genesis, pool);
+    chain.enable_signature_cache();
+    let mut handler = BlockHandler::new(chain);
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, COINBASE - 10)],
+        return_to_sender: None,
+    });
+    assert!(handler.process_tx(tx));
+    assert_eq!(1, handler.simulate_block().0.len());
+    let block = handler.create_block(&bob.vk);
+    assert_eq!(1, block.txs().len());
+    assert!(handler.process_block(block));
+    // verified once when admitted to the mempool, then always found
+    let cache = handler.chain().signature_cache().unwrap();
+    assert_eq!(1, cache.len());
+    assert_eq!(3, cache.hits());
+}
+
+#[test]
+fn block_txids_start_with_coinbase() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 310),
+    });
+    let tx2 = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx1, 0)],
+        outputs: &[(&bob, 290)],
+        return_to_sender: None,
+    });
+    handler.process_tx(tx1);
+    handler.process_tx(tx2);
+    let block = handler.create_block(&bob.vk);
+    let block_hash = block.hash();
+    let mut expected = vec![block.coinbase().hash()];
+    expected.extend(block.txs().iter().map(|tx| tx.hash()));
+    assert!(handler.process_block(block));
+
+    let txids = handler.chain().block_txids(block_hash).unwrap();
+    assert_eq!(3, txids.len());
+    assert_eq!(expected, txids);
+
+    let genesis_hash = handler.chain().best_chain()[0].hash();
+    assert_eq!(
+        Some(vec![genesis_tx.hash()]),
+        handler.chain().block_txids(genesis_hash)
+    );
+    assert_eq!(None, handler.chain().block_txids([7; 32]));
+}
+
+#[test]
+fn mismatched_genesis_pool_rejected() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let new_genesis = |owner: &Participant| {
+        let mut genesis = IncompleteBlock::genesis(&owner.vk);
+        genesis.set_timestamp(1_000);
+        genesis.finalize()
+    };
+    let (pool, genesis_tx) = setup_pool(&new_genesis(&bob));
+    assert_eq!(pool, genesis_pool(&new_genesis(&bob)));
+
+    let mut extra = pool.clone();
+    extra.add_utxo(UTXO::new([1; 32], 0), genesis_tx.output(0).unwrap());
+    for mismatched in [UTXOPool::new(), extra, genesis_pool(&new_genesis(&alice))] {
+        assert!(matches!(
+            Blockchain::try_new(new_genesis(&bob), mismatched),
+            Err(GenesisError::PoolMismatch)
+        ));
+    }
+
+    let params = ChainParams {
+        cut_off_age: 0,
+        ..ChainParams::default()
+    };
+    assert!(matches!(
+        Blockchain::try_with_params(new_genesis(&bob), pool.clone(), params, &Sha256Hasher),
+        Err(GenesisError::NoRetainedBlocks)
+    ));
+
+    let handler = BlockHandler::new(Blockchain::try_new(new_genesis(&bob), pool).unwrap());
+    let block = handler
+        .create_fork(handler.hash_at_max_height(), &alice.vk)
+        .unwrap();
+    let block_pool = genesis_pool(&block);
+    assert!(matches!(
+        Blockchain::try_new(block, block_pool),
+        Err(GenesisError::NotGenesis)
+    ));
+}
+
+#[test]
+fn coinbase_spendable_once_mature() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, _tx) = setup_handler(&bob);
+
+    let block = handler.create_block(&alice.vk);
+    assert!(handler.process_block(block));
+    let balance = |handler: &BlockHandler| {
+        let chain = handler.chain();
+        (
+            balance_of(chain.utxo_pool_at_max_height(), &alice.vk),
+            spendable_balance_of(chain, &alice.vk),
+        )
+    };
+    assert_eq!((COINBASE, 0), balance(&handler));
+
+    for _ in 1..COINBASE_MATURITY {
+        let block = handler.create_block(&bob.vk);
+        assert!(handler.process_block(block));
+        assert_eq!((COINBASE, 0), balance(&handler));
+    }
+
+    let block = handler.create_block(&bob.vk);
+    assert!(handler.process_block(block));
+    assert_eq!((COINBASE, COINBASE), balance(&handler));
+}
+
+#[test]
+fn failed_block_application_leaves_pool_unchanged() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+    let genesis_hash = handler.chain().block_at_max_height().hash();
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 300),
+    });
+    let tx2 = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx1, 0)],
+        outputs: &[(&bob, 300)],
+        return_to_sender: None,
+    });
+    let double_spend = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, COINBASE)],
+        return_to_sender: None,
+    });
+
+    let original = handler.chain().utxo_pool_at_max_height().clone();
+    let mut pool = original.clone();
+
+    // first two txs are applied before the double spend is found invalid
+    let mut invalid = IncompleteBlock::new(genesis_hash, &alice.vk);
+    invalid.add_txs([tx1.clone(), tx2.clone(), double_spend]);
+    assert_eq!(
+        Err(AddBlockError::InvalidTxs),
+        apply_block_to_pool(&mut pool, &invalid.finalize())
+    );
+    assert_eq!(original, pool);
+
+    let mut valid = IncompleteBlock::new(genesis_hash, &alice.vk);
+    valid.add_txs([tx1, tx2]);
+    let valid = valid.finalize();
+    assert_eq!(Ok(()), apply_block_to_pool(&mut pool, &valid));
+    assert_ne!(original, pool);
+    assert!(handler.process_block(valid));
+    assert_eq!(handler.chain().utxo_pool_at_max_height(), &pool);
+}
+
+#[test]
+fn genesis_utxos_of_split_coinbase() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let genesis =
+        IncompleteBlock::genesis_split(&[(100, &bob.vk), (200, &alice.vk), (300, &bob.vk)])
+            .unwrap()
+            .finalize();
+    let coinbase_hash = genesis.coinbase().hash();
+
+    let utxos = genesis_utxos(&genesis);
+    assert_eq!(
+        vec![
+            UTXO::new(coinbase_hash, 0),
+            UTXO::new(coinbase_hash, 1),
+            UTXO::new(coinbase_hash, 2)
+        ],
+        utxos
+            .iter()
+            .map(|(utxo, _)| utxo.clone())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec![100, 200, 300],
+        utxos
+            .iter()
+            .map(|(_, output)| output.value())
+            .collect::<Vec<_>>()
+    );
+
+    let chain = Blockchain::try_new(genesis, utxos.into_iter().collect()).unwrap();
+    let pool = chain.utxo_pool_at_max_height();
+    assert_eq!(400, balance_of(pool, &bob.vk));
+    assert_eq!(200, balance_of(pool, &alice.vk));
+}
+
+#[test]
+fn genesis_split_rejects_unfit_payments() {
+    common::initialize();
+
+    let bob = Participant::new();
+    assert!(matches!(
+        IncompleteBlock::genesis_split(&[]),
+        Err(TxError::NoOutputs)
+    ));
+    let payments = vec![(1, &bob.vk); MAX_OUTPUTS + 1];
+    assert!(matches!(
+        IncompleteBlock::genesis_split(&payments),
+        Err(TxError::TooManyOutputs(len)) if len == MAX_OUTPUTS + 1
+    ));
+}
+
+#[test]
+fn value_sums_saturate() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let genesis = IncompleteBlock::genesis_split(&[(u64::MAX, &bob.vk), (u64::MAX, &bob.vk)])
+        .unwrap()
+        .finalize();
+    let pool = genesis_pool(&genesis);
+    let params = ChainParams {
+        coinbase_maturity: 0,
+        ..ChainParams::default()
+    };
+    let chain = Blockchain::try_with_params(genesis, pool, params, &Sha256Hasher).unwrap();
+
+    let bob_address = PublicKey::from(&bob.vk).address();
+    assert_eq!(u64::MAX, chain.circulating_supply());
+    assert_eq!(Some(&u64::MAX), chain.balances().get(&bob_address));
+    assert_eq!(u64::MAX, balance_of(chain.utxo_pool_at_best_tip(), &bob.vk));
+    assert_eq!(u64::MAX, spendable_balance_of(&chain, &bob.vk));
+}
+
+#[test]
+fn min_relay_fee_is_policy_not_consensus() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (mut handler, genesis_tx) = setup_handler(&bob);
+    handler.set_min_relay_fee(10);
+
+    let free = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 300),
+    });
+    let paying = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&genesis_tx, 0)],
+        outputs: &[(&alice, 300)],
+        return_to_sender: Some(COINBASE - 310),
+    });
+    assert!(!handler.process_tx(free.clone()));
+    assert!(handler.process_tx(paying.clone()));
+
+    // fee of a child is computed with its pending parent applied
+    let child = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&paying, 0)],
+        outputs: &[(&bob, 290)],
+        return_to_sender: None,
+    });
+    assert!(handler.process_tx(child));
+
+    // another miner included the free tx, its block is still valid
+    let mut block = IncompleteBlock::new(handler.hash_at_max_height(), &alice.vk);
+    block.add_tx(free);
+    assert!(handler.process_block(block.finalize()));
+    assert_eq!(
+        300 + COINBASE,
+        balance_of(handler.chain().utxo_pool_at_max_height(), &alice.vk)
+    );
+}
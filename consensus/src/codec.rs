@@ -0,0 +1,167 @@
+use core::fmt;
+use std::collections::HashSet;
+
+use crate::tx::Tx;
+
+/// Writes node state as big endian bytes, sets are written sorted, so equal
+/// states have equal bytes
+#[derive(Default)]
+pub(crate) struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend(value.to_be_bytes());
+    }
+
+    pub(crate) fn f64(&mut self, value: f64) {
+        self.bytes.extend(value.to_be_bytes());
+    }
+
+    pub(crate) fn bools(&mut self, values: &[bool]) {
+        self.bytes.extend(values.iter().map(|&v| v as u8));
+    }
+
+    pub(crate) fn f64s(&mut self, values: &[f64]) {
+        for &value in values.iter() {
+            self.f64(value);
+        }
+    }
+
+    pub(crate) fn set(&mut self, set: &HashSet<u64>) {
+        let mut values: Vec<u64> = set.iter().copied().collect();
+        values.sort();
+        self.u64(values.len() as u64);
+        for value in values {
+            self.u64(value);
+        }
+    }
+
+    /// Writes id of the tx followed by its optional conflict group
+    pub(crate) fn tx(&mut self, tx: &Tx) {
+        self.u64(tx.id);
+        match tx.conflict_group {
+            Some(group) => {
+                self.u8(1);
+                self.u64(group);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    pub(crate) fn txs(&mut self, txs: &HashSet<Tx>) {
+        let mut txs: Vec<&Tx> = txs.iter().collect();
+        txs.sort();
+        self.u64(txs.len() as u64);
+        for tx in txs {
+            self.tx(tx);
+        }
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads values written by `Encoder` in the same order
+pub(crate) struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take<const L: usize>(&mut self) -> Result<[u8; L], DecodeError> {
+        if self.bytes.len() < L {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(L);
+        self.bytes = rest;
+        Ok(taken.try_into().unwrap())
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_be_bytes(self.take()?))
+    }
+
+    pub(crate) fn f64(&mut self) -> Result<f64, DecodeError> {
+        Ok(f64::from_be_bytes(self.take()?))
+    }
+
+    pub(crate) fn bools<const N: usize>(&mut self) -> Result<[bool; N], DecodeError> {
+        let mut values = [false; N];
+        for value in values.iter_mut() {
+            *value = match self.u8()? {
+                0 => false,
+                1 => true,
+                byte => return Err(DecodeError::InvalidByte(byte)),
+            };
+        }
+        Ok(values)
+    }
+
+    pub(crate) fn f64s<const N: usize>(&mut self) -> Result<[f64; N], DecodeError> {
+        let mut values = [0.; N];
+        for value in values.iter_mut() {
+            *value = self.f64()?;
+        }
+        Ok(values)
+    }
+
+    pub(crate) fn set(&mut self) -> Result<HashSet<u64>, DecodeError> {
+        let len = self.u64()?;
+        (0..len).map(|_| self.u64()).collect()
+    }
+
+    pub(crate) fn tx(&mut self) -> Result<Tx, DecodeError> {
+        let id = self.u64()?;
+        let conflict_group = match self.u8()? {
+            0 => None,
+            1 => Some(self.u64()?),
+            byte => return Err(DecodeError::InvalidByte(byte)),
+        };
+        Ok(Tx { id, conflict_group })
+    }
+
+    pub(crate) fn txs(&mut self) -> Result<HashSet<Tx>, DecodeError> {
+        let len = self.u64()?;
+        (0..len).map(|_| self.tx()).collect()
+    }
+
+    /// Fails if there are bytes left
+    pub(crate) fn finish(self) -> Result<(), DecodeError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            left => Err(DecodeError::TrailingBytes(left)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    InvalidByte(u8),
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "bytes ended before node state"),
+            DecodeError::InvalidByte(byte) => write!(f, "invalid byte {} in node state", byte),
+            DecodeError::TrailingBytes(len) => write!(f, "{} bytes left after node state", len),
+        }
+    }
+}
+impl std::error::Error for DecodeError {}
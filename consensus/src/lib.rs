@@ -1,2 +1,4 @@
+pub mod codec;
 pub mod node;
+pub mod simulation;
 pub mod tx;
@@ -1,8 +1,11 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 
-use rand::{rngs::StdRng, Rng};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
 
-use crate::tx::{Candidate, Tx};
+use crate::{
+    codec::{DecodeError, Decoder, Encoder},
+    tx::{Candidate, CandidateBatch, Tx},
+};
 
 pub trait Node<const N: usize> {
     /// If `ith` entry is `true` then this Node follows the `ith` Node
@@ -12,12 +15,30 @@ pub trait Node<const N: usize> {
     fn pending_txs_set(&mut self, pending_txs: HashSet<Tx>);
 
     /// Returns proposed txs, which shall be send to this Node's followers.
-    /// After final round, the behaviour changes and it will return txs,
-    /// on which consensus was reached.
+    /// After `advance_to_final`, the behaviour changes and it will return
+    /// txs, on which consensus was reached.
     fn followers_send(&self) -> &HashSet<Tx>;
 
+    /// Ends the simulation for this Node, afterwards `followers_send` returns
+    /// the consensus instead of proposals. Nodes sending the same txs in
+    /// both cases don't have to do anything.
+    fn advance_to_final(&mut self) {}
+
     /// Candites from different Nodes
-    fn followees_receive(&mut self, candidates: &Vec<Candidate>);
+    fn followees_receive(&mut self, candidates: &[Candidate]);
+
+    /// Same as `followees_receive`, but candidates are grouped by sender
+    fn followees_receive_batch(&mut self, batches: &[CandidateBatch]) {
+        let candidates: Vec<Candidate> = batches
+            .iter()
+            .flat_map(|b| b.txs.iter().map(|&tx| Candidate::new(tx, b.sender)))
+            .collect();
+        self.followees_receive(&candidates);
+    }
+
+    /// Returns sorted txs on which this Node believes consensus was reached,
+    /// independently of the current round
+    fn consensus_set(&self) -> Vec<Tx>;
 
     fn is_byzantine(&self) -> bool;
 }
@@ -28,19 +49,33 @@ pub struct TrustedNode<const N: usize> {
     /// Probability of a Node will be set as byzantine
     p_byzantine: f64,
     /// Probability of assigning a tx to a Node
+    #[allow(dead_code)]
     p_tx_dist: f64,
-    /// Number of rounds in simulation
+    /// Number of remaining rounds in simulation
     num_rounds: u64,
+    /// Whether `advance_to_final` was called, so consensus is sent instead
+    /// of pending txs
+    is_final: bool,
     /// This node's followers, if `i` is true, then this node follows `ith` node
     followees: [bool; N],
     /// The initial set of txs given to this Node
     pending_txs: HashSet<Tx>,
     /// Map of txs to set of its proposers
     received_txs: HashMap<Tx, HashSet<u64>>,
+    /// How much is the `ith` Node trusted, weights of proposers are summed
+    /// and compared against the `consensus_threshold`
+    followee_weights: [f64; N],
     consensus_reached: HashSet<Tx>,
     /// how many of this nodes followees must confirm a tx in order to reach a
     /// consensus on it, minimum 1
     consensus_threshold: usize,
+    /// If set, a tx stays in the consensus set only if it was heard in one of
+    /// the last `recency_window` rounds
+    recency_window: Option<u64>,
+    /// Number of rounds in which this Node received candidates so far
+    round: u64,
+    /// Map of txs to the round in which they were last heard
+    last_heard: HashMap<Tx, u64>,
 }
 
 impl<const N: usize> TrustedNode<N> {
@@ -50,12 +85,153 @@ impl<const N: usize> TrustedNode<N> {
             p_byzantine,
             p_tx_dist,
             num_rounds,
+            is_final: false,
             followees: [false; N],
             pending_txs: HashSet::new(),
             received_txs: HashMap::new(),
+            followee_weights: [1.; N],
             consensus_reached: HashSet::new(),
             consensus_threshold: 0,
+            recency_window: None,
+            round: 0,
+            last_heard: HashMap::new(),
+        }
+    }
+
+    /// Sets how much is each Node trusted, by default all Nodes have weight 1.
+    pub fn set_followee_weights(&mut self, weights: [f64; N]) {
+        self.followee_weights = weights;
+    }
+
+    /// Requires txs to be re-heard within the last `window` rounds, others
+    /// are dropped from the consensus set by `advance_to_final`. By default
+    /// txs stay in the consensus set once they reach it.
+    pub fn set_recency_window(&mut self, window: u64) {
+        self.recency_window = Some(window);
+    }
+
+    /// Drops txs not heard within the recency window from the consensus set
+    fn drop_stale(&mut self) {
+        let Some(window) = self.recency_window else {
+            return;
+        };
+        let (round, last_heard) = (self.round, &self.last_heard);
+        self.consensus_reached.retain(|tx| {
+            last_heard
+                .get(tx)
+                .is_some_and(|&heard| round - heard < window)
+        });
+    }
+
+    /// Adds tx to the consensus set. Of conflicting txs only the smallest
+    /// one is kept, so honest nodes which received the same txs agree on the
+    /// same one.
+    fn reach_consensus(&mut self, tx: Tx) {
+        let conflicts: Vec<Tx> = self
+            .consensus_reached
+            .iter()
+            .filter(|reached| reached.conflicts_with(&tx))
+            .copied()
+            .collect();
+        if conflicts.iter().any(|&conflict| conflict < tx) {
+            return;
+        }
+        for conflict in conflicts.iter() {
+            self.consensus_reached.remove(conflict);
         }
+        self.consensus_reached.insert(tx);
+    }
+
+    /// Returns state of this node as bytes, e.g. for checkpointing a
+    /// simulation and resuming it elsewhere with `from_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut e = Encoder::default();
+        e.f64(self.p_graph);
+        e.f64(self.p_byzantine);
+        e.f64(self.p_tx_dist);
+        e.u64(self.num_rounds);
+        e.u8(self.is_final.into());
+        e.bools(&self.followees);
+        e.txs(&self.pending_txs);
+        let mut received: Vec<(&Tx, &HashSet<u64>)> = self.received_txs.iter().collect();
+        received.sort_by_key(|(tx, _)| **tx);
+        e.u64(received.len() as u64);
+        for (tx, proposers) in received {
+            e.tx(tx);
+            e.set(proposers);
+        }
+        e.f64s(&self.followee_weights);
+        e.txs(&self.consensus_reached);
+        e.u64(self.consensus_threshold as u64);
+        match self.recency_window {
+            Some(window) => {
+                e.u8(1);
+                e.u64(window);
+            }
+            None => e.u8(0),
+        }
+        e.u64(self.round);
+        let mut last_heard: Vec<(&Tx, &u64)> = self.last_heard.iter().collect();
+        last_heard.sort();
+        e.u64(last_heard.len() as u64);
+        for (tx, &heard) in last_heard {
+            e.tx(tx);
+            e.u64(heard);
+        }
+        e.finish()
+    }
+
+    /// Restores a node from bytes returned by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut d = Decoder::new(bytes);
+        let p_graph = d.f64()?;
+        let p_byzantine = d.f64()?;
+        let p_tx_dist = d.f64()?;
+        let num_rounds = d.u64()?;
+        let is_final = match d.u8()? {
+            0 => false,
+            1 => true,
+            byte => return Err(DecodeError::InvalidByte(byte)),
+        };
+        let followees = d.bools()?;
+        let pending_txs = d.txs()?;
+        let mut received_txs = HashMap::new();
+        for _ in 0..d.u64()? {
+            let tx = d.tx()?;
+            received_txs.insert(tx, d.set()?);
+        }
+        let followee_weights = d.f64s()?;
+        let consensus_reached = d.txs()?;
+        let consensus_threshold = d.u64()? as usize;
+        let recency_window = match d.u8()? {
+            0 => None,
+            1 => Some(d.u64()?),
+            byte => return Err(DecodeError::InvalidByte(byte)),
+        };
+        let round = d.u64()?;
+        let mut last_heard = HashMap::new();
+        for _ in 0..d.u64()? {
+            let tx = d.tx()?;
+            last_heard.insert(tx, d.u64()?);
+        }
+        let node = Self {
+            p_graph,
+            p_byzantine,
+            p_tx_dist,
+            num_rounds,
+            is_final,
+            followees,
+            pending_txs,
+            received_txs,
+            followee_weights,
+            consensus_reached,
+            consensus_threshold,
+            recency_window,
+            round,
+            last_heard,
+        };
+        d.finish()?;
+        Ok(node)
     }
 }
 
@@ -72,28 +248,193 @@ impl<const N: usize> Node<N> for TrustedNode<N> {
     }
 
     fn followers_send(&self) -> &HashSet<Tx> {
-        if self.num_rounds == 0 {
+        if self.is_final {
             &self.consensus_reached
         } else {
             &self.pending_txs
         }
     }
 
-    fn followees_receive(&mut self, candidates: &Vec<Candidate>) {
-        self.num_rounds -= 1;
+    fn advance_to_final(&mut self) {
+        self.drop_stale();
+        self.is_final = true;
+    }
+
+    fn followees_receive(&mut self, candidates: &[Candidate]) {
+        self.num_rounds = self.num_rounds.saturating_sub(1);
+        self.round += 1;
 
         for candidate in candidates.iter() {
-            self.received_txs
-                .entry(candidate.tx)
-                .or_insert(HashSet::new())
-                .insert(candidate.sender);
+            let proposers = self.received_txs.entry(candidate.tx).or_default();
+            proposers.insert(candidate.sender);
 
-            if self.received_txs.get(&candidate.tx).unwrap().len() >= self.consensus_threshold {
-                self.consensus_reached.insert(candidate.tx);
+            let weight: f64 = proposers
+                .iter()
+                .map(|&p| self.followee_weights[p as usize])
+                .sum();
+            if weight >= self.consensus_threshold as f64 {
+                self.reach_consensus(candidate.tx);
             }
 
             self.pending_txs.insert(candidate.tx);
+            self.last_heard.insert(candidate.tx, self.round);
+        }
+    }
+
+    fn consensus_set(&self) -> Vec<Tx> {
+        let mut txs: Vec<Tx> = self.consensus_reached.iter().copied().collect();
+        txs.sort();
+        txs
+    }
+
+    fn is_byzantine(&self) -> bool {
+        false
+    }
+}
+
+/// Node repeatedly sampling its followees, in the style of Snowball. Each
+/// round it samples `k` followees, a followee which sent nothing proposes the
+/// empty set, and for each tx checks whether at least `alpha` of them agree
+/// on including or excluding it. Preference on a tx follows the side which had more such
+/// quorums and the decision is final after `beta` consecutive quorums for
+/// the same side.
+pub struct SnowballNode<const N: usize> {
+    /// This node's followers, if `i` is true, then this node follows `ith` node
+    followees: [bool; N],
+    /// Number of followees sampled each round
+    k: usize,
+    /// Minimal number of sampled followees agreeing on a tx for a quorum
+    alpha: usize,
+    /// Consecutive quorums for the same side needed to finalize a tx
+    beta: u32,
+    /// Txs currently preferred, these are sent to followers
+    preferred: HashSet<Tx>,
+    states: HashMap<Tx, SnowballState>,
+    rng: StdRng,
+}
+
+#[derive(Default)]
+struct SnowballState {
+    /// Number of quorums for including the tx
+    accepts: u32,
+    /// Number of quorums for excluding the tx
+    rejects: u32,
+    /// Side of the last quorum, `true` for including
+    last: Option<bool>,
+    /// Number of consecutive quorums for the `last` side
+    confidence: u32,
+}
+
+impl SnowballState {
+    fn is_final(&self, beta: u32) -> bool {
+        self.confidence >= beta
+    }
+}
+
+impl<const N: usize> SnowballNode<N> {
+    /// # Panics
+    ///
+    /// If `alpha` isn't a majority of `k`, or it is greater than `k`
+    pub fn new(k: usize, alpha: usize, beta: u32, rng: StdRng) -> Self {
+        assert!(alpha > k / 2 && alpha <= k, "alpha must be a majority of k");
+        Self {
+            followees: [false; N],
+            k,
+            alpha,
+            beta,
+            preferred: HashSet::new(),
+            states: HashMap::new(),
+            rng,
+        }
+    }
+
+    /// Applies a quorum for including or excluding the tx
+    fn record_quorum(&mut self, tx: Tx, include: bool) {
+        let state = self.states.entry(tx).or_default();
+        if include {
+            state.accepts += 1;
+        } else {
+            state.rejects += 1;
         }
+        if state.last == Some(include) {
+            state.confidence += 1;
+        } else {
+            state.last = Some(include);
+            state.confidence = 1;
+        }
+
+        if state.accepts > state.rejects {
+            self.preferred.insert(tx);
+        } else if state.rejects > state.accepts {
+            self.preferred.remove(&tx);
+        }
+    }
+}
+
+impl<const N: usize> Node<N> for SnowballNode<N> {
+    fn followees_set(&mut self, followees: [bool; N]) {
+        self.followees = followees;
+    }
+
+    fn pending_txs_set(&mut self, pending_txs: HashSet<Tx>) {
+        self.preferred = pending_txs;
+    }
+
+    fn followers_send(&self) -> &HashSet<Tx> {
+        &self.preferred
+    }
+
+    fn followees_receive(&mut self, candidates: &[Candidate]) {
+        let mut proposals: HashMap<u64, HashSet<Tx>> = HashMap::new();
+        for candidate in candidates.iter() {
+            if self.followees[candidate.sender as usize] {
+                proposals
+                    .entry(candidate.sender)
+                    .or_default()
+                    .insert(candidate.tx);
+            }
+        }
+        // followees which sent nothing prefer the empty set
+        let empty = HashSet::new();
+        let followees: Vec<u64> = (0..N)
+            .filter(|&i| self.followees[i])
+            .map(|i| i as u64)
+            .collect();
+        let sample: Vec<&HashSet<Tx>> = followees
+            .choose_multiple(&mut self.rng, self.k)
+            .map(|followee| proposals.get(followee).unwrap_or(&empty))
+            .collect();
+        if sample.len() < self.alpha {
+            return; // not enough responses for any quorum
+        }
+
+        let mut txs: Vec<Tx> = sample
+            .iter()
+            .flat_map(|p| p.iter())
+            .chain(self.preferred.iter())
+            .copied()
+            .collect::<HashSet<Tx>>()
+            .into_iter()
+            .filter(|tx| !self.states.get(tx).is_some_and(|s| s.is_final(self.beta)))
+            .collect();
+        txs.sort();
+
+        for tx in txs {
+            let votes = sample.iter().filter(|p| p.contains(&tx)).count();
+            if votes >= self.alpha {
+                self.record_quorum(tx, true);
+            } else if sample.len() - votes >= self.alpha {
+                self.record_quorum(tx, false);
+            } else if let Some(state) = self.states.get_mut(&tx) {
+                state.confidence = 0;
+            }
+        }
+    }
+
+    fn consensus_set(&self) -> Vec<Tx> {
+        let mut txs: Vec<Tx> = self.preferred.iter().copied().collect();
+        txs.sort();
+        txs
     }
 
     fn is_byzantine(&self) -> bool {
@@ -108,6 +449,12 @@ pub enum ByzantineBehaviour {
     Selfish,
     /// Mixes all other behaviour with given f64 probability for any of them
     Mix(f64),
+    /// Records received candidates and re-sends those received `delay`
+    /// deliveries ago, spamming outdated state. Only calls of
+    /// `followees_receive` are counted, `run_rounds` skips nodes which
+    /// received nothing in a round. It is never chosen by `Simulation::init`,
+    /// so it has to be constructed directly.
+    Replayer { delay: u64 },
 }
 
 pub struct ByzantineNode<const N: usize> {
@@ -119,6 +466,9 @@ pub struct ByzantineNode<const N: usize> {
     /// The initial set of txs given to this Node
     pending_txs: HashSet<Tx>,
     choosen_txs: HashSet<Tx>,
+    /// Txs received in the most recent rounds, the oldest first, only kept
+    /// by `Replayer`s
+    history: VecDeque<HashSet<Tx>>,
     rng: StdRng,
 }
 
@@ -130,9 +480,68 @@ impl<const N: usize> ByzantineNode<N> {
             followees: [false; N],
             pending_txs: HashSet::new(),
             choosen_txs: HashSet::new(),
+            history: VecDeque::new(),
             rng,
         }
     }
+
+    /// Returns state of this node as bytes, without its rng, see
+    /// `from_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut e = Encoder::default();
+        match self.behaviour {
+            ByzantineBehaviour::Dead => e.u8(0),
+            ByzantineBehaviour::Selfish => e.u8(1),
+            ByzantineBehaviour::Mix(p) => {
+                e.u8(2);
+                e.f64(p);
+            }
+            ByzantineBehaviour::Replayer { delay } => {
+                e.u8(3);
+                e.u64(delay);
+            }
+        }
+        e.u64(self.num_rounds);
+        e.bools(&self.followees);
+        e.txs(&self.pending_txs);
+        e.txs(&self.choosen_txs);
+        e.u64(self.history.len() as u64);
+        for received in self.history.iter() {
+            e.txs(received);
+        }
+        e.finish()
+    }
+
+    /// Restores a node from bytes returned by `to_bytes`. The rng isn't part
+    /// of the state, so the caller provides a deterministically seeded one.
+    pub fn from_bytes(bytes: &[u8], rng: StdRng) -> Result<Self, DecodeError> {
+        let mut d = Decoder::new(bytes);
+        let behaviour = match d.u8()? {
+            0 => ByzantineBehaviour::Dead,
+            1 => ByzantineBehaviour::Selfish,
+            2 => ByzantineBehaviour::Mix(d.f64()?),
+            3 => ByzantineBehaviour::Replayer { delay: d.u64()? },
+            tag => return Err(DecodeError::InvalidByte(tag)),
+        };
+        let num_rounds = d.u64()?;
+        let followees = d.bools()?;
+        let pending_txs = d.txs()?;
+        let choosen_txs = d.txs()?;
+        let history = (0..d.u64()?)
+            .map(|_| d.txs())
+            .collect::<Result<VecDeque<_>, _>>()?;
+        d.finish()?;
+
+        Ok(Self {
+            behaviour,
+            num_rounds,
+            followees,
+            pending_txs,
+            choosen_txs,
+            history,
+            rng,
+        })
+    }
 }
 
 impl<const N: usize> Node<N> for ByzantineNode<N> {
@@ -149,8 +558,8 @@ impl<const N: usize> Node<N> for ByzantineNode<N> {
         &self.choosen_txs
     }
 
-    fn followees_receive(&mut self, _candidates: &Vec<Candidate>) {
-        self.num_rounds -= 1;
+    fn followees_receive(&mut self, candidates: &[Candidate]) {
+        self.num_rounds = self.num_rounds.saturating_sub(1);
 
         match self.behaviour {
             ByzantineBehaviour::Dead => {
@@ -166,9 +575,24 @@ impl<const N: usize> Node<N> for ByzantineNode<N> {
                 }
                 self.choosen_txs = self.pending_txs.clone();
             }
+            ByzantineBehaviour::Replayer { delay } => {
+                self.history
+                    .push_back(candidates.iter().map(|c| c.tx).collect());
+                self.choosen_txs = if self.history.len() as u64 > delay {
+                    self.history.pop_front().unwrap_or_default()
+                } else {
+                    HashSet::new()
+                };
+            }
         }
     }
 
+    fn consensus_set(&self) -> Vec<Tx> {
+        let mut txs: Vec<Tx> = self.choosen_txs.iter().copied().collect();
+        txs.sort();
+        txs
+    }
+
     fn is_byzantine(&self) -> bool {
         true
     }
@@ -0,0 +1,599 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    node::{ByzantineBehaviour, ByzantineNode, Node, SnowballNode, TrustedNode},
+    tx::{CandidateBatch, Tx},
+};
+
+/// Number of nodes in a simulation
+pub const NODES: usize = 100;
+
+/// If `ith` row has `true` at `jth` index, then `ith` node follows `jth` node
+pub type Followees = [[bool; NODES]; NODES];
+pub type Nodes = Vec<Box<dyn Node<NODES>>>;
+
+/// Parameters of honest `SnowballNode`s, `k`, `alpha` and `beta`
+pub const SNOWBALL_PARAMS: (usize, usize, u32) = (8, 5, 4);
+
+/// Implementation used for honest nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HonestNode {
+    #[default]
+    Trusted,
+    Snowball,
+}
+
+/// Honest node is at risk if byzantine nodes make up more than this share of
+/// its followees, then honest followees can't outvote them
+pub const AT_RISK_SHARE: f64 = 0.5;
+
+pub const CSV_HEADER: [&str; 16] = [
+    "rounds",
+    "txs",
+    "p_graph",
+    "p_byzantine",
+    "p_tx_dist",
+    "seed",
+    "byzantine_seed",
+    "init_ms",
+    "simulation_ms",
+    "messages",
+    "consensuses",
+    "consensus_size",
+    "groups",
+    "passed",
+    "tries",
+    "at_risk",
+];
+
+/// Configuration of a simulation of consensus among `NODES` nodes, each run
+/// uses a new random graph, byzantine nodes and distribution of txs
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    rounds: u64,
+    txs: u64,
+    p_graph: f64,
+    p_byzantine: f64,
+    p_tx_dist: f64,
+    message_budget: Option<usize>,
+    honest: HonestNode,
+    /// Seed of the rng used in simulation, random for each run if not set
+    seed: Option<u64>,
+}
+
+impl Simulation {
+    /// # Arguments
+    ///
+    /// * `p_graph` - probability that an edge will exist
+    /// * `p_byzantine` - probability that a Node is byzantine
+    /// * `p_tx_dist` - probability that a tx will be distrubed to a Node
+    pub fn new(rounds: u64, txs: u64, p_graph: f64, p_byzantine: f64, p_tx_dist: f64) -> Self {
+        Self {
+            rounds,
+            txs,
+            p_graph,
+            p_byzantine,
+            p_tx_dist,
+            message_budget: None,
+            honest: HonestNode::default(),
+            seed: None,
+        }
+    }
+
+    /// If set, each node can send at most that many candidates per round,
+    /// see `run_rounds`
+    pub fn set_message_budget(&mut self, message_budget: Option<usize>) {
+        self.message_budget = message_budget;
+    }
+
+    /// Sets implementation used for honest nodes
+    pub fn set_honest(&mut self, honest: HonestNode) {
+        self.honest = honest;
+    }
+
+    /// If set, each run uses the same graph, byzantine nodes and distribution
+    /// of txs, as long as parameters affecting them are the same
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+    }
+
+    /// Runs the simulation until trusted nodes reach a single consensus, at
+    /// most `max_tries` times. If none of the runs passed, the one whose most
+    /// common consensus was reached by the most nodes is returned.
+    ///
+    /// # Panics
+    ///
+    /// If `max_tries` is zero.
+    pub fn run_until_converged(&self, max_tries: usize) -> SimulationResult {
+        assert!(max_tries > 0, "simulation must run at least once");
+
+        let mut best: Option<SimulationResult> = None;
+        for tries in 1..=max_tries {
+            let mut result = self.run();
+            result.tries = tries;
+            if result.passed {
+                return result;
+            }
+            log::info!("Simulation didn't converge, try {}/{}", tries, max_tries);
+
+            if best
+                .as_ref()
+                .is_none_or(|b| result.majority_size() > b.majority_size())
+            {
+                best = Some(result);
+            }
+        }
+
+        let mut best = best.unwrap();
+        best.tries = max_tries;
+        best
+    }
+
+    /// Runs the simulation once
+    pub fn run(&self) -> SimulationResult {
+        log::debug!(
+            "========= starting simulation with {} rounds with {} txs =========
+            - probability that an edge will exist = {}
+            - probability that a Node is byzantine = {}
+            - probability that a tx will be distrubed to a Node = {}",
+            self.rounds,
+            self.txs,
+            self.p_graph,
+            self.p_byzantine,
+            self.p_tx_dist
+        );
+
+        let mut before = Instant::now();
+        let (mut nodes, valid_tx_ids, followees, seeds) = self.init();
+        let init_time = before.elapsed();
+        log::debug!("initialized in {:.3?}", init_time);
+
+        before = Instant::now();
+        let messages = run_rounds(
+            &mut nodes,
+            &valid_tx_ids,
+            &followees,
+            self.rounds,
+            self.message_budget,
+        );
+        let simulation_time = before.elapsed();
+        log::debug!("simulation done in {:.3?}", simulation_time);
+
+        let groups = results(&nodes);
+
+        SimulationResult {
+            exposure: exposure(&nodes, &followees),
+            at_risk: at_risk(&nodes, &followees).len(),
+            rounds: self.rounds,
+            txs: self.txs,
+            p_graph: self.p_graph,
+            p_byzantine: self.p_byzantine,
+            p_tx_dist: self.p_tx_dist,
+            seeds,
+            init_time,
+            simulation_time,
+            messages,
+            passed: groups.len() == 1,
+            groups,
+            tries: 1,
+        }
+    }
+
+    /// Returns initialized Nodes, set of valid tx ids and a followers/followee matrix,
+    /// and tuple containing seed for the rng used in simulation and seed used in
+    /// byzantine nodes.
+    ///
+    /// Byzantine assignment, graph edges, tx distribution and rngs of nodes
+    /// each draw from their own rng seeded from the simulation seed, so
+    /// changing e.g. `p_tx_dist` or the `HonestNode` implementation doesn't
+    /// change the graph or which nodes are byzantine.
+    #[allow(clippy::needless_range_loop)]
+    pub fn init(&self) -> (Nodes, HashSet<Tx>, Followees, (u64, u64)) {
+        let mut nodes: Nodes = Vec::with_capacity(NODES);
+        let seed: u64 = self.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        log::debug!("rng seed {}", seed);
+
+        let mut seeds = StdRng::seed_from_u64(seed);
+        let mut assignment_rng = StdRng::seed_from_u64(seeds.gen());
+        let mut graph_rng = StdRng::seed_from_u64(seeds.gen());
+        let mut tx_rng = StdRng::seed_from_u64(seeds.gen());
+        let byzantine_seed: u64 = seeds.gen();
+        log::debug!("byzantine rng seed {}", byzantine_seed);
+        let byzantine_rng = StdRng::seed_from_u64(byzantine_seed);
+        let mut node_seeds = StdRng::seed_from_u64(seeds.gen());
+
+        let mut byzantine = 0;
+        for i in 0..NODES {
+            // drawn for every node, so each honest node gets the same rng
+            // no matter which nodes before it are byzantine
+            let node_seed: u64 = node_seeds.gen();
+            let node: Box<dyn Node<NODES>>;
+            if assignment_rng.gen_bool(self.p_byzantine) {
+                let behaviour = match byzantine % 3 {
+                    0 => ByzantineBehaviour::Dead,
+                    1 => ByzantineBehaviour::Selfish,
+                    _ => ByzantineBehaviour::Mix(0.5),
+                };
+                node = Box::new(ByzantineNode::new(
+                    behaviour,
+                    self.rounds,
+                    byzantine_rng.clone(),
+                ));
+                byzantine += 1;
+            } else {
+                node = match self.honest {
+                    HonestNode::Trusted => Box::new(TrustedNode::new(
+                        self.p_graph,
+                        self.p_byzantine,
+                        self.p_tx_dist,
+                        self.rounds,
+                    )),
+                    HonestNode::Snowball => {
+                        let (k, alpha, beta) = SNOWBALL_PARAMS;
+                        let node_rng = StdRng::seed_from_u64(node_seed);
+                        Box::new(SnowballNode::new(k, alpha, beta, node_rng))
+                    }
+                };
+            }
+
+            nodes.insert(i, node);
+        }
+        log::debug!(
+            "There are {} trusted nodes and {} byzantine",
+            NODES - byzantine,
+            byzantine
+        );
+
+        let mut followees: Followees = [[false; NODES]; NODES];
+        for i in 0..NODES {
+            for j in 0..NODES {
+                if i == j {
+                    continue;
+                }
+
+                if graph_rng.gen_bool(self.p_graph) {
+                    followees[i][j] = true;
+                }
+            }
+        }
+
+        for i in 0..NODES {
+            nodes.get_mut(i).unwrap().followees_set(followees[i]);
+        }
+
+        let mut valid_tx_ids: HashSet<Tx> = HashSet::new();
+        for _ in 0..self.txs {
+            valid_tx_ids.insert(tx_rng.gen::<Tx>());
+        }
+
+        for i in 0..NODES {
+            let mut pending_txs: HashSet<Tx> = HashSet::new();
+            for id in valid_tx_ids.iter() {
+                if tx_rng.gen_bool(self.p_tx_dist) {
+                    pending_txs.insert(*id);
+                }
+            }
+            nodes.get_mut(i).unwrap().pending_txs_set(pending_txs);
+        }
+
+        (nodes, valid_tx_ids, followees, (seed, byzantine_seed))
+    }
+}
+
+pub struct SimulationResult {
+    rounds: u64,
+    txs: u64,
+    p_graph: f64,
+    p_byzantine: f64,
+    p_tx_dist: f64,
+    seeds: (u64, u64),
+    init_time: Duration,
+    simulation_time: Duration,
+    /// Total number of candidates sent to followers across all rounds
+    messages: u64,
+    /// Different consensuses reached by trusted nodes, the most common first
+    groups: Vec<ConsensusGroup>,
+    /// Honest nodes bucketed by the share of their byzantine followees
+    exposure: Vec<ExposureBucket>,
+    /// Number of honest nodes at risk, see `AT_RISK_SHARE`
+    at_risk: usize,
+    passed: bool,
+    /// Number of runs of the simulation until this result
+    tries: usize,
+}
+
+/// Consensus on the same set of txs reached by some of the trusted nodes
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConsensusGroup {
+    pub nodes: usize,
+    pub txs: usize,
+}
+
+/// Honest nodes with similar share of byzantine followees and how many of
+/// them reached the most common consensus
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExposureBucket {
+    /// Share of byzantine followees in tenths, e.g. bucket 3 has shares from
+    /// 0.3 up to 0.4, bucket 10 only nodes following just byzantine nodes
+    pub tenths: usize,
+    pub nodes: usize,
+    pub in_majority: usize,
+}
+
+impl SimulationResult {
+    /// True if all trusted nodes reached the same consensus
+    pub fn passed(&self) -> bool {
+        self.passed
+    }
+
+    pub fn tries(&self) -> usize {
+        self.tries
+    }
+
+    pub fn messages(&self) -> u64 {
+        self.messages
+    }
+
+    pub fn groups(&self) -> &[ConsensusGroup] {
+        &self.groups
+    }
+
+    pub fn exposure(&self) -> &[ExposureBucket] {
+        &self.exposure
+    }
+
+    pub fn at_risk(&self) -> usize {
+        self.at_risk
+    }
+
+    /// Number of txs in the most common consensus
+    pub fn consensus_size(&self) -> usize {
+        self.groups.first().map_or(0, |g| g.txs)
+    }
+
+    /// Number of nodes which reached the most common consensus
+    fn majority_size(&self) -> usize {
+        self.groups.first().map_or(0, |g| g.nodes)
+    }
+
+    /// Groups formatted as `nodes:txs` separated by `;`
+    fn groups_summary(&self) -> String {
+        self.groups
+            .iter()
+            .map(|g| format!("{}:{}", g.nodes, g.txs))
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+
+    /// Returns this result as a row with columns of `CSV_HEADER`
+    pub fn csv_row(&self) -> String {
+        [
+            self.rounds.to_string(),
+            self.txs.to_string(),
+            self.p_graph.to_string(),
+            self.p_byzantine.to_string(),
+            self.p_tx_dist.to_string(),
+            self.seeds.0.to_string(),
+            self.seeds.1.to_string(),
+            self.init_time.as_millis().to_string(),
+            self.simulation_time.as_millis().to_string(),
+            self.messages.to_string(),
+            self.groups.len().to_string(),
+            self.consensus_size().to_string(),
+            self.groups_summary(),
+            self.passed.to_string(),
+            self.tries.to_string(),
+            self.at_risk.to_string(),
+        ]
+        .join(",")
+    }
+}
+
+impl fmt::Display for SimulationResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rounds: {} | txs: {} | p_graph: {} | p_byzantine: {} | p_tx_dist: {}",
+            self.rounds, self.txs, self.p_graph, self.p_byzantine, self.p_tx_dist
+        )?;
+        write!(
+            f,
+            " | initialized in {:.3?} | seeds: {:?} | simulation done in {:.3?}",
+            self.init_time, self.seeds, self.simulation_time
+        )?;
+        write!(f, " | messages: {} | tries: {}", self.messages, self.tries)?;
+        write!(f, " | at risk: {}", self.at_risk)?;
+        if self.groups.len() != 1 {
+            write!(
+                f,
+                " | {} different consensuses reached! (nodes:txs {})",
+                self.groups.len(),
+                self.groups_summary()
+            )?;
+        }
+        write!(
+            f,
+            " | count of tx upon which consensus was reached {}",
+            self.consensus_size()
+        )
+    }
+}
+
+/// Exchanges proposals between followees and followers for given rounds and
+/// returns number of sent candidates. If `message_budget` is set, a node sends
+/// at most that many candidates in a round, followers are served in order of
+/// their index and proposals over the budget are dropped, modeling limited
+/// bandwidth. Afterwards every node is advanced to its final state, see
+/// `Node::advance_to_final`.
+#[allow(clippy::needless_range_loop)]
+pub fn run_rounds(
+    nodes: &mut Nodes,
+    valid_tx_ids: &HashSet<Tx>,
+    followees: &Followees,
+    rounds: u64,
+    message_budget: Option<usize>,
+) -> u64 {
+    let mut messages = 0;
+    for _ in 0..rounds {
+        // key is the index of a Node and value is vec of candidate batches
+        // from other nodes
+        let mut all_proposals: HashMap<usize, Vec<CandidateBatch>> = HashMap::new();
+
+        for i in 0..NODES {
+            let proposals: Vec<Tx> = nodes
+                .get(i)
+                .unwrap()
+                .followers_send()
+                .iter()
+                .filter(|tx| valid_tx_ids.contains(tx)) // controls that each tx is valid
+                .copied()
+                .collect();
+            if proposals.is_empty() {
+                continue;
+            }
+
+            // for each of nodes followers, add txs to their proposals for this turn
+            let mut budget = message_budget.unwrap_or(usize::MAX);
+            for j in 0..NODES {
+                if !followees[j][i] {
+                    continue; // txs are only proposed if `j` follows `i`
+                }
+                if budget == 0 {
+                    log::trace!("Node {} exceeded its message budget", i);
+                    break;
+                }
+
+                let sent = proposals.len().min(budget);
+                budget -= sent;
+                messages += sent as u64;
+                let batch = CandidateBatch::new(i.try_into().unwrap(), proposals[..sent].to_vec());
+                all_proposals.entry(j).or_insert(vec![]).push(batch);
+            }
+        }
+
+        // distributes proposals made in this turn to followers
+        for i in 0..NODES {
+            if !all_proposals.contains_key(&i) {
+                continue;
+            }
+            nodes
+                .get_mut(i)
+                .unwrap()
+                .followees_receive_batch(all_proposals.get(&i).unwrap());
+        }
+    }
+
+    for node in nodes.iter_mut() {
+        node.advance_to_final();
+    }
+    messages
+}
+
+/// Returns different consensuses reached by trusted nodes, ordered by number
+/// of nodes which reached them
+pub fn results(nodes: &Nodes) -> Vec<ConsensusGroup> {
+    let mut consensuses: HashMap<Vec<Tx>, usize> = HashMap::new();
+    for i in 0..NODES {
+        let node = nodes.get(i).unwrap();
+        if node.is_byzantine() {
+            continue;
+        }
+        let txs = node.consensus_set();
+        log::trace!(
+            "Transaction ids that Node {} believes consensus on:\n\t{:?}",
+            i,
+            txs
+        );
+        *consensuses.entry(txs).or_default() += 1;
+    }
+
+    let mut groups: Vec<ConsensusGroup> = consensuses
+        .iter()
+        .map(|(txs, &nodes)| ConsensusGroup {
+            nodes,
+            txs: txs.len(),
+        })
+        .collect();
+    groups.sort_by(|a, b| b.nodes.cmp(&a.nodes).then(b.txs.cmp(&a.txs)));
+
+    if groups.len() != 1 {
+        log::debug!(
+            "There are {} different consensuses reached, {:?}",
+            groups.len(),
+            groups
+        );
+    }
+    log::debug!(
+        "count of tx upon which consensus was reached on {}",
+        groups.first().map_or(0, |g| g.txs)
+    );
+
+    groups
+}
+
+/// Share of byzantine nodes among followees of the `ith` node, 0 if it
+/// follows nobody
+pub fn byzantine_share(nodes: &Nodes, followees: &Followees, i: usize) -> f64 {
+    let followed: Vec<usize> = (0..NODES).filter(|&j| followees[i][j]).collect();
+    if followed.is_empty() {
+        return 0.;
+    }
+    let byzantine = followed
+        .iter()
+        .filter(|&&j| nodes[j].is_byzantine())
+        .count();
+    byzantine as f64 / followed.len() as f64
+}
+
+/// Returns indices of honest nodes, more than `AT_RISK_SHARE` of whose
+/// followees are byzantine. Their honest followees can't compensate for
+/// byzantine ones, no matter how many honest nodes follow them.
+pub fn at_risk(nodes: &Nodes, followees: &Followees) -> Vec<usize> {
+    (0..NODES)
+        .filter(|&i| !nodes[i].is_byzantine())
+        .filter(|&i| byzantine_share(nodes, followees, i) > AT_RISK_SHARE)
+        .collect()
+}
+
+/// Buckets honest nodes by the share of their byzantine followees, see
+/// `ExposureBucket`, ordered by the share. Empty buckets are missing.
+pub fn exposure(nodes: &Nodes, followees: &Followees) -> Vec<ExposureBucket> {
+    let honest: Vec<usize> = (0..NODES).filter(|&i| !nodes[i].is_byzantine()).collect();
+    let mut consensuses: HashMap<Vec<Tx>, usize> = HashMap::new();
+    for &i in honest.iter() {
+        *consensuses.entry(nodes[i].consensus_set()).or_default() += 1;
+    }
+    // same order as groups of `results`
+    let majority = consensuses
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(a.0.len().cmp(&b.0.len())))
+        .map(|(txs, _)| txs);
+
+    let mut buckets: Vec<ExposureBucket> = vec![];
+    for &i in honest.iter() {
+        let tenths = (byzantine_share(nodes, followees, i) * 10.).floor() as usize;
+        let bucket = match buckets.iter().position(|b| b.tenths == tenths) {
+            Some(idx) => &mut buckets[idx],
+            None => {
+                buckets.push(ExposureBucket {
+                    tenths,
+                    nodes: 0,
+                    in_majority: 0,
+                });
+                buckets.last_mut().unwrap()
+            }
+        };
+        bucket.nodes += 1;
+        if majority.as_ref() == Some(&nodes[i].consensus_set()) {
+            bucket.in_majority += 1;
+        }
+    }
+    buckets.sort_by_key(|b| b.tenths);
+    buckets
+}
@@ -1,4 +1,49 @@
-pub type Tx = u64;
+use rand::{distributions::Standard, prelude::Distribution, Rng};
+
+/// Proposed tx, identified by its id. Txs of the same conflict group can't
+/// be valid together, so at most one of them may be agreed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tx {
+    pub id: u64,
+    pub conflict_group: Option<u64>,
+}
+
+impl Tx {
+    /// Creates a tx, which doesn't conflict with any other
+    pub fn new(id: u64) -> Self {
+        Self {
+            id,
+            conflict_group: None,
+        }
+    }
+
+    /// Creates a tx conflicting with all other txs of the group
+    pub fn conflicting(id: u64, group: u64) -> Self {
+        Self {
+            id,
+            conflict_group: Some(group),
+        }
+    }
+
+    pub fn conflicts_with(&self, other: &Tx) -> bool {
+        self.id != other.id
+            && self.conflict_group.is_some()
+            && self.conflict_group == other.conflict_group
+    }
+}
+
+impl From<u64> for Tx {
+    fn from(id: u64) -> Self {
+        Self::new(id)
+    }
+}
+
+/// Generates txs with random ids, which don't conflict
+impl Distribution<Tx> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Tx {
+        Tx::new(rng.gen())
+    }
+}
 
 pub struct Candidate {
     pub tx: Tx,
@@ -6,7 +51,22 @@ pub struct Candidate {
 }
 
 impl Candidate {
-    pub fn new(tx: Tx, sender: u64) -> Self {
-        Self { tx, sender }
+    pub fn new(tx: impl Into<Tx>, sender: u64) -> Self {
+        Self {
+            tx: tx.into(),
+            sender,
+        }
+    }
+}
+
+/// All txs proposed by one sender to one follower in a round
+pub struct CandidateBatch {
+    pub sender: u64,
+    pub txs: Vec<Tx>,
+}
+
+impl CandidateBatch {
+    pub fn new(sender: u64, txs: Vec<Tx>) -> Self {
+        Self { sender, txs }
     }
 }
@@ -0,0 +1,446 @@
+use std::collections::HashSet;
+
+use consensus::{
+    codec::DecodeError,
+    node::{ByzantineBehaviour, ByzantineNode, Node, SnowballNode, TrustedNode},
+    tx::{Candidate, CandidateBatch, Tx},
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+const NODES: usize = 6;
+
+#[test]
+fn high_weight_followee_outweighs_byzantines() {
+    let mut node = TrustedNode::<NODES>::new(1., 0., 0.1, 1);
+    node.followees_set([false, true, true, true, true, true]);
+    node.set_followee_weights([1., 1., 0.2, 0.2, 0.2, 0.2]);
+    node.pending_txs_set(HashSet::new());
+
+    let honest_tx = Tx::new(1);
+    let byzantine_tx = Tx::new(2);
+    let mut candidates = vec![Candidate::new(honest_tx, 1)];
+    for byzantine in 2..NODES {
+        candidates.push(Candidate::new(byzantine_tx, byzantine as u64));
+    }
+    node.followees_receive(&candidates);
+    node.advance_to_final();
+
+    assert_eq!(&HashSet::from([honest_tx]), node.followers_send());
+}
+
+#[test]
+fn default_weights_count_proposers() {
+    let mut node = TrustedNode::<NODES>::new(1., 0., 0.1, 1);
+    node.followees_set([false, true, true, true, true, true]);
+    node.pending_txs_set(HashSet::new());
+
+    node.followees_receive(&[Candidate::new(1, 1), Candidate::new(2, 2)]);
+    node.advance_to_final();
+
+    assert_eq!(
+        &HashSet::from([Tx::new(1), Tx::new(2)]),
+        node.followers_send()
+    );
+}
+
+#[test]
+fn unsustained_tx_drops_out_of_recency_window() {
+    let rounds = 4;
+    let attacker_tx = Tx::new(7);
+    let honest_tx = Tx::new(1);
+    let run = |window: Option<u64>| {
+        let mut node = TrustedNode::<NODES>::new(1., 0., 0.1, rounds);
+        node.followees_set([false, true, true, true, true, true]);
+        node.pending_txs_set(HashSet::new());
+        if let Some(window) = window {
+            node.set_recency_window(window);
+        }
+
+        node.followees_receive(&[Candidate::new(attacker_tx, 1), Candidate::new(honest_tx, 2)]);
+        for _ in 1..rounds {
+            let candidates: Vec<Candidate> = (2..NODES as u64)
+                .map(|i| Candidate::new(honest_tx, i))
+                .collect();
+            node.followees_receive(&candidates);
+        }
+        node.advance_to_final();
+        node.consensus_set()
+    };
+
+    assert_eq!(vec![honest_tx, attacker_tx], run(None));
+    assert_eq!(vec![honest_tx], run(Some(2)));
+    assert_eq!(vec![honest_tx, attacker_tx], run(Some(rounds)));
+}
+
+#[test]
+fn recency_window_counts_received_rounds() {
+    // the node expects fewer rounds than it actually receives
+    let mut node = TrustedNode::<NODES>::new(1., 0., 0.1, 1);
+    node.followees_set([false, true, true, true, true, true]);
+    node.pending_txs_set(HashSet::new());
+    node.set_recency_window(2);
+
+    let attacker_tx = Tx::new(7);
+    let honest_tx = Tx::new(1);
+    node.followees_receive(&[Candidate::new(attacker_tx, 1), Candidate::new(honest_tx, 2)]);
+    for _ in 0..3 {
+        node.followees_receive(&[Candidate::new(honest_tx, 2)]);
+    }
+    node.advance_to_final();
+
+    assert_eq!(vec![honest_tx], node.consensus_set());
+}
+
+#[test]
+fn byzantine_node_outlives_its_rounds() {
+    let rng = StdRng::seed_from_u64(0);
+    let mut node = ByzantineNode::<NODES>::new(ByzantineBehaviour::Selfish, 1, rng);
+    node.pending_txs_set(HashSet::from([Tx::new(1)]));
+
+    for _ in 0..3 {
+        node.followees_receive(&[Candidate::new(2, 1)]);
+    }
+
+    assert_eq!(vec![Tx::new(1)], node.consensus_set());
+}
+
+#[test]
+fn honest_nodes_agree_on_consensus_set() {
+    let rounds = 3;
+    let mut nodes: Vec<Box<dyn Node<NODES>>> = Vec::with_capacity(NODES);
+    for i in 0..NODES {
+        let mut node = TrustedNode::<NODES>::new(1., 0., 0.1, rounds);
+        let mut followees = [true; NODES];
+        followees[i] = false;
+        node.followees_set(followees);
+        node.pending_txs_set(HashSet::from([Tx::new(i as u64 * 10)]));
+        nodes.push(Box::new(node));
+    }
+
+    for _ in 0..rounds {
+        let proposals: Vec<Vec<Tx>> = nodes
+            .iter()
+            .map(|node| node.followers_send().iter().copied().collect())
+            .collect();
+        for (j, node) in nodes.iter_mut().enumerate() {
+            let candidates: Vec<Candidate> = proposals
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != j)
+                .flat_map(|(i, txs)| txs.iter().map(move |&tx| Candidate::new(tx, i as u64)))
+                .collect();
+            node.followees_receive(&candidates);
+        }
+    }
+
+    let expected: Vec<Tx> = (0..NODES as u64).map(|i| Tx::new(i * 10)).collect();
+    for node in nodes.iter() {
+        assert_eq!(expected, node.consensus_set());
+    }
+}
+
+#[test]
+fn honest_nodes_agree_on_one_of_conflicting_txs() {
+    let rounds = 3;
+    let (first, second) = (Tx::conflicting(1, 7), Tx::conflicting(2, 7));
+    let mut nodes: Vec<Box<dyn Node<NODES>>> = Vec::with_capacity(NODES);
+    for i in 0..NODES {
+        let mut node = TrustedNode::<NODES>::new(1., 0., 0.1, rounds);
+        let mut followees = [true; NODES];
+        followees[i] = false;
+        node.followees_set(followees);
+        // the later conflicting tx is proposed by more nodes
+        let pending = match i {
+            0 => first,
+            1 | 2 => second,
+            _ => Tx::new(i as u64 * 10),
+        };
+        node.pending_txs_set(HashSet::from([pending]));
+        nodes.push(Box::new(node));
+    }
+
+    for _ in 0..rounds {
+        let proposals: Vec<Vec<Tx>> = nodes
+            .iter()
+            .map(|node| node.followers_send().iter().copied().collect())
+            .collect();
+        for (j, node) in nodes.iter_mut().enumerate() {
+            let candidates: Vec<Candidate> = proposals
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != j)
+                .flat_map(|(i, txs)| txs.iter().map(move |&tx| Candidate::new(tx, i as u64)))
+                .collect();
+            node.followees_receive(&candidates);
+        }
+    }
+
+    let mut expected = vec![first];
+    expected.extend((3..NODES as u64).map(|i| Tx::new(i * 10)));
+    for node in nodes.iter() {
+        let consensus = node.consensus_set();
+        assert_eq!(expected, consensus);
+        assert!(!consensus.contains(&second));
+    }
+}
+
+#[test]
+fn batch_and_single_delivery_reach_same_consensus() {
+    let batches = [
+        CandidateBatch::new(1, [1, 2, 3].map(Tx::new).to_vec()),
+        CandidateBatch::new(2, [3, 4].map(Tx::new).to_vec()),
+        CandidateBatch::new(3, vec![]),
+    ];
+    let candidates: Vec<Candidate> = batches
+        .iter()
+        .flat_map(|b| b.txs.iter().map(|&tx| Candidate::new(tx, b.sender)))
+        .collect();
+
+    let mut single = TrustedNode::<NODES>::new(1., 0., 0.1, 1);
+    let mut batched = TrustedNode::<NODES>::new(1., 0., 0.1, 1);
+    for node in [&mut single, &mut batched] {
+        node.followees_set([false, true, true, true, false, false]);
+        node.set_followee_weights([1., 0.5, 0.5, 0.5, 1., 1.]);
+        node.pending_txs_set(HashSet::new());
+    }
+
+    single.followees_receive(&candidates);
+    batched.followees_receive_batch(&batches);
+
+    assert_eq!(vec![Tx::new(3)], single.consensus_set());
+    assert_eq!(single.consensus_set(), batched.consensus_set());
+    assert_eq!(single.followers_send(), batched.followers_send());
+}
+
+#[test]
+fn snowball_counts_silent_followees_as_excluding() {
+    let mut node = SnowballNode::<NODES>::new(5, 3, 1, StdRng::seed_from_u64(0));
+    node.followees_set([false, true, true, true, true, true]);
+    node.pending_txs_set(HashSet::from([Tx::new(1)]));
+
+    // only one of five followees still proposes the tx
+    node.followees_receive(&[Candidate::new(1, 1)]);
+
+    assert!(node.consensus_set().is_empty());
+}
+
+#[test]
+fn snowball_converges_to_honest_majority() {
+    const SMALL: usize = 20;
+    let rounds = 30;
+    let byzantine = 5;
+    let honest_txs = HashSet::from([Tx::new(1), Tx::new(2)]);
+
+    // everyone follows everyone, byzantine nodes keep pushing their own tx
+    // and few honest nodes start without the honest txs
+    let mut nodes: Vec<Box<dyn Node<SMALL>>> = Vec::with_capacity(SMALL);
+    for i in 0..SMALL {
+        let rng = StdRng::seed_from_u64(i as u64);
+        let (mut node, pending): (Box<dyn Node<SMALL>>, HashSet<Tx>) = match i {
+            i if i < byzantine => (
+                Box::new(ByzantineNode::new(ByzantineBehaviour::Selfish, rounds, rng)),
+                HashSet::from([Tx::new(99)]),
+            ),
+            i if i < byzantine + 3 => (Box::new(SnowballNode::new(8, 5, 4, rng)), HashSet::new()),
+            _ => (
+                Box::new(SnowballNode::new(8, 5, 4, rng)),
+                honest_txs.clone(),
+            ),
+        };
+        let mut followees = [true; SMALL];
+        followees[i] = false;
+        node.followees_set(followees);
+        node.pending_txs_set(pending);
+        nodes.push(node);
+    }
+
+    for _ in 0..rounds {
+        let proposals: Vec<Vec<Tx>> = nodes
+            .iter()
+            .map(|node| node.followers_send().iter().copied().collect())
+            .collect();
+        for (j, node) in nodes.iter_mut().enumerate() {
+            let batches: Vec<CandidateBatch> = proposals
+                .iter()
+                .enumerate()
+                .filter(|&(i, txs)| i != j && !txs.is_empty())
+                .map(|(i, txs)| CandidateBatch::new(i as u64, txs.clone()))
+                .collect();
+            node.followees_receive_batch(&batches);
+        }
+    }
+
+    for node in nodes.iter().filter(|node| !node.is_byzantine()) {
+        assert_eq!(vec![Tx::new(1), Tx::new(2)], node.consensus_set());
+    }
+}
+
+#[test]
+fn honest_nodes_converge_despite_replayers() {
+    let rounds = 6;
+    let replayers = 2;
+    let mut nodes: Vec<Box<dyn Node<NODES>>> = Vec::with_capacity(NODES);
+    for i in 0..NODES {
+        let (mut node, pending): (Box<dyn Node<NODES>>, HashSet<Tx>) = if i < replayers {
+            let behaviour = ByzantineBehaviour::Replayer { delay: 2 };
+            let rng = StdRng::seed_from_u64(i as u64);
+            (
+                Box::new(ByzantineNode::new(behaviour, rounds, rng)),
+                HashSet::new(),
+            )
+        } else {
+            let node = TrustedNode::<NODES>::new(1., 0., 0.1, rounds);
+            (Box::new(node), HashSet::from([Tx::new(i as u64 * 10)]))
+        };
+        let mut followees = [true; NODES];
+        followees[i] = false;
+        node.followees_set(followees);
+        node.pending_txs_set(pending);
+        nodes.push(node);
+    }
+
+    let mut replayed = HashSet::new();
+    for round in 0..rounds {
+        let proposals: Vec<Vec<Tx>> = nodes
+            .iter()
+            .map(|node| node.followers_send().iter().copied().collect())
+            .collect();
+        if round > 2 {
+            replayed.extend(proposals[..replayers].iter().flatten().copied());
+        } else {
+            // nothing was received long enough ago to be replayed
+            assert!(proposals[..replayers].iter().all(Vec::is_empty));
+        }
+        for (j, node) in nodes.iter_mut().enumerate() {
+            let candidates: Vec<Candidate> = proposals
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != j)
+                .flat_map(|(i, txs)| txs.iter().map(move |&tx| Candidate::new(tx, i as u64)))
+                .collect();
+            node.followees_receive(&candidates);
+        }
+    }
+
+    let expected: Vec<Tx> = (replayers as u64..NODES as u64)
+        .map(|i| Tx::new(i * 10))
+        .collect();
+    assert_eq!(expected.iter().copied().collect::<HashSet<_>>(), replayed);
+    for node in nodes.iter().filter(|node| !node.is_byzantine()) {
+        assert_eq!(expected, node.consensus_set());
+    }
+}
+
+/// Byzantine nodes first, then trusted ones, everyone follows everyone
+fn checkpoint_network(rounds: u64) -> (Vec<ByzantineNode<NODES>>, Vec<TrustedNode<NODES>>) {
+    let behaviours = [
+        ByzantineBehaviour::Selfish,
+        ByzantineBehaviour::Replayer { delay: 1 },
+    ];
+    let followees = |i: usize| {
+        let mut followees = [true; NODES];
+        followees[i] = false;
+        followees
+    };
+
+    let mut byzantine = vec![];
+    for (i, behaviour) in behaviours.into_iter().enumerate() {
+        let mut node = ByzantineNode::new(behaviour, rounds, StdRng::seed_from_u64(i as u64));
+        node.followees_set(followees(i));
+        node.pending_txs_set(HashSet::from([Tx::new(99)]));
+        byzantine.push(node);
+    }
+    let mut trusted = vec![];
+    for i in byzantine.len()..NODES {
+        let mut node = TrustedNode::new(1., 0.3, 0.1, rounds);
+        node.followees_set(followees(i));
+        node.pending_txs_set(HashSet::from([Tx::new(i as u64 * 10)]));
+        trusted.push(node);
+    }
+    (byzantine, trusted)
+}
+
+fn run_checkpoint_rounds(
+    byzantine: &mut [ByzantineNode<NODES>],
+    trusted: &mut [TrustedNode<NODES>],
+    rounds: u64,
+) {
+    for _ in 0..rounds {
+        let mut nodes: Vec<&mut dyn Node<NODES>> = vec![];
+        nodes.extend(byzantine.iter_mut().map(|n| n as &mut dyn Node<NODES>));
+        nodes.extend(trusted.iter_mut().map(|n| n as &mut dyn Node<NODES>));
+
+        let proposals: Vec<Vec<Tx>> = nodes
+            .iter()
+            .map(|node| node.followers_send().iter().copied().collect())
+            .collect();
+        for (j, node) in nodes.iter_mut().enumerate() {
+            let candidates: Vec<Candidate> = proposals
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != j)
+                .flat_map(|(i, txs)| txs.iter().map(move |&tx| Candidate::new(tx, i as u64)))
+                .collect();
+            node.followees_receive(&candidates);
+        }
+    }
+}
+
+#[test]
+fn resumed_nodes_reach_same_consensus() {
+    let rounds = 4;
+
+    let (mut byzantine, mut trusted) = checkpoint_network(rounds);
+    run_checkpoint_rounds(&mut byzantine, &mut trusted, rounds);
+
+    let (mut checkpointed_byzantine, mut checkpointed_trusted) = checkpoint_network(rounds);
+    run_checkpoint_rounds(&mut checkpointed_byzantine, &mut checkpointed_trusted, 2);
+    let mut resumed_byzantine: Vec<ByzantineNode<NODES>> = checkpointed_byzantine
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let rng = StdRng::seed_from_u64(i as u64);
+            ByzantineNode::from_bytes(&node.to_bytes(), rng).unwrap()
+        })
+        .collect();
+    let mut resumed_trusted: Vec<TrustedNode<NODES>> = checkpointed_trusted
+        .iter()
+        .map(|node| TrustedNode::from_bytes(&node.to_bytes()).unwrap())
+        .collect();
+    for (resumed, checkpointed) in resumed_trusted.iter().zip(checkpointed_trusted.iter()) {
+        assert_eq!(checkpointed.to_bytes(), resumed.to_bytes());
+    }
+    run_checkpoint_rounds(&mut resumed_byzantine, &mut resumed_trusted, rounds - 2);
+
+    for (resumed, uninterrupted) in resumed_trusted.iter().zip(trusted.iter()) {
+        assert_eq!(uninterrupted.consensus_set(), resumed.consensus_set());
+        assert_eq!(uninterrupted.followers_send(), resumed.followers_send());
+    }
+    for (resumed, uninterrupted) in resumed_byzantine.iter().zip(byzantine.iter()) {
+        assert_eq!(uninterrupted.to_bytes(), resumed.to_bytes());
+    }
+
+    let bytes = trusted[0].to_bytes();
+    assert_eq!(
+        Err(DecodeError::UnexpectedEnd),
+        TrustedNode::<NODES>::from_bytes(&bytes[..bytes.len() - 1]).map(|_| ())
+    );
+}
+
+#[test]
+fn followers_send_switches_to_consensus_only_when_final() {
+    // more rounds than are run, the switch doesn't depend on the round count
+    let mut node = TrustedNode::<NODES>::new(1., 0., 0.1, 10);
+    node.followees_set([false, true, true, true, true, true]);
+    let pending = HashSet::from([Tx::new(1), Tx::new(2)]);
+    node.pending_txs_set(pending.clone());
+
+    node.followees_receive(&[Candidate::new(3, 1)]);
+    let mut expected_pending = pending;
+    expected_pending.insert(Tx::new(3));
+    assert_eq!(&expected_pending, node.followers_send());
+
+    node.advance_to_final();
+    assert_eq!(&HashSet::from([Tx::new(3)]), node.followers_send());
+    assert_eq!(vec![Tx::new(3)], node.consensus_set());
+}
@@ -1,19 +1,25 @@
-use std::{
-    collections::{HashMap, HashSet},
-    fs::File,
-    io::Write,
-    sync::mpsc,
-    time::Instant,
-    u64, usize,
-};
+use std::{collections::HashSet, env, fs::File, io::Write, sync::mpsc};
 
 use consensus::{
     node::{ByzantineBehaviour, ByzantineNode, Node, TrustedNode},
-    tx::{Candidate, Tx},
+    simulation::{
+        at_risk, exposure, results, run_rounds, ConsensusGroup, ExposureBucket, Followees,
+        HonestNode, Nodes, Simulation, SimulationResult, CSV_HEADER, NODES,
+    },
+    tx::Tx,
 };
-use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand::{rngs::StdRng, SeedableRng};
+
+/// Path of the file with simulation results, `/tmp/sim-result.txt` by default
+const RESULT_PATH_ENV: &str = "SIM_RESULT_PATH";
+/// If set to `csv`, results are written as CSV instead of human readable lines
+const RESULT_FORMAT_ENV: &str = "SIM_RESULT_FORMAT";
+/// If set to `snowball`, honest nodes are `SnowballNode`s instead of `TrustedNode`s
+const HONEST_NODE_ENV: &str = "SIM_HONEST_NODE";
 
-const NODES: usize = 100;
+/// Number of runs of each simulation, until its trusted nodes reach a single
+/// consensus
+const MAX_TRIES: usize = 4;
 
 #[test]
 fn simulations() {
@@ -44,242 +50,274 @@ fn simulations() {
         }
     }
 
-    let (tx, rx) = mpsc::channel::<String>();
+    let honest = match env::var(HONEST_NODE_ENV) {
+        Ok(kind) if kind == "snowball" => HonestNode::Snowball,
+        _ => HonestNode::Trusted,
+    };
+
+    let (tx, rx) = mpsc::channel::<SimulationResult>();
     for permutation in permutations {
         current += 1;
         let tx = tx.clone();
         rayon::spawn(move || {
-            let mut tries = 0;
-            let rounds = permutation.0;
-            let txs = permutation.1;
-            let p_graph = permutation.2;
-            let p_byzantine = permutation.3;
-            let p_tx_dist = permutation.4;
-
-            let (mut result, mut passed) = simulation(rounds, txs, p_graph, p_byzantine, p_tx_dist);
-
-            while tries < 3 && !passed {
-                tries += 1;
-                log::info!("Retrying {}", current);
-                (result, passed) = simulation(rounds, txs, p_graph, p_byzantine, p_tx_dist);
-            }
+            let (rounds, txs, p_graph, p_byzantine, p_tx_dist) = permutation;
+            let mut simulation = Simulation::new(rounds, txs, p_graph, p_byzantine, p_tx_dist);
+            simulation.set_honest(honest);
+            let result = simulation.run_until_converged(MAX_TRIES);
 
             tx.send(result).unwrap();
             log::info!("Finished {}/{}", current, total);
         })
     }
 
-    let mut file = File::create("/tmp/sim-result.txt").unwrap();
+    let path = env::var(RESULT_PATH_ENV).unwrap_or("/tmp/sim-result.txt".to_string());
+    let csv = env::var(RESULT_FORMAT_ENV).is_ok_and(|format| format == "csv");
+    let mut file = File::create(path).unwrap();
+    if csv {
+        writeln!(file, "{}", CSV_HEADER.join(",")).unwrap();
+    }
     for received in rx {
-        write!(file, "{}\n", received).unwrap();
+        if csv {
+            writeln!(file, "{}", received.csv_row()).unwrap();
+        } else {
+            writeln!(file, "{}", received).unwrap();
+        }
     }
 }
 
-fn simulation(
-    rounds: u64,
-    txs: u64,
-    p_graph: f64,
-    p_byzantine: f64,
-    p_tx_dist: f64,
-) -> (String, bool) {
-    log::debug!(
-        "========= starting simulation with {} rounds with {} txs =========
-        - probability that an edge will exist = {}
-        - probability that a Node is byzantine = {}
-        - probability that a tx will be distrubed to a Node = {}",
-        rounds,
-        txs,
-        p_graph,
-        p_byzantine,
-        p_tx_dist
-    );
-    let mut result = format!(
-        "rounds: {} | txs: {} | p_graph: {} | p_byzantine: {} | p_tx_dist: {}",
-        rounds, txs, p_graph, p_byzantine, p_tx_dist
-    );
-
-    let mut before = Instant::now();
-    let (mut nodes, valid_tx_ids, followees, seeds) =
-        init(rounds, txs, p_graph, p_byzantine, p_tx_dist);
-    result.push_str(&format!(
-        " | initialized in {:.3?} | seeds: {:?}",
-        before.elapsed(),
-        seeds
-    ));
-    log::debug!("initialized in {:.3?}", before.elapsed());
-
-    before = Instant::now();
-    for _ in 0..rounds {
-        // key is the index of a Node and value is vec of candidate txs from
-        // other nodes
-        let mut all_proposals: HashMap<usize, Vec<Candidate>> = HashMap::new();
-
-        for i in 0..NODES {
-            let proposals = nodes.get(i).unwrap().followers_send();
-            for tx in proposals.iter() {
-                if !valid_tx_ids.contains(&tx) {
-                    continue; // controls that each tx is valid
-                }
-
-                // for each of nodes followers, add tx to their proposals for this turn
-                for j in 0..NODES {
-                    if !followees[j][i] {
-                        continue; // tx is only proposed if `j` follows `i`
-                    }
+#[test]
+fn simulation_result_as_csv() {
+    let result = Simulation::new(2, 10, 0.1, 0.15, 0.1).run();
+
+    let header = CSV_HEADER.join(",");
+    let row = result.csv_row();
+    let columns: Vec<&str> = row.split(',').collect();
+    assert_eq!(header.split(',').count(), columns.len());
+
+    assert_eq!(Ok(2), columns[0].parse::<u64>());
+    assert_eq!(Ok(10), columns[1].parse::<u64>());
+    assert_eq!(Ok(0.1), columns[2].parse::<f64>());
+    assert_eq!(Ok(0.15), columns[3].parse::<f64>());
+    assert_eq!(Ok(0.1), columns[4].parse::<f64>());
+    for column in columns[5..12].iter() {
+        assert!(column.parse::<u128>().is_ok());
+    }
+    for group in columns[12].split(';') {
+        let (nodes, txs) = group.split_once(':').unwrap();
+        assert!(nodes.parse::<usize>().is_ok());
+        assert!(txs.parse::<usize>().is_ok());
+    }
+    assert_eq!(Ok(result.passed()), columns[13].parse::<bool>());
+    assert_eq!(Ok(1), columns[14].parse::<usize>());
+}
 
-                    let candidate = Candidate::new(*tx, i.try_into().unwrap());
-                    all_proposals.entry(j).or_insert(vec![]).push(candidate);
-                }
-            }
+#[test]
+#[allow(clippy::needless_range_loop)]
+fn divergence_is_recorded() {
+    let rounds = 3;
+    let byzantine_rng = StdRng::seed_from_u64(0);
+    let mut nodes: Nodes = Vec::with_capacity(NODES);
+    let mut followees: Followees = [[false; NODES]; NODES];
+
+    // dead byzantine majority, followed by every trusted node
+    let dead = 60;
+    for _ in 0..dead {
+        let node = ByzantineNode::new(ByzantineBehaviour::Dead, rounds, byzantine_rng.clone());
+        nodes.push(Box::new(node));
+    }
+    for i in dead..NODES {
+        nodes.push(Box::new(TrustedNode::new(0.1, 0.6, 0.1, rounds)));
+        for j in 0..dead {
+            followees[i][j] = true;
         }
+    }
 
-        // distributes proposals made in this turn to followers
-        for i in 0..NODES {
-            if !all_proposals.contains_key(&i) {
-                continue;
-            }
-            nodes
-                .get_mut(i)
-                .unwrap()
-                .followees_receive(all_proposals.get(&i).unwrap());
+    // trusted nodes are split into two groups, each following one trusted
+    // node, which only follows the dead ones
+    let (first, second) = (dead, 80);
+    for i in dead..NODES {
+        if i == first || i == second {
+            continue;
         }
+        followees[i][if i < second { first } else { second }] = true;
     }
-    result.push_str(&format!(" | simulation done in {:.3?}", before.elapsed()));
-    log::debug!("simulation done in {:.3?}", before.elapsed());
 
-    let (res, passed) = results(&nodes);
-    result.push_str(&res);
-
-    (result, passed)
-}
-
-/// Returns initialized Nodes, set of valid tx ids and a followers/followee matrix,
-/// and tuple containing seed for the rng used in simulation and seed used in
-/// byzantine nodes.
-///
-/// # Arguments
-///
-/// * `p_graph` - probability that an edge will exist, can be .1, .2, .3
-/// * `p_byzantine` - probability that a Node is byzantine, can be .15, .3, .45
-/// * `p_tx_dist` - probability that a tx will be distrubed to a Node, can be .01, .05, .1
-fn init(
-    rounds: u64,
-    txs: u64,
-    p_graph: f64,
-    p_byzantine: f64,
-    p_tx_dist: f64,
-) -> (
-    Vec<Box<dyn Node<NODES>>>,
-    HashSet<Tx>,
-    [[bool; NODES]; NODES],
-    (u64, u64),
-) {
-    let mut nodes: Vec<Box<dyn Node<NODES>>> = Vec::with_capacity(NODES);
-    let seed: u64 = rand::thread_rng().gen();
-    log::debug!("rng seed {}", seed);
-    let byzantine_seed: u64 = rand::thread_rng().gen();
-    log::debug!("byzantine rng seed {}", byzantine_seed);
-
-    let mut rng = StdRng::seed_from_u64(seed);
-    let byzantine_rng = StdRng::seed_from_u64(byzantine_seed);
-
-    let mut byzantine = 0;
+    let valid_tx_ids = HashSet::from([Tx::new(1), Tx::new(2)]);
     for i in 0..NODES {
-        let node: Box<dyn Node<NODES>>;
-        if rng.gen_bool(p_byzantine) {
-            let behaviour = match byzantine % 3 {
-                0 => ByzantineBehaviour::Dead,
-                1 => ByzantineBehaviour::Selfish,
-                _ => ByzantineBehaviour::Mix(0.5),
-            };
-            node = Box::new(ByzantineNode::new(behaviour, rounds, byzantine_rng.clone()));
-            byzantine += 1;
-        } else {
-            node = Box::new(TrustedNode::new(p_graph, p_byzantine, p_tx_dist, rounds));
-        }
-
-        nodes.insert(i, node);
+        nodes[i].followees_set(followees[i]);
+        let pending = match i {
+            i if i == first => HashSet::from([Tx::new(1)]),
+            i if i == second => HashSet::from([Tx::new(2)]),
+            _ => HashSet::new(),
+        };
+        nodes[i].pending_txs_set(pending);
     }
-    log::debug!(
-        "There are {} trusted nodes and {} byzantine",
-        NODES - byzantine,
-        byzantine
-    );
 
-    let mut followees: [[bool; NODES]; NODES] = [[false; NODES]; NODES];
-    for i in 0..NODES {
-        for j in 0..NODES {
-            if i == j {
-                continue;
-            }
+    run_rounds(&mut nodes, &valid_tx_ids, &followees, rounds, None);
 
-            if rng.gen_bool(p_graph) {
-                followees[i][j] = true;
-            }
-        }
-    }
+    assert_eq!(
+        vec![
+            ConsensusGroup { nodes: 19, txs: 1 },
+            ConsensusGroup { nodes: 19, txs: 1 },
+            ConsensusGroup { nodes: 2, txs: 0 },
+        ],
+        results(&nodes)
+    );
+}
 
-    for i in 0..NODES {
-        nodes.get_mut(i).unwrap().followees_set(followees[i]);
+#[test]
+#[allow(clippy::needless_range_loop)]
+fn byzantine_majority_of_followees_is_at_risk() {
+    let rounds = 3;
+    let byzantine_rng = StdRng::seed_from_u64(0);
+    let mut nodes: Nodes = Vec::with_capacity(NODES);
+    let mut followees: Followees = [[false; NODES]; NODES];
+
+    let dead = 10;
+    for _ in 0..dead {
+        let node = ByzantineNode::new(ByzantineBehaviour::Dead, rounds, byzantine_rng.clone());
+        nodes.push(Box::new(node));
+    }
+    for _ in dead..NODES {
+        nodes.push(Box::new(TrustedNode::new(0.1, 0.6, 0.1, rounds)));
     }
 
-    let mut valid_tx_ids: HashSet<Tx> = HashSet::new();
-    for _ in 0..txs {
-        valid_tx_ids.insert(rng.gen::<Tx>());
+    // every trusted node follows the next two trusted nodes, the first five
+    // also follow all dead ones, the next five only two of them
+    for i in dead..NODES {
+        for k in 1..=2 {
+            followees[i][dead + (i - dead + k) % (NODES - dead)] = true;
+        }
+        let followed_dead = match i - dead {
+            0..=4 => dead,
+            5..=9 => 2,
+            _ => 0,
+        };
+        for j in 0..followed_dead {
+            followees[i][j] = true;
+        }
     }
 
+    let valid_tx_ids = HashSet::from([Tx::new(1)]);
     for i in 0..NODES {
-        let mut pending_txs: HashSet<Tx> = HashSet::new();
-        for id in valid_tx_ids.iter() {
-            if rng.gen_bool(p_tx_dist) {
-                pending_txs.insert(*id);
-            }
+        nodes[i].followees_set(followees[i]);
+        if i >= dead {
+            nodes[i].pending_txs_set(HashSet::from([Tx::new(1)]));
         }
-        nodes.get_mut(i).unwrap().pending_txs_set(pending_txs);
     }
-
-    (nodes, valid_tx_ids, followees, (seed, byzantine_seed))
+    run_rounds(&mut nodes, &valid_tx_ids, &followees, rounds, None);
+
+    // exactly half of byzantine followees isn't a majority yet
+    assert_eq!(vec![10, 11, 12, 13, 14], at_risk(&nodes, &followees));
+    let bucket = |tenths, nodes| ExposureBucket {
+        tenths,
+        nodes,
+        in_majority: nodes,
+    };
+    assert_eq!(
+        vec![bucket(0, 80), bucket(5, 5), bucket(8, 5)],
+        exposure(&nodes, &followees)
+    );
 }
 
-fn results(nodes: &Vec<Box<dyn Node<NODES>>>) -> (String, bool) {
-    let mut consensuses: HashSet<Vec<Tx>> = HashSet::new();
-    for i in 0..NODES {
-        let node = nodes.get(i).unwrap();
-        if node.is_byzantine() {
-            continue;
+#[test]
+#[allow(clippy::needless_range_loop)]
+fn message_count_with_and_without_budget() {
+    let rounds = 2;
+    let valid_tx_ids = HashSet::from([Tx::new(1), Tx::new(2)]);
+
+    // node 0 proposes 2 txs to nodes 1 and 2, node 1 relays to node 2 and
+    // the rest are dead nodes, which never send anything
+    let setup = || {
+        let byzantine_rng = StdRng::seed_from_u64(0);
+        let mut nodes: Nodes = Vec::with_capacity(NODES);
+        let mut followees: Followees = [[false; NODES]; NODES];
+        followees[1][0] = true;
+        followees[2][0] = true;
+        followees[2][1] = true;
+        for i in 0..NODES {
+            let mut node: Box<dyn Node<NODES>> = if i < 3 {
+                Box::new(TrustedNode::new(0.1, 0.15, 0.1, rounds))
+            } else {
+                let behaviour = ByzantineBehaviour::Dead;
+                Box::new(ByzantineNode::new(behaviour, rounds, byzantine_rng.clone()))
+            };
+            node.followees_set(followees[i]);
+            node.pending_txs_set(if i == 0 {
+                HashSet::from([Tx::new(1), Tx::new(2)])
+            } else {
+                HashSet::new()
+            });
+            nodes.push(node);
         }
-        let mut txs: Vec<Tx> = node.followers_send().iter().map(|tx| *tx).collect();
-        txs.sort();
-        consensuses.insert(txs.clone());
-
-        log::trace!(
-            "Transaction ids that Node {} believes consensus on:\n\t{:?}",
-            i,
-            txs
-        );
-    }
+        (nodes, followees)
+    };
+
+    // round 1: 0 -> 1 (2), 0 -> 2 (2)
+    // round 2: 0 -> 1 (2), 0 -> 2 (2), 1 -> 2 (2)
+    let (mut nodes, followees) = setup();
+    assert_eq!(
+        10,
+        run_rounds(&mut nodes, &valid_tx_ids, &followees, rounds, None)
+    );
 
-    let mut result = String::new();
-    let mut passed = true;
-    if consensuses.len() != 1 {
-        passed = false;
-        result.push_str(&format!(
-            " | {} different consensuses reached!",
-            consensuses.len()
-        ));
-        log::debug!(
-            "There are {} different consensuses reached",
-            consensuses.len()
-        );
-    }
-    result.push_str(&format!(
-        " | count of tx upon which consensus was reached {}",
-        consensuses.iter().collect::<Vec<_>>().get(0).unwrap().len()
-    ));
-    log::debug!(
-        "count of tx upon which consensus was reached on {}",
-        consensuses.iter().collect::<Vec<_>>().get(0).unwrap().len()
+    // round 1: 0 -> 1 (2), 0 -> 2 (1)
+    // round 2: 0 -> 1 (2), 0 -> 2 (1), 1 -> 2 (2)
+    let (mut nodes, followees) = setup();
+    assert_eq!(
+        8,
+        run_rounds(&mut nodes, &valid_tx_ids, &followees, rounds, Some(3))
     );
+}
+
+#[test]
+fn converging_simulation_passes_on_first_try() {
+    // without byzantine nodes, all trusted nodes see all txs in a dense graph
+    let result = Simulation::new(5, 20, 0.3, 0.0, 0.1).run_until_converged(1);
+
+    assert!(result.passed());
+    assert_eq!(1, result.tries());
+}
+
+#[test]
+fn graph_independent_of_tx_distribution() {
+    let mut sparse = Simulation::new(2, 10, 0.1, 0.15, 0.01);
+    sparse.set_seed(Some(42));
+    let mut dense = Simulation::new(2, 10, 0.1, 0.15, 0.1);
+    dense.set_seed(Some(42));
+
+    let (sparse_nodes, _, sparse_followees, _) = sparse.init();
+    let (dense_nodes, _, dense_followees, _) = dense.init();
+
+    assert_eq!(sparse_followees, dense_followees);
+    let byzantine = |nodes: &Nodes| nodes.iter().map(|n| n.is_byzantine()).collect::<Vec<_>>();
+    assert_eq!(byzantine(&sparse_nodes), byzantine(&dense_nodes));
+}
+
+#[test]
+fn honest_implementation_doesnt_change_assignment() {
+    let mut trusted = Simulation::new(2, 10, 0.1, 0.3, 0.05);
+    trusted.set_seed(Some(42));
+    let mut snowball = Simulation::new(2, 10, 0.1, 0.3, 0.05);
+    snowball.set_seed(Some(42));
+    snowball.set_honest(HonestNode::Snowball);
+
+    let (trusted_nodes, trusted_txs, trusted_followees, trusted_seeds) = trusted.init();
+    let (snowball_nodes, snowball_txs, snowball_followees, snowball_seeds) = snowball.init();
+
+    assert_eq!(trusted_followees, snowball_followees);
+    assert_eq!(trusted_txs, snowball_txs);
+    assert_eq!(trusted_seeds, snowball_seeds);
+    let byzantine = |nodes: &Nodes| nodes.iter().map(|n| n.is_byzantine()).collect::<Vec<_>>();
+    assert_eq!(byzantine(&trusted_nodes), byzantine(&snowball_nodes));
+}
+
+#[test]
+fn seeded_init_reproduces_byzantine_seed() {
+    let mut simulation = Simulation::new(2, 10, 0.1, 0.3, 0.05);
+    simulation.set_seed(Some(7));
 
-    (result, passed)
+    let (_, _, _, first) = simulation.init();
+    let (_, _, _, second) = simulation.init();
+    assert_eq!(first, second);
 }
@@ -1,17 +1,158 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
 
-use rsa::{pkcs1v15::Signature, signature::Verifier, RsaPublicKey};
+use sha2::{Digest, Sha256};
 
 use crate::{
-    tx::{raw_tx_from_one_input, Input, Tx},
+    keys::PublicKey,
+    tx::{raw_tx_from_one_input, Input, Output, Tx, MAX_INPUTS},
     utxo::{UTXOPool, UTXO},
 };
 
-pub fn balance_of(pool: &UTXOPool, pub_key: &RsaPublicKey) -> u64 {
-    pool.utxos_of(pub_key)
-        .iter()
-        .map(|output| output.value() as u64)
-        .sum()
+/// Sum of values of outputs owned by the key, saturated at `u64::MAX`
+pub fn balance_of(pool: &UTXOPool, pub_key: impl Into<PublicKey>) -> u64 {
+    saturating_value_sum(pool.utxos_of(pub_key))
+}
+
+/// Sum of values of given outputs, summed as `u128`, so values of up to
+/// `u64::MAX` can't overflow it
+pub fn value_sum<'o>(outputs: impl IntoIterator<Item = &'o Output>) -> u128 {
+    outputs.into_iter().map(|o| u128::from(o.value())).sum()
+}
+
+/// Same as `value_sum`, but saturated at `u64::MAX`
+pub fn saturating_value_sum<'o>(outputs: impl IntoIterator<Item = &'o Output>) -> u64 {
+    u64::try_from(value_sum(outputs)).unwrap_or(u64::MAX)
+}
+
+/// Returns whether outputs spent by the tx are worth at least as much as its
+/// outputs, regardless of signatures or whether `spent` are really unspent
+pub fn conserves_value(tx: &Tx, spent: &[&Output]) -> bool {
+    value_sum(spent.iter().copied()) >= value_sum(tx.outputs())
+}
+
+/// UTXOs spent and created by an accepted tx
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxEffect {
+    pub tx_hash: [u8; 32],
+    pub spent: Vec<UTXO>,
+    /// UTXOs of payment outputs, data outputs are never added to the pool
+    pub created: Vec<UTXO>,
+}
+
+impl TxEffect {
+    fn of(tx: &Tx) -> Self {
+        let created = tx
+            .outputs()
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| !output.is_data())
+            .map(|(i, _)| UTXO::new(tx.hash(), i.try_into().unwrap()))
+            .collect();
+        Self {
+            tx_hash: tx.hash(),
+            spent: tx.inputs().iter().map(input_to_utxo).collect(),
+            created,
+        }
+    }
+}
+
+/// Maximum number of signatures kept by `SignatureCache::new`
+pub const SIGNATURE_CACHE_SIZE: usize = 10_000;
+
+/// SHA-256 of the signed message, the signature and the key verifying it
+type VerifiedSignature = [u8; 32];
+
+/// Signatures which were already verified, keyed by the signed message, the
+/// signature bytes and the key, so a verified signature stays valid in any
+/// pool. At most `capacity` signatures are kept, the one verified first is
+/// evicted first. Clones share the same cache.
+#[derive(Debug, Clone)]
+pub struct SignatureCache {
+    verified: Arc<Mutex<VerifiedSignatures>>,
+}
+
+#[derive(Debug)]
+struct VerifiedSignatures {
+    signatures: HashSet<VerifiedSignature>,
+    order: VecDeque<VerifiedSignature>,
+    capacity: usize,
+    hits: usize,
+}
+
+impl SignatureCache {
+    /// Creates cache keeping at most `SIGNATURE_CACHE_SIZE` signatures
+    pub fn new() -> Self {
+        Self::with_capacity(SIGNATURE_CACHE_SIZE)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let verified = VerifiedSignatures {
+            signatures: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+            hits: 0,
+        };
+        Self {
+            verified: Arc::new(Mutex::new(verified)),
+        }
+    }
+
+    /// Number of verified signatures
+    pub fn len(&self) -> usize {
+        self.verified.lock().unwrap().signatures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of lookups, which found an already verified signature
+    pub fn hits(&self) -> usize {
+        self.verified.lock().unwrap().hits
+    }
+
+    fn contains(&self, key: &VerifiedSignature) -> bool {
+        let mut verified = self.verified.lock().unwrap();
+        let is_hit = verified.signatures.contains(key);
+        if is_hit {
+            verified.hits += 1;
+        }
+        is_hit
+    }
+
+    fn insert(&self, key: VerifiedSignature) {
+        let mut verified = self.verified.lock().unwrap();
+        if verified.capacity == 0 || !verified.signatures.insert(key) {
+            return;
+        }
+        verified.order.push_back(key);
+        if verified.order.len() > verified.capacity {
+            if let Some(oldest) = verified.order.pop_front() {
+                verified.signatures.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl Default for SignatureCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Key of a verified signature in `SignatureCache`, lengths are prefixed so
+/// different splits of the same bytes don't collide
+fn verified_signature(msg: &[u8], signature: &[u8], key: &PublicKey) -> VerifiedSignature {
+    let mut hasher = Sha256::new();
+    for part in [msg, signature, &key.to_bytes()] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().into()
 }
 
 pub trait TxHandler<'a> {
@@ -19,11 +160,38 @@ pub trait TxHandler<'a> {
     /// Checks validity of each, internally updates the UTXO pool, and
     /// returns vector of valid ones.
     ///
+    /// Valid txs must be returned in the order in which they were applied to
+    /// the pool, e.g. as returned by `handle_in_rounds`, whatever order they
+    /// were tried in, because `handle_ordered` and `handle_with_effects`
+    /// rely on it.
+    ///
     /// # Beware
     /// Transactions can be dependent on other ones. Also, multiple
     /// transactions can reference same output.
     fn handle(&mut self, possible_txs: Vec<&'a Tx>) -> Vec<&'a Tx>;
 
+    /// Same as `handle`, but each accepted tx is returned with its sequence
+    /// number, in which it was applied to the internal pool, starting from 0.
+    /// Parents are always applied before their children.
+    fn handle_ordered(&mut self, possible_txs: Vec<&'a Tx>) -> Vec<(&'a Tx, usize)> {
+        // `handle` returns txs in the order they were applied
+        self.handle(possible_txs)
+            .into_iter()
+            .enumerate()
+            .map(|(seq, tx)| (tx, seq))
+            .collect()
+    }
+
+    /// Same as `handle`, but returns effects of accepted txs on the internal
+    /// pool, in the order in which they were applied, so a view of the pool
+    /// can be updated incrementally
+    fn handle_with_effects(&mut self, possible_txs: Vec<&'a Tx>) -> Vec<TxEffect> {
+        self.handle(possible_txs)
+            .into_iter()
+            .map(TxEffect::of)
+            .collect()
+    }
+
     /// Returns reference to internal pool
     fn pool(&self) -> &UTXOPool;
 
@@ -33,15 +201,71 @@ pub trait TxHandler<'a> {
     /// Moves internal pool, while consuming self
     fn move_pool(self) -> UTXOPool;
 
+    /// Runs `handle` on a copy of this handler, returning txs which would be
+    /// accepted and the resulting pool. Internal pool is left untouched.
+    fn dry_run(&self, possible_txs: Vec<&'a Tx>) -> (Vec<&'a Tx>, UTXOPool)
+    where
+        Self: Clone,
+    {
+        let mut handler = self.clone();
+        let accepted = handler.handle(possible_txs);
+        (accepted, handler.move_pool())
+    }
+
+    /// Whether txs with zero sum of outputs are valid. Such txs burn all
+    /// of their inputs as a fee. Allowed by default.
+    fn allows_zero_output(&self) -> bool {
+        true
+    }
+
+    /// Maximum number of inputs of a valid tx, `MAX_INPUTS` by default
+    fn max_inputs(&self) -> usize {
+        MAX_INPUTS
+    }
+
+    /// Cache of verified signatures, which `is_tx_valid` doesn't verify
+    /// again. None by default, so every signature is verified.
+    fn signature_cache(&self) -> Option<&SignatureCache> {
+        None
+    }
+
+    /// Verifies signature of an input, which wasn't found in the
+    /// `signature_cache`, see `PublicKey::verify`
+    fn verify_signature(
+        &self,
+        key: &PublicKey,
+        msg: &[u8],
+        signature: &[u8],
+    ) -> Result<(), rsa::signature::Error> {
+        key.verify(msg, signature)
+    }
+
     /// Checks if:
     ///     1. All UTXO inputs are in pool
-    ///     2. Signatures on inputs are valid
+    ///     2. Signatures on inputs are valid, each covering outputs selected
+    ///         by its `SigHash`
     ///     3. No UTXO is used more than once
-    ///     4. Sum of outputs is not negative
+    ///     4. Sum of outputs is positive, or the tx isn't a coinbase and
+    ///         handler `allows_zero_output`
     ///     5. Sum of inputs >= Sum of outputs
+    ///     6. No two inputs carry byte-identical signatures
+    ///     7. No output is locked to a degenerate key, see `PublicKey::validate`,
+    ///         data outputs aren't locked to any key
+    ///     8. There are at most `max_inputs` inputs, checked before anything
+    ///         else, so huge txs don't cost any signature verification
     fn is_tx_valid(&self, tx: &Tx) -> bool {
-        let mut in_sum = 0;
-        let mut used_outputs: HashSet<([u8; 32], u8)> = HashSet::new();
+        if tx.input_len() > self.max_inputs() {
+            log::debug!(
+                "tx has {} inputs, max is {}",
+                tx.input_len(),
+                self.max_inputs()
+            );
+            return false;
+        }
+
+        let mut spent: Vec<&Output> = Vec::with_capacity(tx.input_len());
+        let mut used_outputs: HashSet<([u8; 32], u16)> = HashSet::new();
+        let mut used_signatures: HashSet<&[u8]> = HashSet::new();
         for (i, input) in tx.inputs().iter().enumerate() {
             if used_outputs.contains(&(input.output_tx_hash(), input.output_idx())) {
                 log::debug!(
@@ -64,22 +288,35 @@ pub trait TxHandler<'a> {
                     return false;
                 }
             };
-
-            let signature = match input.signature() {
-                Some(sig) => sig,
+            let public_key = match output.public_key() {
+                Some(pk) => pk,
                 None => {
-                    log::debug!("unsigned signature here?");
+                    log::debug!("input {} spends a data output", i);
                     return false;
                 }
             };
 
-            let signature = match Signature::try_from(signature.as_ref()) {
-                Ok(sig) => sig,
-                Err(err) => {
-                    log::debug!("failed to convert into signature, {:?}", err);
+            let signature = match input.signature_bytes() {
+                Some(sig) => sig,
+                None => {
+                    log::debug!("unsigned signature here?");
                     return false;
                 }
             };
+            if !used_signatures.insert(signature) {
+                log::debug!("signature of input {} already used by another input", i);
+                return false;
+            }
+
+            let max_signature_len = public_key.max_signature_len();
+            if signature.len() > max_signature_len {
+                log::debug!(
+                    "signature has {} bytes, but max is {}",
+                    signature.len(),
+                    max_signature_len
+                );
+                return false;
+            }
 
             let raw_tx =
                 match raw_tx_from_one_input(tx.inputs(), tx.outputs(), i.try_into().unwrap()) {
@@ -90,46 +327,57 @@ pub trait TxHandler<'a> {
                     }
                 };
 
-            match output.verifying_key().verify(&raw_tx, &signature) {
-                Ok(_) => {}
-                Err(err) => {
-                    log::debug!("invalid signature, {:?}", err);
-                    return false;
+            let cache_key = verified_signature(&raw_tx, signature, public_key);
+            let cache = self.signature_cache();
+            if !cache.is_some_and(|cache| cache.contains(&cache_key)) {
+                match self.verify_signature(public_key, &raw_tx, signature) {
+                    Ok(_) => {}
+                    Err(err) => {
+                        log::debug!("invalid signature, {:?}", err);
+                        return false;
+                    }
+                }
+                if let Some(cache) = cache {
+                    cache.insert(cache_key);
                 }
             }
 
-            in_sum += output.value();
+            spent.push(output);
         }
 
-        let out_sum: u32 = tx.outputs().iter().map(|out| out.value()).sum();
+        for (i, output) in tx.outputs().iter().enumerate() {
+            if let Some(Err(err)) = output.public_key().map(PublicKey::validate) {
+                log::debug!("output {} is locked to an invalid key, {}", i, err);
+                return false;
+            }
+        }
+
+        let has_value = tx.outputs().iter().any(|out| out.value() > 0);
+
+        let is_out_sum_valid = has_value || (self.allows_zero_output() && !tx.is_coinbase());
+        is_out_sum_valid && conserves_value(tx, &spent)
+    }
 
-        out_sum > 0 && in_sum >= out_sum
+    /// Repeatedly handles independent txs, whose outputs may make dependent
+    /// ones independent in the next round. Returns handled txs, in the order
+    /// in which they were applied, and txs stuck on parents, which never
+    /// became available. Dependent txs whose parent isn't among remaining txs
+    /// are dropped in each round, so a round without progress only happens
+    /// if all remaining txs depend on each other, which is guarded against
+    /// anyway.
+    fn handle_in_rounds(&mut self, possible_txs: Vec<&'a Tx>) -> (Vec<&'a Tx>, Vec<&'a Tx>) {
+        handle_in_rounds_with(self, possible_txs, &mut |handler, tx| {
+            handler.apply_tx(tx);
+            tx
+        })
     }
 
     /// Filters independent txs from dependent ones, applies them and returns both sets
     fn handle_independent(&mut self, txs: Vec<&'a Tx>) -> (Vec<&'a Tx>, Vec<&'a Tx>) {
-        let mut handled = vec![];
-        let mut dependent = vec![];
-        let tx_set: HashSet<[u8; 32]> = txs.iter().map(|&tx| tx.hash()).collect();
-
-        for &tx in txs.iter() {
-            if tx.inputs().iter().all(|i| self.is_input_in_pool(i)) {
-                // tx is only dependent on outputs in pool
-                if self.is_tx_valid(tx) {
-                    self.apply_tx(tx);
-                    handled.push(tx);
-                }
-            } else if tx
-                .inputs()
-                .iter()
-                .any(|i| tx_set.contains(&i.output_tx_hash()))
-            {
-                // tx is dependent on some outputs from this batch
-                dependent.push(tx)
-            }
-        }
-
-        (handled, dependent)
+        handle_independent_with(self, txs, &mut |handler, tx| {
+            handler.apply_tx(tx);
+            tx
+        })
     }
 
     /// Applies given tx to the internal pool
@@ -137,42 +385,126 @@ pub trait TxHandler<'a> {
         for input in tx.inputs().iter() {
             self.pool_mut().remove_utxo(&input_to_utxo(input));
         }
+        // data outputs can't be spent, so they are never added
         for (i, output) in tx.outputs().iter().enumerate() {
+            if output.is_data() {
+                continue;
+            }
             let utxo = UTXO::new(tx.hash(), i.try_into().unwrap());
-            self.pool_mut().add_utxo(utxo, &output)
+            self.pool_mut().add_utxo(utxo, output)
         }
     }
 
+    /// Same as `apply_tx`, but outputs are moved from the consumed tx into
+    /// the internal pool instead of being cloned
+    fn apply_owned_tx(&mut self, tx: Tx) {
+        for input in tx.inputs().iter() {
+            self.pool_mut().remove_utxo(&input_to_utxo(input));
+        }
+        let hash = tx.hash();
+        let utxos = tx
+            .into_outputs()
+            .into_iter()
+            .enumerate()
+            .filter(|(_, output)| !output.is_data())
+            .map(|(i, output)| (UTXO::new(hash, i.try_into().unwrap()), output));
+        self.pool_mut().extend(utxos);
+    }
+
     fn is_input_in_pool(&self, input: &Input) -> bool {
         self.pool().contains(&input_to_utxo(input))
     }
 }
 
+/// Extension of `TxHandler` for callers which don't want to keep proposed
+/// transactions alive for the duration of the handling.
+pub trait OwnedTxHandler {
+    /// Same as `TxHandler::handle`, but takes ownership of proposed
+    /// transactions. Returns accepted txs in order in which they were
+    /// applied and rejected ones in order in which they were proposed.
+    fn handle_owned(&mut self, possible_txs: Vec<Tx>) -> (Vec<Tx>, Vec<Tx>);
+
+    /// Same as `TxHandler::handle`, but consumes proposed transactions and
+    /// moves their outputs into the pool without cloning them. Txs are
+    /// checked in order in which they were proposed. Returns hashes of
+    /// accepted txs in order in which they were applied.
+    fn handle_consumed(&mut self, possible_txs: Vec<Tx>) -> Vec<[u8; 32]>;
+}
+
+impl<H> OwnedTxHandler for H
+where
+    H: for<'a> TxHandler<'a>,
+{
+    fn handle_owned(&mut self, possible_txs: Vec<Tx>) -> (Vec<Tx>, Vec<Tx>) {
+        let accepted: Vec<[u8; 32]> = self
+            .handle(possible_txs.iter().collect())
+            .iter()
+            .map(|tx| tx.hash())
+            .collect();
+
+        let mut slots: Vec<Option<Tx>> = possible_txs.into_iter().map(Some).collect();
+        let mut handled = Vec::with_capacity(accepted.len());
+        for hash in accepted.iter() {
+            let slot = slots
+                .iter_mut()
+                .find(|slot| matches!(slot, Some(tx) if tx.hash() == *hash));
+            if let Some(tx) = slot.and_then(|slot| slot.take()) {
+                handled.push(tx);
+            }
+        }
+        let rejected = slots.into_iter().flatten().collect();
+
+        (handled, rejected)
+    }
+
+    fn handle_consumed(&mut self, possible_txs: Vec<Tx>) -> Vec<[u8; 32]> {
+        let (accepted, _) = handle_in_rounds_with(self, possible_txs, &mut |handler, tx| {
+            let hash = tx.hash();
+            handler.apply_owned_tx(tx);
+            hash
+        });
+        accepted
+    }
+}
+
+#[derive(Clone)]
 pub struct Handler {
     pool: UTXOPool,
+    allow_zero_output: bool,
+    max_inputs: usize,
+    signature_cache: Option<SignatureCache>,
 }
 
 impl Handler {
     pub fn new(pool: UTXOPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            allow_zero_output: true,
+            max_inputs: MAX_INPUTS,
+            signature_cache: None,
+        }
+    }
+
+    /// Skips verification of signatures found in the cache and adds newly
+    /// verified ones to it. The cache may be shared with other handlers.
+    pub fn set_signature_cache(&mut self, cache: SignatureCache) {
+        self.signature_cache = Some(cache);
+    }
+
+    /// Sets whether txs with zero sum of outputs are valid
+    pub fn set_allow_zero_output(&mut self, allow: bool) {
+        self.allow_zero_output = allow;
+    }
+
+    /// Sets maximum number of inputs of a valid tx, at most `MAX_INPUTS`
+    pub fn set_max_inputs(&mut self, max: usize) {
+        self.max_inputs = max.min(MAX_INPUTS);
     }
 }
 
 impl<'a> TxHandler<'a> for Handler {
     fn handle(&mut self, possible_txs: Vec<&'a Tx>) -> Vec<&'a Tx> {
-        let mut handled: Vec<&'a Tx> = vec![];
-        let mut to_handle = possible_txs;
-
-        loop {
-            let (independent, dependent) = self.handle_independent(to_handle);
-            handled.extend(independent);
-            if dependent.is_empty() {
-                break;
-            }
-            to_handle = dependent;
-        }
-
-        handled
+        self.handle_in_rounds(possible_txs).0
     }
 
     fn pool(&self) -> &UTXOPool {
@@ -187,80 +519,119 @@ impl<'a> TxHandler<'a> for Handler {
         self.pool
     }
 
+    fn allows_zero_output(&self) -> bool {
+        self.allow_zero_output
+    }
+
+    fn max_inputs(&self) -> usize {
+        self.max_inputs
+    }
+
+    fn signature_cache(&self) -> Option<&SignatureCache> {
+        self.signature_cache.as_ref()
+    }
+
     fn apply_tx(&mut self, tx: &Tx) {
         for input in tx.inputs().iter() {
             self.pool.remove_utxo(&input_to_utxo(input));
         }
         for (i, output) in tx.outputs().iter().enumerate() {
+            if output.is_data() {
+                continue;
+            }
             let utxo = UTXO::new(tx.hash(), i.try_into().unwrap());
             // clone is here necessary, because I want to return the tx back to
             // caller, so I can't consume it
-            self.pool.add_utxo(utxo, &output)
+            self.pool.add_utxo(utxo, output)
         }
     }
 }
 
+#[derive(Clone)]
 pub struct MaxFeeHandler {
     pool: UTXOPool,
+    allow_zero_output: bool,
+    max_inputs: usize,
 }
 
 impl MaxFeeHandler {
     pub fn new(pool: UTXOPool) -> Self {
-        Self { pool }
-    }
-
-    fn calc_fee(&self, tx: &Tx, tx_map: &HashMap<[u8; 32], &Tx>) -> Option<u64> {
-        let mut input_value: u64 = 0;
-        for input in tx.inputs().iter() {
-            let output = match self.pool.utxo_output(&input_to_utxo(input)).or_else(|| {
-                tx_map
-                    .get(&input.output_tx_hash())?
-                    .output(input.output_idx())
-            }) {
-                Some(output) => output,
-                None => return None,
-            };
-
-            input_value += output.value() as u64;
+        Self {
+            pool,
+            allow_zero_output: true,
+            max_inputs: MAX_INPUTS,
         }
+    }
 
-        let output_value: u64 = tx.outputs().iter().map(|o| o.value() as u64).sum();
+    /// Sets whether txs with zero sum of outputs are valid
+    pub fn set_allow_zero_output(&mut self, allow: bool) {
+        self.allow_zero_output = allow;
+    }
 
-        if input_value < output_value {
-            return None;
-        }
-        Some(input_value - output_value)
+    /// Sets maximum number of inputs of a valid tx, at most `MAX_INPUTS`
+    pub fn set_max_inputs(&mut self, max: usize) {
+        self.max_inputs = max.min(MAX_INPUTS);
     }
-}
 
-impl<'a> TxHandler<'a> for MaxFeeHandler {
-    fn handle(&mut self, possible_txs: Vec<&'a Tx>) -> Vec<&'a Tx> {
+    /// Ranks txs by their fees, from the highest. Of txs spending the same
+    /// output only the one with the highest fee is funded by it, the others
+    /// and txs spending their outputs are ranked last with zero fee, so the
+    /// shared value isn't counted twice. They are still handled, in case the
+    /// funded tx turns out to be invalid.
+    pub fn rank_by_fee<'a>(&self, possible_txs: &[&'a Tx]) -> Vec<(u64, &'a Tx)> {
         let tx_map: HashMap<[u8; 32], &'a Tx> =
             possible_txs.iter().map(|&tx| (tx.hash(), tx)).collect();
 
         let mut with_fees: Vec<(u64, &Tx)> = possible_txs
             .iter()
-            .filter_map(|&tx| match self.calc_fee(tx, &tx_map) {
-                Some(fee) => Some((fee, tx)),
-                None => None,
-            })
+            .filter_map(|&tx| calc_fee(&self.pool, tx, &tx_map).map(|fee| (fee, tx)))
             .collect();
-        with_fees.sort_unstable_by(|tx1, tx2| tx1.0.cmp(&tx2.0));
+        with_fees.sort_unstable_by_key(|tx| tx.0);
         with_fees.reverse();
 
-        let mut handled: Vec<&'a Tx> = vec![];
-        let mut to_handle = with_fees.iter().map(|tx| tx.1).collect();
-
+        let mut claimed: HashSet<UTXO> = HashSet::new();
+        let mut unfunded: HashSet<[u8; 32]> = HashSet::new();
+        for &(_, tx) in with_fees.iter() {
+            let utxos: Vec<UTXO> = tx.inputs().iter().map(input_to_utxo).collect();
+            if utxos.iter().any(|utxo| claimed.contains(utxo)) {
+                unfunded.insert(tx.hash());
+            } else {
+                claimed.extend(utxos);
+            }
+        }
+        // outputs of unfunded txs can't fund their children either
         loop {
-            let (independent, dependent) = self.handle_independent(to_handle);
-            handled.extend(independent);
-            if dependent.is_empty() {
+            let children: Vec<[u8; 32]> = with_fees
+                .iter()
+                .filter(|(_, tx)| !unfunded.contains(&tx.hash()))
+                .filter(|(_, tx)| {
+                    tx.inputs()
+                        .iter()
+                        .any(|i| unfunded.contains(&i.output_tx_hash()))
+                })
+                .map(|(_, tx)| tx.hash())
+                .collect();
+            if children.is_empty() {
                 break;
             }
-            to_handle = dependent;
+            unfunded.extend(children);
         }
 
-        handled
+        let (funded, unfunded): (Vec<_>, Vec<_>) = with_fees
+            .into_iter()
+            .partition(|(_, tx)| !unfunded.contains(&tx.hash()));
+        funded
+            .into_iter()
+            .chain(unfunded.into_iter().map(|(_, tx)| (0, tx)))
+            .collect()
+    }
+}
+
+impl<'a> TxHandler<'a> for MaxFeeHandler {
+    fn handle(&mut self, possible_txs: Vec<&'a Tx>) -> Vec<&'a Tx> {
+        let ranked = self.rank_by_fee(&possible_txs);
+        self.handle_in_rounds(ranked.into_iter().map(|(_, tx)| tx).collect())
+            .0
     }
 
     fn pool(&self) -> &UTXOPool {
@@ -274,6 +645,172 @@ impl<'a> TxHandler<'a> for MaxFeeHandler {
     fn move_pool(self) -> UTXOPool {
         self.pool
     }
+
+    fn allows_zero_output(&self) -> bool {
+        self.allow_zero_output
+    }
+
+    fn max_inputs(&self) -> usize {
+        self.max_inputs
+    }
+}
+
+/// Prefers txs with the highest fee per byte of their size, so small txs
+/// aren't outbid by huge ones paying a slightly larger fee
+#[derive(Clone)]
+pub struct FeeRateHandler {
+    pool: UTXOPool,
+    allow_zero_output: bool,
+    max_inputs: usize,
+}
+
+impl FeeRateHandler {
+    pub fn new(pool: UTXOPool) -> Self {
+        Self {
+            pool,
+            allow_zero_output: true,
+            max_inputs: MAX_INPUTS,
+        }
+    }
+
+    /// Sets whether txs with zero sum of outputs are valid
+    pub fn set_allow_zero_output(&mut self, allow: bool) {
+        self.allow_zero_output = allow;
+    }
+
+    /// Sets maximum number of inputs of a valid tx, at most `MAX_INPUTS`
+    pub fn set_max_inputs(&mut self, max: usize) {
+        self.max_inputs = max.min(MAX_INPUTS);
+    }
+}
+
+impl<'a> TxHandler<'a> for FeeRateHandler {
+    fn handle(&mut self, possible_txs: Vec<&'a Tx>) -> Vec<&'a Tx> {
+        let tx_map: HashMap<[u8; 32], &'a Tx> =
+            possible_txs.iter().map(|&tx| (tx.hash(), tx)).collect();
+
+        let mut with_fees: Vec<(u64, u64, &Tx)> = possible_txs
+            .iter()
+            .filter_map(|&tx| {
+                calc_fee(&self.pool, tx, &tx_map).map(|fee| (fee, tx.size() as u64, tx))
+            })
+            .collect();
+        // compares fee rates `fee / size` without rounding
+        with_fees.sort_by(|a, b| {
+            (u128::from(b.0) * u128::from(a.1)).cmp(&(u128::from(a.0) * u128::from(b.1)))
+        });
+
+        self.handle_in_rounds(with_fees.iter().map(|tx| tx.2).collect())
+            .0
+    }
+
+    fn pool(&self) -> &UTXOPool {
+        &self.pool
+    }
+
+    fn pool_mut(&mut self) -> &mut UTXOPool {
+        &mut self.pool
+    }
+
+    fn move_pool(self) -> UTXOPool {
+        self.pool
+    }
+
+    fn allows_zero_output(&self) -> bool {
+        self.allow_zero_output
+    }
+
+    fn max_inputs(&self) -> usize {
+        self.max_inputs
+    }
+}
+
+/// Returns fee of the tx, its inputs are looked up in the pool and in the
+/// other proposed txs
+fn calc_fee(pool: &UTXOPool, tx: &Tx, tx_map: &HashMap<[u8; 32], &Tx>) -> Option<u64> {
+    let mut spent = Vec::with_capacity(tx.input_len());
+    for input in tx.inputs().iter() {
+        let output = pool.utxo_output(&input_to_utxo(input)).or_else(|| {
+            tx_map
+                .get(&input.output_tx_hash())?
+                .output(input.output_idx())
+        })?;
+        spent.push(output);
+    }
+
+    if !conserves_value(tx, &spent) {
+        return None;
+    }
+    u64::try_from(value_sum(spent) - value_sum(tx.outputs())).ok()
+}
+
+/// Same as `TxHandler::handle_in_rounds`, but txs are applied by `apply`,
+/// so owned txs can be moved into the pool. Returns results of `apply` in
+/// the order in which txs were applied, and txs stuck on parents.
+fn handle_in_rounds_with<'a, H, T, R>(
+    handler: &mut H,
+    possible_txs: Vec<T>,
+    apply: &mut impl FnMut(&mut H, T) -> R,
+) -> (Vec<R>, Vec<T>)
+where
+    H: TxHandler<'a> + ?Sized,
+    T: Borrow<Tx>,
+{
+    let mut handled = vec![];
+    let mut to_handle = possible_txs;
+
+    loop {
+        let pending = to_handle.len();
+        let (independent, dependent) = handle_independent_with(handler, to_handle, apply);
+        handled.extend(independent);
+        if dependent.is_empty() {
+            return (handled, vec![]);
+        }
+        if dependent.len() == pending {
+            log::debug!("{} txs made no progress, giving up", dependent.len());
+            return (handled, dependent);
+        }
+        to_handle = dependent;
+    }
+}
+
+/// Same as `TxHandler::handle_independent`, but txs are applied by `apply`
+fn handle_independent_with<'a, H, T, R>(
+    handler: &mut H,
+    txs: Vec<T>,
+    apply: &mut impl FnMut(&mut H, T) -> R,
+) -> (Vec<R>, Vec<T>)
+where
+    H: TxHandler<'a> + ?Sized,
+    T: Borrow<Tx>,
+{
+    let mut handled = vec![];
+    let mut dependent = vec![];
+    let tx_set: HashSet<[u8; 32]> = txs.iter().map(|tx| tx.borrow().hash()).collect();
+
+    for tx in txs {
+        if tx
+            .borrow()
+            .inputs()
+            .iter()
+            .all(|i| handler.is_input_in_pool(i))
+        {
+            // tx is only dependent on outputs in pool
+            if handler.is_tx_valid(tx.borrow()) {
+                handled.push(apply(handler, tx));
+            }
+        } else if tx
+            .borrow()
+            .inputs()
+            .iter()
+            .any(|i| tx_set.contains(&i.output_tx_hash()))
+        {
+            // tx is dependent on some outputs from this batch
+            dependent.push(tx)
+        }
+    }
+
+    (handled, dependent)
 }
 
 fn input_to_utxo(input: &Input) -> UTXO {
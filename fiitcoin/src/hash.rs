@@ -0,0 +1,34 @@
+use core::fmt;
+
+use sha2::{Digest, Sha256};
+
+/// Hash function used for hashing txs and blocks. Signatures are still
+/// created over SHA-256, only the ids of txs and blocks are affected.
+pub trait Hasher: fmt::Debug {
+    fn hash(&self, data: &[u8]) -> [u8; 32];
+}
+
+/// Returns lowercase hex representation of the bytes
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Default hasher, single round of SHA-256
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Sha256Hasher;
+
+impl Hasher for Sha256Hasher {
+    fn hash(&self, data: &[u8]) -> [u8; 32] {
+        Sha256::digest(data).into()
+    }
+}
+
+/// SHA-256 applied twice, as used by Bitcoin
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DoubleSha256Hasher;
+
+impl Hasher for DoubleSha256Hasher {
+    fn hash(&self, data: &[u8]) -> [u8; 32] {
+        Sha256::digest(Sha256::digest(data)).into()
+    }
+}
@@ -0,0 +1,188 @@
+use core::fmt;
+
+use rsa::{
+    pkcs1v15::{Signature, SigningKey, VerifyingKey},
+    signature::{self, SignatureEncoding, Signer, Verifier},
+    traits::PublicKeyParts,
+    BigUint, RsaPublicKey,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{hash::to_hex, wire::write_key};
+
+/// Length of an Ed25519 signature in bytes
+pub const ED25519_SIGNATURE_LEN: usize = ed25519_dalek::SIGNATURE_LENGTH;
+
+/// Minimum number of bits of an RSA modulus an output can be locked to
+pub const MIN_RSA_MODULUS_BITS: usize = 512;
+
+/// Public key to which an output is locked, RSA is the default scheme
+#[derive(Debug, Clone)]
+pub enum PublicKey {
+    Rsa(VerifyingKey<Sha256>),
+    Ed25519(ed25519_dalek::VerifyingKey),
+}
+
+impl PublicKey {
+    /// Returns representation of this key in bytes, which is part of a tx.
+    /// It starts with the scheme of the key and RSA exponent and modulus are
+    /// prefixed by their lengths, so different keys never have the same bytes,
+    /// neither across schemes nor across splits of the exponent and modulus.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut b = vec![];
+        write_key(&mut b, self);
+        b
+    }
+
+    /// Short human readable identifier of the key, first 4 bytes of SHA-256
+    /// of its bytes in hex
+    pub fn fingerprint(&self) -> String {
+        to_hex(&self.fingerprint_bytes()[..4])
+    }
+
+    /// Address of the key, first 20 bytes of SHA-256 of its bytes, so it
+    /// starts with the `fingerprint`
+    pub fn address(&self) -> [u8; 20] {
+        Sha256::digest(self.to_bytes())[..20].try_into().unwrap()
+    }
+
+    /// First 8 bytes of SHA-256 of the key bytes, equal keys always have
+    /// equal fingerprints, so different fingerprints mean different keys
+    pub(crate) fn fingerprint_bytes(&self) -> [u8; 8] {
+        Sha256::digest(self.to_bytes())[..8].try_into().unwrap()
+    }
+
+    /// Maximum length of a signature created by the corresponding private key
+    pub fn max_signature_len(&self) -> usize {
+        match self {
+            PublicKey::Rsa(vk) => vk.as_ref().size(),
+            PublicKey::Ed25519(_) => ED25519_SIGNATURE_LEN,
+        }
+    }
+
+    /// Checks that the key isn't degenerate, so outputs locked to it can be
+    /// spent only by the owner of the private key. RSA modulus must be odd
+    /// and have at least `MIN_RSA_MODULUS_BITS`, the exponent must be odd,
+    /// greater than 1 and less than the modulus.
+    pub fn validate(&self) -> Result<(), KeyError> {
+        match self {
+            PublicKey::Rsa(vk) => {
+                let (n, e) = (vk.as_ref().n(), vk.as_ref().e());
+                let bits = n.bits();
+                if bits < MIN_RSA_MODULUS_BITS {
+                    return Err(KeyError::ModulusTooSmall(bits));
+                }
+                if !is_odd(n) {
+                    return Err(KeyError::EvenModulus);
+                }
+                if !is_odd(e) || e.bits() < 2 || e >= n {
+                    return Err(KeyError::InvalidExponent);
+                }
+                Ok(())
+            }
+            PublicKey::Ed25519(_) => Ok(()),
+        }
+    }
+
+    pub fn verify(&self, msg: &[u8], signature: &[u8]) -> Result<(), signature::Error> {
+        match self {
+            PublicKey::Rsa(vk) => vk.verify(msg, &Signature::try_from(signature)?),
+            PublicKey::Ed25519(vk) => {
+                vk.verify(msg, &ed25519_dalek::Signature::from_slice(signature)?)
+            }
+        }
+    }
+}
+
+/// Keys are equal if they are of the same scheme and have the same contents
+impl PartialEq for PublicKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PublicKey::Rsa(a), PublicKey::Rsa(b)) => a.as_ref() == b.as_ref(),
+            (PublicKey::Ed25519(a), PublicKey::Ed25519(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for PublicKey {}
+
+impl From<&VerifyingKey<Sha256>> for PublicKey {
+    fn from(vk: &VerifyingKey<Sha256>) -> Self {
+        PublicKey::Rsa(vk.clone())
+    }
+}
+
+impl From<&RsaPublicKey> for PublicKey {
+    fn from(pub_key: &RsaPublicKey) -> Self {
+        PublicKey::Rsa(VerifyingKey::new(pub_key.clone()))
+    }
+}
+
+impl From<&ed25519_dalek::VerifyingKey> for PublicKey {
+    fn from(vk: &ed25519_dalek::VerifyingKey) -> Self {
+        PublicKey::Ed25519(*vk)
+    }
+}
+
+impl From<&PublicKey> for PublicKey {
+    fn from(pub_key: &PublicKey) -> Self {
+        pub_key.clone()
+    }
+}
+
+/// Private key, which can sign inputs of a tx
+pub trait TxSigner {
+    fn public_key(&self) -> PublicKey;
+
+    /// Signs given bytes and returns the encoded signature
+    fn sign_raw(&self, msg: &[u8]) -> Box<[u8]>;
+}
+
+impl TxSigner for SigningKey<Sha256> {
+    fn public_key(&self) -> PublicKey {
+        PublicKey::Rsa(rsa::signature::Keypair::verifying_key(self))
+    }
+
+    fn sign_raw(&self, msg: &[u8]) -> Box<[u8]> {
+        self.sign(msg).to_bytes()
+    }
+}
+
+impl TxSigner for ed25519_dalek::SigningKey {
+    fn public_key(&self) -> PublicKey {
+        PublicKey::Ed25519(self.verifying_key())
+    }
+
+    fn sign_raw(&self, msg: &[u8]) -> Box<[u8]> {
+        Box::new(self.sign(msg).to_bytes())
+    }
+}
+
+fn is_odd(n: &BigUint) -> bool {
+    n.to_bytes_le()[0] & 1 == 1
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyError {
+    /// RSA modulus has fewer bits than `MIN_RSA_MODULUS_BITS`
+    ModulusTooSmall(usize),
+    EvenModulus,
+    /// RSA exponent is even, 1 or not less than the modulus
+    InvalidExponent,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::ModulusTooSmall(bits) => write!(
+                f,
+                "modulus has {} bits, min is {}",
+                bits, MIN_RSA_MODULUS_BITS
+            ),
+            KeyError::EvenModulus => write!(f, "modulus is even"),
+            KeyError::InvalidExponent => write!(f, "exponent is even, 1 or not less than modulus"),
+        }
+    }
+}
+impl std::error::Error for KeyError {}
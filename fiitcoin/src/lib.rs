@@ -1,3 +1,9 @@
 pub mod handler;
+pub mod hash;
+pub mod keys;
+#[cfg(feature = "test-util")]
+pub mod testing;
 pub mod tx;
 pub mod utxo;
+pub mod wallet;
+pub mod wire;
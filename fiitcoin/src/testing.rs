@@ -0,0 +1,179 @@
+//! Helpers for tests of code built on top of this crate, available with the
+//! `test-util` feature.
+//!
+//! ```
+//! use fiitcoin::{
+//!     handler::{balance_of, TxHandler},
+//!     testing::{setup_handler, Participant},
+//! };
+//!
+//! // small keys make tests fast, never use them outside tests
+//! let bob = Participant::with_key_bits(512);
+//! let (handler, root_tx) = setup_handler(&bob, 100, 2);
+//!
+//! assert_eq!(2, root_tx.output_len());
+//! assert_eq!(200, balance_of(handler.pool(), &bob.vk));
+//! ```
+
+use std::cell::Cell;
+
+use rsa::{
+    pkcs1v15::{SigningKey, VerifyingKey},
+    signature::Keypair,
+    RsaPrivateKey,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    handler::{Handler, SignatureCache, TxHandler},
+    keys::PublicKey,
+    tx::{Tx, UnsignedTx},
+    utxo::{UTXOPool, UTXO},
+};
+
+/// Size of keys generated by `Participant::new`
+pub const DEFAULT_KEY_BITS: usize = 1024;
+
+pub struct Participant {
+    pub sk: SigningKey<Sha256>,
+    pub vk: VerifyingKey<Sha256>,
+}
+
+/// Only use in tests! Generated keys are by default of length 1024, not
+/// sufficiently secure by today's standards.
+impl Participant {
+    pub fn new() -> Self {
+        Self::with_key_bits(DEFAULT_KEY_BITS)
+    }
+
+    pub fn with_key_bits(bits: usize) -> Self {
+        let mut rng = rand::thread_rng();
+        let priv_key = RsaPrivateKey::new(&mut rng, bits).expect("failed to generate a key");
+        let sk = SigningKey::<Sha256>::new(priv_key);
+        let vk = sk.verifying_key();
+
+        Self { sk, vk }
+    }
+}
+
+impl Default for Participant {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Creates a pool with one root tx, which has `root_outputs` outputs of
+/// `output_value` owned by the `receiver`
+pub fn setup_pool(receiver: &Participant, output_value: u64, root_outputs: u16) -> (UTXOPool, Tx) {
+    let mut hasher = Sha256::new();
+    hasher.update("genesis-hash");
+    let genesis_hash: [u8; 32] = hasher.finalize().into();
+
+    let mut root_tx = UnsignedTx::new();
+    for _ in 0..root_outputs {
+        root_tx.add_output(output_value, &receiver.vk);
+    }
+    root_tx.add_input(genesis_hash, 0);
+    let root_tx = root_tx.sing_inputs_and_finalize(&receiver.sk).unwrap();
+
+    let utxo_pool = root_tx
+        .outputs()
+        .iter()
+        .enumerate()
+        .map(|(i, output)| {
+            (
+                UTXO::new(root_tx.hash(), i.try_into().unwrap()),
+                output.clone(),
+            )
+        })
+        .collect();
+
+    (utxo_pool, root_tx)
+}
+
+/// Same as `setup_pool`, but the pool is wrapped in a `Handler`
+pub fn setup_handler(
+    receiver: &Participant,
+    output_value: u64,
+    root_outputs: u16,
+) -> (Handler, Tx) {
+    let (utxo_pool, root_tx) = setup_pool(receiver, output_value, root_outputs);
+    (Handler::new(utxo_pool), root_tx)
+}
+
+/// Handler wrapping a `Handler`, which counts verified signatures and
+/// outputs cloned into its pool, useful for checking that a code path skips
+/// verifications or doesn't copy outputs
+#[derive(Clone)]
+pub struct CountingHandler {
+    inner: Handler,
+    verifications: Cell<usize>,
+    output_clones: usize,
+}
+
+impl CountingHandler {
+    pub fn new(inner: Handler) -> Self {
+        Self {
+            inner,
+            verifications: Cell::new(0),
+            output_clones: 0,
+        }
+    }
+
+    /// Number of outputs cloned into the pool by `apply_tx`, outputs moved
+    /// by `apply_owned_tx` aren't counted
+    pub fn output_clones(&self) -> usize {
+        self.output_clones
+    }
+
+    /// Number of signatures verified by this handler, cached ones aren't
+    /// counted
+    pub fn signature_verifications(&self) -> usize {
+        self.verifications.get()
+    }
+}
+
+impl<'a> TxHandler<'a> for CountingHandler {
+    fn handle(&mut self, possible_txs: Vec<&'a Tx>) -> Vec<&'a Tx> {
+        self.handle_in_rounds(possible_txs).0
+    }
+
+    fn pool(&self) -> &UTXOPool {
+        self.inner.pool()
+    }
+
+    fn pool_mut(&mut self) -> &mut UTXOPool {
+        self.inner.pool_mut()
+    }
+
+    fn move_pool(self) -> UTXOPool {
+        self.inner.move_pool()
+    }
+
+    fn allows_zero_output(&self) -> bool {
+        TxHandler::allows_zero_output(&self.inner)
+    }
+
+    fn max_inputs(&self) -> usize {
+        TxHandler::max_inputs(&self.inner)
+    }
+
+    fn signature_cache(&self) -> Option<&SignatureCache> {
+        self.inner.signature_cache()
+    }
+
+    fn verify_signature(
+        &self,
+        key: &PublicKey,
+        msg: &[u8],
+        signature: &[u8],
+    ) -> Result<(), rsa::signature::Error> {
+        self.verifications.set(self.verifications.get() + 1);
+        self.inner.verify_signature(key, msg, signature)
+    }
+
+    fn apply_tx(&mut self, tx: &Tx) {
+        self.output_clones += tx.outputs().iter().filter(|o| !o.is_data()).count();
+        self.inner.apply_tx(tx)
+    }
+}
@@ -1,20 +1,77 @@
 use core::fmt;
 
-use rsa::{
-    pkcs1v15::{SigningKey, VerifyingKey},
-    signature::{SignatureEncoding, Signer},
-    traits::PublicKeyParts,
+use rsa::pkcs1v15::VerifyingKey;
+use sha2::Sha256;
+
+use crate::{
+    hash::{to_hex, Hasher, Sha256Hasher},
+    keys::{PublicKey, TxSigner},
+    wire::{write_key, Reader, WireError, MAX_WIRE_TX_SIZE, WIRE_VERSION},
 };
-use sha2::{Digest, Sha256};
+
+/// Maximum length of a signature in bytes, RSA signatures are as long as the
+/// modulus, so this is enough for keys of up to 4096 bits
+pub const MAX_SIGNATURE_LEN: usize = 512;
+
+/// Maximum number of outputs of a tx, so each can be referenced by `u16` index
+pub const MAX_OUTPUTS: usize = u16::MAX as usize + 1;
+
+/// Maximum number of inputs of a valid tx, inputs are signed by their `u8`
+/// index
+pub const MAX_INPUTS: usize = u8::MAX as usize + 1;
+
+/// Maximum length of data carried by a data output in bytes
+pub const MAX_DATA_LEN: usize = 80;
+
+/// Maximum size of `raw_tx` of a tx in bytes, it fits all `MAX_INPUTS` signed
+/// by the longest signatures together with a few hundred outputs
+pub const MAX_TX_SIZE: usize = 200_000;
+
+/// Which outputs are covered by the signature of an input. The signature
+/// always covers only its own input, so other inputs can be added to the tx
+/// without invalidating it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SigHash {
+    /// All outputs are signed
+    #[default]
+    All,
+    /// No outputs are signed, anyone can change them
+    None,
+    /// Only the output at the same index as the input is signed
+    Single,
+}
+
+impl SigHash {
+    /// Representation of the flag in raw tx bytes
+    pub fn to_byte(self) -> u8 {
+        match self {
+            SigHash::All => 1,
+            SigHash::None => 2,
+            SigHash::Single => 3,
+        }
+    }
+
+    /// Inverse of `to_byte`, `None` for unknown bytes
+    pub fn from_byte(byte: u8) -> Option<SigHash> {
+        match byte {
+            1 => Some(SigHash::All),
+            2 => Some(SigHash::None),
+            3 => Some(SigHash::Single),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Input {
     /// Hash of tx, of which output is transformed into this input
     output_tx_hash: [u8; 32],
     /// Index of the output in tx
-    output_idx: u8,
-    /// Signature created by signing whole current transaction with
-    /// private key corresponding to the output's public key
+    output_idx: u16,
+    /// Which outputs are covered by the signature
+    sighash: SigHash,
+    /// Signature created by signing this input and outputs selected by
+    /// `sighash` with private key corresponding to the output's public key
     signature: Option<Box<[u8]>>,
 }
 
@@ -23,28 +80,96 @@ impl Input {
         self.output_tx_hash
     }
 
-    pub fn output_idx(&self) -> u8 {
+    pub fn output_idx(&self) -> u16 {
         self.output_idx
     }
 
+    fn outpoint(&self) -> ([u8; 32], u16) {
+        (self.output_tx_hash, self.output_idx)
+    }
+
+    pub fn sighash(&self) -> SigHash {
+        self.sighash
+    }
+
+    #[allow(clippy::borrowed_box)]
     pub fn signature(&self) -> Option<&Box<[u8]>> {
         self.signature.as_ref()
     }
+
+    /// Same as `signature`, but returns the bytes of the signature directly
+    pub fn signature_bytes(&self) -> Option<&[u8]> {
+        self.signature.as_deref()
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct Output {
-    value: u32,
-    verifying_key: VerifyingKey<Sha256>,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// Spendable output paying value to a public key
+    Payment(PaymentOutput),
+    /// Unspendable output carrying arbitrary data, e.g. a memo. It has no
+    /// value and is never added to a UTXO pool.
+    Data(DataOutput),
+}
+
+/// Outputs are equal if they pay the same value to the same public key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentOutput {
+    value: u64,
+    public_key: PublicKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataOutput {
+    data: Vec<u8>,
 }
 
 impl Output {
-    pub fn verifying_key(&self) -> &VerifyingKey<Sha256> {
-        &self.verifying_key
+    /// Public key to which the output is locked, `None` for data outputs
+    pub fn public_key(&self) -> Option<&PublicKey> {
+        match self {
+            Output::Payment(payment) => Some(&payment.public_key),
+            Output::Data(_) => None,
+        }
     }
 
-    pub fn value(&self) -> u32 {
-        self.value
+    /// Value of the output, data outputs have zero value
+    pub fn value(&self) -> u64 {
+        match self {
+            Output::Payment(payment) => payment.value,
+            Output::Data(_) => 0,
+        }
+    }
+
+    /// Data carried by the output, `None` for payment outputs
+    pub fn data(&self) -> Option<&[u8]> {
+        match self {
+            Output::Payment(_) => None,
+            Output::Data(data) => Some(&data.data),
+        }
+    }
+
+    pub fn is_data(&self) -> bool {
+        matches!(self, Output::Data(_))
+    }
+
+    /// Representation of the output in raw tx bytes. Payment is its value
+    /// followed by the key, data output is zero value followed by length of
+    /// the data and the data.
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Output::Payment(payment) => {
+                let mut b = payment.value.to_be_bytes().to_vec();
+                b.extend(payment.public_key.to_bytes());
+                b
+            }
+            Output::Data(data) => {
+                let mut b = 0u64.to_be_bytes().to_vec();
+                b.push(data.data.len() as u8);
+                b.extend(data.data.iter());
+                b
+            }
+        }
     }
 }
 
@@ -52,6 +177,8 @@ impl Output {
 pub struct UnsignedTx {
     inputs: Vec<Input>,
     outputs: Vec<Output>,
+    /// Whether inputs are kept sorted by their outpoints
+    canonical: bool,
 }
 
 impl UnsignedTx {
@@ -59,12 +186,26 @@ impl UnsignedTx {
         Self {
             inputs: vec![],
             outputs: vec![],
+            canonical: false,
         }
     }
 
-    pub fn sing_inputs_and_finalize(
+    /// Keeps inputs sorted by the outpoint they spend, first by the hash of
+    /// the tx and then by the output index, so txs spending the same outputs
+    /// have the same hash regardless of the order in which inputs were added.
+    /// Outputs keep their insertion order, because spending inputs and
+    /// `SigHash::Single` reference them by index. Inputs are sorted as soon
+    /// as this is set, so it must be set before any input is signed.
+    pub fn set_canonical(&mut self, canonical: bool) {
+        self.canonical = canonical;
+        if canonical {
+            self.inputs.sort_by_key(Input::outpoint);
+        }
+    }
+
+    pub fn sing_inputs_and_finalize<S: TxSigner + ?Sized>(
         mut self,
-        sender_sk: &SigningKey<Sha256>,
+        sender_sk: &S,
     ) -> Result<Tx, TxError> {
         let mut signatures = vec![];
         for idx in 0..self.inputs.len() {
@@ -74,12 +215,12 @@ impl UnsignedTx {
             };
 
             let raw_tx_one_input = raw_tx_from_one_input(&self.inputs, &self.outputs, idx)?;
-            let signature = sender_sk.sign(&raw_tx_one_input).to_bytes();
+            let signature = sender_sk.sign_raw(&raw_tx_one_input);
             signatures.push(signature);
         }
         let signatures_len = signatures.len();
         for (idx, sig) in signatures.into_iter().enumerate() {
-            match self.inputs.get_mut(usize::from(idx)) {
+            match self.inputs.get_mut(idx) {
                 Some(input) => input.signature = Some(sig),
                 None => return Err(TxError::InputIndexOutOfBounds(idx, signatures_len)),
             }
@@ -88,43 +229,138 @@ impl UnsignedTx {
         self.finalize()
     }
 
+    /// Returns bytes which must be signed by the owner of output referenced by
+    /// input at `idx`, e.g. by an external signer without exposing its private key
+    pub fn input_signing_bytes(&self, idx: u8) -> Result<Vec<u8>, TxError> {
+        raw_tx_from_one_input(&self.inputs, &self.outputs, idx)
+    }
+
+    /// Sets signature of input at `idx`, which was produced outside of this
+    /// tx by signing `input_signing_bytes`
+    pub fn attach_signature(&mut self, idx: u8, signature: Box<[u8]>) -> Result<(), TxError> {
+        let inputs_len = self.inputs.len();
+        match self.inputs.get_mut(usize::from(idx)) {
+            Some(input) => {
+                input.signature = Some(signature);
+                Ok(())
+            }
+            None => Err(TxError::InputIndexOutOfBounds(usize::from(idx), inputs_len)),
+        }
+    }
+
+    /// Estimates the size of `raw_tx` of this tx once it is signed. Unsigned
+    /// inputs are counted with a signature of `MAX_SIGNATURE_LEN`, so the
+    /// estimate is never lower than the final size.
+    pub fn estimated_size(&self) -> usize {
+        let outputs_size: usize = self.outputs.iter().map(|o| o.to_bytes().len()).sum();
+        let inputs_size: usize = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let signature_len = input
+                    .signature
+                    .as_ref()
+                    .map_or(MAX_SIGNATURE_LEN, |sig| sig.len());
+                input.output_tx_hash.len() + size_of::<u16>() + size_of::<u8>() + signature_len
+            })
+            .sum();
+
+        inputs_size + outputs_size
+    }
+
     /// Finalizes this transaction by internally hashing its contents and returning finalized Tx
     pub fn finalize(self) -> Result<Tx, TxError> {
+        self.finalize_with(&Sha256Hasher)
+    }
+
+    /// Same as `finalize`, but contents are hashed by given hasher
+    pub fn finalize_with(self, hasher: &dyn Hasher) -> Result<Tx, TxError> {
         let tx_bytes = raw_tx(&self.inputs, &self.outputs)?;
-        let mut hasher = Sha256::new();
-        hasher.update(tx_bytes);
         Ok(Tx {
-            hash: hasher.finalize().into(),
+            hash: hasher.hash(&tx_bytes),
+            prev_block: None,
             inputs: self.inputs,
             outputs: self.outputs,
         })
     }
 
-    pub fn add_input(&mut self, output_tx_hash: [u8; 32], output_idx: u8) {
-        self.inputs.push(Input {
+    pub fn add_input(&mut self, output_tx_hash: [u8; 32], output_idx: u16) {
+        self.add_input_with_sighash(output_tx_hash, output_idx, SigHash::All)
+    }
+
+    /// Same as `add_input`, but the signature of the input covers only
+    /// outputs selected by `sighash`
+    pub fn add_input_with_sighash(
+        &mut self,
+        output_tx_hash: [u8; 32],
+        output_idx: u16,
+        sighash: SigHash,
+    ) {
+        let input = Input {
             output_tx_hash,
             output_idx,
+            sighash,
             signature: None,
-        })
+        };
+        let idx = if self.canonical {
+            let outpoint = input.outpoint();
+            self.inputs.partition_point(|i| i.outpoint() <= outpoint)
+        } else {
+            self.inputs.len()
+        };
+        self.inputs.insert(idx, input)
     }
 
-    pub fn add_output(&mut self, value: u32, receiver_verifying_key: &VerifyingKey<Sha256>) {
-        self.outputs.push(Output {
+    /// Adds an output and returns its index, which wraps around after
+    /// `MAX_OUTPUTS` outputs, but then `finalize` fails anyway
+    pub fn add_output(&mut self, value: u64, receiver: impl Into<PublicKey>) -> u16 {
+        self.outputs.push(Output::Payment(PaymentOutput {
             value,
-            verifying_key: receiver_verifying_key.clone(),
-        });
+            public_key: receiver.into(),
+        }));
+        self.last_output_idx()
+    }
+
+    /// Adds an unspendable output carrying given data and returns its index,
+    /// fails if the data is longer than `MAX_DATA_LEN`
+    pub fn add_data_output(&mut self, data: impl Into<Vec<u8>>) -> Result<u16, TxError> {
+        let data = data.into();
+        if data.len() > MAX_DATA_LEN {
+            return Err(TxError::DataTooLong(data.len()));
+        }
+        self.outputs.push(Output::Data(DataOutput { data }));
+        Ok(self.last_output_idx())
+    }
+
+    fn last_output_idx(&self) -> u16 {
+        (self.outputs.len() - 1) as u16
+    }
+
+    /// Number of outputs added so far, `finalize` fails with more than
+    /// `MAX_OUTPUTS` outputs
+    pub fn output_count(&self) -> usize {
+        self.outputs.len()
+    }
+}
+
+impl Default for UnsignedTx {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Tx {
     hash: [u8; 32],
+    /// Hash of the parent of a block, in which this coinbase tx is, `None`
+    /// for all other txs
+    prev_block: Option<[u8; 32]>,
     inputs: Vec<Input>,
     outputs: Vec<Output>,
 }
 
 impl Tx {
-    pub fn coinbase(value: u32, address: &VerifyingKey<Sha256>) -> Self {
+    pub fn coinbase(value: u64, address: &VerifyingKey<Sha256>) -> Self {
         let mut unsigned = UnsignedTx::new();
         unsigned.add_output(value, address);
         // the unwrap is safe, because coinbase doesn't have any input,
@@ -132,11 +368,180 @@ impl Tx {
         unsigned.finalize().unwrap()
     }
 
+    /// Creates coinbase tx for a block with given parent. Hash of the parent is
+    /// part of this tx's hash, so coinbases paying same value to the same address
+    /// in different blocks don't share the same hash, and thus the same UTXO.
+    pub fn block_coinbase(value: u64, address: &VerifyingKey<Sha256>, prev: [u8; 32]) -> Self {
+        Self::block_coinbase_with(value, address, prev, &Sha256Hasher)
+    }
+
+    /// Same as `block_coinbase`, but the tx is hashed by given hasher
+    pub fn block_coinbase_with(
+        value: u64,
+        address: &VerifyingKey<Sha256>,
+        prev: [u8; 32],
+        hasher: &dyn Hasher,
+    ) -> Self {
+        Self::block_coinbase_split_with(&[(value, address)], prev, hasher)
+            .expect("coinbase with one output is valid")
+    }
+
+    /// Same as `block_coinbase_with`, but the coinbase has an output for each
+    /// of given values and addresses, in the same order. Fails if there are
+    /// no payments, or the coinbase exceeds `MAX_OUTPUTS` or `MAX_TX_SIZE`.
+    pub fn block_coinbase_split_with(
+        payments: &[(u64, &VerifyingKey<Sha256>)],
+        prev: [u8; 32],
+        hasher: &dyn Hasher,
+    ) -> Result<Self, TxError> {
+        if payments.is_empty() {
+            return Err(TxError::NoOutputs);
+        }
+        let mut unsigned = UnsignedTx::new();
+        for &(value, address) in payments {
+            unsigned.add_output(value, address);
+        }
+        // coinbase doesn't have any input, so no need to sign any
+        let coinbase = unsigned.finalize_with(hasher)?;
+
+        Ok(Self {
+            hash: block_coinbase_hash(prev, coinbase.hash, hasher),
+            prev_block: Some(prev),
+            ..coinbase
+        })
+    }
+
+    /// Encodes this tx for sending it to other nodes. Unlike `raw_tx`, the
+    /// encoding starts with `WIRE_VERSION`, carries the hash and the block
+    /// parent of a coinbase, and every variable length field is prefixed by
+    /// its length, so it can be decoded by `from_wire`.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut b = vec![WIRE_VERSION];
+        b.extend(self.hash);
+        match self.prev_block {
+            Some(prev) => {
+                b.push(1);
+                b.extend(prev);
+            }
+            None => b.push(0),
+        }
+
+        b.extend((self.inputs.len() as u32).to_be_bytes());
+        for input in self.inputs.iter() {
+            b.extend(input.output_tx_hash);
+            b.extend(input.output_idx.to_be_bytes());
+            b.push(input.sighash.to_byte());
+            // finalized txs have all inputs signed by at most
+            // `MAX_SIGNATURE_LEN` bytes
+            let signature = input.signature.as_deref().unwrap_or_default();
+            b.extend((signature.len() as u16).to_be_bytes());
+            b.extend(signature);
+        }
+
+        b.extend((self.outputs.len() as u32).to_be_bytes());
+        for output in self.outputs.iter() {
+            match output {
+                Output::Payment(payment) => {
+                    b.push(PAYMENT_OUTPUT);
+                    b.extend(payment.value.to_be_bytes());
+                    write_key(&mut b, &payment.public_key);
+                }
+                Output::Data(data) => {
+                    b.push(DATA_OUTPUT);
+                    b.push(data.data.len() as u8);
+                    b.extend(data.data.iter());
+                }
+            }
+        }
+        b
+    }
+
+    /// Decodes tx encoded by `to_wire`. Its hash is recomputed from the
+    /// decoded contents and must match the sent one. Like `finalize`, it
+    /// fails on txs larger than `MAX_TX_SIZE`, encodings longer than
+    /// `MAX_WIRE_TX_SIZE` aren't parsed at all.
+    pub fn from_wire(bytes: &[u8]) -> Result<Tx, WireError> {
+        Self::from_wire_with(bytes, &Sha256Hasher)
+    }
+
+    /// Same as `from_wire`, but the hash is recomputed by given hasher
+    pub fn from_wire_with(bytes: &[u8], hasher: &dyn Hasher) -> Result<Tx, WireError> {
+        if bytes.len() > MAX_WIRE_TX_SIZE {
+            return Err(WireError::TooLarge(bytes.len()));
+        }
+        let mut reader = Reader::new(bytes);
+        let version = reader.u8()?;
+        if version != WIRE_VERSION {
+            return Err(WireError::UnsupportedVersion(version));
+        }
+        let hash = reader.hash()?;
+        let prev_block = match reader.flag()? {
+            true => Some(reader.hash()?),
+            false => None,
+        };
+
+        // lengths aren't trusted for preallocation, they are only as long
+        // as the bytes really are
+        let mut inputs = vec![];
+        for _ in 0..reader.u32()? {
+            let output_tx_hash = reader.hash()?;
+            let output_idx = reader.u16()?;
+            let sighash = reader.u8()?;
+            let sighash = SigHash::from_byte(sighash).ok_or(WireError::InvalidByte(sighash))?;
+            let len = reader.u16()?;
+            let signature = reader.bytes(usize::from(len))?;
+            inputs.push(Input {
+                output_tx_hash,
+                output_idx,
+                sighash,
+                signature: (!signature.is_empty()).then(|| signature.into()),
+            });
+        }
+
+        let mut outputs = vec![];
+        for _ in 0..reader.u32()? {
+            let output = match reader.u8()? {
+                PAYMENT_OUTPUT => Output::Payment(PaymentOutput {
+                    value: reader.u64()?,
+                    public_key: reader.key()?,
+                }),
+                DATA_OUTPUT => {
+                    let len = reader.u8()?;
+                    Output::Data(DataOutput {
+                        data: reader.bytes(usize::from(len))?.to_vec(),
+                    })
+                }
+                byte => return Err(WireError::InvalidByte(byte)),
+            };
+            outputs.push(output);
+        }
+        reader.finish()?;
+
+        let tx_hash = hasher.hash(&raw_tx(&inputs, &outputs)?);
+        let expected = match prev_block {
+            Some(prev) => block_coinbase_hash(prev, tx_hash, hasher),
+            None => tx_hash,
+        };
+        if expected != hash {
+            return Err(WireError::HashMismatch);
+        }
+        Ok(Tx {
+            hash,
+            prev_block,
+            inputs,
+            outputs,
+        })
+    }
+
+    pub fn prev_block(&self) -> Option<[u8; 32]> {
+        self.prev_block
+    }
+
     pub fn hash(&self) -> [u8; 32] {
         self.hash
     }
 
-    pub fn output(&self, idx: u8) -> Option<&Output> {
+    pub fn output(&self, idx: u16) -> Option<&Output> {
         self.outputs.get(usize::from(idx))
     }
 
@@ -144,14 +549,38 @@ impl Tx {
         self.outputs.len()
     }
 
+    pub fn input(&self, idx: u8) -> Option<&Input> {
+        self.inputs.get(usize::from(idx))
+    }
+
+    pub fn input_len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Coinbase txs mint new coins, so they are the only txs without inputs
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
     pub fn inputs(&self) -> &Vec<Input> {
         &self.inputs
     }
 
+    /// Returns the size of `raw_tx` of this tx in bytes, also if it would
+    /// be rejected by `raw_tx`, e.g. because of a forced oversized signature
+    pub fn size(&self) -> usize {
+        raw_tx_unchecked(&self.inputs, &self.outputs).len()
+    }
+
     pub fn outputs(&self) -> &Vec<Output> {
         &self.outputs
     }
 
+    /// Consumes this tx and returns its outputs, without cloning them
+    pub fn into_outputs(self) -> Vec<Output> {
+        self.outputs
+    }
+
     /// # DO NOT USE, don't use this function outside tests!
     pub fn force_signature_on_input(&mut self, idx: u8, signature: Box<[u8]>) {
         let input = self.inputs.get_mut(usize::from(idx)).unwrap();
@@ -159,9 +588,49 @@ impl Tx {
     }
 }
 
+/// Summarizes the tx, inputs are shown as shortened outpoints and outputs
+/// with fingerprints of their keys
+impl fmt::Display for Tx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|i| format!("{}:{}", to_hex(&i.output_tx_hash[..4]), i.output_idx))
+            .collect();
+        let outputs: Vec<String> = self
+            .outputs
+            .iter()
+            .map(|o| match o {
+                Output::Payment(p) => format!("{} -> {}", p.value, p.public_key.fingerprint()),
+                Output::Data(d) => format!("data {}", to_hex(&d.data)),
+            })
+            .collect();
+        write!(
+            f,
+            "tx {} | {} inputs [{}] | {} outputs [{}]",
+            to_hex(&self.hash),
+            inputs.len(),
+            inputs.join(", "),
+            outputs.len(),
+            outputs.join(", ")
+        )
+    }
+}
+
+const PAYMENT_OUTPUT: u8 = 0;
+const DATA_OUTPUT: u8 = 1;
+
+/// Hash of a block coinbase is hash of its parent followed by hash of its
+/// contents
+fn block_coinbase_hash(prev: [u8; 32], tx_hash: [u8; 32], hasher: &dyn Hasher) -> [u8; 32] {
+    let mut b = prev.to_vec();
+    b.extend(tx_hash);
+    hasher.hash(&b)
+}
+
 pub fn raw_tx_from_one_input(
-    inputs: &Vec<Input>,
-    outputs: &Vec<Output>,
+    inputs: &[Input],
+    outputs: &[Output],
     idx: u8,
 ) -> Result<Vec<u8>, TxError> {
     let input = match inputs.get(usize::from(idx)) {
@@ -174,47 +643,86 @@ pub fn raw_tx_from_one_input(
         }
     };
 
+    let signed_outputs = match input.sighash {
+        SigHash::All => outputs,
+        SigHash::None => &[],
+        SigHash::Single => match outputs.get(usize::from(idx)) {
+            Some(output) => std::slice::from_ref(output),
+            None => return Err(TxError::NoOutputForSingle(usize::from(idx))),
+        },
+    };
+
     let mut tx = vec![];
     tx.extend(input.output_tx_hash);
-    tx.push(input.output_idx);
+    tx.extend(input.output_idx.to_be_bytes());
+    tx.push(input.sighash.to_byte());
 
-    for output in outputs.iter() {
-        tx.extend(output.value.to_be_bytes());
-        tx.extend(output.verifying_key.as_ref().e().to_bytes_be());
-        tx.extend(output.verifying_key.as_ref().n().to_bytes_be());
+    for output in signed_outputs.iter() {
+        tx.extend(output.to_bytes());
     }
 
     Ok(tx)
 }
 
 /// Returns representation of this transaction in bytes
-pub fn raw_tx(inputs: &Vec<Input>, outputs: &Vec<Output>) -> Result<Vec<u8>, TxError> {
-    let mut tx = vec![];
-
+pub fn raw_tx(inputs: &[Input], outputs: &[Output]) -> Result<Vec<u8>, TxError> {
+    if outputs.len() > MAX_OUTPUTS {
+        return Err(TxError::TooManyOutputs(outputs.len()));
+    }
     for input in inputs.iter() {
         match &input.signature {
-            Some(sig) => {
-                tx.extend(input.output_tx_hash);
-                tx.push(input.output_idx);
-                tx.extend(sig.iter());
+            Some(sig) if sig.len() > MAX_SIGNATURE_LEN => {
+                return Err(TxError::SignatureTooLong(sig.len()))
             }
+            Some(_) => {}
             None => return Err(TxError::UnsignedInput(input.clone())),
         }
     }
     for output in outputs.iter() {
-        tx.extend(output.value.to_be_bytes());
-        tx.extend(output.verifying_key.as_ref().e().to_bytes_be());
-        tx.extend(output.verifying_key.as_ref().n().to_bytes_be());
+        if let Some(data) = output.data().filter(|data| data.len() > MAX_DATA_LEN) {
+            return Err(TxError::DataTooLong(data.len()));
+        }
     }
 
+    let tx = raw_tx_unchecked(inputs, outputs);
+    if tx.len() > MAX_TX_SIZE {
+        return Err(TxError::TooLarge(tx.len()));
+    }
     Ok(tx)
 }
 
+/// Same as `raw_tx`, but without any checks, missing signatures are skipped
+fn raw_tx_unchecked(inputs: &[Input], outputs: &[Output]) -> Vec<u8> {
+    let mut tx = vec![];
+    for input in inputs.iter() {
+        tx.extend(input.output_tx_hash);
+        tx.extend(input.output_idx.to_be_bytes());
+        tx.push(input.sighash.to_byte());
+        if let Some(sig) = &input.signature {
+            tx.extend(sig.iter());
+        }
+    }
+    for output in outputs.iter() {
+        tx.extend(output.to_bytes());
+    }
+    tx
+}
+
 #[derive(Debug)]
 pub enum TxError {
     UnsignedInput(Input),
     InputIndexOutOfBounds(usize, usize),
     DownCastFromUsize(usize),
+    SignatureTooLong(usize),
+    TooManyOutputs(usize),
+    /// Input at the index is signed with `SigHash::Single`, but there is no
+    /// output at the same index
+    NoOutputForSingle(usize),
+    DataTooLong(usize),
+    /// Size of `raw_tx` exceeds `MAX_TX_SIZE`
+    TooLarge(usize),
+    /// Coinbase was created without any payment
+    NoOutputs,
 }
 
 impl fmt::Display for TxError {
@@ -225,6 +733,24 @@ impl fmt::Display for TxError {
                 write!(f, "tried to access idx {}, max is {}", idx, max)
             }
             TxError::DownCastFromUsize(u) => write!(f, "failed to downcast usize {} to u8", u),
+            TxError::SignatureTooLong(len) => write!(
+                f,
+                "signature has {} bytes, max is {}",
+                len, MAX_SIGNATURE_LEN
+            ),
+            TxError::TooManyOutputs(len) => {
+                write!(f, "tx has {} outputs, max is {}", len, MAX_OUTPUTS)
+            }
+            TxError::NoOutputForSingle(idx) => {
+                write!(f, "input {} signs single output, but it doesn't exist", idx)
+            }
+            TxError::DataTooLong(len) => {
+                write!(f, "data output has {} bytes, max is {}", len, MAX_DATA_LEN)
+            }
+            TxError::TooLarge(len) => {
+                write!(f, "tx has {} bytes, max is {}", len, MAX_TX_SIZE)
+            }
+            TxError::NoOutputs => write!(f, "coinbase has no outputs"),
         }
     }
 }
@@ -1,30 +1,62 @@
 use std::collections::HashMap;
 
-use rsa::RsaPublicKey;
-
-use crate::tx::Output;
+use crate::{keys::PublicKey, tx::Output};
 
 #[derive(Eq, PartialEq, Hash, Clone, Debug)]
 pub struct UTXO {
     /// hash of tx from which this utxo comes from
     tx_hash: [u8; 32],
     /// index at which this utxo is in tx
-    output_idx: u8,
+    output_idx: u16,
 }
 
 impl UTXO {
-    pub fn new(tx_hash: [u8; 32], output_idx: u8) -> Self {
+    pub fn new(tx_hash: [u8; 32], output_idx: u16) -> Self {
         Self {
             tx_hash,
             output_idx,
         }
     }
+
+    pub fn tx_hash(&self) -> [u8; 32] {
+        self.tx_hash
+    }
+
+    pub fn output_idx(&self) -> u16 {
+        self.output_idx
+    }
+}
+
+/// Output kept in a pool together with the fingerprint of its key, so
+/// lookups by key compare full keys only when fingerprints match
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct PooledOutput {
+    /// `None` for outputs without a key
+    key_fingerprint: Option<[u8; 8]>,
+    output: Output,
+}
+
+impl From<Output> for PooledOutput {
+    fn from(output: Output) -> Self {
+        Self {
+            key_fingerprint: output.public_key().map(PublicKey::fingerprint_bytes),
+            output,
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+impl PooledOutput {
+    fn is_owned_by(&self, pub_key: &PublicKey, fingerprint: &[u8; 8]) -> bool {
+        self.key_fingerprint.as_ref() == Some(fingerprint)
+            && self.output.public_key() == Some(pub_key)
+    }
+}
+
+/// Pools are equal when they contain the same UTXOs with equal outputs
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct UTXOPool {
     /// collection of unspent UTXO mapped to corresponding tx output
-    utxos: HashMap<UTXO, Output>,
+    utxos: HashMap<UTXO, PooledOutput>,
 }
 
 impl UTXOPool {
@@ -35,7 +67,7 @@ impl UTXOPool {
     }
 
     pub fn add_utxo(&mut self, utxo: UTXO, output: &Output) {
-        self.utxos.insert(utxo, output.clone());
+        self.utxos.insert(utxo, output.clone().into());
     }
 
     pub fn remove_utxo(&mut self, utxo: &UTXO) {
@@ -43,17 +75,68 @@ impl UTXOPool {
     }
 
     pub fn utxo_output(&self, utxo: &UTXO) -> Option<&Output> {
-        self.utxos.get(utxo)
+        self.utxos.get(utxo).map(|pooled| &pooled.output)
+    }
+
+    /// Looks up outputs of all given UTXOs, results are in the same order
+    pub fn get_many<'a>(&self, utxos: impl IntoIterator<Item = &'a UTXO>) -> Vec<Option<&Output>> {
+        utxos
+            .into_iter()
+            .map(|utxo| self.utxo_output(utxo))
+            .collect()
     }
 
     pub fn contains(&self, utxo: &UTXO) -> bool {
         self.utxos.contains_key(utxo)
     }
 
-    pub fn utxos_of(&self, pub_key: &RsaPublicKey) -> Vec<&Output> {
+    pub fn outputs(&self) -> Vec<&Output> {
+        self.utxos.values().map(|pooled| &pooled.output).collect()
+    }
+
+    /// Returns unspent outputs owned by the key, together with their UTXOs
+    pub fn owned_by(&self, pub_key: impl Into<PublicKey>) -> Vec<(&UTXO, &Output)> {
+        let pub_key = pub_key.into();
+        let fingerprint = pub_key.fingerprint_bytes();
+        self.utxos
+            .iter()
+            .filter(|(_, pooled)| pooled.is_owned_by(&pub_key, &fingerprint))
+            .map(|(utxo, pooled)| (utxo, &pooled.output))
+            .collect()
+    }
+
+    pub fn utxos_of(&self, pub_key: impl Into<PublicKey>) -> Vec<&Output> {
+        let pub_key = pub_key.into();
+        let fingerprint = pub_key.fingerprint_bytes();
         self.utxos
             .values()
-            .filter(|o| o.verifying_key().as_ref() == pub_key)
+            .filter(|pooled| pooled.is_owned_by(&pub_key, &fingerprint))
+            .map(|pooled| &pooled.output)
             .collect()
     }
 }
+
+impl Default for UTXOPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromIterator<(UTXO, Output)> for UTXOPool {
+    fn from_iter<I: IntoIterator<Item = (UTXO, Output)>>(iter: I) -> Self {
+        Self {
+            utxos: iter
+                .into_iter()
+                .map(|(utxo, output)| (utxo, output.into()))
+                .collect(),
+        }
+    }
+}
+
+/// Adds all given UTXOs, outputs of already present UTXOs are replaced
+impl Extend<(UTXO, Output)> for UTXOPool {
+    fn extend<I: IntoIterator<Item = (UTXO, Output)>>(&mut self, iter: I) {
+        self.utxos
+            .extend(iter.into_iter().map(|(utxo, output)| (utxo, output.into())))
+    }
+}
@@ -0,0 +1,143 @@
+use core::fmt;
+
+use rsa::{
+    pkcs1v15::{SigningKey, VerifyingKey},
+    signature::Keypair,
+    RsaPrivateKey,
+};
+use sha2::Sha256;
+
+use crate::{
+    handler::balance_of,
+    tx::{Tx, TxError, UnsignedTx},
+    utxo::UTXOPool,
+};
+
+/// Size of keys generated by `Wallet::new`
+pub const KEY_BITS: usize = 2048;
+
+/// Owner of a key pair, which can create signed txs spending its outputs
+pub struct Wallet {
+    sk: SigningKey<Sha256>,
+    vk: VerifyingKey<Sha256>,
+    /// Change smaller than this is left to the miner as fee instead of
+    /// creating a dust output
+    dust_limit: u64,
+}
+
+impl Wallet {
+    /// Generates a new wallet with random `KEY_BITS` long key
+    pub fn new() -> Self {
+        let mut rng = rand::thread_rng();
+        let priv_key = RsaPrivateKey::new(&mut rng, KEY_BITS).expect("failed to generate a key");
+        Self::from_signing_key(SigningKey::<Sha256>::new(priv_key))
+    }
+
+    pub fn from_signing_key(sk: SigningKey<Sha256>) -> Self {
+        let vk = sk.verifying_key();
+        Self {
+            sk,
+            vk,
+            dust_limit: 0,
+        }
+    }
+
+    /// Sets the smallest change returned to this wallet by `send`, smaller
+    /// change is added to the fee. By default any non-zero change is returned.
+    pub fn set_dust_limit(&mut self, dust_limit: u64) {
+        self.dust_limit = dust_limit;
+    }
+
+    pub fn verifying_key(&self) -> &VerifyingKey<Sha256> {
+        &self.vk
+    }
+
+    pub fn balance(&self, pool: &UTXOPool) -> u64 {
+        balance_of(pool, &self.vk)
+    }
+
+    /// Creates a signed tx paying `amount` to `to` and `fee` to the miner.
+    /// Largest outputs of this wallet are spent first and the change is
+    /// returned to this wallet, unless it is below the dust limit.
+    pub fn send(
+        &self,
+        pool: &UTXOPool,
+        to: &VerifyingKey<Sha256>,
+        amount: u64,
+        fee: u64,
+    ) -> Result<Tx, WalletError> {
+        let required = amount.checked_add(fee).ok_or(WalletError::ValueOverflow)?;
+
+        let mut owned = pool.owned_by(&self.vk);
+        owned.sort_by(|a, b| {
+            b.1.value()
+                .cmp(&a.1.value())
+                .then_with(|| a.0.tx_hash().cmp(&b.0.tx_hash()))
+                .then_with(|| a.0.output_idx().cmp(&b.0.output_idx()))
+        });
+
+        let mut tx = UnsignedTx::new();
+        let mut selected: u64 = 0;
+        for (utxo, output) in owned.iter() {
+            if selected >= required {
+                break;
+            }
+            tx.add_input(utxo.tx_hash(), utxo.output_idx());
+            selected = selected
+                .checked_add(output.value())
+                .ok_or(WalletError::ValueOverflow)?;
+        }
+        if selected < required {
+            return Err(WalletError::InsufficientFunds {
+                available: selected,
+                required,
+            });
+        }
+
+        tx.add_output(amount, to);
+        // zero change never creates an output, dust change is added to the fee
+        let change = selected - required;
+        if change > 0 && change >= self.dust_limit {
+            tx.add_output(change, &self.vk);
+        }
+
+        tx.sing_inputs_and_finalize(&self.sk)
+            .map_err(WalletError::Tx)
+    }
+}
+
+impl Default for Wallet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub enum WalletError {
+    InsufficientFunds {
+        available: u64,
+        required: u64,
+    },
+    /// Sum of the amount and fee, or of the selected outputs, exceeds
+    /// `u64::MAX`
+    ValueOverflow,
+    Tx(TxError),
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletError::InsufficientFunds {
+                available,
+                required,
+            } => write!(
+                f,
+                "insufficient funds, available {}, required {}",
+                available, required
+            ),
+            WalletError::ValueOverflow => write!(f, "sum of values overflows u64"),
+            WalletError::Tx(err) => write!(f, "failed to create tx, {}", err),
+        }
+    }
+}
+impl std::error::Error for WalletError {}
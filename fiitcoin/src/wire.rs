@@ -0,0 +1,171 @@
+use core::fmt;
+
+use rsa::{pkcs1v15::VerifyingKey, traits::PublicKeyParts, BigUint, RsaPublicKey};
+
+use crate::{
+    keys::PublicKey,
+    tx::{TxError, MAX_TX_SIZE},
+};
+
+/// Version of the wire encoding, it is the first byte of every encoded tx
+pub const WIRE_VERSION: u8 = 1;
+
+/// Max length of an encoded tx accepted by `Tx::from_wire`. Each input and
+/// output takes at most twice as many bytes as in a raw tx, which has at
+/// most `MAX_TX_SIZE` bytes, the rest are the version, hash, coinbase parent
+/// and counts of inputs and outputs.
+pub const MAX_WIRE_TX_SIZE: usize = 2 * MAX_TX_SIZE + 1 + 32 + 1 + 32 + 4 + 4;
+
+const RSA_KEY: u8 = 0;
+const ED25519_KEY: u8 = 1;
+
+/// Writes the key as its scheme followed by its contents, RSA exponent and
+/// modulus are prefixed by their lengths
+pub(crate) fn write_key(bytes: &mut Vec<u8>, key: &PublicKey) {
+    match key {
+        PublicKey::Rsa(vk) => {
+            bytes.push(RSA_KEY);
+            for part in [vk.as_ref().e(), vk.as_ref().n()] {
+                let part = part.to_bytes_be();
+                bytes.extend((part.len() as u16).to_be_bytes());
+                bytes.extend(part);
+            }
+        }
+        PublicKey::Ed25519(vk) => {
+            bytes.push(ED25519_KEY);
+            bytes.extend(vk.to_bytes());
+        }
+    }
+}
+
+/// Reads values written in big endian, fails instead of panicking when
+/// there aren't enough bytes
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], WireError> {
+        if self.bytes.len() < len {
+            return Err(WireError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const L: usize>(&mut self) -> Result<[u8; L], WireError> {
+        Ok(self.bytes(L)?.try_into().unwrap())
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, WireError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, WireError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, WireError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn hash(&mut self) -> Result<[u8; 32], WireError> {
+        self.array()
+    }
+
+    /// Reads a flag written as 0 or 1
+    pub(crate) fn flag(&mut self) -> Result<bool, WireError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(WireError::InvalidByte(byte)),
+        }
+    }
+
+    pub(crate) fn key(&mut self) -> Result<PublicKey, WireError> {
+        match self.u8()? {
+            RSA_KEY => {
+                let len = self.u16()?;
+                let e = BigUint::from_bytes_be(self.bytes(usize::from(len))?);
+                let len = self.u16()?;
+                let n = BigUint::from_bytes_be(self.bytes(usize::from(len))?);
+                let key = RsaPublicKey::new(n, e).map_err(|_| WireError::InvalidKey)?;
+                Ok(PublicKey::Rsa(VerifyingKey::new(key)))
+            }
+            ED25519_KEY => {
+                let key = ed25519_dalek::VerifyingKey::from_bytes(&self.array()?)
+                    .map_err(|_| WireError::InvalidKey)?;
+                Ok(PublicKey::Ed25519(key))
+            }
+            byte => Err(WireError::InvalidByte(byte)),
+        }
+    }
+
+    /// Fails if there are bytes left
+    pub(crate) fn finish(self) -> Result<(), WireError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            left => Err(WireError::TrailingBytes(left)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    UnexpectedEnd,
+    UnsupportedVersion(u8),
+    /// Byte of a flag, key scheme or output kind has unknown value
+    InvalidByte(u8),
+    /// Key bytes don't form a valid key of its scheme
+    InvalidKey,
+    /// Decoded contents don't form a valid tx, e.g. an input is unsigned
+    Tx(TxError),
+    /// Hash recomputed from the decoded contents differs from the sent one
+    HashMismatch,
+    TrailingBytes(usize),
+    /// Encoding is longer than `MAX_WIRE_TX_SIZE`, so it isn't parsed
+    TooLarge(usize),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::UnexpectedEnd => write!(f, "bytes ended before tx"),
+            WireError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "unsupported version {}, expected {}",
+                    version, WIRE_VERSION
+                )
+            }
+            WireError::InvalidByte(byte) => write!(f, "invalid byte {} in tx", byte),
+            WireError::InvalidKey => write!(f, "invalid public key in tx"),
+            WireError::Tx(err) => write!(f, "invalid tx, {}", err),
+            WireError::HashMismatch => write!(f, "hash doesn't match tx contents"),
+            WireError::TrailingBytes(len) => write!(f, "{} bytes left after tx", len),
+            WireError::TooLarge(len) => {
+                write!(
+                    f,
+                    "encoded tx has {} bytes, max is {}",
+                    len, MAX_WIRE_TX_SIZE
+                )
+            }
+        }
+    }
+}
+impl std::error::Error for WireError {}
+
+impl From<TxError> for WireError {
+    fn from(err: TxError) -> Self {
+        WireError::Tx(err)
+    }
+}
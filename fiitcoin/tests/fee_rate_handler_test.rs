@@ -0,0 +1,96 @@
+use common::{new_tx, NewTxParams, OUTPUT_VALUE};
+use fiitcoin::handler::{balance_of, FeeRateHandler, TxHandler};
+use fiitcoin::testing::{setup_pool, Participant};
+
+mod common;
+
+#[test]
+fn smaller_tx_with_same_fee_is_preferred() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 1);
+    let mut handler = FeeRateHandler::new(utxo_pool);
+
+    // both pay fee 10 and spend the same output
+    let tx_big = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, 30), (&alice, 30), (&alice, 30)],
+        return_to_sender: None,
+    });
+    let tx_small = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, 90)],
+        return_to_sender: None,
+    });
+    assert!(tx_small.size() < tx_big.size());
+
+    let txs = handler.handle(vec![&tx_big, &tx_small]);
+    assert_eq!(1, txs.len());
+    assert_eq!(tx_small.hash(), txs[0].hash());
+    assert_eq!(90, balance_of(handler.pool(), alice.vk.as_ref()));
+}
+
+#[test]
+fn higher_fee_rate_wins_over_higher_fee() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 1);
+    let mut handler = FeeRateHandler::new(utxo_pool);
+
+    // fee 11, but much larger than the other one
+    let tx_big = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, 30), (&alice, 30), (&alice, 29)],
+        return_to_sender: None,
+    });
+    // fee 10
+    let tx_small = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, 90)],
+        return_to_sender: None,
+    });
+
+    let txs = handler.handle(vec![&tx_big, &tx_small]);
+    assert_eq!(1, txs.len());
+    assert_eq!(tx_small.hash(), txs[0].hash());
+}
+
+#[test]
+fn oversized_signature_is_rejected_without_panic() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let mut handler = FeeRateHandler::new(utxo_pool);
+
+    let mut oversized = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, 90)],
+        return_to_sender: None,
+    });
+    oversized.force_signature_on_input(0, vec![1; 10 * 1024].into_boxed_slice());
+    assert!(oversized.size() > 10 * 1024);
+    let valid = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 1)],
+        outputs: &[(&alice, 90)],
+        return_to_sender: None,
+    });
+
+    let txs = handler.handle(vec![&oversized, &valid]);
+    assert_eq!(1, txs.len());
+    assert_eq!(valid.hash(), txs[0].hash());
+}
@@ -1,6 +1,10 @@
-use crate::common::{new_tx, setup_pool, NewTxParams, Participant, OUTPUT_VALUE};
-use fiitcoin::handler::{balance_of, Handler, TxHandler};
-use fiitcoin::tx::UnsignedTx;
+use crate::common::{new_tx, NewTxParams, OUTPUT_VALUE};
+use fiitcoin::handler::{
+    balance_of, conserves_value, FeeRateHandler, Handler, MaxFeeHandler, OwnedTxHandler,
+    SignatureCache, TxEffect, TxHandler,
+};
+use fiitcoin::testing::{setup_pool, CountingHandler, Participant};
+use fiitcoin::tx::{Output, UnsignedTx};
 use fiitcoin::utxo::{UTXOPool, UTXO};
 use rsa::signature::{SignatureEncoding, Signer};
 use sha2::{Digest, Sha256};
@@ -27,7 +31,7 @@ fn transaction_is_valid_and_possible() {
     let mut utxo_pool = UTXOPool::new();
     let root_utxo = UTXO::new(root_bob_tx.hash(), 0);
     let output = root_bob_tx.output(0).unwrap();
-    utxo_pool.add_utxo(root_utxo, &output);
+    utxo_pool.add_utxo(root_utxo, output);
 
     let mut tx_to_alice = UnsignedTx::new();
     tx_to_alice.add_input(root_bob_tx.hash(), 0);
@@ -99,10 +103,7 @@ fn outputs_greater_than_inputs() {
     assert_eq!(0, txs.len());
 
     assert_eq!(0, balance_of(handler.pool(), alice.vk.as_ref()));
-    assert_eq!(
-        OUTPUT_VALUE as u64,
-        balance_of(handler.pool(), bob.vk.as_ref())
-    );
+    assert_eq!(OUTPUT_VALUE, balance_of(handler.pool(), bob.vk.as_ref()));
 }
 
 #[test]
@@ -229,10 +230,7 @@ fn non_existent_utxo_as_input() {
     assert_eq!(0, txs.len());
 
     assert_eq!(0, balance_of(handler.pool(), alice.vk.as_ref()));
-    assert_eq!(
-        OUTPUT_VALUE as u64,
-        balance_of(handler.pool(), bob.vk.as_ref())
-    );
+    assert_eq!(OUTPUT_VALUE, balance_of(handler.pool(), bob.vk.as_ref()));
 }
 
 #[test]
@@ -316,14 +314,8 @@ fn multiple_rounds() {
     });
     let mut txs = handler.handle(vec![&tx1]);
     assert_eq!(1, txs.len());
-    assert_eq!(
-        OUTPUT_VALUE as u64,
-        balance_of(handler.pool(), alice.vk.as_ref())
-    );
-    assert_eq!(
-        OUTPUT_VALUE as u64,
-        balance_of(handler.pool(), bob.vk.as_ref())
-    );
+    assert_eq!(OUTPUT_VALUE, balance_of(handler.pool(), alice.vk.as_ref()));
+    assert_eq!(OUTPUT_VALUE, balance_of(handler.pool(), bob.vk.as_ref()));
 
     let tx2 = new_tx(NewTxParams {
         sender: &alice,
@@ -335,7 +327,490 @@ fn multiple_rounds() {
     assert_eq!(1, txs.len());
     assert_eq!(25 + 25 + 20, balance_of(handler.pool(), alice.vk.as_ref()));
     assert_eq!(
-        OUTPUT_VALUE as u64 + 30,
+        OUTPUT_VALUE + 30,
         balance_of(handler.pool(), bob.vk.as_ref())
     );
 }
+
+#[test]
+fn owned_handling_matches_reference_handling() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let mut ref_handler = Handler::new(utxo_pool.clone());
+    let mut owned_handler = Handler::new(utxo_pool);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, 50)],
+        return_to_sender: Some(50),
+    });
+    let tx2_from_tx1 = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx1, 0)],
+        outputs: &[(&charlie, 40)],
+        return_to_sender: None,
+    });
+    let tx3_doublespend = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&charlie, 100)],
+        return_to_sender: None,
+    });
+    let tx4_invalid = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 1)],
+        outputs: &[(&alice, OUTPUT_VALUE + 1)],
+        return_to_sender: None,
+    });
+
+    let txs = vec![tx2_from_tx1, tx1, tx3_doublespend, tx4_invalid];
+    let accepted_refs: Vec<[u8; 32]> = ref_handler
+        .handle(txs.iter().collect())
+        .iter()
+        .map(|tx| tx.hash())
+        .collect();
+
+    let (accepted, rejected) = owned_handler.handle_owned(txs.clone());
+    let accepted_owned: Vec<[u8; 32]> = accepted.iter().map(|tx| tx.hash()).collect();
+    assert_eq!(accepted_refs, accepted_owned);
+    assert_eq!(2, accepted.len());
+    assert_eq!(2, rejected.len());
+    assert_eq!(txs[2].hash(), rejected[0].hash());
+    assert_eq!(txs[3].hash(), rejected[1].hash());
+
+    for participant in [&bob, &alice, &charlie] {
+        assert_eq!(
+            balance_of(ref_handler.pool(), participant.vk.as_ref()),
+            balance_of(owned_handler.pool(), participant.vk.as_ref())
+        );
+    }
+}
+
+#[test]
+fn handle_consumed_moves_outputs() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 1);
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, 60)],
+        return_to_sender: Some(40),
+    });
+    let tx2_from_tx1 = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx1, 0)],
+        outputs: &[(&bob, 20), (&bob, 20), (&bob, 20)],
+        return_to_sender: None,
+    });
+    let txs = vec![tx2_from_tx1, tx1];
+    let hashes: Vec<[u8; 32]> = txs.iter().rev().map(|tx| tx.hash()).collect();
+
+    let mut ref_handler = CountingHandler::new(Handler::new(utxo_pool.clone()));
+    assert_eq!(2, ref_handler.handle(txs.iter().collect()).len());
+    assert_eq!(5, ref_handler.output_clones());
+
+    let mut consuming_handler = CountingHandler::new(Handler::new(utxo_pool));
+    assert_eq!(hashes, consuming_handler.handle_consumed(txs));
+    assert_eq!(0, consuming_handler.output_clones());
+
+    assert_eq!(100, balance_of(consuming_handler.pool(), bob.vk.as_ref()));
+    assert_eq!(0, balance_of(consuming_handler.pool(), alice.vk.as_ref()));
+}
+
+#[test]
+fn dry_run_leaves_pool_untouched() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let mut handler = Handler::new(utxo_pool);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, 60)],
+        return_to_sender: Some(40),
+    });
+    let tx2_doublespend = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, 100)],
+        return_to_sender: None,
+    });
+
+    let (accepted, pool) = handler.dry_run(vec![&tx1, &tx2_doublespend]);
+    assert_eq!(1, accepted.len());
+    assert_eq!(tx1.hash(), accepted[0].hash());
+    assert_eq!(60, balance_of(&pool, &alice.vk));
+    assert_eq!(OUTPUT_VALUE + 40, balance_of(&pool, &bob.vk));
+
+    assert_eq!(0, balance_of(handler.pool(), &alice.vk));
+    assert_eq!(2 * OUTPUT_VALUE, balance_of(handler.pool(), &bob.vk));
+    assert!(handler.pool().contains(&UTXO::new(root_tx.hash(), 0)));
+
+    let accepted_for_real = handler.handle(vec![&tx1, &tx2_doublespend]);
+    assert_eq!(accepted.len(), accepted_for_real.len());
+    assert_eq!(
+        balance_of(&pool, &alice.vk),
+        balance_of(handler.pool(), &alice.vk)
+    );
+}
+
+#[test]
+fn zero_output_tx_policy() {
+    common::initialize();
+
+    let bob = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 1);
+    let burn_tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[],
+        return_to_sender: None,
+    });
+    let empty_tx = UnsignedTx::new().finalize().unwrap();
+
+    let mut handler = Handler::new(utxo_pool.clone());
+    assert!(handler.allows_zero_output());
+    assert!(!handler.is_tx_valid(&empty_tx));
+    assert_eq!(1, handler.handle(vec![&burn_tx, &empty_tx]).len());
+    assert_eq!(0, balance_of(handler.pool(), &bob.vk));
+
+    let mut handler = Handler::new(utxo_pool);
+    handler.set_allow_zero_output(false);
+    assert!(!handler.is_tx_valid(&burn_tx));
+    assert!(handler.handle(vec![&burn_tx]).is_empty());
+    assert_eq!(OUTPUT_VALUE, balance_of(handler.pool(), &bob.vk));
+}
+
+#[test]
+fn missing_parent_doesnt_stall_handling() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let mut handler = Handler::new(utxo_pool);
+
+    let tx1 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+    // parent of tx3 is never submitted
+    let tx2_never_submitted = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 1)],
+        outputs: &[(&alice, OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+    let tx3_orphan = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&tx2_never_submitted, 0)],
+        outputs: &[(&bob, OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+    let tx4_from_orphan = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&tx3_orphan, 0)],
+        outputs: &[(&alice, OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+
+    let (handled, stuck) = handler.handle_in_rounds(vec![&tx4_from_orphan, &tx3_orphan, &tx1]);
+    assert_eq!(
+        vec![tx1.hash()],
+        handled.iter().map(|tx| tx.hash()).collect::<Vec<_>>()
+    );
+    assert!(stuck.is_empty());
+    assert_eq!(OUTPUT_VALUE, balance_of(handler.pool(), alice.vk.as_ref()));
+}
+
+#[test]
+fn value_conservation_of_spent_outputs() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (_, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let spent: Vec<&Output> = root_tx.outputs().iter().collect();
+
+    let spend = |value: u64| {
+        let mut tx = UnsignedTx::new();
+        tx.add_input(root_tx.hash(), 0);
+        tx.add_input(root_tx.hash(), 1);
+        tx.add_output(value, &alice.vk);
+        tx.sing_inputs_and_finalize(&bob.sk).unwrap()
+    };
+
+    let balanced = spend(2 * OUTPUT_VALUE);
+    assert!(conserves_value(&balanced, &spent));
+    let with_fee = spend(2 * OUTPUT_VALUE - 1);
+    assert!(conserves_value(&with_fee, &spent));
+    let inflating = spend(2 * OUTPUT_VALUE + 1);
+    assert!(!conserves_value(&inflating, &spent));
+    assert!(!conserves_value(&balanced, &spent[..1]));
+}
+
+#[test]
+fn handle_ordered_applies_parent_before_child() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let mut handler = Handler::new(utxo_pool);
+
+    let parent = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+    let child = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&parent, 0)],
+        outputs: &[(&bob, OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+    let unrelated = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 1)],
+        outputs: &[(&alice, OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+
+    let ordered = handler.handle_ordered(vec![&child, &unrelated, &parent]);
+    assert_eq!(3, ordered.len());
+    let seq_of = |tx: &fiitcoin::tx::Tx| {
+        ordered
+            .iter()
+            .find(|(handled, _)| handled.hash() == tx.hash())
+            .map(|(_, seq)| *seq)
+            .unwrap()
+    };
+    assert!(seq_of(&parent) < seq_of(&child));
+
+    let mut seqs: Vec<usize> = ordered.iter().map(|(_, seq)| *seq).collect();
+    seqs.sort();
+    assert_eq!(vec![0, 1, 2], seqs);
+}
+
+/// Child pays a higher fee than its parent, so fee based handlers try it
+/// first, but it can only be applied after the parent
+fn assert_handled_in_applied_order(
+    new_handler: impl Fn(UTXOPool) -> Box<dyn for<'a> TxHandler<'a>>,
+) {
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let mut handler = new_handler(utxo_pool);
+
+    let parent = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, OUTPUT_VALUE - 1)],
+        return_to_sender: None,
+    });
+    let child = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&parent, 0)],
+        outputs: &[(&bob, OUTPUT_VALUE / 2)],
+        return_to_sender: None,
+    });
+    let unrelated = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 1)],
+        outputs: &[(&alice, OUTPUT_VALUE - 2)],
+        return_to_sender: None,
+    });
+
+    let ordered = handler.handle_ordered(vec![&child, &unrelated, &parent]);
+    let hashes: Vec<[u8; 32]> = ordered.iter().map(|(tx, _)| tx.hash()).collect();
+    let position = |tx: &fiitcoin::tx::Tx| hashes.iter().position(|&h| h == tx.hash()).unwrap();
+    assert_eq!(3, hashes.len());
+    assert!(position(&parent) < position(&child));
+    for (i, (_, seq)) in ordered.iter().enumerate() {
+        assert_eq!(i, *seq);
+    }
+}
+
+#[test]
+fn every_handler_returns_txs_in_applied_order() {
+    common::initialize();
+
+    assert_handled_in_applied_order(|pool| Box::new(Handler::new(pool)));
+    assert_handled_in_applied_order(|pool| Box::new(MaxFeeHandler::new(pool)));
+    assert_handled_in_applied_order(|pool| Box::new(FeeRateHandler::new(pool)));
+    assert_handled_in_applied_order(|pool| Box::new(CountingHandler::new(Handler::new(pool))));
+}
+
+#[test]
+fn handle_with_effects_lists_spent_and_created_utxos() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let mut handler = Handler::new(utxo_pool);
+
+    let parent = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0), (&root_tx, 1)],
+        outputs: &[(&alice, OUTPUT_VALUE)],
+        return_to_sender: Some(OUTPUT_VALUE),
+    });
+    let child = new_tx(NewTxParams {
+        sender: &alice,
+        inputs: &[(&parent, 0)],
+        outputs: &[(&bob, OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+
+    let effects = handler.handle_with_effects(vec![&child, &parent]);
+    assert_eq!(
+        vec![
+            TxEffect {
+                tx_hash: parent.hash(),
+                spent: vec![UTXO::new(root_tx.hash(), 0), UTXO::new(root_tx.hash(), 1)],
+                created: vec![UTXO::new(parent.hash(), 0), UTXO::new(parent.hash(), 1)],
+            },
+            TxEffect {
+                tx_hash: child.hash(),
+                spent: vec![UTXO::new(parent.hash(), 0)],
+                created: vec![UTXO::new(child.hash(), 0)],
+            },
+        ],
+        effects
+    );
+}
+
+#[test]
+fn cached_signatures_verified_once() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0), (&root_tx, 1)],
+        outputs: &[(&alice, 2 * OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+
+    let uncached = CountingHandler::new(Handler::new(utxo_pool.clone()));
+    assert!(uncached.is_tx_valid(&tx));
+    assert!(uncached.is_tx_valid(&tx));
+    assert_eq!(4, uncached.signature_verifications());
+
+    let cache = SignatureCache::new();
+    let mut cached = Handler::new(utxo_pool.clone());
+    cached.set_signature_cache(cache.clone());
+    let cached = CountingHandler::new(cached);
+    assert!(cached.is_tx_valid(&tx));
+    assert!(cached.is_tx_valid(&tx));
+    assert_eq!(2, cached.signature_verifications());
+    assert_eq!(2, cache.len());
+
+    // another handler sharing the cache doesn't verify the tx again
+    let mut other = Handler::new(utxo_pool);
+    other.set_signature_cache(cache);
+    let mut other = CountingHandler::new(other);
+    assert_eq!(1, other.handle(vec![&tx]).len());
+    assert_eq!(0, other.signature_verifications());
+}
+
+#[test]
+fn invalid_signatures_not_cached() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 1);
+    let mut tx = UnsignedTx::new();
+    tx.add_input(root_tx.hash(), 0);
+    tx.add_output(OUTPUT_VALUE, &alice.vk);
+    let forged = tx.sing_inputs_and_finalize(&alice.sk).unwrap();
+
+    let cache = SignatureCache::new();
+    let mut handler = Handler::new(utxo_pool);
+    handler.set_signature_cache(cache.clone());
+    let handler = CountingHandler::new(handler);
+    assert!(!handler.is_tx_valid(&forged));
+    assert!(!handler.is_tx_valid(&forged));
+    assert_eq!(2, handler.signature_verifications());
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn forced_signature_not_trusted_from_cache() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 1);
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+
+    let cache = SignatureCache::new();
+    let mut handler = Handler::new(utxo_pool);
+    handler.set_signature_cache(cache.clone());
+    assert!(handler.is_tx_valid(&tx));
+
+    // hash of the tx isn't updated by forcing the signature
+    let mut forged = tx.clone();
+    forged.force_signature_on_input(0, bob.sk.sign(b"random data").to_bytes());
+    assert_eq!(tx.hash(), forged.hash());
+    assert!(!handler.is_tx_valid(&forged));
+    assert_eq!(0, cache.hits());
+}
+
+#[test]
+fn signature_cache_evicts_oldest_signatures() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0), (&root_tx, 1)],
+        outputs: &[(&alice, 2 * OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+
+    let cache = SignatureCache::with_capacity(1);
+    let mut handler = Handler::new(utxo_pool);
+    handler.set_signature_cache(cache.clone());
+    assert!(handler.is_tx_valid(&tx));
+    assert_eq!(1, cache.len());
+
+    // the first input was evicted by the second one
+    assert!(handler.is_tx_valid(&tx));
+    assert_eq!(1, cache.len());
+    assert_eq!(0, cache.hits());
+}
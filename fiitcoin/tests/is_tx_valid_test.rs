@@ -1,9 +1,15 @@
-use common::{new_tx, setup_pool, NewTxParams, Participant};
+use common::{new_tx, NewTxParams};
 use fiitcoin::{
     handler::{Handler, TxHandler},
-    tx::raw_tx,
+    keys::{KeyError, PublicKey},
+    testing::{setup_pool, Participant},
+    tx::{raw_tx, TxError, UnsignedTx, MAX_INPUTS},
+};
+use rsa::{
+    signature::{SignatureEncoding, Signer},
+    traits::PublicKeyParts,
+    BigUint, RsaPublicKey,
 };
-use rsa::signature::{SignatureEncoding, Signer};
 
 use crate::common::{new_tx_forged_signatures, OUTPUT_VALUE};
 
@@ -194,6 +200,139 @@ fn one_output_multiple_times() {
 }
 
 // Phase 1 test 7 - this test is meaningless, because outputs have values of
-// type u32. Even if I serialized a negative value, it would only be treated
+// type u64. Even if I serialized a negative value, it would only be treated
 // as a really big one, in which case inputs < outputs case would catch it as
 // an invalid tx. Thus, I didn't write this test.
+
+#[test]
+fn oversized_signature() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 1);
+    let handler = Handler::new(utxo_pool);
+
+    let mut tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+    assert!(handler.is_tx_valid(&tx));
+
+    let blob: Box<[u8]> = vec![1; 10 * 1024].into_boxed_slice();
+    tx.force_signature_on_input(0, blob.clone());
+    assert!(!handler.is_tx_valid(&tx));
+
+    let mut unsigned = UnsignedTx::new();
+    unsigned.add_input(root_tx.hash(), 0);
+    unsigned.add_output(OUTPUT_VALUE, &alice.vk);
+    unsigned.attach_signature(0, blob).unwrap();
+    assert!(matches!(
+        unsigned.finalize(),
+        Err(TxError::SignatureTooLong(len)) if len == 10 * 1024
+    ));
+}
+
+#[test]
+fn signature_reused_across_inputs() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let handler = Handler::new(utxo_pool);
+
+    let mut tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0), (&root_tx, 1)],
+        outputs: &[(&alice, 2 * OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+    assert!(handler.is_tx_valid(&tx));
+
+    let signature = tx.input(0).unwrap().signature().unwrap().clone();
+    tx.force_signature_on_input(1, signature);
+    assert_eq!(
+        tx.input(0).unwrap().signature(),
+        tx.input(1).unwrap().signature()
+    );
+    assert!(!handler.is_tx_valid(&tx));
+}
+
+#[test]
+fn output_locked_to_degenerate_key() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 1);
+    let handler = Handler::new(utxo_pool);
+
+    let alice_n = alice.vk.as_ref().n().clone();
+    let degenerate = [
+        (
+            RsaPublicKey::new_unchecked(BigUint::from(1u8), BigUint::from(3u8)),
+            KeyError::ModulusTooSmall(1),
+        ),
+        (
+            RsaPublicKey::new_unchecked(alice_n.clone() + 1u8, BigUint::from(3u8)),
+            KeyError::EvenModulus,
+        ),
+        (
+            RsaPublicKey::new_unchecked(alice_n.clone(), BigUint::from(65536u32)),
+            KeyError::InvalidExponent,
+        ),
+        (
+            RsaPublicKey::new_unchecked(alice_n, BigUint::from(1u8)),
+            KeyError::InvalidExponent,
+        ),
+    ];
+
+    assert_eq!(Ok(()), PublicKey::from(&alice.vk).validate());
+    for (key, expected) in degenerate {
+        let key = PublicKey::from(&key);
+        assert_eq!(Err(expected), key.validate());
+
+        let mut tx = UnsignedTx::new();
+        tx.add_input(root_tx.hash(), 0);
+        tx.add_output(OUTPUT_VALUE, key);
+        let tx = tx.sing_inputs_and_finalize(&bob.sk).unwrap();
+        assert!(!handler.is_tx_valid(&tx));
+    }
+}
+
+#[test]
+fn too_many_inputs() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, 1, 4);
+    let mut handler = Handler::new(utxo_pool);
+    handler.set_max_inputs(3);
+
+    let at_limit = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0), (&root_tx, 1), (&root_tx, 2)],
+        outputs: &[(&alice, 3)],
+        return_to_sender: None,
+    });
+    let over_limit = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0), (&root_tx, 1), (&root_tx, 2), (&root_tx, 3)],
+        outputs: &[(&alice, 4)],
+        return_to_sender: None,
+    });
+    assert!(handler.is_tx_valid(&at_limit));
+    assert!(!handler.is_tx_valid(&over_limit));
+
+    // inputs past `MAX_INPUTS` couldn't be signed by their index
+    handler.set_max_inputs(usize::MAX);
+    assert_eq!(MAX_INPUTS, handler.max_inputs());
+}
@@ -1,5 +1,6 @@
-use common::{new_tx, setup_pool, NewTxParams, Participant, OUTPUT_VALUE};
+use common::{new_tx, NewTxParams, OUTPUT_VALUE};
 use fiitcoin::handler::{balance_of, MaxFeeHandler, TxHandler};
+use fiitcoin::testing::{setup_pool, Participant};
 
 mod common;
 
@@ -70,7 +71,7 @@ fn transactions_with_same_output() {
 
     let txs = handler.handle(vec![&tx_fee_10, &tx_fee_50]);
     assert_eq!(1, txs.len());
-    assert_eq!(tx_fee_50.hash(), txs.get(0).unwrap().hash());
+    assert_eq!(tx_fee_50.hash(), txs.first().unwrap().hash());
     assert_eq!(40, balance_of(handler.pool(), bob.vk.as_ref()));
     assert_eq!(10, balance_of(handler.pool(), alice.vk.as_ref()));
     assert_eq!(0, balance_of(handler.pool(), charlie.vk.as_ref()));
@@ -112,7 +113,7 @@ fn mix_of_txs() {
     assert_eq!(2, txs.len());
     assert_eq!(20, balance_of(handler.pool(), alice.vk.as_ref()));
     assert_eq!(
-        2 * OUTPUT_VALUE as u64 + 20,
+        2 * OUTPUT_VALUE + 20,
         balance_of(handler.pool(), bob.vk.as_ref())
     );
     assert_eq!(0, balance_of(handler.pool(), charlie.vk.as_ref()));
@@ -134,8 +135,103 @@ fn mix_of_txs() {
     assert_eq!(1, txs.len());
     assert_eq!(0, balance_of(handler.pool(), alice.vk.as_ref()));
     assert_eq!(
-        2 * OUTPUT_VALUE as u64 + 20,
+        2 * OUTPUT_VALUE + 20,
         balance_of(handler.pool(), bob.vk.as_ref())
     );
     assert_eq!(15, balance_of(handler.pool(), charlie.vk.as_ref()));
 }
+
+#[test]
+fn zero_output_tx_policy() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 1);
+    let burn_tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[],
+        return_to_sender: None,
+    });
+    let tx_fee_10 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, OUTPUT_VALUE - 10)],
+        return_to_sender: None,
+    });
+
+    // whole input is the fee, so burning wins the conflict
+    let mut handler = MaxFeeHandler::new(utxo_pool.clone());
+    let txs = handler.handle(vec![&tx_fee_10, &burn_tx]);
+    assert_eq!(1, txs.len());
+    assert_eq!(burn_tx.hash(), txs[0].hash());
+    assert_eq!(0, balance_of(handler.pool(), alice.vk.as_ref()));
+
+    let mut handler = MaxFeeHandler::new(utxo_pool);
+    handler.set_allow_zero_output(false);
+    let txs = handler.handle(vec![&tx_fee_10, &burn_tx]);
+    assert_eq!(1, txs.len());
+    assert_eq!(tx_fee_10.hash(), txs[0].hash());
+    assert_eq!(
+        OUTPUT_VALUE - 10,
+        balance_of(handler.pool(), alice.vk.as_ref())
+    );
+}
+
+#[test]
+fn conflicting_txs_dont_share_funding() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let mut handler = MaxFeeHandler::new(utxo_pool);
+
+    let tx_fee_90 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, 10)],
+        return_to_sender: None,
+    });
+    let tx_fee_80 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&charlie, 20)],
+        return_to_sender: None,
+    });
+    let child_of_fee_80 = new_tx(NewTxParams {
+        sender: &charlie,
+        inputs: &[(&tx_fee_80, 0)],
+        outputs: &[(&alice, 5)],
+        return_to_sender: None,
+    });
+    let tx_fee_50 = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 1)],
+        outputs: &[(&charlie, 50)],
+        return_to_sender: None,
+    });
+
+    let txs = vec![&tx_fee_80, &child_of_fee_80, &tx_fee_50, &tx_fee_90];
+    let ranked = handler.rank_by_fee(&txs);
+    let ranked_fees: Vec<(u64, [u8; 32])> =
+        ranked.iter().map(|(fee, tx)| (*fee, tx.hash())).collect();
+    assert_eq!(
+        vec![
+            (90, tx_fee_90.hash()),
+            (50, tx_fee_50.hash()),
+            (0, tx_fee_80.hash()),
+            (0, child_of_fee_80.hash()),
+        ],
+        ranked_fees
+    );
+
+    let accepted = handler.handle(txs);
+    assert_eq!(2, accepted.len());
+    assert_eq!(10, balance_of(handler.pool(), alice.vk.as_ref()));
+    assert_eq!(50, balance_of(handler.pool(), charlie.vk.as_ref()));
+}
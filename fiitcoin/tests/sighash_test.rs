@@ -0,0 +1,111 @@
+use common::OUTPUT_VALUE;
+use fiitcoin::{
+    handler::{Handler, TxHandler},
+    testing::{setup_pool, Participant},
+    tx::{SigHash, Tx, TxError, UnsignedTx},
+    utxo::UTXO,
+};
+use rsa::signature::{SignatureEncoding, Signer};
+
+mod common;
+
+/// Handler with one root output owned by bob and one owned by alice
+fn setup(bob: &Participant, alice: &Participant) -> (Handler, Tx, Tx) {
+    let (mut pool, bob_root) = setup_pool(bob, OUTPUT_VALUE, 1);
+    let (alice_pool, alice_root) = setup_pool(alice, OUTPUT_VALUE, 1);
+    let utxo = UTXO::new(alice_root.hash(), 0);
+    pool.add_utxo(utxo.clone(), alice_pool.utxo_output(&utxo).unwrap());
+    (Handler::new(pool), bob_root, alice_root)
+}
+
+fn sign(tx: &mut UnsignedTx, idx: u8, signer: &Participant) {
+    let bytes = tx.input_signing_bytes(idx).unwrap();
+    tx.attach_signature(idx, signer.sk.sign(&bytes).to_bytes())
+        .unwrap();
+}
+
+#[test]
+fn signature_stays_valid_when_unrelated_input_is_added() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (handler, bob_root, alice_root) = setup(&bob, &alice);
+
+    // bob pays charlie and signs before alice joins with her input
+    let mut tx = UnsignedTx::new();
+    tx.add_input(bob_root.hash(), 0);
+    tx.add_output(2 * OUTPUT_VALUE - 10, &charlie.vk);
+    sign(&mut tx, 0, &bob);
+
+    tx.add_input(alice_root.hash(), 0);
+    sign(&mut tx, 1, &alice);
+
+    assert!(handler.is_tx_valid(&tx.finalize().unwrap()));
+}
+
+#[test]
+fn single_signs_only_output_at_same_index() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (handler, bob_root, alice_root) = setup(&bob, &alice);
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input_with_sighash(bob_root.hash(), 0, SigHash::Single);
+    tx.add_output(OUTPUT_VALUE, &charlie.vk);
+    sign(&mut tx, 0, &bob);
+
+    // alice adds her own input and output later
+    tx.add_input(alice_root.hash(), 0);
+    tx.add_output(OUTPUT_VALUE - 10, &alice.vk);
+    sign(&mut tx, 1, &alice);
+    let tx = tx.finalize().unwrap();
+    assert_eq!(SigHash::Single, tx.input(0).unwrap().sighash());
+    assert!(handler.is_tx_valid(&tx));
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input_with_sighash(bob_root.hash(), 0, SigHash::Single);
+    assert!(matches!(
+        tx.input_signing_bytes(0),
+        Err(TxError::NoOutputForSingle(0))
+    ));
+}
+
+#[test]
+fn none_doesnt_sign_outputs() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (handler, bob_root, _) = setup(&bob, &alice);
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input_with_sighash(bob_root.hash(), 0, SigHash::None);
+    sign(&mut tx, 0, &bob);
+    tx.add_output(OUTPUT_VALUE, &charlie.vk);
+
+    assert!(handler.is_tx_valid(&tx.finalize().unwrap()));
+}
+
+#[test]
+fn all_invalidated_by_added_output() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let charlie = Participant::new();
+    let (handler, bob_root, _) = setup(&bob, &alice);
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input(bob_root.hash(), 0);
+    tx.add_output(OUTPUT_VALUE / 2, &alice.vk);
+    sign(&mut tx, 0, &bob);
+    tx.add_output(OUTPUT_VALUE / 2, &charlie.vk);
+
+    assert!(!handler.is_tx_valid(&tx.finalize().unwrap()));
+}
@@ -0,0 +1,162 @@
+use fiitcoin::{
+    handler::{balance_of, Handler, TxHandler},
+    keys::{PublicKey, TxSigner},
+    tx::{Tx, UnsignedTx},
+    utxo::{UTXOPool, UTXO},
+};
+use rsa::{pkcs1v15::SigningKey, traits::PublicKeyParts, BigUint, RsaPrivateKey, RsaPublicKey};
+use sha2::Sha256;
+
+mod common;
+
+const VALUE: u64 = 100;
+
+fn rsa_signer() -> SigningKey<Sha256> {
+    let mut rng = rand::thread_rng();
+    let priv_key = RsaPrivateKey::new(&mut rng, 1024).expect("failed to generate a key");
+    SigningKey::<Sha256>::new(priv_key)
+}
+
+fn ed25519_signer() -> ed25519_dalek::SigningKey {
+    ed25519_dalek::SigningKey::from_bytes(&rand::random())
+}
+
+/// Returns pool with two outputs owned by the signer
+fn setup_pool<S: TxSigner>(owner: &S) -> (UTXOPool, Tx) {
+    let mut root_tx = UnsignedTx::new();
+    root_tx.add_input([1; 32], 0);
+    root_tx.add_output(VALUE, owner.public_key());
+    root_tx.add_output(VALUE, owner.public_key());
+    let root_tx = root_tx.sing_inputs_and_finalize(owner).unwrap();
+
+    let mut pool = UTXOPool::new();
+    for idx in 0..2 {
+        pool.add_utxo(UTXO::new(root_tx.hash(), idx), root_tx.output(idx).unwrap());
+    }
+    (pool, root_tx)
+}
+
+fn spend<S: TxSigner>(signer: &S, inputs: &[(&Tx, u16)], receiver: &S, value: u64) -> Tx {
+    let mut tx = UnsignedTx::new();
+    for (input, idx) in inputs.iter() {
+        tx.add_input(input.hash(), *idx);
+    }
+    tx.add_output(value, receiver.public_key());
+    tx.sing_inputs_and_finalize(signer).unwrap()
+}
+
+fn valid_tx<S: TxSigner>(new_signer: fn() -> S) {
+    let (bob, alice) = (new_signer(), new_signer());
+    let (pool, root_tx) = setup_pool(&bob);
+    let mut handler = Handler::new(pool);
+
+    let tx = spend(&bob, &[(&root_tx, 0), (&root_tx, 1)], &alice, 2 * VALUE);
+    assert!(tx.size() > 0);
+    assert_eq!(1, handler.handle(vec![&tx]).len());
+    assert_eq!(2 * VALUE, balance_of(handler.pool(), alice.public_key()));
+    assert_eq!(0, balance_of(handler.pool(), bob.public_key()));
+}
+
+fn forged_signature<S: TxSigner>(new_signer: fn() -> S) {
+    let (bob, alice) = (new_signer(), new_signer());
+    let (pool, root_tx) = setup_pool(&bob);
+    let handler = Handler::new(pool);
+
+    let tx = spend(&alice, &[(&root_tx, 0)], &alice, VALUE);
+    assert!(!handler.is_tx_valid(&tx));
+}
+
+fn double_spend<S: TxSigner>(new_signer: fn() -> S) {
+    let (bob, alice) = (new_signer(), new_signer());
+    let (pool, root_tx) = setup_pool(&bob);
+    let mut handler = Handler::new(pool);
+
+    let tx1 = spend(&bob, &[(&root_tx, 0)], &alice, VALUE);
+    let tx2 = spend(&bob, &[(&root_tx, 0)], &bob, VALUE);
+    assert_eq!(1, handler.handle(vec![&tx1, &tx2]).len());
+}
+
+#[test]
+fn rsa_valid_tx() {
+    common::initialize();
+    valid_tx(rsa_signer);
+}
+
+#[test]
+fn ed25519_valid_tx() {
+    common::initialize();
+    valid_tx(ed25519_signer);
+}
+
+#[test]
+fn rsa_forged_signature() {
+    common::initialize();
+    forged_signature(rsa_signer);
+}
+
+#[test]
+fn ed25519_forged_signature() {
+    common::initialize();
+    forged_signature(ed25519_signer);
+}
+
+#[test]
+fn rsa_double_spend() {
+    common::initialize();
+    double_spend(rsa_signer);
+}
+
+#[test]
+fn ed25519_double_spend() {
+    common::initialize();
+    double_spend(ed25519_signer);
+}
+
+#[test]
+fn signature_of_other_scheme_is_rejected() {
+    common::initialize();
+
+    let bob = ed25519_signer();
+    let rsa = rsa_signer();
+    let (pool, root_tx) = setup_pool(&bob);
+    let handler = Handler::new(pool);
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input(root_tx.hash(), 0);
+    tx.add_output(VALUE, bob.public_key());
+    let tx = tx.sing_inputs_and_finalize(&rsa).unwrap();
+    assert!(!handler.is_tx_valid(&tx));
+}
+
+#[test]
+fn key_bytes_are_domain_separated() {
+    let rsa = rsa_signer().public_key();
+    let PublicKey::Rsa(vk) = &rsa else {
+        unreachable!("rsa signer has rsa key")
+    };
+    let (e, n) = (vk.as_ref().e().to_bytes_be(), vk.as_ref().n().to_bytes_be());
+    assert_eq!(vec![1, 0, 1], e);
+
+    // same exponent and modulus bytes concatenated, but split differently
+    let moved_e = BigUint::from_bytes_be(&e[..2]);
+    let moved_n = BigUint::from_bytes_be(&[&e[2..], &n[..]].concat());
+    // encoding is checked regardless of validity of the key
+    let moved = PublicKey::from(&RsaPublicKey::new_unchecked(moved_n, moved_e));
+    assert_ne!(rsa, moved);
+    assert_ne!(rsa.to_bytes(), moved.to_bytes());
+    assert_ne!(rsa.address(), moved.address());
+
+    // rsa key with the same exponent and modulus bytes as an ed25519 key
+    let ed25519 = (0..=u8::MAX)
+        .map(|seed| ed25519_dalek::SigningKey::from_bytes(&[seed; 32]).verifying_key())
+        .find(|vk| vk.as_bytes()[0] > 1 && vk.as_bytes()[1] != 0)
+        .unwrap();
+    let bytes = ed25519.as_bytes();
+    let rsa = RsaPublicKey::new_unchecked(
+        BigUint::from_bytes_be(&bytes[1..]),
+        BigUint::from_bytes_be(&bytes[..1]),
+    );
+    let (rsa, ed25519) = (PublicKey::from(&rsa), PublicKey::from(&ed25519));
+    assert_ne!(rsa.to_bytes(), ed25519.to_bytes());
+    assert_ne!(rsa.address(), ed25519.address());
+}
@@ -0,0 +1,347 @@
+use common::{new_tx, NewTxParams, OUTPUT_VALUE};
+use fiitcoin::{
+    handler::{Handler, TxHandler},
+    testing::{setup_pool, Participant},
+    tx::{Tx, TxError, UnsignedTx, MAX_DATA_LEN, MAX_OUTPUTS, MAX_SIGNATURE_LEN},
+    utxo::UTXO,
+};
+use rsa::signature::{SignatureEncoding, Signer};
+
+mod common;
+
+#[test]
+fn outputs_to_same_key_and_value_are_equal() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let mut tx = UnsignedTx::new();
+    tx.add_output(10, &bob.vk);
+    tx.add_output(10, &bob.vk);
+    tx.add_output(11, &bob.vk);
+    tx.add_output(10, &alice.vk);
+    let tx = tx.finalize().unwrap();
+
+    assert_eq!(tx.output(0), tx.output(1));
+    assert_ne!(tx.output(0), tx.output(2));
+    assert_ne!(tx.output(0), tx.output(3));
+}
+
+#[test]
+fn externally_signed_tx_is_valid() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let handler = Handler::new(utxo_pool);
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input(root_tx.hash(), 0);
+    tx.add_input(root_tx.hash(), 1);
+    tx.add_output(2 * OUTPUT_VALUE, &alice.vk);
+
+    // bob's key is only used to sign the bytes, as would an external signer
+    for idx in 0..2 {
+        let bytes = tx.input_signing_bytes(idx).unwrap();
+        let signature = bob.sk.sign(&bytes).to_bytes();
+        tx.attach_signature(idx, signature).unwrap();
+    }
+    assert!(tx.input_signing_bytes(2).is_err());
+    assert!(tx.attach_signature(2, Box::new([0; 128])).is_err());
+
+    let tx = tx.finalize().unwrap();
+    assert!(handler.is_tx_valid(&tx));
+}
+
+#[test]
+fn finalize_without_attached_signature_fails() {
+    common::initialize();
+
+    let bob = Participant::new();
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input([1; 32], 0);
+    tx.add_input([1; 32], 1);
+    tx.add_output(OUTPUT_VALUE, &bob.vk);
+
+    let bytes = tx.input_signing_bytes(0).unwrap();
+    tx.attach_signature(0, bob.sk.sign(&bytes).to_bytes())
+        .unwrap();
+    assert!(tx.finalize().is_err());
+}
+
+#[test]
+fn input_and_output_by_index() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (_utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0), (&root_tx, 1)],
+        outputs: &[(&alice, 150)],
+        return_to_sender: Some(50),
+    });
+
+    assert_eq!(2, tx.input_len());
+    assert_eq!(1, tx.input(1).unwrap().output_idx());
+    assert_eq!(root_tx.hash(), tx.input(0).unwrap().output_tx_hash());
+    assert!(tx.input(2).is_none());
+
+    assert_eq!(2, tx.output_len());
+    assert_eq!(50, tx.output(1).unwrap().value());
+    assert!(tx.output(2).is_none());
+
+    assert_eq!(1, root_tx.input_len());
+}
+
+#[test]
+fn estimated_size_bounds_final_size() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input([1; 32], 0);
+    tx.add_input([1; 32], 1);
+    tx.add_output(OUTPUT_VALUE, &alice.vk);
+    tx.add_output(OUTPUT_VALUE, &bob.vk);
+
+    let estimated = tx.estimated_size();
+    let tx = tx.sing_inputs_and_finalize(&bob.sk).unwrap();
+
+    assert!(estimated >= tx.size());
+    assert!(estimated - tx.size() <= 2 * MAX_SIGNATURE_LEN);
+}
+
+#[test]
+fn tx_with_300_outputs() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, 1, 300);
+    assert_eq!(300, root_tx.output_len());
+    let mut handler = Handler::new(utxo_pool);
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 299), (&root_tx, 256)],
+        outputs: &[(&alice, 2)],
+        return_to_sender: None,
+    });
+    assert_eq!(1, handler.handle(vec![&tx]).len());
+    assert!(handler.pool().contains(&UTXO::new(root_tx.hash(), 255)));
+    assert!(!handler.pool().contains(&UTXO::new(root_tx.hash(), 256)));
+}
+
+#[test]
+fn too_many_outputs() {
+    common::initialize();
+
+    let bob = Participant::new();
+
+    let mut tx = UnsignedTx::new();
+    for _ in 0..=MAX_OUTPUTS {
+        tx.add_output(1, &bob.vk);
+    }
+    assert_eq!(MAX_OUTPUTS + 1, tx.output_count());
+    assert!(matches!(
+        tx.finalize(),
+        Err(TxError::TooManyOutputs(len)) if len == MAX_OUTPUTS + 1
+    ));
+}
+
+#[test]
+fn display_summarizes_inputs_and_outputs() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (_utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0), (&root_tx, 1)],
+        outputs: &[(&alice, 150)],
+        return_to_sender: Some(50),
+    });
+
+    let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() };
+    let summary = tx.to_string();
+    assert!(summary.starts_with(&format!("tx {}", hex(&tx.hash()))));
+    assert!(summary.contains(&format!(
+        "2 inputs [{0}:0, {0}:1]",
+        hex(&root_tx.hash()[..4])
+    )));
+    assert!(summary.contains(&format!(
+        "2 outputs [150 -> {}, 50 -> {}]",
+        tx.output(0).unwrap().public_key().unwrap().fingerprint(),
+        tx.output(1).unwrap().public_key().unwrap().fingerprint()
+    )));
+}
+
+#[test]
+fn only_tx_without_inputs_is_coinbase() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let coinbase = Tx::coinbase(OUTPUT_VALUE, &bob.vk);
+    assert!(coinbase.is_coinbase());
+    assert!(Tx::block_coinbase(OUTPUT_VALUE, &bob.vk, [1; 32]).is_coinbase());
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&coinbase, 0)],
+        outputs: &[(&alice, OUTPUT_VALUE)],
+        return_to_sender: None,
+    });
+    assert!(!tx.is_coinbase());
+}
+
+#[test]
+fn data_output_hashed_but_not_spendable() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 1);
+    let mut handler = Handler::new(utxo_pool);
+
+    let unsigned_with_memo = |memo: &[u8]| {
+        let mut tx = UnsignedTx::new();
+        tx.add_input(root_tx.hash(), 0);
+        tx.add_output(OUTPUT_VALUE, &alice.vk);
+        tx.add_data_output(memo).unwrap();
+        tx
+    };
+    let tx = unsigned_with_memo(b"memo")
+        .sing_inputs_and_finalize(&bob.sk)
+        .unwrap();
+    let other_memo = unsigned_with_memo(b"other memo")
+        .sing_inputs_and_finalize(&bob.sk)
+        .unwrap();
+    assert_ne!(tx.hash(), other_memo.hash());
+    assert_eq!(Some(&b"memo"[..]), tx.output(1).unwrap().data());
+    assert_eq!(0, tx.output(1).unwrap().value());
+    assert!(tx.output(1).unwrap().public_key().is_none());
+
+    // memo is signed, so it can't be changed
+    let mut forged = unsigned_with_memo(b"other memo");
+    forged
+        .attach_signature(0, tx.input(0).unwrap().signature().unwrap().clone())
+        .unwrap();
+    assert!(!handler.is_tx_valid(&forged.finalize().unwrap()));
+
+    assert_eq!(1, handler.handle(vec![&tx]).len());
+    assert!(handler.pool().contains(&UTXO::new(tx.hash(), 0)));
+    assert!(!handler.pool().contains(&UTXO::new(tx.hash(), 1)));
+
+    let mut too_long = UnsignedTx::new();
+    assert!(matches!(
+        too_long.add_data_output(vec![0; MAX_DATA_LEN + 1]),
+        Err(TxError::DataTooLong(len)) if len == MAX_DATA_LEN + 1
+    ));
+    assert!(too_long.add_data_output(vec![0; MAX_DATA_LEN]).is_ok());
+}
+
+#[test]
+fn add_output_returns_its_index() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input([1; 32], 0);
+    assert_eq!(0, tx.add_output(10, &alice.vk));
+    assert_eq!(1, tx.add_data_output(b"memo".to_vec()).unwrap());
+    let change_idx = tx.add_output(20, &bob.vk);
+    assert_eq!(2, change_idx);
+
+    let tx = tx.sing_inputs_and_finalize(&bob.sk).unwrap();
+    assert_eq!(20, tx.output(change_idx).unwrap().value());
+    assert_eq!(Some(b"memo".as_slice()), tx.output(1).unwrap().data());
+}
+
+#[test]
+fn canonical_inputs_give_same_hash_in_any_order() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 3);
+    let outpoints = [(root_tx.hash(), 2), ([1; 32], 0), (root_tx.hash(), 0)];
+
+    let build = |order: &[usize], canonical: bool| {
+        let mut tx = UnsignedTx::new();
+        tx.set_canonical(canonical);
+        for &i in order {
+            tx.add_input(outpoints[i].0, outpoints[i].1);
+        }
+        tx.add_output(OUTPUT_VALUE, &alice.vk);
+        tx.add_output(OUTPUT_VALUE, &bob.vk);
+        tx.sing_inputs_and_finalize(&bob.sk).unwrap()
+    };
+
+    assert_ne!(
+        build(&[0, 1, 2], false).hash(),
+        build(&[2, 1, 0], false).hash()
+    );
+
+    let tx = build(&[0, 1, 2], true);
+    assert_eq!(tx.hash(), build(&[2, 1, 0], true).hash());
+    assert_eq!(tx.hash(), build(&[1, 2, 0], true).hash());
+    let mut sorted = outpoints.to_vec();
+    sorted.sort();
+    let inputs: Vec<([u8; 32], u16)> = tx
+        .inputs()
+        .iter()
+        .map(|i| (i.output_tx_hash(), i.output_idx()))
+        .collect();
+    assert_eq!(sorted, inputs);
+
+    // inputs were signed in the canonical order
+    let mut unsorted = UnsignedTx::new();
+    for &i in [2, 0].iter() {
+        unsorted.add_input(outpoints[i].0, outpoints[i].1);
+    }
+    unsorted.add_output(OUTPUT_VALUE, &alice.vk);
+    unsorted.add_output(OUTPUT_VALUE, &bob.vk);
+    unsorted.set_canonical(true);
+    let tx = unsorted.sing_inputs_and_finalize(&bob.sk).unwrap();
+    let handler = Handler::new(utxo_pool);
+    assert!(handler.is_tx_valid(&tx));
+}
+
+#[test]
+fn signature_bytes_match_stored_signature() {
+    common::initialize();
+
+    let bob = Participant::new();
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input([1; 32], 0);
+    tx.add_output(OUTPUT_VALUE, &bob.vk);
+    let signature = bob.sk.sign(&tx.input_signing_bytes(0).unwrap()).to_vec();
+    tx.attach_signature(0, signature.clone().into_boxed_slice())
+        .unwrap();
+    let tx = tx.finalize().unwrap();
+
+    let input = tx.input(0).unwrap();
+    assert_eq!(Some(signature.as_slice()), input.signature_bytes());
+    assert_eq!(
+        input.signature().map(|sig| sig.as_ref()),
+        input.signature_bytes()
+    );
+}
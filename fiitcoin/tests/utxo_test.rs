@@ -0,0 +1,130 @@
+use common::{new_tx, NewTxParams, OUTPUT_VALUE};
+use fiitcoin::{
+    handler::{Handler, TxHandler},
+    keys::PublicKey,
+    testing::{setup_pool, Participant},
+    tx::UnsignedTx,
+    utxo::{UTXOPool, UTXO},
+};
+
+mod common;
+
+#[test]
+fn get_many_matches_single_lookups() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+
+    let utxos = [
+        UTXO::new(root_tx.hash(), 1),
+        UTXO::new(root_tx.hash(), 2),
+        UTXO::new([1; 32], 0),
+        UTXO::new(root_tx.hash(), 0),
+    ];
+    let outputs = utxo_pool.get_many(utxos.iter());
+
+    assert_eq!(utxos.len(), outputs.len());
+    for (utxo, output) in utxos.iter().zip(outputs.iter()) {
+        assert_eq!(utxo_pool.utxo_output(utxo), *output);
+    }
+    assert_eq!(
+        vec![true, false, false, true],
+        outputs.iter().map(Option::is_some).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn pool_collected_from_iterator() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (_, bob_root) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let (_, alice_root) = setup_pool(&alice, OUTPUT_VALUE, 1);
+
+    let mut utxo_pool: UTXOPool = bob_root
+        .outputs()
+        .iter()
+        .enumerate()
+        .map(|(i, output)| (UTXO::new(bob_root.hash(), i as u16), output.clone()))
+        .collect();
+    assert_eq!(2, utxo_pool.outputs().len());
+    assert_eq!(2, utxo_pool.owned_by(&bob.vk).len());
+
+    let alice_utxo = UTXO::new(alice_root.hash(), 0);
+    utxo_pool.extend([(alice_utxo.clone(), alice_root.output(0).unwrap().clone())]);
+    assert_eq!(3, utxo_pool.outputs().len());
+    assert_eq!(alice_root.output(0), utxo_pool.utxo_output(&alice_utxo));
+}
+
+#[test]
+fn utxos_of_matches_naive_lookup() {
+    common::initialize();
+
+    let keys: Vec<PublicKey> = (0..8)
+        .map(|_| PublicKey::from(&Participant::with_key_bits(512).vk))
+        .chain((0..4).map(|i| {
+            PublicKey::from(&ed25519_dalek::SigningKey::from_bytes(&[i; 32]).verifying_key())
+        }))
+        .collect();
+
+    let mut tx = UnsignedTx::new();
+    for (i, key) in keys.iter().enumerate() {
+        for j in 0..=i {
+            tx.add_output((i * 100 + j) as u64, key);
+        }
+    }
+    tx.add_data_output(b"memo".to_vec()).unwrap();
+    let tx = tx.finalize().unwrap();
+    let utxo_pool: UTXOPool = tx
+        .outputs()
+        .iter()
+        .enumerate()
+        .map(|(i, output)| (UTXO::new(tx.hash(), i as u16), output.clone()))
+        .collect();
+
+    let unknown = PublicKey::from(&Participant::with_key_bits(512).vk);
+    for key in keys.iter().chain([&unknown]) {
+        let mut naive: Vec<u64> = utxo_pool
+            .outputs()
+            .into_iter()
+            .filter(|o| o.public_key() == Some(key))
+            .map(|o| o.value())
+            .collect();
+        let mut fast: Vec<u64> = utxo_pool.utxos_of(key).iter().map(|o| o.value()).collect();
+        naive.sort();
+        fast.sort();
+        assert_eq!(naive, fast);
+        assert_eq!(naive.len(), utxo_pool.owned_by(key).len());
+    }
+    assert!(utxo_pool.utxos_of(&unknown).is_empty());
+}
+
+#[test]
+fn reverting_applied_tx_restores_equal_pool() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let (utxo_pool, root_tx) = setup_pool(&bob, OUTPUT_VALUE, 2);
+    let snapshot = utxo_pool.clone();
+
+    let tx = new_tx(NewTxParams {
+        sender: &bob,
+        inputs: &[(&root_tx, 0)],
+        outputs: &[(&alice, OUTPUT_VALUE / 2)],
+        return_to_sender: Some(OUTPUT_VALUE / 2),
+    });
+    let mut handler = Handler::new(utxo_pool);
+    assert_eq!(1, handler.handle(vec![&tx]).len());
+    assert_ne!(&snapshot, handler.pool());
+
+    let pool = handler.pool_mut();
+    for i in 0..tx.outputs().len() {
+        pool.remove_utxo(&UTXO::new(tx.hash(), i as u16));
+    }
+    let spent = UTXO::new(root_tx.hash(), 0);
+    pool.add_utxo(spent.clone(), snapshot.utxo_output(&spent).unwrap());
+    assert_eq!(&snapshot, handler.pool());
+}
@@ -0,0 +1,99 @@
+use common::OUTPUT_VALUE;
+use fiitcoin::{
+    handler::{balance_of, Handler, TxHandler},
+    testing::{setup_pool, Participant},
+    wallet::{Wallet, WalletError},
+};
+
+mod common;
+
+#[test]
+fn send_with_change() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let wallet = Wallet::from_signing_key(bob.sk.clone());
+
+    let (utxo_pool, _) = setup_pool(&bob, OUTPUT_VALUE, 3);
+    assert_eq!(3 * OUTPUT_VALUE, wallet.balance(&utxo_pool));
+
+    let tx = wallet.send(&utxo_pool, &alice.vk, 150, 10).unwrap();
+    assert_eq!(2, tx.input_len());
+
+    let mut handler = Handler::new(utxo_pool);
+    assert_eq!(1, handler.handle(vec![&tx]).len());
+    assert_eq!(150, balance_of(handler.pool(), alice.vk.as_ref()));
+    assert_eq!(140, wallet.balance(handler.pool()));
+}
+
+#[test]
+fn send_insufficient_funds() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let wallet = Wallet::from_signing_key(bob.sk.clone());
+
+    let (utxo_pool, _) = setup_pool(&bob, OUTPUT_VALUE, 3);
+
+    let res = wallet.send(&utxo_pool, &alice.vk, 3 * OUTPUT_VALUE, 1);
+    assert!(matches!(
+        res,
+        Err(WalletError::InsufficientFunds {
+            available: 300,
+            required: 301
+        })
+    ));
+}
+
+#[test]
+fn send_rejected_when_selected_inputs_overflow() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let wallet = Wallet::from_signing_key(bob.sk.clone());
+
+    let (utxo_pool, _) = setup_pool(&bob, u64::MAX - 1, 2);
+
+    // covered only by both outputs, whose sum exceeds `u64::MAX`
+    let res = wallet.send(&utxo_pool, &alice.vk, u64::MAX - 1, 1);
+    assert!(matches!(res, Err(WalletError::ValueOverflow)));
+    let res = wallet.send(&utxo_pool, &alice.vk, u64::MAX, 1);
+    assert!(matches!(res, Err(WalletError::ValueOverflow)));
+
+    let tx = wallet.send(&utxo_pool, &alice.vk, u64::MAX - 2, 1).unwrap();
+    assert_eq!(1, tx.input_len());
+}
+
+#[test]
+fn change_output_only_above_dust_limit() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let mut wallet = Wallet::from_signing_key(bob.sk.clone());
+    wallet.set_dust_limit(20);
+
+    let (utxo_pool, _) = setup_pool(&bob, OUTPUT_VALUE, 2);
+
+    // exact change, no change output
+    let tx = wallet.send(&utxo_pool, &alice.vk, 90, 10).unwrap();
+    assert_eq!(1, tx.output_len());
+
+    // dust change is folded into the fee
+    let tx = wallet.send(&utxo_pool, &alice.vk, 75, 10).unwrap();
+    assert_eq!(1, tx.output_len());
+    let mut handler = Handler::new(utxo_pool.clone());
+    assert_eq!(1, handler.handle(vec![&tx]).len());
+    assert_eq!(75, balance_of(handler.pool(), alice.vk.as_ref()));
+    assert_eq!(OUTPUT_VALUE, wallet.balance(handler.pool()));
+
+    // change at the dust limit is returned
+    let tx = wallet.send(&utxo_pool, &alice.vk, 70, 10).unwrap();
+    assert_eq!(2, tx.output_len());
+    let mut handler = Handler::new(utxo_pool);
+    assert_eq!(1, handler.handle(vec![&tx]).len());
+    assert_eq!(OUTPUT_VALUE + 20, wallet.balance(handler.pool()));
+}
@@ -0,0 +1,208 @@
+use fiitcoin::{
+    handler::{balance_of, Handler, TxHandler},
+    hash::DoubleSha256Hasher,
+    keys::TxSigner,
+    testing::{setup_pool, Participant},
+    tx::{SigHash, Tx, TxError, UnsignedTx, MAX_DATA_LEN, MAX_TX_SIZE},
+    wire::{WireError, MAX_WIRE_TX_SIZE, WIRE_VERSION},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+mod common;
+
+/// Tx with RSA and Ed25519 outputs, a data output and inputs with different
+/// sighashes
+fn sample_tx(bob: &Participant) -> Tx {
+    let ed25519 = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input([1; 32], 0);
+    tx.add_input_with_sighash([2; 32], 300, SigHash::Single);
+    tx.add_output(100, &bob.vk);
+    tx.add_output(50, ed25519.public_key());
+    tx.add_data_output(b"memo".to_vec()).unwrap();
+    tx.sing_inputs_and_finalize(&bob.sk).unwrap()
+}
+
+fn assert_same_tx(expected: &Tx, decoded: &Tx) {
+    assert_eq!(expected.hash(), decoded.hash());
+    assert_eq!(expected.prev_block(), decoded.prev_block());
+    assert_eq!(expected.outputs(), decoded.outputs());
+    assert_eq!(expected.input_len(), decoded.input_len());
+    for (a, b) in expected.inputs().iter().zip(decoded.inputs().iter()) {
+        assert_eq!(a.output_tx_hash(), b.output_tx_hash());
+        assert_eq!(a.output_idx(), b.output_idx());
+        assert_eq!(a.sighash(), b.sighash());
+        assert_eq!(a.signature(), b.signature());
+    }
+}
+
+#[test]
+fn tx_round_trips_through_wire() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let tx = sample_tx(&bob);
+
+    let bytes = tx.to_wire();
+    assert_eq!(WIRE_VERSION, bytes[0]);
+    let decoded = Tx::from_wire(&bytes).unwrap();
+    assert_same_tx(&tx, &decoded);
+    assert_eq!(bytes, decoded.to_wire());
+
+    let coinbase = Tx::block_coinbase(625, &bob.vk, [3; 32]);
+    assert_same_tx(&coinbase, &Tx::from_wire(&coinbase.to_wire()).unwrap());
+}
+
+#[test]
+fn value_above_u32_round_trips_and_validates() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let alice = Participant::new();
+    let value = 3 * u64::from(u32::MAX);
+    let (utxo_pool, root_tx) = setup_pool(&bob, value, 1);
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input(root_tx.hash(), 0);
+    tx.add_output(value - 1, &alice.vk);
+    let tx = tx.sing_inputs_and_finalize(&bob.sk).unwrap();
+
+    let decoded = Tx::from_wire(&tx.to_wire()).unwrap();
+    assert_same_tx(&tx, &decoded);
+    assert_eq!(Some(value - 1), decoded.output(0).map(|o| o.value()));
+
+    let mut handler = Handler::new(utxo_pool);
+    assert_eq!(1, handler.handle(vec![&decoded]).len());
+    assert_eq!(value - 1, balance_of(handler.pool(), &alice.vk));
+}
+
+#[test]
+fn hash_recomputed_by_given_hasher() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let mut tx = UnsignedTx::new();
+    tx.add_output(100, &bob.vk);
+    let tx = tx.finalize_with(&DoubleSha256Hasher).unwrap();
+
+    let bytes = tx.to_wire();
+    assert!(matches!(
+        Tx::from_wire(&bytes),
+        Err(WireError::HashMismatch)
+    ));
+    let decoded = Tx::from_wire_with(&bytes, &DoubleSha256Hasher).unwrap();
+    assert_same_tx(&tx, &decoded);
+}
+
+#[test]
+fn truncated_bytes_fail() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let bytes = sample_tx(&bob).to_wire();
+
+    for len in 0..bytes.len() {
+        assert!(
+            matches!(Tx::from_wire(&bytes[..len]), Err(WireError::UnexpectedEnd)),
+            "prefix of {} bytes decoded",
+            len
+        );
+    }
+
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert!(matches!(
+        Tx::from_wire(&longer),
+        Err(WireError::TrailingBytes(1))
+    ));
+}
+
+#[test]
+fn corrupted_bytes_fail() {
+    common::initialize();
+
+    let bob = Participant::new();
+    let bytes = sample_tx(&bob).to_wire();
+
+    let mut wrong_version = bytes.clone();
+    wrong_version[0] = WIRE_VERSION + 1;
+    assert!(matches!(
+        Tx::from_wire(&wrong_version),
+        Err(WireError::UnsupportedVersion(v)) if v == WIRE_VERSION + 1
+    ));
+
+    for idx in 0..bytes.len() {
+        let mut corrupted = bytes.clone();
+        corrupted[idx] ^= 0xff;
+        assert!(
+            Tx::from_wire(&corrupted).is_err(),
+            "corrupted byte {} decoded",
+            idx
+        );
+    }
+}
+
+#[test]
+fn garbage_bytes_fail() {
+    common::initialize();
+
+    let mut rng = StdRng::seed_from_u64(174);
+    for _ in 0..1000 {
+        let len = rng.gen_range(0..512);
+        let mut bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+        if let Some(version) = bytes.first_mut() {
+            *version = WIRE_VERSION;
+        }
+        assert!(Tx::from_wire(&bytes).is_err());
+    }
+}
+
+#[test]
+fn oversized_tx_rejected() {
+    common::initialize();
+
+    // data output takes its zero value, length and data in raw tx
+    let output_size = 8 + 1 + MAX_DATA_LEN;
+    let outputs = MAX_TX_SIZE / output_size + 1;
+
+    let mut tx = UnsignedTx::new();
+    for _ in 0..outputs {
+        tx.add_data_output(vec![0; MAX_DATA_LEN]).unwrap();
+    }
+    assert!(matches!(
+        tx.finalize(),
+        Err(TxError::TooLarge(len)) if len == outputs * output_size
+    ));
+
+    // oversized tx can't be finalized, so its encoding is put together from
+    // a tx with a single data output, whose encoding is at the end
+    let mut tx = UnsignedTx::new();
+    tx.add_data_output(vec![0; MAX_DATA_LEN]).unwrap();
+    let mut bytes = tx.finalize().unwrap().to_wire();
+    let output = bytes.split_off(bytes.len() - (1 + 1 + MAX_DATA_LEN));
+    // version, hash, coinbase flag and zero inputs precede output count
+    let count_at = 1 + 32 + 1 + 4;
+    bytes[count_at..count_at + 4].copy_from_slice(&(outputs as u32).to_be_bytes());
+    for _ in 0..outputs {
+        bytes.extend(&output);
+    }
+    assert!(matches!(
+        Tx::from_wire(&bytes),
+        Err(WireError::Tx(TxError::TooLarge(len))) if len == outputs * output_size
+    ));
+}
+
+#[test]
+fn overlong_encoding_rejected_before_parsing() {
+    let mut bytes = vec![0; MAX_WIRE_TX_SIZE + 1];
+    bytes[0] = WIRE_VERSION;
+    assert!(matches!(
+        Tx::from_wire(&bytes),
+        Err(WireError::TooLarge(len)) if len == MAX_WIRE_TX_SIZE + 1
+    ));
+    assert!(matches!(
+        Tx::from_wire(&bytes[..MAX_WIRE_TX_SIZE]),
+        Err(WireError::TrailingBytes(_))
+    ));
+}
@@ -10,6 +10,7 @@ pub type Sha256Digest = [u8; 32];
 #[derive(Debug)]
 pub struct IncompleteBlock {
     prev: Sha256Digest,
+    is_genesis: bool,
     coinbase: Tx,
     txs: Vec<Tx>,
 }
@@ -19,11 +20,20 @@ impl IncompleteBlock {
         let coinbase = Tx::coinbase(COINBASE, address, threshold);
         Self {
             prev,
+            is_genesis: false,
             coinbase,
             txs: vec![],
         }
     }
 
+    /// Creates the first block of a chain, which has no parent
+    pub fn genesis(address: Vec<&VerifyingKey<Sha256>>, threshold: usize) -> Self {
+        Self {
+            is_genesis: true,
+            ..Self::new([0; 32], address, threshold)
+        }
+    }
+
     pub fn finalize(self) -> Block {
         let raw = self.raw();
 
@@ -33,6 +43,7 @@ impl IncompleteBlock {
         Block {
             hash: hasher.finalize().into(),
             prev: self.prev,
+            is_genesis: self.is_genesis,
             coinbase: self.coinbase,
             txs: self.txs,
         }
@@ -42,13 +53,43 @@ impl IncompleteBlock {
         self.txs.push(tx);
     }
 
+    /// Raises value of the coinbase to `COINBASE + fees`, where fees are paid
+    /// by txs of this block. Returns `false` and keeps the coinbase if the
+    /// value doesn't fit into an output.
+    pub fn claim_fees(&mut self, fees: u64) -> bool {
+        let Some(value) = u32::try_from(fees)
+            .ok()
+            .and_then(|fees| COINBASE.checked_add(fees))
+        else {
+            return false;
+        };
+        let output = self.coinbase.output(0).expect("coinbase has one output");
+        self.coinbase = Tx::coinbase(
+            value,
+            output.verifiers().iter().collect(),
+            output.threshold(),
+        );
+        true
+    }
+
+    /// Adds all given txs, skipping those which are already in this block
+    pub fn add_txs(&mut self, txs: impl IntoIterator<Item = Tx>) {
+        for tx in txs {
+            if tx.hash() == self.coinbase.hash() || self.txs.iter().any(|t| t.hash() == tx.hash()) {
+                log::debug!("tx {:?} is already in block", tx.hash());
+                continue;
+            }
+            self.txs.push(tx);
+        }
+    }
+
     fn raw(&self) -> Vec<u8> {
         let mut b = vec![];
 
-        if !self.prev.iter().all(|&x| x == 0) {
-            // not a genesis block
+        if !self.is_genesis {
             b.extend(self.prev);
         }
+        b.extend(self.coinbase.hash());
 
         for tx in self.txs.iter() {
             b.extend(tx.hash());
@@ -62,6 +103,7 @@ impl IncompleteBlock {
 pub struct Block {
     hash: Sha256Digest,
     prev: Sha256Digest,
+    is_genesis: bool,
     coinbase: Tx,
     txs: Vec<Tx>,
 }
@@ -83,6 +125,10 @@ impl Block {
         self.prev
     }
 
+    pub fn is_genesis(&self) -> bool {
+        self.is_genesis
+    }
+
     /// # DO NOT USE, don't use this function outside tests!
     pub fn set_prev(&mut self, prev: Sha256Digest) {
         self.prev = prev;
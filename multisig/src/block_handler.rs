@@ -4,8 +4,9 @@ use sha2::Sha256;
 use crate::{
     block::{Block, IncompleteBlock},
     blockchain::Blockchain,
-    handler::Handler,
+    handler::{calc_fees, Handler},
     tx::Tx,
+    utxo::UTXOPool,
 };
 
 #[derive(Debug)]
@@ -18,6 +19,10 @@ impl BlockHandler {
         Self { chain }
     }
 
+    pub fn chain(&self) -> &Blockchain {
+        &self.chain
+    }
+
     pub fn hash_at_max_height(&self) -> [u8; 32] {
         self.chain.block_at_max_height().hash()
     }
@@ -26,11 +31,52 @@ impl BlockHandler {
         self.chain.add_block(block)
     }
 
+    /// Adds blocks in given order, e.g. received during sync. Blocks whose
+    /// parent comes later in the batch are kept until it is added. Returns
+    /// for each block whether it was added to the chain. Unlike in the
+    /// blockchain crate, this chain has no orphan buffer, so waiting blocks
+    /// aren't limited in number, but those whose parent isn't in the batch
+    /// are dropped once it is processed.
+    pub fn process_blocks(&mut self, blocks: Vec<Block>) -> Vec<bool> {
+        let mut accepted = vec![false; blocks.len()];
+        let mut waiting: Vec<(usize, Block)> = vec![];
+        for (idx, block) in blocks.into_iter().enumerate() {
+            if !block.is_genesis() && self.chain.at_block_hash(block.prev()).is_none() {
+                waiting.push((idx, block));
+                continue;
+            }
+            let hash = block.hash();
+            if !self.process_block(block) {
+                continue;
+            }
+            accepted[idx] = true;
+
+            let mut parents = vec![hash];
+            while let Some(parent) = parents.pop() {
+                let (children, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut waiting)
+                    .into_iter()
+                    .partition(|(_, block)| block.prev() == parent);
+                waiting = rest;
+                for (idx, child) in children {
+                    let hash = child.hash();
+                    if self.process_block(child) {
+                        accepted[idx] = true;
+                        parents.push(hash);
+                    }
+                }
+            }
+        }
+        accepted
+    }
+
     pub fn process_tx(&mut self, tx: Tx) {
         self.chain.add_tx(tx);
     }
 
-    pub fn create_block(&self, address: Vec<&VerifyingKey<Sha256>>, threshold: usize) -> Block {
+    /// Creates a block on top of the block at max height with valid txs from
+    /// the mempool. Each created block starts a new mempool round, see
+    /// `Blockchain::advance_mempool_round`.
+    pub fn create_block(&mut self, address: Vec<&VerifyingKey<Sha256>>, threshold: usize) -> Block {
         let parent = self.chain.block_at_max_height();
         let mut new_b = IncompleteBlock::new(parent.hash(), address, threshold);
 
@@ -41,10 +87,10 @@ impl BlockHandler {
         let txs = tx_pool.txs();
         let handled = handler.handle(txs);
 
-        for &tx in handled.iter() {
-            new_b.add_tx(tx.clone());
-        }
-        new_b.finalize()
+        add_claimable_txs(&mut new_b, utxo_pool, handled);
+        let block = new_b.finalize();
+        self.chain.advance_mempool_round();
+        block
     }
 
     pub fn create_fork(
@@ -61,9 +107,19 @@ impl BlockHandler {
         let txs = tx_pool.txs();
         let handled = handler.handle(txs);
 
-        for &tx in handled.iter() {
-            new_b.add_tx(tx.clone());
-        }
+        add_claimable_txs(&mut new_b, utxo_pool, handled);
         Some(new_b.finalize())
     }
 }
+
+/// Adds txs to the block and claims their fees. Txs are dropped from the end
+/// until their fees fit into the coinbase, handled txs come after their
+/// parents, so the remaining ones stay valid.
+fn add_claimable_txs(block: &mut IncompleteBlock, pool: &UTXOPool, mut txs: Vec<&Tx>) {
+    while !block.claim_fees(calc_fees(pool, &txs)) {
+        txs.pop();
+    }
+    for tx in txs {
+        block.add_tx(tx.clone());
+    }
+}
@@ -1,9 +1,21 @@
+use core::fmt;
+
 use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
 
-use crate::{block::Block, handler::Handler, tx::Tx, tx_pool::TxPool, utxo::UTXOPool};
+use crate::{
+    block::{Block, COINBASE},
+    handler::{calc_fees, Handler},
+    tx::Tx,
+    tx_pool::TxPool,
+    utxo::UTXOPool,
+};
 
 pub const CUT_OFF_AGE: usize = 12;
 
+/// Number of mempool rounds for which a tx stays pending, see
+/// `Blockchain::advance_mempool_round`
+pub const MEMPOOL_EXPIRY: u64 = 50;
+
 pub type BlockNode = (Block, UTXOPool);
 
 #[derive(Debug)]
@@ -13,11 +25,20 @@ pub struct Blockchain {
 }
 
 impl Blockchain {
+    /// Panics if the block isn't a genesis block, see `try_new`
     pub fn new(genesis: Block, utxo_pool: UTXOPool) -> Self {
+        Self::try_new(genesis, utxo_pool).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as `new`, but fails instead of panicking
+    pub fn try_new(genesis: Block, utxo_pool: UTXOPool) -> Result<Self, GenesisError> {
+        if !genesis.is_genesis() {
+            return Err(GenesisError::NotGenesis);
+        }
         let mut chain = ConstGenericRingBuffer::new();
         chain.push((genesis, utxo_pool));
         let mempool = TxPool::new();
-        Self { chain, mempool }
+        Ok(Self { chain, mempool })
     }
 
     pub fn at_block_hash(&self, hash: [u8; 32]) -> Option<&BlockNode> {
@@ -45,19 +66,34 @@ impl Blockchain {
     }
 
     pub fn add_block(&mut self, block: Block) -> bool {
+        if block.is_genesis() {
+            log::warn!("Chain already has a genesis block!");
+            return false;
+        }
         let node = match self.at_block_hash(block.prev()) {
             Some(parent) => parent,
             None => return false,
         };
 
         let mut handler = Handler::new(node.1.clone());
-        let txs: Vec<&Tx> = block.txs().iter().map(|tx| tx).collect();
+        let txs: Vec<&Tx> = block.txs().iter().collect();
 
         if handler.handle(txs).len() != block.txs().len() {
             log::warn!("Block contained invalid txs!");
             return false;
         };
 
+        // coinbase must mint exactly the block reward and fees of its txs
+        let coinbase = block.coinbase();
+        let fees = calc_fees(&node.1, &block.txs().iter().collect::<Vec<_>>());
+        let is_coinbase_valid = coinbase.is_coinbase()
+            && coinbase.output_len() == 1
+            && coinbase.output(0).map(|o| o.value() as u64) == Some(COINBASE as u64 + fees);
+        if !is_coinbase_valid {
+            log::warn!("Block contained invalid coinbase!");
+            return false;
+        }
+
         for tx in block.txs().iter() {
             self.mempool.remove(tx.hash());
         }
@@ -66,7 +102,29 @@ impl Blockchain {
         true
     }
 
+    /// Starts a new mempool round and drops txs admitted more than
+    /// `MEMPOOL_EXPIRY` rounds ago, returns their hashes
+    pub fn advance_mempool_round(&mut self) -> Vec<[u8; 32]> {
+        self.mempool.advance_round();
+        let cutoff = self.mempool.round().saturating_sub(MEMPOOL_EXPIRY);
+        self.mempool.expire_older_than(cutoff)
+    }
+
     pub fn add_tx(&mut self, tx: Tx) {
         self.mempool.add(tx);
     }
 }
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum GenesisError {
+    NotGenesis,
+}
+
+impl fmt::Display for GenesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenesisError::NotGenesis => write!(f, "chain must start with a genesis block"),
+        }
+    }
+}
+impl std::error::Error for GenesisError {}
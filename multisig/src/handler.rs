@@ -1,9 +1,16 @@
-use std::collections::HashSet;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
 
-use rsa::signature::Verifier;
+use rsa::{
+    pkcs1v15::{Signature, VerifyingKey},
+    signature::Verifier,
+};
+use sha2::Sha256;
 
 use crate::{
-    tx::{Input, Tx},
+    tx::{Input, Tx, MAX_OUTPUTS},
     utxo::{UTXOPool, UTXO},
 };
 
@@ -38,11 +45,17 @@ impl Handler {
         let mut to_handle = possible_txs;
 
         loop {
+            let pending = to_handle.len();
             let (independent, dependent) = self.handle_independent(to_handle);
             handled.extend(independent);
             if dependent.is_empty() {
                 break;
             }
+            if dependent.len() == pending {
+                // no tx was handled nor dropped, so the next round would be the same
+                log::debug!("{} txs made no progress, giving up", dependent.len());
+                break;
+            }
             to_handle = dependent;
         }
 
@@ -53,23 +66,31 @@ impl Handler {
         for input in tx.inputs().iter() {
             self.pool.remove_utxo(&input.into());
         }
-        for (i, output) in tx.outputs().iter().enumerate() {
-            let utxo = UTXO::new(tx.hash(), i.try_into().unwrap());
-            self.pool.add_utxo(utxo, &output)
+        // valid txs have at most `MAX_OUTPUTS` outputs, others can't be
+        // addressed by `u8` indices
+        for (i, output) in (0..=u8::MAX).zip(tx.outputs().iter()) {
+            self.pool.add_utxo(UTXO::new(tx.hash(), i), output)
         }
     }
 
     /// Checks if:
     ///     1. All UTXO inputs are in pool
     ///     2. Signatures on inputs are valid and there are enough of them
-    ///         to satisfy correspondings output multisig threshold
+    ///         to satisfy correspondings output multisig threshold, each
+    ///         made by a distinct key, even if the output lists a key twice
     ///     3. No UTXO is used more than once
     ///     4. Sum of outputs is not negative
     ///     5. Sum of inputs >= Sum of outputs
+    ///     6. There are at most `MAX_OUTPUTS` outputs, so all are spendable
     pub fn is_tx_valid(&self, tx: &Tx) -> bool {
+        if tx.output_len() > MAX_OUTPUTS {
+            log::debug!("tx has {} outputs, max is {}", tx.output_len(), MAX_OUTPUTS);
+            return false;
+        }
+
         let mut in_sum = 0;
         let mut used_outputs: HashSet<UTXO> = HashSet::new();
-        for input in tx.inputs().into_iter() {
+        for input in tx.inputs().iter() {
             if used_outputs.contains(&input.into()) {
                 log::debug!(
                     "output {:?}-{} already used in same tx!",
@@ -93,16 +114,12 @@ impl Handler {
             };
 
             let raw_tx = tx.raw_tx();
-            let mut valid_sigs = 0;
-
-            for signature in input.signatures().into_iter() {
-                for verifier in output.verifiers().into_iter() {
-                    if verifier.verify(&raw_tx, signature).is_ok() {
-                        valid_sigs += 1;
-                        break;
-                    }
-                }
-            }
+            let valid_sigs = valid_signatures(
+                &raw_tx,
+                input.signatures(),
+                &distinct_verifiers(output.verifiers()),
+                output.threshold(),
+            );
             if valid_sigs < output.threshold() {
                 log::debug!(
                     "there were only {} valid signatures, need {}",
@@ -146,3 +163,80 @@ impl Handler {
         (handled, dependent)
     }
 }
+
+/// Returns sum of fees paid by given txs. Inputs are looked up in the pool and
+/// in outputs of the given txs, so txs spending outputs of each other can be
+/// passed in any order. Txs must be valid, unknown inputs are ignored.
+pub fn calc_fees(pool: &UTXOPool, txs: &[&Tx]) -> u64 {
+    let tx_map: HashMap<[u8; 32], &Tx> = txs.iter().map(|&tx| (tx.hash(), tx)).collect();
+
+    let mut fees: u64 = 0;
+    for tx in txs.iter() {
+        let input_value: u64 = tx
+            .inputs()
+            .iter()
+            .filter_map(|input| {
+                pool.utxo_output(&input.into()).or_else(|| {
+                    tx_map
+                        .get(&input.output_tx_hash())?
+                        .output(input.output_idx())
+                })
+            })
+            .map(|output| output.value() as u64)
+            .sum();
+        let output_value: u64 = tx.outputs().iter().map(|o| o.value() as u64).sum();
+        fees += input_value.saturating_sub(output_value);
+    }
+    fees
+}
+
+/// Returns verifiers without repeated keys, in their original order. A key
+/// listed multiple times in an output can still sign only once, so the
+/// threshold is always reached by distinct keys.
+fn distinct_verifiers(verifiers: &[VerifyingKey<Sha256>]) -> Cow<'_, [VerifyingKey<Sha256>]> {
+    let is_repeated = |i: usize| {
+        verifiers[..i]
+            .iter()
+            .any(|v| v.as_ref() == verifiers[i].as_ref())
+    };
+    if !(0..verifiers.len()).any(is_repeated) {
+        return Cow::Borrowed(verifiers);
+    }
+    let distinct = (0..verifiers.len())
+        .filter(|&i| !is_repeated(i))
+        .map(|i| verifiers[i].clone())
+        .collect();
+    Cow::Owned(distinct)
+}
+
+/// Counts signatures of `msg`, each made by a different verifier. Every verifier
+/// can be matched by at most one signature and counting stops once `threshold`
+/// is reached, so no more verifications than needed are done.
+pub fn valid_signatures<V: Verifier<Signature>>(
+    msg: &[u8],
+    signatures: &[Signature],
+    verifiers: &[V],
+    threshold: usize,
+) -> usize {
+    let mut matched = vec![false; verifiers.len()];
+    let mut valid_sigs = 0;
+
+    for signature in signatures.iter() {
+        if valid_sigs >= threshold {
+            break;
+        }
+
+        for (i, verifier) in verifiers.iter().enumerate() {
+            if matched[i] {
+                continue;
+            }
+            if verifier.verify(msg, signature).is_ok() {
+                matched[i] = true;
+                valid_sigs += 1;
+                break;
+            }
+        }
+    }
+
+    valid_sigs
+}
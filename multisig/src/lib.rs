@@ -2,6 +2,9 @@ pub mod block;
 pub mod block_handler;
 pub mod blockchain;
 pub mod handler;
+#[cfg(feature = "test-util")]
+pub mod testing;
 pub mod tx;
 pub mod tx_pool;
 pub mod utxo;
+pub mod wire;
@@ -0,0 +1,155 @@
+//! Helpers for tests of code built on top of this crate, available with the
+//! `test-util` feature.
+//!
+//! ```
+//! use multisig::testing::{setup_block_handler, Wallet};
+//!
+//! // small keys make tests fast, never use them outside tests
+//! let bob = Wallet::random_with_key_bits(3, 2, 512);
+//! let (mut handler, genesis_tx) = setup_block_handler(&bob);
+//!
+//! assert_eq!(3, genesis_tx.output(0).unwrap().verifiers().len());
+//! let block = handler.create_block(bob.verifiers(), bob.threshold());
+//! assert!(handler.process_block(block));
+//! ```
+
+use rsa::{
+    pkcs1v15::{SigningKey, VerifyingKey},
+    signature::Keypair,
+    RsaPrivateKey,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    block::{Block, IncompleteBlock},
+    block_handler::BlockHandler,
+    blockchain::Blockchain,
+    handler::Handler,
+    tx::{Tx, UnsignedTx},
+    utxo::{UTXOPool, UTXO},
+};
+
+/// Size of keys generated by `KeyPair::new`
+pub const DEFAULT_KEY_BITS: usize = 1024;
+
+/// Owner of multisig outputs, `threshold` of its keys must sign to spend them
+#[derive(Debug, Clone)]
+pub struct Wallet {
+    keys: Vec<KeyPair>,
+    threshold: usize,
+}
+
+impl Wallet {
+    pub fn new(key: KeyPair) -> Self {
+        Self {
+            keys: vec![key],
+            threshold: 1,
+        }
+    }
+
+    pub fn random(n: usize, threshold: usize) -> Self {
+        Self::random_with_key_bits(n, threshold, DEFAULT_KEY_BITS)
+    }
+
+    pub fn random_with_key_bits(n: usize, threshold: usize, bits: usize) -> Self {
+        let mut keys = vec![];
+        for _ in 0..n {
+            keys.push(KeyPair::with_key_bits(bits));
+        }
+        Self { keys, threshold }
+    }
+
+    pub fn multisig(keys: Vec<KeyPair>, threshold: usize) -> Self {
+        Self { keys, threshold }
+    }
+
+    pub fn keys(&self) -> &[KeyPair] {
+        &self.keys
+    }
+
+    pub fn verifiers(&self) -> Vec<&VerifyingKey<Sha256>> {
+        self.keys.iter().map(|kp| &kp.vk).collect()
+    }
+
+    pub fn signers(&self) -> Vec<&SigningKey<Sha256>> {
+        self.keys.iter().map(|kp| &kp.sk).collect()
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyPair {
+    pub sk: SigningKey<Sha256>,
+    pub vk: VerifyingKey<Sha256>,
+}
+
+/// Only use in tests! Generated keys are by default of length 1024, not
+/// sufficiently secure by today's standards.
+impl KeyPair {
+    pub fn new() -> Self {
+        Self::with_key_bits(DEFAULT_KEY_BITS)
+    }
+
+    pub fn with_key_bits(bits: usize) -> Self {
+        let mut rng = rand::thread_rng();
+        let priv_key = RsaPrivateKey::new(&mut rng, bits).expect("failed to generate a key");
+        let sk = SigningKey::<Sha256>::new(priv_key);
+        let vk = sk.verifying_key();
+
+        Self { sk, vk }
+    }
+}
+
+impl Default for KeyPair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Creates a chain, whose genesis coinbase is owned by the `receiver`
+pub fn setup_block_handler(receiver: &Wallet) -> (BlockHandler, Tx) {
+    let genesis = IncompleteBlock::genesis(receiver.verifiers(), receiver.threshold).finalize();
+    let (pool, genesis_tx) = setup_genesis_pool(&genesis);
+    let chain = Blockchain::new(genesis, pool);
+    (BlockHandler::new(chain), genesis_tx)
+}
+
+/// Creates a pool containing only the coinbase of the genesis block
+pub fn setup_genesis_pool(genesis_block: &Block) -> (UTXOPool, Tx) {
+    let coinbase = genesis_block.coinbase();
+    let root_utxo = UTXO::new(coinbase.hash(), 0);
+    let utxo_pool = UTXOPool::from_iter([(root_utxo, coinbase.output(0).unwrap().clone())]);
+    (utxo_pool, coinbase.clone())
+}
+
+/// Same as `setup_pool`, but the pool is wrapped in a `Handler`
+pub fn setup_handler(receiver: &Wallet, output_value: u32, root_outputs: u8) -> (Handler, Tx) {
+    let (utxo_pool, genesis_tx) = setup_pool(receiver, output_value, root_outputs);
+    (Handler::new(utxo_pool), genesis_tx)
+}
+
+/// Creates a pool with one root tx, which has `root_outputs` outputs of
+/// `output_value` owned by the `receiver`
+pub fn setup_pool(receiver: &Wallet, output_value: u32, root_outputs: u8) -> (UTXOPool, Tx) {
+    let mut hasher = Sha256::new();
+    hasher.update("genesis-hash");
+    let genesis_hash: [u8; 32] = hasher.finalize().into();
+
+    let mut root_tx = UnsignedTx::new();
+    for _ in 0..root_outputs {
+        root_tx.add_output(output_value, receiver.verifiers(), receiver.threshold);
+    }
+    root_tx.add_input(genesis_hash, 0);
+    // `root_outputs` is at most `u8::MAX`, so the tx has few enough outputs
+    let root_tx = root_tx.finalize(receiver.signers()).unwrap();
+
+    let utxo_pool = (0..=u8::MAX)
+        .zip(root_tx.outputs().iter())
+        .map(|(i, output)| (UTXO::new(root_tx.hash(), i), output.clone()))
+        .collect();
+
+    (utxo_pool, root_tx)
+}
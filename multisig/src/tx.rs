@@ -1,13 +1,21 @@
+use core::fmt;
+
+use fiitcoin::hash::to_hex;
 use rsa::{
     pkcs1v15::{Signature, SigningKey, VerifyingKey},
-    signature::Signer,
+    signature::{Keypair, Signer},
     traits::PublicKeyParts,
 };
 
 use sha2::{Digest, Sha256};
 
+use crate::wire::{write_key, write_signature, Reader, WireError, MAX_WIRE_TX_SIZE, WIRE_VERSION};
+
 pub type Hash = [u8; 32];
 
+/// Maximum number of outputs of a tx, outputs are indexed by `u8`
+pub const MAX_OUTPUTS: usize = u8::MAX as usize + 1;
+
 pub struct UnsignedTx {
     inputs: Vec<UnsignedInput>,
     outputs: Vec<Output>,
@@ -21,30 +29,65 @@ impl UnsignedTx {
         }
     }
 
-    pub fn finalize(self, signers: Vec<&SigningKey<Sha256>>) -> Tx {
+    /// Signs every input by all signers. Signatures of an input are ordered
+    /// by fingerprints of signers' keys, so the tx is the same no matter in
+    /// which order the signers are given. Fails if the tx has more than
+    /// `MAX_OUTPUTS` outputs.
+    pub fn finalize(self, mut signers: Vec<&SigningKey<Sha256>>) -> Result<Tx, TxError> {
+        self.check_outputs()?;
+        signers.sort_by_cached_key(|signer| key_fingerprint(&signer.verifying_key()));
         let raw_tx = self.raw_tx();
-        let mut inputs = vec![];
-        for input in self.inputs.iter() {
+        let mut per_input = vec![];
+        for _ in self.inputs.iter() {
             let mut signatures = vec![];
             for signer in signers.iter() {
                 let signature = signer.sign(&raw_tx);
                 signatures.push(signature);
             }
+            per_input.push(signatures);
+        }
+
+        self.attach_signatures(per_input)
+    }
+
+    /// Returns bytes which must be signed by owners of outputs referenced by
+    /// inputs, e.g. by external signers without exposing their private keys
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        self.raw_tx()
+    }
+
+    /// Finalizes this transaction with signatures produced outside of this tx
+    /// by signing `signing_bytes`. `ith` entry contains signatures of `ith` input,
+    /// inputs without an entry are left without signatures. Like `finalize`,
+    /// it fails if the tx has more than `MAX_OUTPUTS` outputs.
+    pub fn attach_signatures(self, per_input: Vec<Vec<Signature>>) -> Result<Tx, TxError> {
+        self.check_outputs()?;
+        let raw_tx = self.raw_tx();
+        let mut per_input = per_input.into_iter();
+        let mut inputs = vec![];
+        for input in self.inputs.iter() {
             inputs.push(Input {
                 output_tx_hash: input.output_tx_hash,
                 output_idx: input.output_idx,
-                signatures,
+                signatures: per_input.next().unwrap_or_default(),
             });
         }
 
         let mut hasher = Sha256::new();
         hasher.update(raw_tx);
         let hash = hasher.finalize().into();
-        Tx {
+        Ok(Tx {
             hash,
             inputs,
             outputs: self.outputs,
+        })
+    }
+
+    fn check_outputs(&self) -> Result<(), TxError> {
+        if self.outputs.len() > MAX_OUTPUTS {
+            return Err(TxError::TooManyOutputs(self.outputs.len()));
         }
+        Ok(())
     }
 
     /// Returns representation of this transaction in bytes
@@ -54,13 +97,7 @@ impl UnsignedTx {
             tx.extend(input.output_tx_hash);
             tx.push(input.output_idx);
         }
-        for output in self.outputs.iter() {
-            tx.extend(output.value.to_be_bytes());
-            for verifying_key in output.verifiers.iter() {
-                tx.extend(verifying_key.as_ref().e().to_bytes_be());
-                tx.extend(verifying_key.as_ref().n().to_bytes_be());
-            }
-        }
+        write_raw_outputs(&mut tx, &self.outputs);
         tx
     }
 
@@ -71,13 +108,15 @@ impl UnsignedTx {
         })
     }
 
+    /// Adds an output and returns its index, which wraps around after
+    /// `MAX_OUTPUTS` outputs, but such tx can't be finalized anyway
     pub fn add_output(
         &mut self,
         value: u32,
         verifiers: Vec<&VerifyingKey<Sha256>>,
         threshold: usize,
-    ) {
-        let verifiers = verifiers.into_iter().map(|v| v.clone()).collect();
+    ) -> u8 {
+        let verifiers = verifiers.into_iter().cloned().collect();
         self.outputs.push({
             Output {
                 value,
@@ -85,6 +124,19 @@ impl UnsignedTx {
                 threshold,
             }
         });
+        (self.outputs.len() - 1) as u8
+    }
+
+    /// Number of outputs added so far, a tx with more than `MAX_OUTPUTS`
+    /// outputs can't be finalized
+    pub fn output_count(&self) -> usize {
+        self.outputs.len()
+    }
+}
+
+impl Default for UnsignedTx {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -100,7 +152,7 @@ impl Tx {
         let mut unsigned = UnsignedTx::new();
         unsigned.add_output(value, address, threshold);
         // coinbase txs don't have inputs, so no signers are needed
-        unsigned.finalize(vec![])
+        unsigned.finalize(vec![]).expect("coinbase has one output")
     }
 
     pub fn hash(&self) -> [u8; 32] {
@@ -115,6 +167,19 @@ impl Tx {
         self.outputs.len()
     }
 
+    pub fn input(&self, idx: u8) -> Option<&Input> {
+        self.inputs.get(usize::from(idx))
+    }
+
+    pub fn input_len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Coinbase txs mint new coins, so they are the only txs without inputs
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
     pub fn inputs(&self) -> &Vec<Input> {
         &self.inputs
     }
@@ -130,14 +195,101 @@ impl Tx {
             tx.extend(input.output_tx_hash);
             tx.push(input.output_idx);
         }
+        write_raw_outputs(&mut tx, &self.outputs);
+        tx
+    }
+
+    /// Encodes this tx for sending it to other nodes. The encoding starts
+    /// with `WIRE_VERSION` and the hash, every input carries the number of
+    /// its signatures and every output the number of its verifiers and the
+    /// threshold, so it can be decoded by `from_wire`.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut b = vec![WIRE_VERSION];
+        b.extend(self.hash);
+
+        b.extend((self.inputs.len() as u32).to_be_bytes());
+        for input in self.inputs.iter() {
+            b.extend(input.output_tx_hash);
+            b.push(input.output_idx);
+            b.extend((input.signatures.len() as u32).to_be_bytes());
+            for signature in input.signatures.iter() {
+                write_signature(&mut b, signature);
+            }
+        }
+
+        b.extend((self.outputs.len() as u32).to_be_bytes());
         for output in self.outputs.iter() {
-            tx.extend(output.value.to_be_bytes());
-            for verifying_key in output.verifiers.iter() {
-                tx.extend(verifying_key.as_ref().e().to_bytes_be());
-                tx.extend(verifying_key.as_ref().n().to_bytes_be());
+            b.extend(output.value.to_be_bytes());
+            b.extend((output.verifiers.len() as u32).to_be_bytes());
+            for verifier in output.verifiers.iter() {
+                write_key(&mut b, verifier);
             }
+            b.extend((output.threshold as u64).to_be_bytes());
         }
-        tx
+        b
+    }
+
+    /// Decodes tx encoded by `to_wire`. Its hash is recomputed from the
+    /// decoded contents and must match the sent one. Encodings longer than
+    /// `MAX_WIRE_TX_SIZE` aren't parsed at all.
+    pub fn from_wire(bytes: &[u8]) -> Result<Tx, WireError> {
+        if bytes.len() > MAX_WIRE_TX_SIZE {
+            return Err(WireError::TooLarge(bytes.len()));
+        }
+        let mut reader = Reader::new(bytes);
+        let version = reader.u8()?;
+        if version != WIRE_VERSION {
+            return Err(WireError::UnsupportedVersion(version));
+        }
+        let hash = reader.hash()?;
+
+        // counts aren't trusted for preallocation, they are only as long as
+        // the bytes really are
+        let mut inputs = vec![];
+        for _ in 0..reader.u32()? {
+            let output_tx_hash = reader.hash()?;
+            let output_idx = reader.u8()?;
+            let mut signatures = vec![];
+            for _ in 0..reader.u32()? {
+                signatures.push(reader.signature()?);
+            }
+            inputs.push(Input {
+                output_tx_hash,
+                output_idx,
+                signatures,
+            });
+        }
+
+        let mut outputs = vec![];
+        for _ in 0..reader.u32()? {
+            let value = reader.u32()?;
+            let mut verifiers = vec![];
+            for _ in 0..reader.u32()? {
+                verifiers.push(reader.key()?);
+            }
+            let threshold = reader.u64()?;
+            let threshold = threshold
+                .try_into()
+                .map_err(|_| WireError::InvalidThreshold(threshold))?;
+            outputs.push(Output {
+                value,
+                verifiers,
+                threshold,
+            });
+        }
+        reader.finish()?;
+
+        let tx = Tx {
+            hash,
+            inputs,
+            outputs,
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(tx.raw_tx());
+        if <[u8; 32]>::from(hasher.finalize()) != hash {
+            return Err(WireError::HashMismatch);
+        }
+        Ok(tx)
     }
 }
 
@@ -220,4 +372,101 @@ impl Output {
     pub fn threshold(&self) -> usize {
         self.threshold
     }
+
+    /// Short human readable identifier of the owners, e.g. `2-of-3 [fp, fp, fp]`,
+    /// where each fingerprint is first 4 bytes of SHA-256 of the owner's key
+    pub fn fingerprint(&self) -> String {
+        let fingerprints: Vec<String> = self
+            .verifiers
+            .iter()
+            .map(|vk| to_hex(&key_fingerprint(vk)[..4]))
+            .collect();
+        format!(
+            "{}-of-{} [{}]",
+            self.threshold,
+            self.verifiers.len(),
+            fingerprints.join(", ")
+        )
+    }
+}
+
+/// Writes outputs as they are hashed and signed, each with the number of its
+/// verifiers, their length prefixed keys and the threshold, so no part of an
+/// output can be changed without changing the hash
+fn write_raw_outputs(tx: &mut Vec<u8>, outputs: &[Output]) {
+    for output in outputs.iter() {
+        tx.extend(output.value.to_be_bytes());
+        tx.extend((output.verifiers.len() as u32).to_be_bytes());
+        for verifying_key in output.verifiers.iter() {
+            write_key(tx, verifying_key);
+        }
+        tx.extend((output.threshold as u64).to_be_bytes());
+    }
+}
+
+/// SHA-256 of the key's exponent followed by its modulus
+fn key_fingerprint(vk: &VerifyingKey<Sha256>) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(vk.as_ref().e().to_bytes_be());
+    hasher.update(vk.as_ref().n().to_bytes_be());
+    hasher.finalize().into()
+}
+
+/// Outputs are equal if they pay the same value to the same owners in the
+/// same order with the same threshold
+impl PartialEq for Output {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+            && self.threshold == other.threshold
+            && self.verifiers.len() == other.verifiers.len()
+            && self
+                .verifiers
+                .iter()
+                .zip(other.verifiers.iter())
+                .all(|(v1, v2)| v1.as_ref() == v2.as_ref())
+    }
+}
+
+impl Eq for Output {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TxError {
+    TooManyOutputs(usize),
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxError::TooManyOutputs(len) => {
+                write!(f, "tx has {} outputs, max is {}", len, MAX_OUTPUTS)
+            }
+        }
+    }
+}
+impl std::error::Error for TxError {}
+
+/// Summarizes the tx, inputs are shown as shortened outpoints and outputs
+/// with fingerprints of their owners
+impl fmt::Display for Tx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|i| format!("{}:{}", to_hex(&i.output_tx_hash[..4]), i.output_idx))
+            .collect();
+        let outputs: Vec<String> = self
+            .outputs
+            .iter()
+            .map(|o| format!("{} -> {}", o.value, o.fingerprint()))
+            .collect();
+        write!(
+            f,
+            "tx {} | {} inputs [{}] | {} outputs [{}]",
+            to_hex(&self.hash),
+            inputs.len(),
+            inputs.join(", "),
+            outputs.len(),
+            outputs.join(", ")
+        )
+    }
 }
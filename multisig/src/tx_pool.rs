@@ -2,31 +2,82 @@ use std::collections::HashMap;
 
 use crate::tx::{Hash, Tx};
 
+/// Pending txs, each remembers the round in which it was admitted, so txs
+/// which never get into a block can expire
 #[derive(Debug)]
 pub struct TxPool {
-    txs: HashMap<Hash, Tx>,
+    txs: HashMap<Hash, PooledTx>,
+    round: u64,
+}
+
+#[derive(Debug)]
+struct PooledTx {
+    tx: Tx,
+    admitted: u64,
 }
 
 impl TxPool {
     pub fn new() -> Self {
         Self {
             txs: HashMap::new(),
+            round: 0,
         }
     }
 
     pub fn txs(&self) -> Vec<&Tx> {
-        self.txs.values().collect()
+        self.txs.values().map(|pooled| &pooled.tx).collect()
     }
 
     pub fn tx(&self, hash: [u8; 32]) -> Option<&Tx> {
-        self.txs.get(&hash)
+        self.txs.get(&hash).map(|pooled| &pooled.tx)
+    }
+
+    /// Current admission round, newly added txs are admitted in it
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    pub fn advance_round(&mut self) {
+        self.round += 1;
     }
 
+    /// Round in which the tx was admitted, `None` if it isn't pooled
+    pub fn admitted_in(&self, hash: [u8; 32]) -> Option<u64> {
+        self.txs.get(&hash).map(|pooled| pooled.admitted)
+    }
+
+    /// Removes txs admitted before the `cutoff` round and returns their
+    /// sorted hashes
+    pub fn expire_older_than(&mut self, cutoff: u64) -> Vec<Hash> {
+        let mut expired: Vec<Hash> = self
+            .txs
+            .iter()
+            .filter(|(_, pooled)| pooled.admitted < cutoff)
+            .map(|(&hash, _)| hash)
+            .collect();
+        expired.sort();
+        for hash in expired.iter() {
+            self.txs.remove(hash);
+        }
+        expired
+    }
+
+    /// Adds tx admitted in the current round, a tx which is already pooled
+    /// keeps its original admission round
     pub fn add(&mut self, tx: Tx) {
-        self.txs.insert(tx.hash(), tx);
+        let admitted = self.round;
+        self.txs
+            .entry(tx.hash())
+            .or_insert(PooledTx { tx, admitted });
     }
 
     pub fn remove(&mut self, hash: [u8; 32]) {
         self.txs.remove(&hash);
     }
 }
+
+impl Default for TxPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,8 @@
 use std::collections::HashMap;
 
+use rsa::pkcs1v15::VerifyingKey;
+use sha2::Sha256;
+
 use crate::tx::Output;
 
 #[derive(Eq, PartialEq, Hash, Clone, Debug)]
@@ -27,7 +30,8 @@ impl UTXO {
     }
 }
 
-#[derive(Clone, Debug)]
+/// Pools are equal when they contain the same UTXOs with equal outputs
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct UTXOPool {
     /// collection of unspent UTXO mapped to corresponding tx output
     utxos: HashMap<UTXO, Output>,
@@ -52,7 +56,43 @@ impl UTXOPool {
         self.utxos.get(utxo)
     }
 
+    /// Looks up outputs of all given UTXOs, results are in the same order
+    pub fn get_many<'a>(&self, utxos: impl IntoIterator<Item = &'a UTXO>) -> Vec<Option<&Output>> {
+        utxos.into_iter().map(|utxo| self.utxos.get(utxo)).collect()
+    }
+
     pub fn contains(&self, utxo: &UTXO) -> bool {
         self.utxos.contains_key(utxo)
     }
+
+    /// Returns unspent outputs, which the key can help to spend, because it
+    /// is one of their verifiers
+    pub fn utxos_spendable_by(&self, vk: &VerifyingKey<Sha256>) -> Vec<(UTXO, &Output)> {
+        self.utxos
+            .iter()
+            .filter(|(_, o)| o.verifiers().iter().any(|v| v.as_ref() == vk.as_ref()))
+            .map(|(utxo, o)| (utxo.clone(), o))
+            .collect()
+    }
+}
+
+impl Default for UTXOPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromIterator<(UTXO, Output)> for UTXOPool {
+    fn from_iter<I: IntoIterator<Item = (UTXO, Output)>>(iter: I) -> Self {
+        Self {
+            utxos: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Adds all given UTXOs, outputs of already present UTXOs are replaced
+impl Extend<(UTXO, Output)> for UTXOPool {
+    fn extend<I: IntoIterator<Item = (UTXO, Output)>>(&mut self, iter: I) {
+        self.utxos.extend(iter)
+    }
 }
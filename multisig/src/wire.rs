@@ -0,0 +1,138 @@
+use core::fmt;
+
+use rsa::{
+    pkcs1v15::{Signature, VerifyingKey},
+    signature::SignatureEncoding,
+    traits::PublicKeyParts,
+    BigUint, RsaPublicKey,
+};
+use sha2::Sha256;
+
+/// Version of the wire encoding, it is the first byte of every encoded tx
+pub const WIRE_VERSION: u8 = 1;
+
+/// Max length of an encoded tx accepted by `Tx::from_wire`, it fits a tx
+/// with `MAX_OUTPUTS` outputs of several owners and as many signed inputs
+pub const MAX_WIRE_TX_SIZE: usize = 1 << 20;
+
+/// Writes exponent and modulus of the key, each prefixed by its length
+pub(crate) fn write_key(bytes: &mut Vec<u8>, key: &VerifyingKey<Sha256>) {
+    for part in [key.as_ref().e(), key.as_ref().n()] {
+        write_bytes(bytes, &part.to_bytes_be());
+    }
+}
+
+pub(crate) fn write_signature(bytes: &mut Vec<u8>, signature: &Signature) {
+    write_bytes(bytes, &signature.to_bytes());
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    bytes.extend((value.len() as u16).to_be_bytes());
+    bytes.extend(value);
+}
+
+/// Reads values written in big endian, fails instead of panicking when
+/// there aren't enough bytes
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], WireError> {
+        if self.bytes.len() < len {
+            return Err(WireError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const L: usize>(&mut self) -> Result<[u8; L], WireError> {
+        Ok(self.take(L)?.try_into().unwrap())
+    }
+
+    /// Reads bytes prefixed by their length
+    fn bytes(&mut self) -> Result<&'a [u8], WireError> {
+        let len = u16::from_be_bytes(self.array()?);
+        self.take(usize::from(len))
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, WireError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, WireError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn hash(&mut self) -> Result<[u8; 32], WireError> {
+        self.array()
+    }
+
+    pub(crate) fn key(&mut self) -> Result<VerifyingKey<Sha256>, WireError> {
+        let e = BigUint::from_bytes_be(self.bytes()?);
+        let n = BigUint::from_bytes_be(self.bytes()?);
+        let key = RsaPublicKey::new(n, e).map_err(|_| WireError::InvalidKey)?;
+        Ok(VerifyingKey::new(key))
+    }
+
+    pub(crate) fn signature(&mut self) -> Result<Signature, WireError> {
+        Signature::try_from(self.bytes()?).map_err(|_| WireError::InvalidSignature)
+    }
+
+    /// Fails if there are bytes left
+    pub(crate) fn finish(self) -> Result<(), WireError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            left => Err(WireError::TrailingBytes(left)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WireError {
+    UnexpectedEnd,
+    UnsupportedVersion(u8),
+    /// Key bytes don't form a valid RSA key
+    InvalidKey,
+    InvalidSignature,
+    /// Threshold doesn't fit into `usize`
+    InvalidThreshold(u64),
+    /// Hash recomputed from the decoded contents differs from the sent one
+    HashMismatch,
+    TrailingBytes(usize),
+    /// Encoding is longer than `MAX_WIRE_TX_SIZE`, so it isn't parsed
+    TooLarge(usize),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::UnexpectedEnd => write!(f, "bytes ended before tx"),
+            WireError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported version {}, expected {}",
+                version, WIRE_VERSION
+            ),
+            WireError::InvalidKey => write!(f, "invalid public key in tx"),
+            WireError::InvalidSignature => write!(f, "invalid signature in tx"),
+            WireError::InvalidThreshold(threshold) => write!(f, "invalid threshold {}", threshold),
+            WireError::HashMismatch => write!(f, "hash doesn't match tx contents"),
+            WireError::TrailingBytes(len) => write!(f, "{} bytes left after tx", len),
+            WireError::TooLarge(len) => write!(
+                f,
+                "encoded tx has {} bytes, max is {}",
+                len, MAX_WIRE_TX_SIZE
+            ),
+        }
+    }
+}
+impl std::error::Error for WireError {}
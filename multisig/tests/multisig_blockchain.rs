@@ -1,8 +1,13 @@
 mod common;
-use common::{initialize, new_tx, Wallet};
-use multisig::utxo::UTXO;
+use common::{initialize, new_tx};
+use multisig::{
+    block::{IncompleteBlock, COINBASE},
+    blockchain::{Blockchain, GenesisError, CUT_OFF_AGE, MEMPOOL_EXPIRY},
+    testing::{setup_block_handler, Wallet},
+    utxo::{UTXOPool, UTXO},
+};
 
-use crate::common::{new_tx_first_n_signers_only, setup_block_handler, NewTxParams};
+use crate::common::{new_tx_first_n_signers_only, NewTxParams};
 
 #[test]
 fn block_with_normal_tx() {
@@ -181,3 +186,242 @@ fn block_with_valid_3_out_of_3() {
     assert_eq!(1, block.txs().len());
     assert!(handler.process_block(block));
 }
+
+#[test]
+fn add_txs_skips_duplicates() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let alice = Wallet::random(1, 1);
+    let (handler, genesis_tx) = setup_block_handler(&bob);
+
+    let tx1 = new_tx(NewTxParams {
+        signer: &bob,
+        inputs: vec![(UTXO::new(genesis_tx.hash(), 0))],
+        outputs: vec![(&alice, 400)],
+        return_to_sender: Some(100),
+    });
+
+    let mut block = IncompleteBlock::new(
+        handler.hash_at_max_height(),
+        bob.verifiers(),
+        bob.threshold(),
+    );
+    block.add_txs(vec![tx1.clone(), tx1.clone()]);
+    block.add_txs(vec![tx1.clone()]);
+    let block = block.finalize();
+    assert_eq!(1, block.txs().len());
+    assert_eq!(tx1.hash(), block.txs()[0].hash());
+}
+
+#[test]
+fn zero_prev_block_is_not_genesis() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let (mut handler, _genesis_tx) = setup_block_handler(&bob);
+
+    let zero_prev = IncompleteBlock::new([0; 32], bob.verifiers(), bob.threshold()).finalize();
+    let genesis = IncompleteBlock::genesis(bob.verifiers(), bob.threshold()).finalize();
+    assert!(!zero_prev.is_genesis());
+    assert!(genesis.is_genesis());
+    assert_ne!(genesis.hash(), zero_prev.hash());
+
+    assert!(!handler.process_block(zero_prev));
+    assert!(!handler.process_block(genesis));
+
+    let zero_prev = IncompleteBlock::new([0; 32], bob.verifiers(), bob.threshold()).finalize();
+    assert!(matches!(
+        Blockchain::try_new(zero_prev, UTXOPool::new()),
+        Err(GenesisError::NotGenesis)
+    ));
+}
+
+#[test]
+fn coinbase_claims_fees() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let alice = Wallet::random(3, 2);
+    let charlie = Wallet::random(2, 2);
+    let (mut handler, genesis_tx) = setup_block_handler(&bob);
+
+    let tx1 = new_tx(NewTxParams {
+        signer: &bob,
+        inputs: vec![(UTXO::new(genesis_tx.hash(), 0))],
+        outputs: vec![(&alice, 400)],
+        return_to_sender: Some(100),
+    });
+    let tx2 = new_tx_first_n_signers_only(
+        NewTxParams {
+            signer: &alice,
+            inputs: vec![(UTXO::new(tx1.hash(), 0))],
+            outputs: vec![(&charlie, 350)],
+            return_to_sender: None,
+        },
+        2,
+    );
+    let fees = (COINBASE - 500) + 50;
+
+    let over_claiming = |fees: u32| {
+        let mut block = IncompleteBlock::new(
+            handler.hash_at_max_height(),
+            charlie.verifiers(),
+            charlie.threshold(),
+        );
+        block.add_txs(vec![tx1.clone(), tx2.clone()]);
+        assert!(block.claim_fees(fees.into()));
+        block.finalize()
+    };
+    let over = over_claiming(fees + 1);
+    let under = over_claiming(fees - 1);
+
+    handler.process_tx(tx1.clone());
+    handler.process_tx(tx2.clone());
+    let block = handler.create_block(charlie.verifiers(), charlie.threshold());
+    assert_eq!(2, block.txs().len());
+    let coinbase = block.coinbase().output(0).unwrap();
+    assert_eq!(COINBASE + fees, coinbase.value());
+    assert_eq!(2, coinbase.threshold());
+
+    assert!(!handler.process_block(over));
+    assert!(!handler.process_block(under));
+    assert!(handler.process_block(block));
+}
+
+#[test]
+fn fees_overflowing_coinbase_not_claimed() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let mut block = IncompleteBlock::new([0; 32], bob.verifiers(), bob.threshold());
+
+    assert!(!block.claim_fees(u64::from(u32::MAX - COINBASE + 1)));
+    assert_eq!(
+        COINBASE,
+        block.finalize().coinbase().output(0).unwrap().value()
+    );
+
+    let mut block = IncompleteBlock::new([0; 32], bob.verifiers(), bob.threshold());
+    assert!(block.claim_fees(u64::from(u32::MAX - COINBASE)));
+    assert_eq!(
+        u32::MAX,
+        block.finalize().coinbase().output(0).unwrap().value()
+    );
+}
+
+#[test]
+fn accept_block_before_cut_off_age() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let alice = Wallet::random(1, 1);
+    let charlie = Wallet::random(1, 1);
+    let (mut handler, _genesis_tx) = setup_block_handler(&bob);
+    let genesis_block_hash = handler.hash_at_max_height();
+
+    // genesis is the oldest retained block
+    for i in 0..(CUT_OFF_AGE - 1) {
+        let miner = match i % 3 {
+            0 => &bob,
+            1 => &alice,
+            _ => &charlie,
+        };
+        let block = handler.create_block(miner.verifiers(), miner.threshold());
+        assert!(handler.process_block(block));
+    }
+
+    let new_b = handler.create_fork(genesis_block_hash, alice.verifiers(), alice.threshold());
+    assert!(new_b.is_some());
+    assert!(handler.process_block(new_b.unwrap()));
+}
+
+#[test]
+fn reject_block_after_cut_off_age() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let alice = Wallet::random(1, 1);
+    let charlie = Wallet::random(1, 1);
+    let (mut handler, _genesis_tx) = setup_block_handler(&bob);
+    let genesis_block_hash = handler.hash_at_max_height();
+
+    for i in 0..CUT_OFF_AGE {
+        let miner = match i % 3 {
+            0 => &bob,
+            1 => &alice,
+            _ => &charlie,
+        };
+        let block = handler.create_block(miner.verifiers(), miner.threshold());
+        assert!(handler.process_block(block));
+    }
+
+    let new_b = handler.create_fork(genesis_block_hash, alice.verifiers(), alice.threshold());
+    assert!(new_b.is_none());
+}
+
+#[test]
+fn process_shuffled_blocks() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let (mut handler, _) = setup_block_handler(&bob);
+
+    let mut blocks = vec![];
+    let mut prev = handler.hash_at_max_height();
+    for _ in 0..5 {
+        let block = IncompleteBlock::new(prev, bob.verifiers(), bob.threshold()).finalize();
+        prev = block.hash();
+        blocks.push(block);
+    }
+    let tip_hash = prev;
+    let mut shuffled: Vec<_> = blocks.into_iter().map(Some).collect();
+    let shuffled: Vec<_> = [3, 0, 4, 2, 1]
+        .iter()
+        .map(|&idx: &usize| shuffled[idx].take().unwrap())
+        .collect();
+
+    assert_eq!(vec![true; 5], handler.process_blocks(shuffled));
+    assert_eq!(tip_hash, handler.hash_at_max_height());
+
+    let unknown_parent = IncompleteBlock::new([7; 32], bob.verifiers(), bob.threshold()).finalize();
+    assert_eq!(vec![false], handler.process_blocks(vec![unknown_parent]));
+}
+
+#[test]
+fn unmined_txs_expire_from_mempool() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let alice = Wallet::random(1, 1);
+    let (mut handler, genesis_tx) = setup_block_handler(&bob);
+
+    let tx = new_tx(NewTxParams {
+        signer: &bob,
+        inputs: vec![(UTXO::new(genesis_tx.hash(), 0))],
+        outputs: vec![(&alice, 400)],
+        return_to_sender: Some(100),
+    });
+    let tx_hash = tx.hash();
+    handler.process_tx(tx);
+    assert_eq!(
+        Some(0),
+        handler.chain().tx_pool_at_max_height().admitted_in(tx_hash)
+    );
+
+    // created blocks are never processed, so the tx stays unmined
+    for _ in 0..MEMPOOL_EXPIRY {
+        handler.create_block(bob.verifiers(), bob.threshold());
+    }
+    assert!(handler
+        .chain()
+        .tx_pool_at_max_height()
+        .tx(tx_hash)
+        .is_some());
+    handler.create_block(bob.verifiers(), bob.threshold());
+    assert!(handler
+        .chain()
+        .tx_pool_at_max_height()
+        .tx(tx_hash)
+        .is_none());
+}
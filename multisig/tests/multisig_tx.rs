@@ -1,9 +1,22 @@
 mod common;
 
+use std::cell::Cell;
+
 use common::{initialize, new_tx, NewTxParams};
-use multisig::utxo::UTXO;
+use multisig::{
+    handler::valid_signatures,
+    testing::{setup_handler, setup_pool, KeyPair, Wallet},
+    tx::{Output, Tx, TxError, UnsignedTx, MAX_OUTPUTS},
+    utxo::UTXO,
+    wire::{WireError, MAX_WIRE_TX_SIZE, WIRE_VERSION},
+};
+use rsa::{
+    pkcs1v15::{Signature, VerifyingKey},
+    signature::{Signer, Verifier},
+};
+use sha2::Sha256;
 
-use crate::common::{new_tx_first_n_signers_only, setup_handler, Wallet};
+use crate::common::{new_tx_first_n_signers_only, new_tx_multi_signer, SignedInput};
 
 #[test]
 fn normal_tx() {
@@ -22,6 +35,28 @@ fn normal_tx() {
     assert!(handler.is_tx_valid(&tx1));
 }
 
+#[test]
+fn too_many_outputs() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let alice = Wallet::random(1, 1);
+    let (_, genesis_tx) = setup_handler(&bob, 500, 1);
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input(genesis_tx.hash(), 0);
+    for _ in 0..=MAX_OUTPUTS {
+        tx.add_output(1, alice.verifiers(), 1);
+    }
+    assert_eq!(MAX_OUTPUTS + 1, tx.output_count());
+
+    // output at index 256 couldn't be addressed by any input
+    assert_eq!(
+        Err(TxError::TooManyOutputs(MAX_OUTPUTS + 1)),
+        tx.finalize(bob.signers()).map(|tx| tx.hash())
+    );
+}
+
 #[test]
 fn invalid_1_out_of_3() {
     initialize();
@@ -118,3 +153,501 @@ fn valid_3_out_of_3() {
     });
     assert!(handler.is_tx_valid(&tx1));
 }
+
+#[test]
+fn outputs_equality_respects_threshold() {
+    initialize();
+
+    let bob = Wallet::random(3, 2);
+    let verifiers: Vec<_> = bob.verifiers().into_iter().cloned().collect();
+
+    let two_of_three = Output::multisig(100, verifiers.clone(), 2);
+    let same_two_of_three = Output::multisig(100, verifiers.clone(), 2);
+    let three_of_three = Output::multisig(100, verifiers.clone(), 3);
+    let other_value = Output::multisig(50, verifiers, 2);
+
+    assert_eq!(two_of_three, same_two_of_three);
+    assert_ne!(two_of_three, three_of_three);
+    assert_ne!(two_of_three, other_value);
+}
+
+struct CountingVerifier<'a> {
+    inner: VerifyingKey<Sha256>,
+    calls: &'a Cell<usize>,
+}
+
+impl Verifier<Signature> for CountingVerifier<'_> {
+    fn verify(&self, msg: &[u8], signature: &Signature) -> Result<(), rsa::signature::Error> {
+        self.calls.set(self.calls.get() + 1);
+        self.inner.verify(msg, signature)
+    }
+}
+
+#[test]
+fn verifications_bounded_by_threshold() {
+    initialize();
+
+    let bob = Wallet::random(3, 2);
+    let alice = Wallet::random(1, 1);
+    let (handler, genesis_tx) = setup_handler(&bob, 500, 1);
+
+    let tx1 = new_tx(NewTxParams {
+        signer: &bob,
+        inputs: vec![(UTXO::new(genesis_tx.hash(), 0))],
+        outputs: vec![(&alice, 400)],
+        return_to_sender: Some(100),
+    });
+    assert!(handler.is_tx_valid(&tx1));
+
+    let signatures = tx1.inputs().first().unwrap().signatures();
+    assert_eq!(3, signatures.len());
+
+    // signatures are ordered by signers' fingerprints, verifiers are put in
+    // the same order
+    let calls = Cell::new(0);
+    let verifiers: Vec<CountingVerifier> = signatures
+        .iter()
+        .map(|sig| {
+            let vk = bob
+                .verifiers()
+                .into_iter()
+                .find(|vk| vk.verify(&tx1.raw_tx(), sig).is_ok())
+                .unwrap();
+            CountingVerifier {
+                inner: vk.clone(),
+                calls: &calls,
+            }
+        })
+        .collect();
+
+    let valid = valid_signatures(&tx1.raw_tx(), signatures, &verifiers, bob.threshold());
+    assert_eq!(2, valid);
+    // first signature matches first verifier and second one the second,
+    // already matched verifiers are skipped and third signature isn't checked
+    assert_eq!(2, calls.get());
+
+    calls.set(0);
+    let mut reversed = signatures.clone();
+    reversed.reverse();
+    let valid = valid_signatures(&tx1.raw_tx(), &reversed, &verifiers, bob.threshold());
+    assert_eq!(2, valid);
+    // 3rd signature needs 3 tries, 2nd needs 2 (1st verifier is not matched yet)
+    assert_eq!(5, calls.get());
+}
+
+#[test]
+fn one_signature_counts_for_one_verifier() {
+    initialize();
+
+    let bob = Wallet::random(3, 2);
+    let alice = Wallet::random(1, 1);
+    let (_handler, genesis_tx) = setup_handler(&bob, 500, 1);
+
+    let tx1 = new_tx_first_n_signers_only(
+        NewTxParams {
+            signer: &bob,
+            inputs: vec![(UTXO::new(genesis_tx.hash(), 0))],
+            outputs: vec![(&alice, 400)],
+            return_to_sender: Some(100),
+        },
+        1,
+    );
+    let signature = tx1.inputs().first().unwrap().signatures()[0].clone();
+    let duplicated = vec![signature.clone(), signature];
+    let verifiers: Vec<VerifyingKey<Sha256>> = bob.verifiers().into_iter().cloned().collect();
+
+    assert_eq!(
+        1,
+        valid_signatures(&tx1.raw_tx(), &duplicated, &verifiers, 2)
+    );
+}
+
+#[test]
+fn externally_signed_2_out_of_3() {
+    initialize();
+
+    let bob = Wallet::random(3, 2);
+    let alice = Wallet::random(1, 1);
+    let (handler, genesis_tx) = setup_handler(&bob, 500, 1);
+
+    let unsigned = || {
+        let mut tx = UnsignedTx::new();
+        tx.add_input(genesis_tx.hash(), 0);
+        tx.add_output(400, alice.verifiers(), alice.threshold());
+        tx
+    };
+
+    // each co-signer signs the bytes on its own
+    let bytes = unsigned().signing_bytes();
+    let signatures: Vec<Signature> = bob.keys().iter().map(|kp| kp.sk.sign(&bytes)).collect();
+
+    let tx = unsigned()
+        .attach_signatures(vec![vec![signatures[0].clone(), signatures[2].clone()]])
+        .unwrap();
+    assert!(handler.is_tx_valid(&tx));
+
+    let tx = unsigned()
+        .attach_signatures(vec![vec![signatures[1].clone()]])
+        .unwrap();
+    assert!(!handler.is_tx_valid(&tx));
+
+    let tx = unsigned().attach_signatures(vec![]).unwrap();
+    assert!(!handler.is_tx_valid(&tx));
+}
+
+#[test]
+fn input_by_index() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let alice = Wallet::random(1, 1);
+    let (_handler, genesis_tx) = setup_handler(&bob, 100, 2);
+
+    let tx1 = new_tx(NewTxParams {
+        signer: &bob,
+        inputs: vec![
+            UTXO::new(genesis_tx.hash(), 0),
+            UTXO::new(genesis_tx.hash(), 1),
+        ],
+        outputs: vec![(&alice, 200)],
+        return_to_sender: None,
+    });
+
+    assert_eq!(2, tx1.input_len());
+    assert_eq!(1, tx1.input(1).unwrap().output_idx());
+    assert_eq!(genesis_tx.hash(), tx1.input(0).unwrap().output_tx_hash());
+    assert!(tx1.input(2).is_none());
+}
+
+#[test]
+fn get_many_matches_single_lookups() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let (utxo_pool, root_tx) = setup_pool(&bob, 100, 2);
+
+    let utxos = [
+        UTXO::new(root_tx.hash(), 1),
+        UTXO::new(root_tx.hash(), 2),
+        UTXO::new([1; 32], 0),
+        UTXO::new(root_tx.hash(), 0),
+    ];
+    let outputs = utxo_pool.get_many(utxos.iter());
+
+    assert_eq!(utxos.len(), outputs.len());
+    for (utxo, output) in utxos.iter().zip(outputs.iter()) {
+        assert_eq!(utxo_pool.utxo_output(utxo), *output);
+    }
+    assert_eq!(
+        vec![true, false, false, true],
+        outputs.iter().map(Option::is_some).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn display_summarizes_inputs_and_outputs() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let alice = Wallet::random(3, 2);
+    let (_handler, genesis_tx) = setup_handler(&bob, 100, 2);
+
+    let tx = new_tx(NewTxParams {
+        signer: &bob,
+        inputs: vec![UTXO::new(genesis_tx.hash(), 1)],
+        outputs: vec![(&alice, 100)],
+        return_to_sender: None,
+    });
+
+    let hex = |bytes: &[u8]| -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() };
+    let summary = tx.to_string();
+    assert!(summary.starts_with(&format!("tx {}", hex(&tx.hash()))));
+    assert!(summary.contains(&format!("1 inputs [{}:1]", hex(&genesis_tx.hash()[..4]))));
+    assert!(summary.contains(&format!(
+        "1 outputs [100 -> {}]",
+        tx.output(0).unwrap().fingerprint()
+    )));
+    assert!(summary.contains("100 -> 2-of-3 ["));
+}
+
+#[test]
+fn only_tx_without_inputs_is_coinbase() {
+    initialize();
+
+    let bob = Wallet::random(2, 2);
+    let alice = Wallet::random(1, 1);
+
+    let coinbase = Tx::coinbase(100, bob.verifiers(), bob.threshold());
+    assert!(coinbase.is_coinbase());
+
+    let tx = new_tx(NewTxParams {
+        signer: &bob,
+        inputs: vec![UTXO::new(coinbase.hash(), 0)],
+        outputs: vec![(&alice, 100)],
+        return_to_sender: None,
+    });
+    assert!(!tx.is_coinbase());
+}
+
+#[test]
+fn shared_output_spendable_by_each_cosigner() {
+    initialize();
+
+    let shared = Wallet::random(3, 2);
+    let outsider = Wallet::random(1, 1);
+    let (pool, genesis_tx) = setup_pool(&shared, 100, 1);
+
+    for key in shared.keys() {
+        let spendable = pool.utxos_spendable_by(&key.vk);
+        assert_eq!(1, spendable.len());
+        assert_eq!(UTXO::new(genesis_tx.hash(), 0), spendable[0].0);
+        assert_eq!(2, spendable[0].1.threshold());
+    }
+    assert!(pool.utxos_spendable_by(&outsider.keys()[0].vk).is_empty());
+}
+
+#[test]
+fn add_output_returns_its_index() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let alice = Wallet::random(2, 2);
+
+    let mut tx = UnsignedTx::new();
+    tx.add_input([1; 32], 0);
+    assert_eq!(0, tx.add_output(10, alice.verifiers(), 2));
+    let change_idx = tx.add_output(20, bob.verifiers(), 1);
+    assert_eq!(1, change_idx);
+
+    let tx = tx.finalize(bob.signers()).unwrap();
+    let change = tx.output(change_idx).unwrap();
+    assert_eq!(20, change.value());
+    assert_eq!(1, change.threshold());
+    assert_eq!(2, tx.output(0).unwrap().threshold());
+}
+
+#[test]
+fn tx_round_trips_through_wire() {
+    initialize();
+
+    let bob = Wallet::random(3, 2);
+    let alice = Wallet::random(3, 2);
+    let (handler, genesis_tx) = setup_handler(&bob, 500, 2);
+
+    let tx = new_tx_first_n_signers_only(
+        NewTxParams {
+            signer: &bob,
+            inputs: vec![
+                UTXO::new(genesis_tx.hash(), 0),
+                UTXO::new(genesis_tx.hash(), 1),
+            ],
+            outputs: vec![(&alice, 700)],
+            return_to_sender: Some(300),
+        },
+        2,
+    );
+
+    let bytes = tx.to_wire();
+    assert_eq!(WIRE_VERSION, bytes[0]);
+    let decoded = Tx::from_wire(&bytes).unwrap();
+    assert_eq!(tx.hash(), decoded.hash());
+    assert_eq!(tx.outputs(), decoded.outputs());
+    assert_eq!(2, decoded.output(0).unwrap().threshold());
+    for (a, b) in tx.inputs().iter().zip(decoded.inputs().iter()) {
+        assert_eq!(a.output_tx_hash(), b.output_tx_hash());
+        assert_eq!(a.output_idx(), b.output_idx());
+        assert_eq!(2, b.signatures().len());
+        assert_eq!(a.signatures(), b.signatures());
+    }
+    assert_eq!(bytes, decoded.to_wire());
+    assert!(handler.is_tx_valid(&decoded));
+}
+
+#[test]
+fn malformed_wire_tx_rejected() {
+    initialize();
+
+    let bob = Wallet::random(3, 2);
+    let alice = Wallet::random(3, 2);
+    let (_, genesis_tx) = setup_handler(&bob, 500, 1);
+
+    let tx = new_tx_first_n_signers_only(
+        NewTxParams {
+            signer: &bob,
+            inputs: vec![UTXO::new(genesis_tx.hash(), 0)],
+            outputs: vec![(&alice, 500)],
+            return_to_sender: None,
+        },
+        2,
+    );
+    let bytes = tx.to_wire();
+
+    for len in 0..bytes.len() {
+        assert_eq!(
+            Err(WireError::UnexpectedEnd),
+            Tx::from_wire(&bytes[..len]).map(|tx| tx.hash())
+        );
+    }
+
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert_eq!(
+        Err(WireError::TrailingBytes(1)),
+        Tx::from_wire(&longer).map(|tx| tx.hash())
+    );
+
+    let mut wrong_version = bytes.clone();
+    wrong_version[0] = WIRE_VERSION + 1;
+    assert_eq!(
+        Err(WireError::UnsupportedVersion(WIRE_VERSION + 1)),
+        Tx::from_wire(&wrong_version).map(|tx| tx.hash())
+    );
+
+    // output index is part of the hash
+    let mut wrong_hash = bytes.clone();
+    wrong_hash[1 + 32 + 4 + 32] ^= 1;
+    assert_eq!(
+        Err(WireError::HashMismatch),
+        Tx::from_wire(&wrong_hash).map(|tx| tx.hash())
+    );
+
+    assert_eq!(
+        Err(WireError::TooLarge(MAX_WIRE_TX_SIZE + 1)),
+        Tx::from_wire(&vec![WIRE_VERSION; MAX_WIRE_TX_SIZE + 1]).map(|tx| tx.hash())
+    );
+}
+
+#[test]
+fn tampered_threshold_rejected() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let alice = Wallet::random(3, 2);
+    let (_, genesis_tx) = setup_handler(&bob, 500, 1);
+
+    let tx = new_tx(NewTxParams {
+        signer: &bob,
+        inputs: vec![UTXO::new(genesis_tx.hash(), 0)],
+        outputs: vec![(&alice, 500)],
+        return_to_sender: None,
+    });
+    let bytes = tx.to_wire();
+
+    // threshold of the only output is encoded last, anyone could spend the
+    // output if it was lowered to 0
+    let threshold_at = bytes.len() - 8;
+    assert_eq!(2u64.to_be_bytes(), bytes[threshold_at..]);
+    for threshold in [0u64, 1] {
+        let mut tampered = bytes.clone();
+        tampered[threshold_at..].copy_from_slice(&threshold.to_be_bytes());
+        assert_eq!(
+            Err(WireError::HashMismatch),
+            Tx::from_wire(&tampered).map(|tx| tx.hash())
+        );
+    }
+}
+
+#[test]
+fn inputs_signed_by_different_wallets() {
+    initialize();
+
+    let bob = Wallet::random(1, 1);
+    let alice = Wallet::random(3, 2);
+    let charlie = Wallet::random(1, 1);
+    let (mut handler, genesis_tx) = setup_handler(&bob, 500, 1);
+
+    let to_alice = new_tx(NewTxParams {
+        signer: &bob,
+        inputs: vec![UTXO::new(genesis_tx.hash(), 0)],
+        outputs: vec![(&alice, 300)],
+        return_to_sender: Some(200),
+    });
+    assert_eq!(1, handler.handle(vec![&to_alice]).len());
+
+    let from_alice = SignedInput {
+        utxo: UTXO::new(to_alice.hash(), 0),
+        signer: &alice,
+        n_signers: 2,
+    };
+    let from_bob = SignedInput {
+        utxo: UTXO::new(to_alice.hash(), 1),
+        signer: &bob,
+        n_signers: 1,
+    };
+    let combined = new_tx_multi_signer(vec![from_alice, from_bob], vec![(&charlie, 500)]);
+    assert_eq!(2, combined.input(0).unwrap().signatures().len());
+    assert_eq!(1, combined.input(1).unwrap().signatures().len());
+    assert!(handler.is_tx_valid(&combined));
+
+    // alice's input signed by bob isn't valid
+    let wrong_signer = new_tx_multi_signer(
+        vec![
+            SignedInput {
+                utxo: UTXO::new(to_alice.hash(), 0),
+                signer: &bob,
+                n_signers: 1,
+            },
+            SignedInput {
+                utxo: UTXO::new(to_alice.hash(), 1),
+                signer: &bob,
+                n_signers: 1,
+            },
+        ],
+        vec![(&charlie, 500)],
+    );
+    assert!(!handler.is_tx_valid(&wrong_signer));
+}
+
+#[test]
+fn threshold_counts_distinct_keys() {
+    initialize();
+
+    let a = KeyPair::with_key_bits(512);
+    let b = KeyPair::with_key_bits(512);
+    // `a` is listed twice, but it must not be able to unlock the output alone
+    let bob = Wallet::multisig(vec![a.clone(), a.clone(), b.clone()], 2);
+    let alice = Wallet::random_with_key_bits(1, 1, 512);
+    let (handler, genesis_tx) = setup_handler(&bob, 500, 1);
+
+    let spend = |signers: Vec<&KeyPair>| {
+        let mut tx = UnsignedTx::new();
+        tx.add_input(genesis_tx.hash(), 0);
+        tx.add_output(500, alice.verifiers(), alice.threshold());
+        tx.finalize(signers.iter().map(|kp| &kp.sk).collect())
+            .unwrap()
+    };
+
+    assert!(handler.is_tx_valid(&spend(vec![&a, &b])));
+    assert!(handler.is_tx_valid(&spend(vec![&b, &a])));
+    assert!(!handler.is_tx_valid(&spend(vec![&a, &a])));
+    assert!(!handler.is_tx_valid(&spend(vec![&a, &a, &a])));
+    assert!(!handler.is_tx_valid(&spend(vec![&b, &b])));
+}
+
+#[test]
+fn signature_order_doesnt_depend_on_signers_order() {
+    initialize();
+
+    let keys: Vec<KeyPair> = (0..3).map(|_| KeyPair::with_key_bits(512)).collect();
+    let bob = Wallet::multisig(keys.clone(), 2);
+    let alice = Wallet::random_with_key_bits(1, 1, 512);
+    let (handler, genesis_tx) = setup_handler(&bob, 500, 1);
+
+    let spend = |signers: Vec<&KeyPair>| {
+        let mut tx = UnsignedTx::new();
+        tx.add_input(genesis_tx.hash(), 0);
+        tx.add_output(500, alice.verifiers(), alice.threshold());
+        tx.finalize(signers.iter().map(|kp| &kp.sk).collect())
+            .unwrap()
+    };
+
+    let tx = spend(vec![&keys[0], &keys[2]]);
+    let reversed = spend(vec![&keys[2], &keys[0]]);
+    assert!(handler.is_tx_valid(&tx));
+    assert_eq!(tx.to_wire(), reversed.to_wire());
+    assert_ne!(
+        tx.to_wire(),
+        spend(vec![&keys[0], &keys[1]]).to_wire(),
+        "different signers must give different inputs"
+    );
+}